@@ -0,0 +1,104 @@
+//! Errors for AMF file reading
+
+use geometry::impl_load_error;
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use xml_tools::{AttributeError, MAX_PLACED_FACES, MAX_PLACEMENTS};
+
+/// An AMF error wraps all the different types of errors you can get back from reading
+/// an AMF file.
+#[derive(Debug)]
+pub enum AmfError {
+    /// An error that came from IO
+    IOError(Error),
+    /// The file is not well formed XML
+    XmlError(quick_xml::Error),
+    /// The outermost element is not `<amf>`
+    NotAmf(String),
+    /// A required element or attribute is missing
+    MissingValue(String),
+    /// A number could not be read
+    InvalidNumber { token: String, expected: String },
+    /// An id was used which is not defined anywhere in the file
+    UnknownId { kind: &'static str, id: String },
+    /// A triangle refers to a vertex which does not exist
+    IndexOutOfRange { index: usize, count: usize },
+    /// A triangle uses a vertex that could not be read (lenient mode only)
+    UsesBadVertex(usize),
+    /// Constellations contain themselves, or are nested too deeply
+    TooMuchNesting(String),
+    /// Objects are placed too many times, counting copies of copies
+    TooManyPlacements,
+    /// Found no triangles
+    NoFacesFound,
+    /// An error at a known position in the file. Lines and columns start at 1.
+    At { file: Option<String>, line: usize, column: usize, error: Box<AmfError> }
+}
+
+impl_load_error!(AmfError, IOError);
+
+impl Display for AmfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmfError::IOError(e) => write!(f, "Could not read AMF file: {}", e),
+            AmfError::XmlError(e) => write!(f, "AMF file is not valid XML: {}", e),
+            AmfError::NotAmf(root) => write!(f, "expected an <amf> element, found <{}>", root),
+            AmfError::MissingValue(expected) => write!(f, "expected {}", expected),
+            AmfError::InvalidNumber { token, expected } => write!(f, "expected {}, found '{}'", expected, token),
+            AmfError::UnknownId { kind, id } => write!(f, "there is no {} with the id '{}'", kind, id),
+            AmfError::IndexOutOfRange { index, count } =>
+                write!(f, "vertex {} does not exist, the object only has {} vertices", index, count),
+            AmfError::UsesBadVertex(index) => write!(f, "triangle uses vertex {} which could not be read", index),
+            AmfError::TooMuchNesting(id) => write!(f, "constellation '{}' is nested too deeply", id),
+            AmfError::TooManyPlacements =>
+                write!(f, "objects are placed more than {} times, or with more than {} triangles", MAX_PLACEMENTS, MAX_PLACED_FACES),
+            AmfError::NoFacesFound => write!(f, "AMF file has no triangles"),
+            AmfError::At { file, line, column, error } =>
+                write!(f, "{}:{}:{}: {}", file.as_deref().unwrap_or("<amf>"), line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for AmfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AmfError::IOError(e) => Some(e),
+            AmfError::XmlError(e) => Some(e),
+            AmfError::At { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl std::convert::From<Error> for AmfError {
+    fn from(error: Error) -> Self {
+        AmfError::IOError(error)
+    }
+}
+
+impl std::convert::From<quick_xml::Error> for AmfError {
+    fn from(error: quick_xml::Error) -> Self {
+        AmfError::XmlError(error)
+    }
+}
+
+impl std::convert::From<AttributeError> for AmfError {
+    fn from(error: AttributeError) -> Self {
+        match error {
+            AttributeError::Xml(e) => AmfError::XmlError(e),
+            AttributeError::Missing(expected) => AmfError::MissingValue(expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AmfError;
+    use geometry::LoadError;
+
+    #[test]
+    fn test_display() {
+        let e = AmfError::UnknownId { kind: "material", id: "2".to_owned() }.at(7, 9).in_file("part.amf");
+        assert_eq!(e.to_string(), "part.amf:7:9: there is no material with the id '2'");
+    }
+}
@@ -0,0 +1,636 @@
+//! The amf-loader reads Additive Manufacturing File Format (AMF) files and converts them into
+//! a `Scene`.
+//!
+//! An AMF file is XML. Each `<object>` has a list of vertices and one or more volumes of
+//! triangles. `<constellation>`s place objects (and other constellations) with a translation and
+//! rotation. Objects that are not in a constellation are placed once, where they are.
+//!
+//! Colours can be given to objects, volumes, materials and triangles. The most specific one
+//! is used: a triangle's own colour, then its volume's, then the volume's material, then the
+//! object's. Zipped AMF files are extracted by the file-loader before they get here.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::str::FromStr;
+
+use quick_xml::events::BytesStart;
+use xml_tools::{attribute, colour_name, read_elements, required, with_colour, Budget, Colour, ElementHandler, Location, ReadState};
+
+mod errors;
+
+pub use errors::AmfError;
+pub use geometry::{Face, LoadError, Scene, Strictness, Vec3};
+use geometry::{Group, Material, Mesh, Transform, Units};
+
+/// How deeply constellations can be nested inside each other.
+const MAX_NESTING: usize = 16;
+
+/// Elements which are valid AMF but describe things Vitrum does not draw, such as curved
+/// triangle edges and textures. These are skipped with a warning.
+const UNSUPPORTED_ELEMENTS: &[&str] = &["edge", "texmap", "texture", "composite"];
+
+/// Read in and parse an AMF file. The file must already be extracted if it was zipped.
+///
+/// # Arguments
+///
+/// * `filename` - The path to the file to read.
+/// * `strictness` - Whether bad elements are errors or are skipped with a warning.
+pub fn read_amf_file(filename: &str, strictness: Strictness) -> Result<Scene, AmfError> {
+    println!("Reading AMF file {}", filename);
+    read_amf(&fs::read(filename)?, strictness).map_err(|e| e.in_file(filename))
+}
+
+/// Read in and parse AMF data.
+///
+/// # Arguments
+///
+/// * `data` - The XML of the AMF file.
+/// * `strictness` - Whether bad elements are errors or are skipped with a warning.
+pub fn read_amf(data: &[u8], strictness: Strictness) -> Result<Scene, AmfError> {
+    let mut parser = AmfData::new(strictness);
+    read_elements(data, &mut parser)?;
+    parser.into_scene()
+}
+
+struct Object {
+    id: String,
+    name: Option<String>,
+    colour: Option<Colour>,
+    vertices: Vec<Vec3>,
+    /// Vertices which could not be read and have a placeholder (lenient mode only)
+    bad_vertices: HashSet<usize>,
+    volumes: Vec<Volume>,
+}
+
+impl Object {
+    fn triangle_count(&self) -> usize {
+        self.volumes.iter().map(|v| v.triangles.len()).sum()
+    }
+}
+
+struct Volume {
+    name: Option<String>,
+    material: Option<String>,
+    colour: Option<Colour>,
+    triangles: Vec<Triangle>,
+    location: Location,
+}
+
+struct Triangle {
+    vertices: [usize; 3],
+    colour: Option<Colour>,
+}
+
+struct AmfMaterial {
+    id: String,
+    name: Option<String>,
+    colour: Option<Colour>,
+}
+
+struct Constellation {
+    id: String,
+    instances: Vec<Instance>,
+}
+
+/// A placement of an object or constellation in a constellation.
+struct Instance {
+    id: String,
+    transform: Transform,
+    location: Location,
+}
+
+/// Everything read from the file so far.
+struct AmfData {
+    state: ReadState,
+    /// The numbers read for the vertex, triangle or instance being read
+    fields: HashMap<String, f64>,
+    /// Whether one of `fields` could not be read (lenient mode only)
+    bad: bool,
+    /// The numbers read for the colour being read
+    colour_fields: HashMap<String, f64>,
+    colour_bad: bool,
+    /// The colour of the triangle being read
+    triangle_colour: Option<Colour>,
+    /// The type of the metadata being read
+    metadata_type: Option<String>,
+    /// The id placed by the instance being read, and where the instance starts
+    instance: Option<(String, Location)>,
+    units: Option<Units>,
+    metadata: BTreeMap<String, String>,
+    objects: Vec<Object>,
+    materials: Vec<AmfMaterial>,
+    constellations: Vec<Constellation>,
+}
+
+impl ElementHandler for AmfData {
+    type Error = AmfError;
+
+    fn state(&mut self) -> &mut ReadState {
+        &mut self.state
+    }
+
+    /// An element has started.
+    fn start(&mut self, parent: Option<&str>, name: &str, element: &BytesStart) -> Result<(), AmfError> {
+        match (parent, name) {
+            (None, "amf") => if let Some(unit) = attribute(element, "unit")? {
+                self.units = units(&unit);
+                if self.units.is_none() {
+                    self.state.warn(format!("unknown unit '{}'", unit));
+                }
+            },
+            (None, other) => return Err(AmfError::NotAmf(other.to_owned())),
+            (Some("amf"), "object") => self.objects.push(Object {
+                id: required(element, "id", "object")?,
+                name: None,
+                colour: None,
+                vertices: Vec::new(),
+                bad_vertices: HashSet::new(),
+                volumes: Vec::new(),
+            }),
+            (Some("mesh"), "volume") => {
+                let volume = Volume {
+                    name: None,
+                    material: attribute(element, "materialid")?,
+                    colour: None,
+                    triangles: Vec::new(),
+                    location: self.state.location,
+                };
+                if let Some(object) = self.objects.last_mut() {
+                    object.volumes.push(volume);
+                }
+            },
+            (Some("amf"), "material") => self.materials.push(AmfMaterial {
+                id: required(element, "id", "material")?,
+                name: None,
+                colour: None,
+            }),
+            (Some("amf"), "constellation") => self.constellations.push(Constellation {
+                id: required(element, "id", "constellation")?,
+                instances: Vec::new(),
+            }),
+            (Some("constellation"), "instance") => {
+                self.instance = Some((required(element, "objectid", "instance")?, self.state.location));
+                self.fields.clear();
+                self.bad = false;
+            },
+            (Some("vertices"), "vertex") | (Some("volume"), "triangle") => {
+                self.fields.clear();
+                self.bad = false;
+                self.triangle_colour = None;
+            },
+            (_, "color") => {
+                self.colour_fields.clear();
+                self.colour_bad = false;
+            },
+            (_, "metadata") => self.metadata_type = attribute(element, "type")?,
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// The innermost open element has ended.
+    fn end(&mut self, parent: Option<&str>, name: &str) -> Result<(), AmfError> {
+        match (parent.unwrap_or_default(), name) {
+            ("coordinates", axis @ "x") | ("coordinates", axis @ "y") | ("coordinates", axis @ "z") =>
+                self.field(axis, &format!("a number for vertex {}", axis))?,
+            ("triangle", index @ "v1") | ("triangle", index @ "v2") | ("triangle", index @ "v3") =>
+                match self.parse::<usize>("a vertex index")? {
+                    Some(i) => { self.fields.insert(index.to_owned(), i as f64); },
+                    None => self.bad = true
+                },
+            ("instance", field) if ["deltax", "deltay", "deltaz", "rx", "ry", "rz"].contains(&field) =>
+                self.field(field, &format!("a number for the instance {}", field))?,
+            ("color", channel) if ["r", "g", "b", "a"].contains(&channel) =>
+                match self.parse::<f64>(&format!("a number for the colour {}", channel))? {
+                    Some(c) => { self.colour_fields.insert(channel.to_owned(), c); },
+                    None => self.colour_bad = true
+                },
+            ("vertices", "vertex") => self.end_vertex()?,
+            ("volume", "triangle") => self.end_triangle()?,
+            ("constellation", "instance") => self.end_instance()?,
+            (parent, "color") => self.end_colour(parent)?,
+            (parent, "metadata") => {
+                let key = self.metadata_type.take().unwrap_or_default();
+                let value = self.state.text.trim().to_owned();
+                match (parent, key.as_str()) {
+                    ("amf", key) if !key.is_empty() => { self.metadata.insert(key.to_owned(), value); },
+                    ("object", "name") => if let Some(o) = self.objects.last_mut() {
+                        o.name = Some(value);
+                    },
+                    ("volume", "name") => if let Some(v) = self.objects.last_mut().and_then(|o| o.volumes.last_mut()) {
+                        v.name = Some(value);
+                    },
+                    ("material", "name") => if let Some(m) = self.materials.last_mut() {
+                        m.name = Some(value);
+                    },
+                    _ => ()
+                }
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+impl AmfData {
+    fn new(strictness: Strictness) -> AmfData {
+        AmfData {
+            state: ReadState::new(strictness, UNSUPPORTED_ELEMENTS),
+            fields: HashMap::new(),
+            bad: false,
+            colour_fields: HashMap::new(),
+            colour_bad: false,
+            triangle_colour: None,
+            metadata_type: None,
+            instance: None,
+            // Millimetres are the default in AMF
+            units: Some(Units::Millimetres),
+            metadata: BTreeMap::new(),
+            objects: Vec::new(),
+            materials: Vec::new(),
+            constellations: Vec::new(),
+        }
+    }
+
+    /// Read the text of the element which just ended as a number.
+    ///
+    /// # Arguments
+    /// * `expected` - What the number is, for error messages
+    fn parse<T: FromStr>(&mut self, expected: &str) -> Result<Option<T>, AmfError> {
+        let token = self.state.text.trim();
+        match token.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => {
+                let error = AmfError::InvalidNumber { token: token.to_owned(), expected: expected.to_owned() };
+                self.state.fail(error)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Read the text of the element which just ended as a number of the vertex or instance.
+    fn field(&mut self, key: &str, expected: &str) -> Result<(), AmfError> {
+        match self.parse::<f64>(expected)? {
+            Some(value) => { self.fields.insert(key.to_owned(), value); },
+            None => self.bad = true
+        }
+        Ok(())
+    }
+
+    /// Get numbers from `fields`, or None if any of them could not be read.
+    ///
+    /// # Arguments
+    /// * `keys` - The fields to get
+    /// * `expected` - What the fields are, for the error if some are missing
+    fn take_fields(&mut self, keys: &[&str], expected: &str) -> Result<Option<Vec<f64>>, AmfError> {
+        if self.bad {
+            return Ok(None);
+        }
+        let values: Option<Vec<f64>> = keys.iter().map(|k| self.fields.get(*k).copied()).collect();
+        if values.is_none() {
+            self.state.fail(AmfError::MissingValue(expected.to_owned()))?;
+        }
+        Ok(values)
+    }
+
+    fn end_vertex(&mut self) -> Result<(), AmfError> {
+        let coordinates = self.take_fields(&["x", "y", "z"], "<x>, <y> and <z> in the vertex coordinates")?;
+        if let Some(object) = self.objects.last_mut() {
+            match coordinates {
+                Some(c) => object.vertices.push(Vec3::new(c[0], c[1], c[2])),
+                None => {
+                    object.bad_vertices.insert(object.vertices.len());
+                    object.vertices.push(Vec3::zeros());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn end_triangle(&mut self) -> Result<(), AmfError> {
+        let indices = match self.take_fields(&["v1", "v2", "v3"], "<v1>, <v2> and <v3> in the triangle")? {
+            Some(indices) => [indices[0] as usize, indices[1] as usize, indices[2] as usize],
+            None => return Ok(())
+        };
+        let object = match self.objects.last() {
+            Some(object) => object,
+            None => return Ok(())
+        };
+        let error = indices.iter().find_map(|i| if *i >= object.vertices.len() {
+            Some(AmfError::IndexOutOfRange { index: *i, count: object.vertices.len() })
+        } else if object.bad_vertices.contains(i) {
+            Some(AmfError::UsesBadVertex(*i))
+        } else {
+            None
+        });
+        if let Some(e) = error {
+            return self.state.fail(e);
+        }
+
+        let triangle = Triangle { vertices: indices, colour: self.triangle_colour.take() };
+        if let Some(volume) = self.objects.last_mut().and_then(|o| o.volumes.last_mut()) {
+            volume.triangles.push(triangle);
+        }
+        Ok(())
+    }
+
+    fn end_instance(&mut self) -> Result<(), AmfError> {
+        let (id, location) = match self.instance.take() {
+            Some(instance) if !self.bad => instance,
+            _ => return Ok(())
+        };
+        let field = |key: &str| self.fields.get(key).copied().unwrap_or(0.0);
+        let transform = Transform::from_rotation_degrees(Vec3::new(field("rx"), field("ry"), field("rz")),
+                                                         Vec3::new(field("deltax"), field("deltay"), field("deltaz")));
+        let instance = Instance { id, transform, location };
+        if let Some(c) = self.constellations.last_mut() {
+            c.instances.push(instance);
+        }
+        Ok(())
+    }
+
+    /// A colour has ended. It belongs to the element it is in.
+    fn end_colour(&mut self, parent: &str) -> Result<(), AmfError> {
+        if self.colour_bad {
+            return Ok(());
+        }
+        let channel = |c: &str| self.colour_fields.get(c).copied();
+        let colour = match (channel("r"), channel("g"), channel("b")) {
+            (Some(r), Some(g), Some(b)) => [r, g, b, channel("a").unwrap_or(1.0)],
+            _ => return self.state.fail(AmfError::MissingValue("<r>, <g> and <b> in the colour".to_owned()))
+        };
+        match parent {
+            "object" => if let Some(o) = self.objects.last_mut() {
+                o.colour = Some(colour);
+            },
+            "volume" => if let Some(v) = self.objects.last_mut().and_then(|o| o.volumes.last_mut()) {
+                v.colour = Some(colour);
+            },
+            "material" => if let Some(m) = self.materials.last_mut() {
+                m.colour = Some(colour);
+            },
+            "triangle" => self.triangle_colour = Some(colour),
+            // Vertex colours cannot be drawn
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// Find where every object is placed.
+    fn placements(&mut self) -> Result<Vec<(usize, Transform)>, AmfError> {
+        let placed: HashSet<String> = self.constellations.iter()
+                                          .flat_map(|c| c.instances.iter().map(|i| i.id.clone()))
+                                          .collect();
+        let mut placements = Vec::new();
+        let mut budget = Budget::default();
+        for c in 0..self.constellations.len() {
+            if !placed.contains(&self.constellations[c].id) {
+                self.place(c, Transform::identity(), 0, &mut placements, &mut budget)?;
+            }
+        }
+        for (i, object) in self.objects.iter().enumerate() {
+            if !placed.contains(&object.id) {
+                if !budget.place(object.triangle_count()) {
+                    return Err(AmfError::TooManyPlacements);
+                }
+                placements.push((i, Transform::identity()));
+            }
+        }
+        Ok(placements)
+    }
+
+    /// Place the contents of a constellation.
+    ///
+    /// # Arguments
+    /// * `constellation` - The index of the constellation
+    /// * `transform` - Where the constellation is placed
+    /// * `depth` - How many constellations this one is inside
+    /// * `placements` - Where to add the objects, with their transforms
+    /// * `budget` - What has been placed so far
+    fn place(&mut self, constellation: usize, transform: Transform, depth: usize,
+             placements: &mut Vec<(usize, Transform)>, budget: &mut Budget) -> Result<(), AmfError> {
+        let c = &self.constellations[constellation];
+        if depth > MAX_NESTING {
+            return Err(AmfError::TooMuchNesting(c.id.clone()));
+        }
+        let instances: Vec<(String, Transform, Location)> = c.instances.iter()
+                                                             .map(|i| (i.id.clone(), i.transform.then(&transform), i.location))
+                                                             .collect();
+        for (id, transform, location) in instances {
+            let object = self.objects.iter().position(|o| o.id == id);
+            let faces = object.map_or(0, |o| self.objects[o].triangle_count());
+            if !budget.place(faces) {
+                return Err(location.error(AmfError::TooManyPlacements));
+            }
+            if let Some(object) = object {
+                placements.push((object, transform));
+            } else if let Some(inner) = self.constellations.iter().position(|c| c.id == id) {
+                self.place(inner, transform, depth + 1, placements, budget)?;
+            } else {
+                self.state.fail_at(AmfError::UnknownId { kind: "object or constellation", id }, location)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_scene(mut self) -> Result<Scene, AmfError> {
+        let placements = self.placements()?;
+        let mut scene = Scene::new();
+
+        let mut material_ids = HashMap::new();
+        for m in &self.materials {
+            let name = m.name.clone().unwrap_or_else(|| format!("material {}", m.id));
+            let mut material = Material::named(&name);
+            if let Some(c) = m.colour {
+                material = with_colour(material, c);
+            }
+            material_ids.insert(m.id.clone(), scene.add_material(material));
+        }
+        let mut palette = HashMap::new();
+        let mut colour_material = |scene: &mut Scene, colour: Colour| -> usize {
+            let name = colour_name(&colour);
+            *palette.entry(name.clone()).or_insert_with(|| scene.add_material(with_colour(Material::named(&name), colour)))
+        };
+
+        // The material of each volume, found once however many times the object is placed
+        let mut volume_materials = HashMap::new();
+        let mut errors = Vec::new();
+        for (o, object) in self.objects.iter().enumerate() {
+            for (v, volume) in object.volumes.iter().enumerate() {
+                let material = match (volume.colour, &volume.material) {
+                    (Some(c), _) => Some(colour_material(&mut scene, c)),
+                    (None, Some(id)) => match material_ids.get(id) {
+                        Some(m) => Some(*m),
+                        None => {
+                            errors.push((AmfError::UnknownId { kind: "material", id: id.clone() }, volume.location));
+                            None
+                        }
+                    },
+                    (None, None) => None
+                };
+                volume_materials.insert((o, v), material);
+            }
+        }
+        for (error, location) in errors {
+            self.state.fail_at(error, location)?;
+        }
+
+        for (o, transform) in placements {
+            let object = &self.objects[o];
+            let mut faces = Vec::new();
+            let mut groups = Vec::new();
+            for (v, volume) in object.volumes.iter().enumerate() {
+                let start = faces.len();
+                for t in &volume.triangles {
+                    let [a, b, c] = t.vertices;
+                    let vertex = |i: usize| transform.apply(object.vertices[i]);
+                    let material = t.colour.map(|c| colour_material(&mut scene, c)).or(volume_materials[&(o, v)]);
+                    faces.push(Face::from_points(vertex(a), vertex(b), vertex(c)).with_material(material));
+                }
+                if let Some(name) = &volume.name {
+                    groups.push(Group::from_indices(name, start..faces.len()));
+                }
+            }
+            let name = object.name.clone().unwrap_or_else(|| format!("object {}", object.id));
+            let mut mesh = Mesh::new(Some(name), faces);
+            mesh.groups = groups;
+            mesh.material = object.colour.map(|c| colour_material(&mut scene, c));
+            scene.meshes.push(mesh);
+        }
+
+        if scene.face_count() == 0 {
+            return Err(AmfError::NoFacesFound);
+        }
+        scene.units = self.units;
+        scene.metadata = self.metadata;
+        scene.warnings = self.state.warnings;
+        Ok(scene)
+    }
+}
+
+/// The units named by the `unit` attribute.
+fn units(unit: &str) -> Option<Units> {
+    match unit.to_ascii_lowercase().as_str() {
+        "micron" | "micrometer" | "micrometre" => Some(Units::Micrometres),
+        "millimeter" | "millimetre" => Some(Units::Millimetres),
+        "meter" | "metre" => Some(Units::Metres),
+        "inch" => Some(Units::Inches),
+        "feet" | "foot" => Some(Units::Feet),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AmfError, Strictness, Vec3};
+    use geometry::Units;
+
+    /// A triangle (object 0) with two volumes, placed twice by a constellation.
+    const TRIANGLES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<amf unit="inch" version="1.1">
+  <metadata type="name">Test</metadata>
+  <object id="0">
+    <metadata type="name">Triangle</metadata>
+    <color><r>0</r><g>1</g><b>0</b></color>
+    <mesh>
+      <vertices>
+        <vertex><coordinates><x>0</x><y>0</y><z>0</z></coordinates></vertex>
+        <vertex><coordinates><x>1</x><y>0</y><z>0</z></coordinates></vertex>
+        <vertex><coordinates><x>0</x><y>1</y><z>0</z></coordinates></vertex>
+      </vertices>
+      <volume materialid="2">
+        <metadata type="name">Top</metadata>
+        <triangle><v1>0</v1><v2>1</v2><v3>2</v3></triangle>
+        <triangle><color><r>1</r><g>0</g><b>0</b></color><v1>0</v1><v2>2</v2><v3>1</v3></triangle>
+      </volume>
+      <volume>
+        <triangle><v1>1</v1><v2>2</v2><v3>0</v3></triangle>
+      </volume>
+    </mesh>
+  </object>
+  <material id="2">
+    <metadata type="name">Steel</metadata>
+    <color><r>0.5</r><g>0.5</g><b>0.5</b><a>0.5</a></color>
+  </material>
+  <constellation id="3">
+    <instance objectid="0"><deltax>10</deltax><deltay>0</deltay><deltaz>0</deltaz></instance>
+    <instance objectid="0"><deltax>0</deltax><deltay>0</deltay><deltaz>0</deltaz><rz>90</rz></instance>
+  </constellation>
+</amf>
+"#;
+
+    #[test]
+    fn test_read() {
+        let scene = super::read_amf(TRIANGLES.as_bytes(), Strictness::Strict).unwrap();
+        assert_eq!(scene.units, Some(Units::Inches));
+        assert_eq!(scene.metadata["name"], "Test");
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.face_count(), 6);
+
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.display_name(), "Triangle");
+        assert_eq!(mesh.groups.len(), 1);
+        assert_eq!(mesh.groups[0].name, "Top");
+        assert_eq!(mesh.groups[0].ranges, vec![0..2]);
+        assert_eq!(mesh.faces[0].vertices()[1], Vec3::new(11.0, 0.0, 0.0));
+
+        // Triangle colour, then material, then the object colour (for the mesh)
+        let names: Vec<Option<&str>> = mesh.faces.iter()
+                                           .map(|f| f.material().map(|m| scene.materials[m].name.as_str()))
+                                           .collect();
+        assert_eq!(names, vec![Some("Steel"), Some("#ff0000"), None]);
+        assert_eq!(scene.materials[mesh.material.unwrap()].name, "#00ff00");
+        assert_eq!(scene.materials[0].opacity, 0.5);
+
+        let rotated = scene.meshes[1].faces[0].vertices()[1];
+        assert!((rotated - Vec3::new(0.0, 1.0, 0.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_objects_without_constellation() {
+        let data = TRIANGLES.replace("objectid=\"0\"", "objectid=\"9\"");
+        let e = super::read_amf(data.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<amf>:28:5: there is no object or constellation with the id '9'");
+
+        // Without any instances the object is placed once
+        let scene = super::read_amf(data.as_bytes(), Strictness::Lenient).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        match super::read_amf(b"<stl></stl>", Strictness::Strict) {
+            Err(AmfError::At { line: 1, column: 1, error, .. }) => assert!(matches!(*error, AmfError::NotAmf(_))),
+            other => panic!("Expected not AMF, got {:?}", other)
+        }
+
+        let bad_index = TRIANGLES.replace("<v3>0</v3>", "<v3>3</v3>");
+        let e = super::read_amf(bad_index.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<amf>:19:49: vertex 3 does not exist, the object only has 3 vertices");
+
+        let bad_number = TRIANGLES.replace("<x>1</x>", "<x>one</x>");
+        let e = super::read_amf(bad_number.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<amf>:10:36: expected a number for vertex x, found 'one'");
+
+        // The bad vertex is used by every triangle
+        let scene = super::read_amf(bad_number.as_bytes(), Strictness::Lenient);
+        assert!(matches!(scene, Err(AmfError::NoFacesFound)));
+
+        assert!(matches!(super::read_amf(b"<amf><object", Strictness::Strict), Err(AmfError::At { .. })));
+    }
+
+    #[test]
+    fn test_too_many_placements() {
+        // Each constellation places the one before 10 times, so the triangle is placed millions of times
+        let mut data = TRIANGLES.replace("</amf>", "");
+        for id in 4..10 {
+            let instances = format!(r#"<instance objectid="{}"/>"#, id - 1).repeat(10);
+            data += &format!(r#"<constellation id="{}">{}</constellation>"#, id, instances);
+        }
+        data += "</amf>";
+        let e = super::read_amf(data.as_bytes(), Strictness::Lenient).unwrap_err();
+        assert!(e.to_string().ends_with("objects are placed more than 100000 times, or with more than 10000000 triangles"),
+                "{}", e);
+    }
+}
@@ -1,4 +1,4 @@
-use geometry::{Plane, Ray, Collision, Vec3};
+use geometry::{Bounded, Plane, Ray, Collision, Vec3};
 
 use num::Float;
 
@@ -15,29 +15,29 @@ struct Cost<T> {
     cost: f64
 }
 
-impl <T: Plane> Ord for Cost<&BoundingVolumeHierarchy<T>> {
+impl <T: Bounded> Ord for Cost<&BoundingVolumeHierarchy<T>> {
     fn cmp(&self, other: &Self) -> Ordering {
         // Panics if it is NAN. You cannot have a NAN cost.
         self.partial_cmp(other).unwrap()
     }
 }
 
-impl <T: Plane> PartialOrd for Cost<&BoundingVolumeHierarchy<T>> {
+impl <T: Bounded> PartialOrd for Cost<&BoundingVolumeHierarchy<T>> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         other.cost.partial_cmp(&self.cost)
     }
 }
 
-impl <T: Plane> Eq for Cost<&BoundingVolumeHierarchy<T>> {}
+impl <T: Bounded> Eq for Cost<&BoundingVolumeHierarchy<T>> {}
 
-impl <T: Plane> PartialEq for Cost<&BoundingVolumeHierarchy<T>> {
+impl <T: Bounded> PartialEq for Cost<&BoundingVolumeHierarchy<T>> {
     fn eq(&self, other: &Self) -> bool {
         self.cost == other.cost
     }
 }
 
 #[derive(Debug)]
-pub enum BoundingVolumeHierarchy<T: Plane> {
+pub enum BoundingVolumeHierarchy<T: Bounded> {
     Node {
         min: Vec3,
         max: Vec3,
@@ -48,7 +48,7 @@ pub enum BoundingVolumeHierarchy<T: Plane> {
     Empty
 }
 
-impl <T: Plane + Display> BoundingVolumeHierarchy<T> {
+impl <T: Bounded + Display> BoundingVolumeHierarchy<T> {
     pub fn pretty_print(&self) {
         self.pretty_print_helper(&"".to_owned())
     }
@@ -68,7 +68,7 @@ impl <T: Plane + Display> BoundingVolumeHierarchy<T> {
     }
 }
 
-impl <T: Plane> BoundingVolumeHierarchy<T> {
+impl <T: Bounded> BoundingVolumeHierarchy<T> {
     pub fn leaf(face: T) -> BoundingVolumeHierarchy<T> {
         BoundingVolumeHierarchy::Child(face)
     }
@@ -86,12 +86,56 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
         }
     }
 
+    /// Find everything whose bounding box overlaps (or touches) a box.
+    ///
+    /// # Arguments
+    /// * `min` - The smallest corner of the box
+    /// * `max` - The largest corner of the box
+    pub fn overlapping(&self, min: &Vec3, max: &Vec3) -> Vec<&T> {
+        let mut found = Vec::new();
+        self.overlapping_helper(min, max, &mut found);
+        found
+    }
+
+    fn overlapping_helper<'a>(&'a self, min: &Vec3, max: &Vec3, found: &mut Vec<&'a T>) {
+        let overlaps = |a_min: &Vec3, a_max: &Vec3| {
+            a_min.iter().zip(max.iter()).all(|(a, b)| a <= b) &&
+                min.iter().zip(a_max.iter()).all(|(a, b)| a <= b)
+        };
+        match self {
+            BoundingVolumeHierarchy::Empty => (),
+            BoundingVolumeHierarchy::Child(p) => {
+                if overlaps(&p.min_extents(), &p.max_extents()) {
+                    found.push(p);
+                }
+            },
+            BoundingVolumeHierarchy::Node { min: node_min, max: node_max, left, right } => {
+                if overlaps(node_min, node_max) {
+                    left.overlapping_helper(min, max, found);
+                    right.overlapping_helper(min, max, found);
+                }
+            }
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            BoundingVolumeHierarchy::Empty => 0,
+            BoundingVolumeHierarchy::Child(_) => 1,
+            BoundingVolumeHierarchy::Node { left, right, .. } => left.size() + right.size()
+        }
+    }
+}
+
+impl <T: Plane> BoundingVolumeHierarchy<T> {
+    #[allow(clippy::too_many_arguments)]
     fn collide_child<'a>(&'a self, ray: &Ray, t: Vec3, s: Vec3,
                      min_t: &mut f64, result: &mut Option<Collision>,
-                     heap: &mut BinaryHeap<Cost<&'a BoundingVolumeHierarchy<T>>>) {
+                     heap: &mut BinaryHeap<Cost<&'a BoundingVolumeHierarchy<T>>>, tests: &mut usize) {
         match self {
             BoundingVolumeHierarchy::Empty => (),
             BoundingVolumeHierarchy::Child(p) => {
+                *tests += 1;
                 let hit = p.hits(ray);
                 if let Some(c) = hit {
                     if c.distance < *min_t {
@@ -101,7 +145,8 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
                 }
             },
             BoundingVolumeHierarchy::Node { .. } => {
-                let (h, t) = self.collide_box(t, s);
+                *tests += 1;
+                let (h, t) = self.collide_box(ray, t, s);
                 if h && t < *min_t {
                     heap.push(Cost { data: &self , cost: t});
                 }
@@ -109,13 +154,16 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
         }
     }
 
-    fn collide(&self, ray: &Ray, t: Vec3, s: Vec3) -> Option<Collision> {
+    /// The nearest hit below this node, counting the boxes and faces tested in `tests`.
+    fn collide(&self, ray: &Ray, t: Vec3, s: Vec3, tests: &mut usize) -> Option<Collision> {
         if let BoundingVolumeHierarchy::Node {..} = self {
             let mut heap: BinaryHeap<Cost<&BoundingVolumeHierarchy<T>>> = BinaryHeap::new();
+            // Boxes beyond the end of the ray are culled by collide_box
             let mut min_t = f64::infinity();
             let mut result: Option<Collision> = None;
             {
-                let (h, t) = self.collide_box(t, s);
+                *tests += 1;
+                let (h, t) = self.collide_box(ray, t, s);
                 if h {
                     heap.push(Cost{ data: &self, cost: t});
                 }
@@ -126,8 +174,8 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
                     return result;
                 }
                 if let BoundingVolumeHierarchy::Node {left, right, ..} = element.data {
-                    left.collide_child(ray, t, s, &mut min_t, &mut result, &mut heap);
-                    right.collide_child(ray, t, s, &mut min_t, &mut result, &mut heap);
+                    left.collide_child(ray, t, s, &mut min_t, &mut result, &mut heap, tests);
+                    right.collide_child(ray, t, s, &mut min_t, &mut result, &mut heap, tests);
                 } else {
                     panic!("Non node child put into queue");
                 }
@@ -137,7 +185,32 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
         None
     }
 
-    fn collide_box(&self, t: Vec3, s: Vec3) -> (bool, f64) {
+    /// Depth first search for any hit within the ray's interval, stopping at the first one.
+    /// There is no need to visit the boxes in order, as any hit will do.
+    fn any_hit(&self, ray: &Ray, t: Vec3, s: Vec3) -> bool {
+        let mut stack = vec![self];
+        while let Some(element) = stack.pop() {
+            match element {
+                BoundingVolumeHierarchy::Empty => (),
+                BoundingVolumeHierarchy::Child(p) => {
+                    if p.occluded(ray) {
+                        return true;
+                    }
+                },
+                BoundingVolumeHierarchy::Node { left, right, .. } => {
+                    if element.collide_box(ray, t, s).0 {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether the ray passes through the box of this node within the ray's interval, and
+    /// the distance at which it enters (or starts, if it starts inside the box).
+    fn collide_box(&self, ray: &Ray, t: Vec3, s: Vec3) -> (bool, f64) {
         if let BoundingVolumeHierarchy::Node {min, max, ..} = self {
             let mut min_t = f64::neg_infinity();
             let mut max_t = f64::infinity();
@@ -161,12 +234,10 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
             max_t = f64::min(max_t, u);
 
             return if  !(min_t.is_infinite() && min_t.is_sign_positive()) && max_t >= min_t {
-                if max_t <= 0.0 {
+                if max_t < ray.t_min || min_t > ray.t_max {
                     (false, f64::infinity())
-                } else if min_t >= 0.0 {
-                    (true, min_t)
                 } else {
-                    (true, 0.0)
+                    (true, f64::max(min_t, ray.t_min))
                 }
             } else {
                 (false, f64::infinity())
@@ -194,16 +265,26 @@ impl <T: Plane> BoundingVolumeHierarchy<T> {
         (t, s)
     }
 
-    pub fn size(&self) -> usize {
+    /// How many boxes and faces are tested to find the first hit along a ray, for seeing
+    /// where the hierarchy is slow.
+    pub fn traversal_cost(&self, ray: &Ray) -> usize {
+        let mut tests = 0;
         match self {
-            BoundingVolumeHierarchy::Empty => 0,
-            BoundingVolumeHierarchy::Child(_) => 1,
-            BoundingVolumeHierarchy::Node { left, right, .. } => left.size() + right.size()
+            BoundingVolumeHierarchy::Empty => (),
+            BoundingVolumeHierarchy::Child(f) => {
+                tests += 1;
+                f.hits(ray);
+            },
+            BoundingVolumeHierarchy::Node { .. } => {
+                let (t, s) = <BoundingVolumeHierarchy<T>>::compute_t_s(ray);
+                self.collide(ray, t, s, &mut tests);
+            }
         }
+        tests
     }
 }
 
-impl <T: Plane + Clone> BoundingVolumeHierarchy<T> {
+impl <T: Bounded + Clone> BoundingVolumeHierarchy<T> {
     pub fn new(faces: Vec<T>) -> BoundingVolumeHierarchy<T> {
         <BoundingVolumeHierarchy<T>>::new_helper(faces, 0)
     }
@@ -244,11 +325,24 @@ impl <T: Plane> Plane for BoundingVolumeHierarchy<T> {
             BoundingVolumeHierarchy::Child(f) => f.hits(ray),
             BoundingVolumeHierarchy::Node { .. } => {
                 let (t, s) = <BoundingVolumeHierarchy<T>>::compute_t_s(ray);
-                self.collide(ray, t, s)
+                self.collide(ray, t, s, &mut 0)
+            }
+        }
+    }
+
+    fn occluded(&self, ray: &Ray) -> bool {
+        match self {
+            BoundingVolumeHierarchy::Empty => false,
+            BoundingVolumeHierarchy::Child(f) => f.occluded(ray),
+            BoundingVolumeHierarchy::Node { .. } => {
+                let (t, s) = <BoundingVolumeHierarchy<T>>::compute_t_s(ray);
+                self.any_hit(ray, t, s)
             }
         }
     }
+}
 
+impl <T: Bounded> Bounded for BoundingVolumeHierarchy<T> {
     fn min_extents(&self) -> Vec3 {
         match self {
             BoundingVolumeHierarchy::Empty => Vec3::new(f64::infinity(), f64::infinity(), f64::infinity()),
@@ -288,7 +382,7 @@ impl <T: Plane> Plane for BoundingVolumeHierarchy<T> {
 mod tests {
     use super::BoundingVolumeHierarchy;
     use super::Vec3;
-    use geometry::{Face, Ray};
+    use geometry::{Face, Plane, Ray};
     use std::f64;
 
     #[test]
@@ -341,6 +435,63 @@ mod tests {
         assert!(b < f64::INFINITY);
     }
 
+    #[test]
+    fn test_overlapping() {
+        let triangle = |x: f64| Face::from_points(Vec3::new(x, 0.0, 0.0),
+                                                  Vec3::new(x + 1.0, 0.0, 0.0),
+                                                  Vec3::new(x, 1.0, 0.0));
+        let bvh = BoundingVolumeHierarchy::new((0..8).map(|i| triangle(i as f64 * 2.0)).collect());
+        let found = bvh.overlapping(&Vec3::new(2.5, -1.0, -1.0), &Vec3::new(4.0, 1.0, 1.0));
+        let mut xs: Vec<f64> = found.iter().map(|f| f.vertices()[0].x).collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(xs, vec![2.0, 4.0]);
+        assert!(bvh.overlapping(&Vec3::new(100.0, 0.0, 0.0), &Vec3::new(101.0, 1.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn test_occluded() {
+        let wall = |z: f64| Face::from_points(Vec3::new(-1.0, -1.0, z),
+                                              Vec3::new( 1.0, -1.0, z),
+                                              Vec3::new( 0.0,  1.0, z));
+        let bvh = BoundingVolumeHierarchy::new((1..9).map(|i| wall(i as f64 * 2.0)).collect());
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::z());
+        assert!(bvh.occluded(&ray.clone().with_interval(0.0, 2.5)));
+        assert!(!bvh.occluded(&ray.clone().with_interval(0.0, 1.9)));
+        assert!(!bvh.occluded(&ray.clone().with_interval(2.1, 3.9)));
+        assert!(!bvh.occluded(&Ray::new(Vec3::new(0.0, 0.0, 20.0), Vec3::z())));
+        assert!(bvh.occluded(&Ray::new(Vec3::new(0.0, 0.0, 20.0), -Vec3::z())));
+        assert!(!bvh.occluded(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::z())));
+    }
+
+    #[test]
+    fn test_interval() {
+        let wall = |z: f64| Face::from_points(Vec3::new(-1.0, -1.0, z),
+                                              Vec3::new( 1.0, -1.0, z),
+                                              Vec3::new( 0.0,  1.0, z));
+        let bvh = BoundingVolumeHierarchy::new((1..9).map(|i| wall(i as f64 * 2.0)).collect());
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::z());
+        assert_eq!(bvh.hits(&ray).unwrap().distance, 2.0);
+        assert_eq!(bvh.hits(&ray.clone().with_interval(5.0, f64::INFINITY)).unwrap().distance, 6.0);
+        assert_eq!(bvh.hits(&ray.clone().with_interval(6.0, 6.0)).unwrap().distance, 6.0);
+        assert!(bvh.hits(&ray.clone().with_interval(6.5, 7.5)).is_none());
+        assert!(bvh.hits(&ray.with_interval(16.5, 100.0)).is_none());
+    }
+
+    #[test]
+    fn test_traversal_cost() {
+        let wall = |z: f64| Face::from_points(Vec3::new(-1.0, -1.0, z),
+                                              Vec3::new( 1.0, -1.0, z),
+                                              Vec3::new( 0.0,  1.0, z));
+        let bvh = BoundingVolumeHierarchy::new((1..9).map(|i| wall(i as f64 * 2.0)).collect());
+        // Only the root box for a miss
+        assert_eq!(bvh.traversal_cost(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::z())), 1);
+        // The nearest wall is found without testing every box and face
+        let cost = bvh.traversal_cost(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::z()));
+        assert!(cost > 1 && cost < 15, "{}", cost);
+        assert_eq!(BoundingVolumeHierarchy::leaf(wall(1.0)).traversal_cost(&Ray::new(Vec3::zeros(), Vec3::z())), 1);
+        assert_eq!(BoundingVolumeHierarchy::<Face>::empty().traversal_cost(&Ray::new(Vec3::zeros(), Vec3::z())), 0);
+    }
+
     /// Test the ray hitting when the slabs are hit max first then min
     #[test]
     fn test_hit2() {
@@ -0,0 +1,262 @@
+//! Detection and decompression of compressed models (gzip, zstd and zip archives).
+
+use super::{MeshError, Source};
+use super::source::normalise;
+
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Models can be compressed inside compressed files (e.g. a `.stl.gz` in a `.zip`).
+/// This limits how deep that goes so a malicious file cannot recurse forever.
+const MAX_NESTING: usize = 4;
+
+/// The largest a decompressed file can be. Compression can make a file thousands of times
+/// smaller, so without a limit a small file could use up all the memory.
+const MAX_DECOMPRESSED_SIZE: u64 = 1 << 30;
+
+/// The compression formats that can be read.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Zip
+}
+
+impl Compression {
+    /// Work out the compression from the magic number at the start of the data.
+    ///
+    /// # Arguments
+    /// * `data` - the start of the file
+    pub fn detect(data: &[u8]) -> Option<Compression> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if data.starts_with(b"PK\x03\x04") {
+            Some(Compression::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// The file extensions used for this compression format.
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Compression::Gzip => &["gz", "gzip"],
+            Compression::Zstd => &["zst", "zstd"],
+            Compression::Zip => &["zip"],
+        }
+    }
+}
+
+/// Decompress a model until it is no longer compressed. Uncompressed models are returned as is.
+///
+/// For gzip and zstd, the name of the model has the compression extension removed
+/// (`cube.stl.gz` becomes `cube.stl`). For zip archives, the first entry `is_model` accepts is
+/// used as the model and the rest of the archive is used to resolve references from it. Other
+/// entries are only extracted when the model refers to them.
+///
+/// # Arguments
+/// * `source` - The model to decompress
+/// * `is_model` - Check whether a file in an archive (by name) is a model that can be loaded
+pub fn decompress(source: Source, is_model: &dyn Fn(&str) -> bool) -> Result<Source, MeshError> {
+    let mut source = source;
+    for _ in 0..MAX_NESTING {
+        let compression = match Compression::detect(source.data()) {
+            None => return Ok(source),
+            Some(c) => c
+        };
+        println!("Decompressing {} ({:?})", source.name(), compression);
+
+        source = match compression {
+            Compression::Gzip => {
+                let data = read_limited(MultiGzDecoder::new(source.data()), MAX_DECOMPRESSED_SIZE)
+                                       .map_err(MeshError::DecompressionError)?;
+                let name = strip_extension(source.name(), compression);
+                source.with_content(name, data)
+            },
+            Compression::Zstd => {
+                let data = zstd::stream::read::Decoder::new(source.data())
+                                       .and_then(|decoder| read_limited(decoder, MAX_DECOMPRESSED_SIZE))
+                                       .map_err(MeshError::DecompressionError)?;
+                let name = strip_extension(source.name(), compression);
+                source.with_content(name, data)
+            },
+            Compression::Zip => extract_model(source.data(), is_model)?
+        };
+    }
+    Err(MeshError::TooMuchNesting)
+}
+
+/// Remove the compression extension from a name, if it has one.
+///
+/// # Arguments
+/// * `name` - the name to strip
+/// * `compression` - the compression format used
+fn strip_extension(name: &str, compression: Compression) -> String {
+    let path = Path::new(name);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if compression.extensions().iter().any(|c| c.eq_ignore_ascii_case(e)) =>
+            path.with_extension("").to_string_lossy().into_owned(),
+        _ => name.to_owned()
+    }
+}
+
+/// Read everything from a decompressing reader, failing if it is larger than a limit.
+///
+/// # Arguments
+/// * `reader` - the decompressed data
+/// * `limit` - the most bytes to read
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(Error::new(ErrorKind::InvalidData, format!("it is larger than {} bytes when decompressed", limit)));
+    }
+    Ok(data)
+}
+
+/// Find the model in a zip archive and extract it.
+///
+/// # Arguments
+/// * `data` - the zip archive
+/// * `is_model` - Check whether a file in the archive (by name) is a model that can be loaded
+fn extract_model(data: &[u8], is_model: &dyn Fn(&str) -> bool) -> Result<Source, MeshError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut names = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        let name = normalise(file.name());
+        // Skip the resource forks macOS adds to archives
+        if !file.is_dir() && !name.starts_with("__MACOSX/") {
+            names.push((i, name));
+        }
+    }
+
+    // Prefer a model in a format we know, otherwise try compressed files in the archive
+    let mut model = names.iter().find(|(_, n)| is_model(n)).cloned();
+    if model.is_none() {
+        for (i, name) in &names {
+            let mut magic = Vec::new();
+            archive.by_index(*i)?.take(4).read_to_end(&mut magic)?;
+            if Compression::detect(&magic).is_some() {
+                model = Some((*i, name.clone()));
+                break;
+            }
+        }
+    }
+    let (index, name) = model.ok_or(MeshError::NoModelInArchive)?;
+    println!("Reading {} from archive", name);
+    let content = read_limited(archive.by_index(index)?, MAX_DECOMPRESSED_SIZE).map_err(MeshError::DecompressionError)?;
+    Ok(Source::from_archive(&name, content, Rc::new(data.to_vec())))
+}
+
+/// Extract one file from a zip archive. If no file has exactly the name, one whose name only
+/// differs in case is used.
+///
+/// # Arguments
+/// * `data` - the zip archive
+/// * `path` - the normalised path of the file
+pub(crate) fn extract_file(data: &[u8], path: &str) -> Result<Option<Vec<u8>>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut names = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        names.push(normalise(archive.by_index(i)?.name()));
+    }
+    let index = names.iter().position(|n| n == path)
+                     .or_else(|| names.iter().position(|n| n.eq_ignore_ascii_case(path)));
+    match index {
+        Some(i) => read_limited(archive.by_index(i)?, MAX_DECOMPRESSED_SIZE).map(Some),
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, read_limited, strip_extension, Compression};
+    use crate::Source;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(&gzip(b"solid ")), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(&zstd::stream::encode_all(&b"solid "[..], 0).unwrap()), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(b"PK\x03\x04rest"), Some(Compression::Zip));
+        assert_eq!(Compression::detect(b"solid "), None);
+        assert_eq!(Compression::detect(b""), None);
+    }
+
+    #[test]
+    fn test_strip_extension() {
+        assert_eq!(strip_extension("dir/cube.stl.gz", Compression::Gzip), "dir/cube.stl");
+        assert_eq!(strip_extension("cube.obj.ZST", Compression::Zstd), "cube.obj");
+        assert_eq!(strip_extension("cube.stl", Compression::Gzip), "cube.stl");
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let source = Source::from_bytes("cube.stl.gz", gzip(b"solid cube"));
+        let source = decompress(source, &|_| true).unwrap();
+        assert_eq!(source.name(), "cube.stl");
+        assert_eq!(source.data(), b"solid cube");
+    }
+
+    #[test]
+    fn test_read_limited() {
+        let data = gzip(&[0; 1000]);
+        assert_eq!(read_limited(flate2::read::GzDecoder::new(&data[..]), 1000).unwrap().len(), 1000);
+        let e = read_limited(flate2::read::GzDecoder::new(&data[..]), 999).unwrap_err();
+        assert_eq!(e.to_string(), "it is larger than 999 bytes when decompressed");
+    }
+
+    #[test]
+    fn test_decompress_nested() {
+        let inner = zstd::stream::encode_all(&b"v 1 2 3"[..], 0).unwrap();
+        let source = Source::from_bytes("cube.obj.zst.gz", gzip(&inner));
+        let source = decompress(source, &|_| true).unwrap();
+        assert_eq!(source.name(), "cube.obj");
+        assert_eq!(source.data(), b"v 1 2 3");
+    }
+
+    #[test]
+    fn test_decompress_zip() {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("readme.txt", options).unwrap();
+        writer.write_all(b"Not a model").unwrap();
+        writer.start_file("model/cube.obj", options).unwrap();
+        writer.write_all(b"mtllib cube.mtl").unwrap();
+        writer.start_file("model/cube.mtl", options).unwrap();
+        writer.write_all(b"newmtl red").unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let source = Source::from_bytes("bundle.zip", archive.clone());
+        let source = decompress(source, &|n| n.ends_with(".obj")).unwrap();
+        assert_eq!(source.name(), "model/cube.obj");
+        assert_eq!(source.data(), b"mtllib cube.mtl");
+        assert_eq!(source.open_related("cube.mtl").unwrap(), b"newmtl red");
+
+        let source = Source::from_bytes("bundle.zip", archive);
+        assert!(decompress(source, &|n| n.ends_with(".stl")).is_err());
+    }
+
+    #[test]
+    fn test_uncompressed() {
+        let source = Source::from_bytes("cube.stl", b"solid cube".to_vec());
+        let source = decompress(source, &|_| true).unwrap();
+        assert_eq!(source.name(), "cube.stl");
+        assert_eq!(source.data(), b"solid cube");
+    }
+}
@@ -1,52 +1,136 @@
 //! The file loader handles reading in 3D models in different file formats and ensuring that
 //! the resulting geometry is created using Virtum's data types so it can be used in the
 //! renderer.
+//!
+//! Formats are provided by `MeshLoader`s held in a `LoaderRegistry`. The STL, OBJ, OFF, AMF and
+//! 3MF loaders are registered by default, and other crates can register their own loaders at
+//! runtime.
+//!
+//! Models compressed with gzip or zstd, or stored in zip archives, are decompressed before
+//! being passed to a loader. This is also how the zipped AMF and 3MF formats are opened.
 
 use stl_loader::StlError;
 use obj_loader::ObjError;
-use geometry::Face;
+use geometry::Scene;
+use std::fmt::{Display, Formatter};
 
-use std::vec::Vec;
-use std::path::Path;
+mod compression;
+mod loaders;
+mod registry;
+mod source;
 
+pub use compression::Compression;
+pub use loaders::{AmfLoader, ObjLoader, OffLoader, StlLoader, ThreeMfLoader};
+pub use registry::{LoaderRegistry, MeshLoader};
+pub use source::Source;
 
 /// Errors that can be returned from file reading.
 #[derive(Debug)]
 pub enum MeshError {
+    /// No registered loader recognised the file extension or contents
     UnknownFileType,
+    /// The file could not be opened or read
+    IOError(std::io::Error),
+    /// A gzip or zstd stream could not be decompressed
+    DecompressionError(std::io::Error),
+    /// A zip archive could not be read
+    ZipError(zip::result::ZipError),
+    /// A zip archive did not contain a file any loader could read
+    NoModelInArchive,
+    /// Compressed files were nested too deeply inside each other
+    TooMuchNesting,
     StlScanError(StlError),
-    ObjScanError(ObjError)
+    ObjScanError(ObjError),
+    /// An error from any other loader, such as the OFF, AMF and 3MF loaders or one registered
+    /// from outside this crate
+    LoaderError(Box<dyn std::error::Error>)
 }
 
-/// Load a mesh from a file. The file extension is used to determine how to read the file.
+impl Display for MeshError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshError::UnknownFileType => write!(f, "Unknown 3D model format"),
+            MeshError::IOError(e) => write!(f, "Could not read model: {}", e),
+            MeshError::DecompressionError(e) => write!(f, "Could not decompress model: {}", e),
+            MeshError::ZipError(e) => write!(f, "Could not read zip archive: {}", e),
+            MeshError::NoModelInArchive => write!(f, "Archive does not contain a 3D model"),
+            MeshError::TooMuchNesting => write!(f, "Compressed files are nested too deeply"),
+            MeshError::StlScanError(e) => write!(f, "{}", e),
+            MeshError::ObjScanError(e) => write!(f, "{}", e),
+            MeshError::LoaderError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::IOError(e) => Some(e),
+            MeshError::DecompressionError(e) => Some(e),
+            MeshError::ZipError(e) => Some(e),
+            MeshError::StlScanError(e) => Some(e),
+            MeshError::ObjScanError(e) => Some(e),
+            MeshError::LoaderError(e) => Some(e.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl std::convert::From<std::io::Error> for MeshError {
+    fn from(error: std::io::Error) -> Self {
+        MeshError::IOError(error)
+    }
+}
+
+impl std::convert::From<zip::result::ZipError> for MeshError {
+    fn from(error: zip::result::ZipError) -> Self {
+        MeshError::ZipError(error)
+    }
+}
+
+impl std::convert::From<StlError> for MeshError {
+    fn from(error: StlError) -> Self {
+        MeshError::StlScanError(error)
+    }
+}
+
+impl std::convert::From<ObjError> for MeshError {
+    fn from(error: ObjError) -> Self {
+        MeshError::ObjScanError(error)
+    }
+}
+
+/// Load a model from a file using the default set of loaders.
 ///
-/// If an extension is not recognised, the file will not be read.
+/// Compressed files are decompressed first. The file extension is then used to pick the loader.
+/// If no loader claims the extension, the start of the file is offered to each loader to see if
+/// it recognises the content.
 ///
 /// # Arguments
 /// * `filename` - the path to the 3D model file.
 ///
 /// # Errors
 /// Errors are triggered when:
-/// * File extension is not supported
+/// * Neither the file extension nor the content is recognised
 /// * File extension did not match content (even if it could have been read with a diffferent extension)
-/// * The file was unable to be opened / read
+/// * The file was unable to be opened / read / decompressed
 /// * There is an error (or unsupported format feature) in the file
-pub fn load_file(filename: &str) -> Result<Vec<Face>, MeshError> {
-    println!("Loading file {}", filename);
+pub fn load_file(filename: &str) -> Result<Scene, MeshError> {
+    LoaderRegistry::default().load_file(filename)
+}
 
-    let extension = Path::new(filename).extension().and_then(|e| e.to_str());
+/// Save a scene to a file. The format is chosen from the file extension.
+/// Only (binary) STL files can be written, which keep the colours of the faces.
+///
+/// # Arguments
+/// * `filename` - the path to write to.
+/// * `scene` - the scene to save.
+pub fn save_file(filename: &str, scene: &Scene) -> Result<(), MeshError> {
+    let extension = std::path::Path::new(filename).extension().and_then(|e| e.to_str());
     match extension {
-        None => Err(MeshError::UnknownFileType),
-        Some("stl") => match stl_loader::read_stl_file(filename) {
-                Ok(o) => Ok(o),
-                Err(e) => Err(MeshError::StlScanError(e))
-            },
-        Some("obj") => match obj_loader::read_obj_file(filename) {
-                Ok(o) => Ok(o),
-                Err(e) => Err(MeshError::ObjScanError(e))
-            },
-        Some(_) => Err(MeshError::UnknownFileType)
-        }
+        Some(e) if e.eq_ignore_ascii_case("stl") => Ok(stl_loader::write_stl_file(filename, scene)?),
+        _ => Err(MeshError::UnknownFileType)
+    }
 }
 
 #[cfg(test)]
@@ -56,4 +140,10 @@ mod tests {
         assert!(crate::load_file(&"test.3ds".to_owned()).is_err(), "Must reject 3ds file");
         assert!(crate::load_file(&"teststl".to_owned()).is_err(), "Must stl must be prefixed by a . in filename");
     }
+
+    #[test]
+    fn reject_save_not_stl() {
+        let scene = geometry::Scene::new();
+        assert!(matches!(crate::save_file("model.obj", &scene), Err(crate::MeshError::UnknownFileType)));
+    }
 }
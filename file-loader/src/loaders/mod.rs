@@ -0,0 +1,170 @@
+//! The loaders for the formats that ship with Vitrum.
+
+use geometry::{LoadError, Scene, Strictness};
+use super::{MeshError, MeshLoader, Source};
+
+/// Loads binary and ASCII STL files with the stl-loader.
+pub struct StlLoader;
+
+impl MeshLoader for StlLoader {
+    fn name(&self) -> &str {
+        "STL"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["stl"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        // Only ASCII files have a recognisable start. Binary headers are free text.
+        header.starts_with(b"solid ")
+    }
+
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError> {
+        Ok(stl_loader::read_stl(source.data(), strictness).map_err(|e| e.in_file(source.name()))?)
+    }
+}
+
+/// Loads ASCII OBJ files with the obj-loader.
+pub struct ObjLoader;
+
+impl MeshLoader for ObjLoader {
+    fn name(&self) -> &str {
+        "OBJ"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["obj"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        // Look at the first line which is not blank or a comment
+        let text = String::from_utf8_lossy(header);
+        let first = text.lines()
+                        .map(str::trim)
+                        .find(|l| !l.is_empty() && !l.starts_with('#'));
+        match first.and_then(|l| l.split_whitespace().next()) {
+            Some(keyword) => ["v", "vn", "vt", "f", "o", "g", "s", "mtllib", "usemtl"].contains(&keyword),
+            None => false
+        }
+    }
+
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError> {
+        Ok(obj_loader::read_obj(source.data(), |name| source.open_related(name), strictness)
+               .map_err(|e| e.in_file(source.name()))?)
+    }
+}
+
+/// Loads OFF files with the off-loader.
+pub struct OffLoader;
+
+impl MeshLoader for OffLoader {
+    fn name(&self) -> &str {
+        "OFF"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["off"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        // The header is optional, but without it there is nothing to recognise
+        let text = String::from_utf8_lossy(header);
+        let first = text.lines()
+                        .map(|l| l.split('#').next().unwrap_or("").trim())
+                        .find(|l| !l.is_empty());
+        match first.and_then(|l| l.split_whitespace().next()) {
+            Some(keyword) => keyword.ends_with("OFF") && keyword.chars().all(|c| "STCN4nOF".contains(c)),
+            None => false
+        }
+    }
+
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError> {
+        off_loader::read_off(source.data(), strictness)
+            .map_err(|e| MeshError::LoaderError(Box::new(e.in_file(source.name()))))
+    }
+}
+
+/// Loads AMF files with the amf-loader. Zipped AMF files are extracted first like any other
+/// archive.
+pub struct AmfLoader;
+
+impl MeshLoader for AmfLoader {
+    fn name(&self) -> &str {
+        "AMF"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["amf"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.windows(4).any(|w| w == b"<amf")
+    }
+
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError> {
+        amf_loader::read_amf(source.data(), strictness)
+            .map_err(|e| MeshError::LoaderError(Box::new(e.in_file(source.name()))))
+    }
+}
+
+/// Loads 3MF files with the threemf-loader. A 3MF file is a zip archive, which is extracted
+/// like any other. The model inside it (`3D/3dmodel.model`) is then found by its extension.
+pub struct ThreeMfLoader;
+
+impl MeshLoader for ThreeMfLoader {
+    fn name(&self) -> &str {
+        "3MF"
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["3mf", "model"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        header.windows(6).any(|w| w == b"<model") &&
+            header.windows(15).any(|w| w == b"3dmanufacturing")
+    }
+
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError> {
+        threemf_loader::read_3mf(source.data(), strictness)
+            .map_err(|e| MeshError::LoaderError(Box::new(e.in_file(source.name()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AmfLoader, ObjLoader, OffLoader, StlLoader, ThreeMfLoader};
+    use crate::MeshLoader;
+
+    #[test]
+    fn test_sniff_stl() {
+        assert!(StlLoader.sniff(b"solid cube\n  facet normal 0 0 1"));
+        assert!(!StlLoader.sniff(b"binary header written by exporter"));
+    }
+
+    #[test]
+    fn test_sniff_obj() {
+        assert!(ObjLoader.sniff(b"# Exported model\n\nv 1 2 3\nv 4 5 6"));
+        assert!(ObjLoader.sniff(b"mtllib cube.mtl\n"));
+        assert!(!ObjLoader.sniff(b"solid cube\n"));
+        assert!(!ObjLoader.sniff(b"# only a comment"));
+    }
+
+    #[test]
+    fn test_sniff_off() {
+        assert!(OffLoader.sniff(b"# cube\nOFF\n8 6 12\n"));
+        assert!(OffLoader.sniff(b"COFF 8 6 12\n"));
+        assert!(!OffLoader.sniff(b"8 6 12\n"));
+        assert!(!OffLoader.sniff(b"solid cube\n"));
+    }
+
+    #[test]
+    fn test_sniff_xml() {
+        assert!(AmfLoader.sniff(b"<?xml version=\"1.0\"?>\n<amf unit=\"millimeter\">"));
+        assert!(!ThreeMfLoader.sniff(b"<?xml version=\"1.0\"?>\n<amf unit=\"millimeter\">"));
+        let model = b"<model unit=\"millimeter\" xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">";
+        assert!(ThreeMfLoader.sniff(model));
+        assert!(!AmfLoader.sniff(model));
+    }
+}
@@ -0,0 +1,237 @@
+//! The registry of mesh loaders used to pick a reader for a file.
+
+use geometry::{Scene, Strictness};
+use super::{AmfLoader, MeshError, ObjLoader, OffLoader, StlLoader, Source, ThreeMfLoader};
+use super::compression::decompress;
+
+use std::boxed::Box;
+use std::cmp::min;
+use std::path::Path;
+use std::vec::Vec;
+
+/// The number of bytes from the start of a file that are offered to `MeshLoader::sniff`.
+const SNIFF_LENGTH: usize = 512;
+
+/// A reader for a single 3D model format.
+///
+/// Implement this to add a new format and add it to a `LoaderRegistry` with `register`.
+pub trait MeshLoader {
+    /// A human readable name for the format (e.g. "STL")
+    fn name(&self) -> &str;
+
+    /// The file extensions (without the leading `.`) that this loader reads.
+    /// Extensions are matched ignoring case.
+    fn extensions(&self) -> &[&str];
+
+    /// Check whether the start of a file looks like this format.
+    /// This is only used when no loader claims the file extension.
+    ///
+    /// # Arguments
+    /// * `header` - up to the first 512 bytes of the file.
+    fn sniff(&self, _header: &[u8]) -> bool {
+        false
+    }
+
+    /// Read the model into a scene.
+    ///
+    /// # Arguments
+    /// * `source` - the (decompressed) model. Referenced files are read with `Source::open_related`.
+    /// * `strictness` - whether problems in the file are errors, or are skipped (or repaired) and
+    ///   added to `Scene::warnings`.
+    fn load(&self, source: &Source, strictness: Strictness) -> Result<Scene, MeshError>;
+}
+
+/// A set of loaders which are used to read files.
+///
+/// When several loaders claim the same extension (or content) the one registered last wins,
+/// so a built in loader can be replaced by registering a new one.
+pub struct LoaderRegistry {
+    loaders: Vec<Box<dyn MeshLoader>>,
+    strictness: Strictness,
+}
+
+impl LoaderRegistry {
+    /// Create a registry with no loaders in it.
+    pub fn new() -> LoaderRegistry {
+        LoaderRegistry { loaders: Vec::new(), strictness: Strictness::Strict }
+    }
+
+    /// Choose how loaders treat problems in files. The default is strict.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    /// Add a loader to the registry.
+    pub fn register(&mut self, loader: Box<dyn MeshLoader>) {
+        self.loaders.push(loader);
+    }
+
+    /// The registered loaders, in order of registration.
+    pub fn loaders(&self) -> impl Iterator<Item = &dyn MeshLoader> {
+        self.loaders.iter().map(|l| l.as_ref())
+    }
+
+    /// Find the loader responsible for an extension (without the leading `.`).
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn MeshLoader> {
+        self.loaders.iter()
+            .rev()
+            .find(|l| l.extensions().iter().any(|e| e.eq_ignore_ascii_case(extension)))
+            .map(|l| l.as_ref())
+    }
+
+    /// Find a loader which recognises the start of a file.
+    pub fn for_content(&self, header: &[u8]) -> Option<&dyn MeshLoader> {
+        self.loaders.iter()
+            .rev()
+            .find(|l| l.sniff(header))
+            .map(|l| l.as_ref())
+    }
+
+    /// Load a mesh from a file. See `load` for how the loader is chosen.
+    ///
+    /// # Arguments
+    /// * `filename` - the path to the 3D model file.
+    pub fn load_file(&self, filename: &str) -> Result<Scene, MeshError> {
+        println!("Loading file {}", filename);
+        self.load(Source::from_file(filename)?)
+    }
+
+    /// Load a mesh. Compressed models are decompressed first. The extension of the
+    /// (decompressed) name is then used to determine how to read the model. If no loader claims
+    /// the extension, the start of the content is used instead.
+    ///
+    /// # Arguments
+    /// * `source` - the model to load.
+    pub fn load(&self, source: Source) -> Result<Scene, MeshError> {
+        let is_model = |name: &str| Path::new(name).extension()
+                                                   .and_then(|e| e.to_str())
+                                                   .and_then(|e| self.for_extension(e))
+                                                   .is_some();
+        let source = decompress(source, &is_model)?;
+
+        if let Some(loader) = source.extension().and_then(|e| self.for_extension(e)) {
+            return loader.load(&source, self.strictness);
+        }
+
+        let header = &source.data()[..min(SNIFF_LENGTH, source.data().len())];
+        match self.for_content(header) {
+            Some(loader) => {
+                println!("Reading {} as {}", source.name(), loader.name());
+                loader.load(&source, self.strictness)
+            },
+            None => Err(MeshError::UnknownFileType)
+        }
+    }
+}
+
+impl Default for LoaderRegistry {
+    /// Create a registry with all the loaders that ship with Vitrum.
+    fn default() -> LoaderRegistry {
+        let mut registry = LoaderRegistry::new();
+        registry.register(Box::new(StlLoader));
+        registry.register(Box::new(ObjLoader));
+        registry.register(Box::new(OffLoader));
+        registry.register(Box::new(AmfLoader));
+        registry.register(Box::new(ThreeMfLoader));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoaderRegistry, MeshLoader};
+    use crate::{MeshError, Source};
+    use geometry::{Scene, Strictness};
+
+    struct TestLoader(&'static str);
+
+    impl MeshLoader for TestLoader {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["stl", "tst"]
+        }
+
+        fn sniff(&self, header: &[u8]) -> bool {
+            header.starts_with(b"TEST")
+        }
+
+        fn load(&self, source: &Source, _strictness: Strictness) -> Result<Scene, MeshError> {
+            if source.data().is_empty() {
+                Err(MeshError::UnknownFileType)
+            } else {
+                Ok(Scene::new())
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_extensions() {
+        let registry = LoaderRegistry::default();
+        assert_eq!(registry.for_extension("stl").unwrap().name(), "STL");
+        assert_eq!(registry.for_extension("OBJ").unwrap().name(), "OBJ");
+        assert_eq!(registry.for_extension("off").unwrap().name(), "OFF");
+        assert_eq!(registry.for_extension("amf").unwrap().name(), "AMF");
+        assert_eq!(registry.for_extension("3mf").unwrap().name(), "3MF");
+        assert_eq!(registry.for_extension("model").unwrap().name(), "3MF");
+        assert!(registry.for_extension("3ds").is_none());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = LoaderRegistry::default();
+        registry.register(Box::new(TestLoader("test")));
+        assert_eq!(registry.loaders().count(), 6);
+        assert_eq!(registry.for_extension("tst").unwrap().name(), "test");
+        // Later registrations take precedence
+        assert_eq!(registry.for_extension("stl").unwrap().name(), "test");
+        assert_eq!(registry.for_extension("obj").unwrap().name(), "OBJ");
+    }
+
+    #[test]
+    fn test_content() {
+        let mut registry = LoaderRegistry::new();
+        assert!(registry.for_content(b"TEST file").is_none());
+        registry.register(Box::new(TestLoader("test")));
+        assert_eq!(registry.for_content(b"TEST file").unwrap().name(), "test");
+        assert!(registry.for_content(b"solid ").is_none());
+    }
+
+    #[test]
+    fn test_load() {
+        let mut registry = LoaderRegistry::new();
+        registry.register(Box::new(TestLoader("test")));
+        assert!(registry.load(Source::from_bytes("model.tst", b"data".to_vec())).is_ok());
+        assert!(registry.load(Source::from_bytes("model", b"TEST data".to_vec())).is_ok());
+        assert!(registry.load(Source::from_bytes("model", b"data".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_load_3mf() {
+        use std::io::Write;
+        let model = br#"<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+  <resources>
+    <object id="1" type="model">
+      <mesh>
+        <vertices><vertex x="0" y="0" z="0" /><vertex x="1" y="0" z="0" /><vertex x="0" y="1" z="0" /></vertices>
+        <triangles><triangle v1="0" v2="1" v3="2" /></triangles>
+      </mesh>
+    </object>
+  </resources>
+  <build><item objectid="1" /></build>
+</model>"#;
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("[Content_Types].xml", options).unwrap();
+        writer.write_all(b"<Types />").unwrap();
+        writer.start_file("3D/3dmodel.model", options).unwrap();
+        writer.write_all(model).unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let scene = LoaderRegistry::default().load(Source::from_bytes("part.3mf", archive)).unwrap();
+        assert_eq!(scene.face_count(), 1);
+        assert_eq!(scene.units, Some(geometry::Units::Millimetres));
+    }
+}
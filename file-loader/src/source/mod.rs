@@ -0,0 +1,199 @@
+//! The data for a model and any files it refers to, such as OBJ material libraries.
+
+use super::compression::extract_file;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Where files referred to by a model are found.
+#[derive(Debug, Clone)]
+enum Resolver {
+    /// References are relative to a directory on disk
+    Directory(PathBuf),
+    /// References are relative to a directory inside a zip archive, and are extracted from it
+    Archive { base: String, archive: Rc<Vec<u8>> },
+    /// References cannot be resolved
+    Unresolvable
+}
+
+/// A model to be loaded. This is the (decompressed) content of the model along with the name
+/// it was stored under, which is used to pick a loader.
+#[derive(Debug, Clone)]
+pub struct Source {
+    name: String,
+    data: Vec<u8>,
+    resolver: Resolver
+}
+
+impl Source {
+    /// Read a model from a file. Files it refers to are read from the same directory.
+    ///
+    /// # Arguments
+    /// * `filename` - the path to the 3D model file.
+    pub fn from_file(filename: &str) -> Result<Source, Error> {
+        let data = fs::read(filename)?;
+        let directory = Path::new(filename).parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(Source {
+            name: filename.to_owned(),
+            data,
+            resolver: Resolver::Directory(directory)
+        })
+    }
+
+    /// Create a model from data in memory. Any references to other files will fail to resolve.
+    ///
+    /// # Arguments
+    /// * `name` - the name of the model, including the extension of its format.
+    /// * `data` - the content of the model.
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Source {
+        Source {
+            name: name.to_owned(),
+            data,
+            resolver: Resolver::Unresolvable
+        }
+    }
+
+    /// Create a model from one entry of a zip archive. References are resolved against the
+    /// other entries in the archive.
+    ///
+    /// # Arguments
+    /// * `name` - the name of the entry for the model.
+    /// * `data` - the (extracted) content of the entry.
+    /// * `archive` - the zip archive.
+    pub(crate) fn from_archive(name: &str, data: Vec<u8>, archive: Rc<Vec<u8>>) -> Source {
+        let name = normalise(name);
+        let base = match name.rfind('/') {
+            Some(i) => name[..i].to_owned(),
+            None => String::new()
+        };
+        Source {
+            data,
+            name,
+            resolver: Resolver::Archive { base, archive }
+        }
+    }
+
+    /// Replace the content (and name) of the model, keeping the same place to resolve references.
+    /// This is used when the model was compressed.
+    pub(crate) fn with_content(self, name: String, data: Vec<u8>) -> Source {
+        Source {
+            name,
+            data,
+            resolver: self.resolver
+        }
+    }
+
+    /// The name of the model. For a file this is its path.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The extension of the model name (without the leading `.`) if there is one.
+    pub fn extension(&self) -> Option<&str> {
+        Path::new(&self.name).extension().and_then(|e| e.to_str())
+    }
+
+    /// The content of the model.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Read a file the model refers to, such as an OBJ material library or a texture.
+    ///
+    /// # Arguments
+    /// * `reference` - the path to the file, as written in the model (relative to the model).
+    pub fn open_related(&self, reference: &str) -> Result<Vec<u8>, Error> {
+        match &self.resolver {
+            // On disk the reference is used as written, so `..` and absolute paths work
+            Resolver::Directory(directory) => fs::read(directory.join(reference)),
+            Resolver::Archive { base, archive } => {
+                let path = normalise(&format!("{}/{}", base, reference));
+                extract_file(archive, &path)?
+                    .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} is not in the archive", path)))
+            },
+            Resolver::Unresolvable => Err(Error::new(ErrorKind::NotFound,
+                                                     format!("Cannot resolve {} for {}", reference, self.name)))
+        }
+    }
+}
+
+/// Normalise a relative path so that it can be used as a key into an archive.
+/// Windows separators are converted and `.` and `..` components are removed.
+///
+/// # Arguments
+/// * `path` - the path to normalise.
+pub(crate) fn normalise(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(&['/', '\\'][..]) {
+        match part {
+            "" | "." => (),
+            ".." => { parts.pop(); },
+            p => parts.push(p)
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalise, Source};
+    use std::fs;
+    use std::io::{Cursor, Write};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(normalise("a/b/c.mtl"), "a/b/c.mtl");
+        assert_eq!(normalise("./a//b/../c.mtl"), "a/c.mtl");
+        assert_eq!(normalise("textures\\wood.png"), "textures/wood.png");
+        assert_eq!(normalise("/c.mtl"), "c.mtl");
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(Source::from_bytes("dir/cube.stl", Vec::new()).extension(), Some("stl"));
+        assert_eq!(Source::from_bytes("cube", Vec::new()).extension(), None);
+    }
+
+    #[test]
+    fn test_archive_references() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in &[("models/cube.obj", "mtllib cube.mtl"), ("models/cube.mtl", "newmtl red"),
+                                 ("textures/Wood.png", "png")] {
+            writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+        let source = Source::from_archive("models/cube.obj", b"mtllib cube.mtl".to_vec(), Rc::new(archive));
+
+        assert_eq!(source.data(), b"mtllib cube.mtl");
+        assert_eq!(source.open_related("cube.mtl").unwrap(), b"newmtl red");
+        assert_eq!(source.open_related("./cube.mtl").unwrap(), b"newmtl red");
+        assert_eq!(source.open_related("..\\textures\\wood.png").unwrap(), b"png");
+        assert!(source.open_related("missing.mtl").is_err());
+    }
+
+    #[test]
+    fn test_directory_references() {
+        let root = std::env::temp_dir().join(format!("file-loader-references-{}", std::process::id()));
+        fs::create_dir_all(root.join("models")).unwrap();
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("models/cube.obj"), "mtllib ../shared/cube.mtl").unwrap();
+        fs::write(root.join("shared/cube.mtl"), "newmtl red").unwrap();
+
+        let source = Source::from_file(root.join("models/cube.obj").to_str().unwrap()).unwrap();
+        assert_eq!(source.open_related("../shared/cube.mtl").unwrap(), b"newmtl red");
+        let absolute = root.join("shared/cube.mtl");
+        assert_eq!(source.open_related(absolute.to_str().unwrap()).unwrap(), b"newmtl red");
+        assert!(source.open_related("cube.mtl").is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_unresolvable() {
+        let source = Source::from_bytes("cube.obj", Vec::new());
+        assert!(source.open_related("cube.mtl").is_err());
+    }
+}
@@ -5,7 +5,15 @@ pub struct Collision {
     pub contact_point: Vec3,
     pub normal: Vec3,
     pub distance: f64,
-    pub direction: CollisionDirection
+    pub direction: CollisionDirection,
+    /// The index of the material of the surface that was hit
+    pub material: Option<usize>,
+    /// The normal of the flat triangle, rather than the one interpolated from its corners
+    pub geometric_normal: Vec3,
+    /// How much of each corner of the triangle the point is made of, adding up to 1
+    pub barycentric: Vec3,
+    /// Which face was hit, if the faces have been numbered
+    pub face: Option<usize>
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
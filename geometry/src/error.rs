@@ -0,0 +1,61 @@
+use super::Warning;
+use std::fmt::Display;
+
+/// An error from reading a model file, which can say where in the file it happened.
+///
+/// The errors of each loader implement this with `impl_load_error!`.
+pub trait LoadError: Display + Sized {
+    /// Record where in the file this error happened.
+    ///
+    /// # Arguments
+    /// * `line` - the line number (starting from 1)
+    /// * `column` - the column (starting from 1)
+    fn at(self, line: usize, column: usize) -> Self;
+
+    /// Record which file this error happened in.
+    ///
+    /// # Arguments
+    /// * `filename` - the name of the file being read
+    fn in_file(self, filename: &str) -> Self;
+
+    /// Turn an error in a record which was skipped (or repaired) into a warning.
+    fn into_warning(self) -> Warning;
+}
+
+/// Implement `LoadError` for an error enum with an
+/// `At { file: Option<String>, line: usize, column: usize, error: Box<Self> }` variant.
+///
+/// # Arguments
+/// * `error` - The error enum
+/// * `unplaced` - Variants (with one field) which `at` leaves alone, such as IO errors
+#[macro_export]
+macro_rules! impl_load_error {
+    ($error:ident $(, $unplaced:ident)*) => {
+        impl $crate::LoadError for $error {
+            fn at(self, line: usize, column: usize) -> $error {
+                match self {
+                    // IO errors are not about any particular part of the file
+                    $($error::$unplaced(_) |)* $error::At { .. } => self,
+                    error => $error::At { file: None, line, column, error: Box::new(error) }
+                }
+            }
+
+            fn in_file(self, filename: &str) -> $error {
+                match self {
+                    $error::At { file: None, line, column, error } =>
+                        $error::At { file: Some(filename.to_owned()), line, column, error },
+                    error => error
+                }
+            }
+
+            fn into_warning(self) -> $crate::Warning {
+                match self {
+                    $error::At { file: Some(file), line, error, .. } =>
+                        $crate::Warning::new(Some(line), error.to_string()).in_file(&file),
+                    $error::At { file: None, line, error, .. } => $crate::Warning::new(Some(line), error.to_string()),
+                    error => $crate::Warning::new(None, error.to_string())
+                }
+            }
+        }
+    };
+}
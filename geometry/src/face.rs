@@ -1,4 +1,4 @@
-use super::{Plane, Ray, Collision, CollisionDirection, Vec3};
+use super::{Bounded, Plane, Ray, Collision, CollisionDirection, Vec3};
 use std::cmp::PartialEq;
 use std::fmt::Display;
 
@@ -17,6 +17,10 @@ pub struct Face {
     a_texture: Option<Vec3>,
     b_texture: Option<Vec3>,
     c_texture: Option<Vec3>,
+
+    material: Option<usize>,
+    /// Which face of the model this is, for telling faces apart
+    id: Option<usize>,
 }
 
 impl Face {
@@ -39,7 +43,10 @@ impl Face {
 
             a_texture,
             b_texture,
-            c_texture
+            c_texture,
+
+            material: None,
+            id: None
         }
     }
 
@@ -55,7 +62,9 @@ impl Face {
             c_normal : cn.normalize(),
             a_texture: None,
             b_texture: None,
-            c_texture: None
+            c_texture: None,
+            material: None,
+            id: None
         }
     }
 
@@ -74,7 +83,10 @@ impl Face {
 
             a_texture: None,
             b_texture: None,
-            c_texture: None
+            c_texture: None,
+
+            material: None,
+            id: None
         }
     }
 
@@ -84,6 +96,78 @@ impl Face {
         Self::from_points_with_face(normal_dir, a, b, c)
     }
 
+    /// The corners of the triangle, in order.
+    pub fn vertices(&self) -> [Vec3; 3] {
+        [self.a, self.b, self.c]
+    }
+
+    /// The (unit) normal of the flat triangle.
+    pub fn normal(&self) -> Vec3 {
+        self.face_normal
+    }
+
+    /// The index of the material of this face in its `Scene`, if it has one.
+    pub fn material(&self) -> Option<usize> {
+        self.material
+    }
+
+    pub fn set_material(&mut self, material: Option<usize>) {
+        self.material = material;
+    }
+
+    /// Set the material of this face, returning the updated face.
+    pub fn with_material(mut self, material: Option<usize>) -> Face {
+        self.material = material;
+        self
+    }
+
+    /// Which face of the model this is, if it has been numbered.
+    pub fn id(&self) -> Option<usize> {
+        self.id
+    }
+
+    /// Number this face, returning the updated face.
+    pub fn with_id(mut self, id: Option<usize>) -> Face {
+        self.id = id;
+        self
+    }
+
+    /// Replace the normals at the corners of the triangle, which are interpolated across it
+    /// when it is hit. The normal of the flat triangle is unchanged.
+    ///
+    /// # Arguments
+    /// * `normals` - The normals at each corner, in order. They will be normalised.
+    pub fn with_vertex_normals(mut self, normals: [Vec3; 3]) -> Face {
+        self.a_normal = normals[0].normalize();
+        self.b_normal = normals[1].normalize();
+        self.c_normal = normals[2].normalize();
+        self
+    }
+
+    /// The normals at the corners of the triangle, in order.
+    pub fn vertex_normals(&self) -> [Vec3; 3] {
+        [self.a_normal, self.b_normal, self.c_normal]
+    }
+
+    /// The same triangle facing the other way: the last two corners are swapped and
+    /// every normal is reversed.
+    pub fn flipped(&self) -> Face {
+        Face {
+            face_normal: -self.face_normal,
+            a: self.a,
+            b: self.c,
+            c: self.b,
+            a_normal: -self.a_normal,
+            b_normal: -self.c_normal,
+            c_normal: -self.b_normal,
+            a_texture: self.a_texture,
+            b_texture: self.c_texture,
+            c_texture: self.b_texture,
+            material: self.material,
+            id: self.id
+        }
+    }
+
 }
 
 impl Display for Face {
@@ -101,65 +185,78 @@ impl PartialEq for Face {
 
 impl Plane for Face {
     fn hits(&self, ray: &Ray) -> Option<Collision> {
-        let epsilon: f64 = 1e-5;
-
-        // Check if ray parallel to triangle (i.e. orthogonal to normal)
-        // Check if ray facing back of triangle
-        // Note: ray . norm == 0 if they are perpendicular
-        // ray . norm > 0 if the ray is facing the *back* of the triangle
-        let ray_norm_dot = ray.direction.dot(&self.face_normal);
-        let collision_face = if ray_norm_dot > -epsilon {
-                CollisionDirection::BackFace
-            } else {
-                CollisionDirection::FrontFace
-            };
-
-        // Find intersection of ray and triangle
-        let t = (self.face_normal.dot(&self.a) - self.face_normal.dot(&ray.origin)) / ray_norm_dot;
+        // Watertight ray/triangle intersection (Woop, Benthin and Wald 2013). The triangle is
+        // moved into a space where the ray starts at the origin and goes along the z axis,
+        // then the hit is found from the signed areas of the triangle's edges around the
+        // ray. An edge shared by two triangles gives exactly the same area (with opposite
+        // signs) for both, so rays cannot slip between them. It does not use the normals.
+
+        // Make the largest component of the direction the z axis, keeping the winding
+        let kz = ray.direction.iamax();
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if ray.direction[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
 
-        // println!("-(({} * {}) + {})/ {} = {}", self.normal, ray.origin, d, ray_norm_dot, t);
-        if t < 0.0 {
-            // point behind ray origin
-            // println!("Hit behind ray origin at t = {}", t);
+        // Shear so the ray goes straight along z
+        let sx = ray.direction[kx] / ray.direction[kz];
+        let sy = ray.direction[ky] / ray.direction[kz];
+        let sz = 1.0 / ray.direction[kz];
+
+        let a = self.a - ray.origin;
+        let b = self.b - ray.origin;
+        let c = self.c - ray.origin;
+        let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+        let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+        let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+        // Twice the signed areas of the ray with each edge, which are the (unscaled)
+        // barycentric coordinates of the hit. Zero is on the edge, and counts as a hit.
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            // The ray is in the plane of the triangle
             return None;
         }
 
-        let hit: Vec3 = ray.at(t);
-
-        // Check if this point is inside the triangle
-        // We do this by conversion to Barycentric co-ordinates in order to interpolate
-        // the normal. Note that this relies on the ratio of areas being the same.
-
-        let ab = self.b - self.a;
-        let bc = self.c - self.b;
-        let ca = self.a - self.c;
-
-        let area = 0.5 * ab.cross(&bc).dot(&self.face_normal);
-
-        let area_bc = 0.5 * bc.cross(&(hit - self.b)).dot(&self.face_normal);
-        let area_ca = 0.5 * ca.cross(&(hit - self.c)).dot(&self.face_normal);
-
-        let u = area_bc / area;
-        let v = area_ca / area;
-        let w = 1.0 - u - v;
-
-        // If any of the coordinates are negative, the point is outside
-        if u < 0.0 || v < 0.0 || w < 0.0 {
+        // Distance along the ray, interpolated from the corners
+        let t = (u * a[kz] + v * b[kz] + w * c[kz]) * sz / det;
+        if !ray.contains(t) {
+            // point behind ray origin, or outside the part of the ray that counts
             return None;
         }
+        let (u, v, w) = (u / det, v / det, w / det);
+
+        // The normal only decides which side of the triangle is the front
+        let collision_face = if ray.direction.dot(&self.face_normal) < 0.0 {
+                CollisionDirection::FrontFace
+            } else {
+                CollisionDirection::BackFace
+            };
 
         let interpolated_normal = (u * self.a_normal + v * self.b_normal + w * self.c_normal).normalize();
         //println!("-- ({:0.2},{:0.2},{:0.2}) {:?}", u, v, w, interpolated_normal);
         Some(
             Collision {
                 normal: interpolated_normal,
-                contact_point: hit,
+                contact_point: ray.at(t),
                 distance: t,
-                direction: collision_face
+                direction: collision_face,
+                material: self.material,
+                geometric_normal: self.face_normal,
+                barycentric: Vec3::new(u, v, w),
+                face: self.id
             }
         )
     }
+}
 
+impl Bounded for Face {
     fn min_extents(&self) -> Vec3 {
         self.a.inf(&self.b).inf(&self.c)
     }
@@ -179,12 +276,14 @@ impl Plane for Face {
                   self.a_texture,
                   self.b_texture,
                   self.c_texture,)
+            .with_material(self.material)
+            .with_id(self.id)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Face, Vec3};
+    use super::{Face, Plane, Ray, Vec3};
 
     #[test]
     fn test_compute_normal() {
@@ -198,8 +297,83 @@ mod tests {
         assert_eq!(f.face_normal, normal);
     }
 
+    #[test]
+    fn test_flipped() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let f = Face::from_points(a, b, c).with_material(Some(2)).flipped();
+
+        assert_eq!(f.vertices(), [a, c, b]);
+        assert_eq!(f.normal(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(f.c_normal, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(f.material(), Some(2));
+    }
+
+    #[test]
+    fn test_with_vertex_normals() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let f = Face::from_points(a, b, c).with_material(Some(1))
+            .with_vertex_normals([Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 1.0), up]);
+
+        assert_eq!(f.vertex_normals(), [up, Vec3::new(1.0, 0.0, 1.0).normalize(), up]);
+        assert_eq!(f.normal(), up);
+        assert_eq!(f.material(), Some(1));
+    }
+
     #[test]
     fn test_interpolate_normal() {
-        //Do something h ere.
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(2.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 2.0, 0.0);
+        let f = Face::from_points_with_normals(a, b, c, Vec3::new(-1.0, 0.0, 1.0),
+                                               Vec3::new(1.0, 0.0, 1.0), Vec3::z());
+
+        // Half way along the edge from a to b
+        let h = f.hits(&Ray::new(Vec3::new(1.0, 0.0, 1.0), -Vec3::z())).unwrap();
+        assert!((h.normal - Vec3::z()).norm() < 1e-12);
+        assert!((h.barycentric - Vec3::new(0.5, 0.5, 0.0)).norm() < 1e-12);
+        assert_eq!(h.geometric_normal, f.normal());
+        // At c
+        let h = f.hits(&Ray::new(Vec3::new(0.0, 2.0, 1.0), -Vec3::z())).unwrap();
+        assert!((h.normal - Vec3::z()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_ignores_supplied_normal() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        for normal in &[Vec3::z(), -Vec3::z(), Vec3::x(), Vec3::new(1.0, 1.0, 0.0)] {
+            let f = Face::from_points_with_face(*normal, a, b, c);
+            let h = f.hits(&ray).unwrap();
+            assert!((h.distance - 3.0).abs() < 1e-12);
+            assert_eq!(h.contact_point, ray.at(h.distance));
+        }
+        // Wound the other way
+        assert!(Face::from_points(a, c, b).hits(&ray).is_some());
+    }
+
+    #[test]
+    fn test_shared_edge() {
+        // A square split along its diagonal, at awkward coordinates
+        let p = |x: f64, y: f64| Vec3::new(0.1 + x * 0.7, 0.3 + y * 0.3, 0.2 * x + 0.1 * y);
+        let first = Face::from_points(p(0.0, 0.0), p(1.0, 0.0), p(1.0, 1.0));
+        let second = Face::from_points(p(0.0, 0.0), p(1.0, 1.0), p(0.0, 1.0));
+        let origin = Vec3::new(0.37, 0.41, 5.0);
+        for i in 1..1000 {
+            // Rays at points along the inside of the shared edge. Rays near the ends can
+            // rightly miss, as the corners of the square are on the outside.
+            let target = p(i as f64 / 1000.0, i as f64 / 1000.0);
+            let ray = Ray::new(origin, target - origin);
+            assert!(first.hits(&ray).is_some() || second.hits(&ray).is_some(), "ray {} leaked", i);
+        }
+        // A ray straight down the corner shared by both
+        let ray = Ray::new(p(1.0, 1.0) + Vec3::z(), -Vec3::z());
+        assert!(first.hits(&ray).is_some() && second.hits(&ray).is_some());
     }
 }
\ No newline at end of file
@@ -1,26 +1,68 @@
 mod face;
 mod collision;
+mod error;
 mod plane;
+mod scene;
+mod transform;
 
 use nalgebra::{Vector3, Vector4};
 pub use face::Face;
 pub use collision::{Collision, CollisionDirection};
-pub use plane::Plane;
+pub use error::LoadError;
+pub use plane::{Bounded, Plane};
+pub use scene::{Group, Material, Mesh, Scene, Strictness, Units, Warning};
+pub use transform::Transform;
 
 pub type Vec3 = Vector3<f64>;
 pub type Vec4 = Vector4<f64>;
 
-/// A ray is a line in 3 space with a defined origin and direction
+/// How far (relative to the size of its coordinates) a ray leaving a surface starts from it.
+const SURFACE_OFFSET: f64 = 1e-9;
+
+/// A ray is a line in 3 space with a defined origin and direction. Only the part of the line
+/// between `t_min` and `t_max` (as distances from the origin) can hit anything.
 #[derive(Debug, Clone)]
 pub struct Ray {
     pub origin: Vec3,
-    pub direction: Vec3
+    pub direction: Vec3,
+    pub t_min: f64,
+    pub t_max: f64,
 }
 
 impl Ray {
-    /// Create a new ray. The direction will be normalised.
+    /// Create a new ray starting at its origin and going on forever. The direction will
+    /// be normalised.
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction: direction.normalize() }
+        Ray { origin, direction: direction.normalize(), t_min: 0.0, t_max: f64::INFINITY }
+    }
+
+    /// Create a ray leaving a point on a surface, such as a shadow or reflected ray. The ray
+    /// starts slightly away from the point, so that rounding errors in the point do not make
+    /// it hit the surface it is leaving. The gap depends on the size of the coordinates of the
+    /// point (as rounding errors do), not on the size of the scene.
+    ///
+    /// # Arguments
+    /// * `point` - The point on the surface
+    /// * `direction` - The direction of the ray. It will be normalised.
+    pub fn leaving(point: Vec3, direction: Vec3) -> Ray {
+        let t_min = SURFACE_OFFSET * (1.0 + point.amax());
+        Ray::new(point, direction).with_interval(t_min, f64::INFINITY)
+    }
+
+    /// Set the part of the ray which can hit things, returning the updated ray.
+    ///
+    /// # Arguments
+    /// * `t_min` - The smallest distance along the ray at which a hit counts
+    /// * `t_max` - The largest distance along the ray at which a hit counts
+    pub fn with_interval(mut self, t_min: f64, t_max: f64) -> Ray {
+        self.t_min = t_min;
+        self.t_max = t_max;
+        self
+    }
+
+    /// Whether a distance along the ray is within its interval.
+    pub fn contains(&self, t: f64) -> bool {
+        self.t_min <= t && t <= self.t_max
     }
 
     /// Computer the position of the ray at a distance t
@@ -126,4 +168,45 @@ mod tests {
         assert_eq!(r.at(h.distance), h.contact_point);
     }
 
-}
\ No newline at end of file
+    /// Faces across the z axis at 1, 2, 3 and -3, out of order
+    fn stacked_faces() -> Vec<Face> {
+        [3.0, 1.0, 2.0, -3.0].iter().map(|z| Face::from_points(
+            Vec3::new(-2.0, -1.0, *z),
+            Vec3::new( 1.0,  3.0, *z),
+            Vec3::new( 1.0, -1.0, *z)
+        )).collect()
+    }
+
+    #[test]
+    fn test_interval_on_vec() {
+        let v = stacked_faces();
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let h = v.hits(&r.clone().with_interval(1.5, 10.0)).unwrap();
+        assert_eq!(h.distance, 2.0);
+        assert!(v.hits(&r.clone().with_interval(1.5, 1.9)).is_none());
+    }
+
+    #[test]
+    fn test_occluded_on_vec() {
+        let v = stacked_faces();
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(v.occluded(&r.clone().with_interval(0.0, 1.5)));
+        assert!(!v.occluded(&r.clone().with_interval(0.0, 0.9)));
+        // Behind the ray
+        assert!(!v[3].occluded(&r));
+    }
+
+    #[test]
+    fn test_leaving_surface() {
+        // Far from the origin, where rounding errors are bigger
+        let face = Face::from_points(Vec3::new(1000.0, 1000.0, 1000.0),
+                                     Vec3::new(1003.0, 1001.0, 1000.5),
+                                     Vec3::new(1000.5, 1003.0, 1001.0));
+        let r = Ray::new(Vec3::new(1001.0, 1001.0, 1010.0), Vec3::new(0.01, 0.02, -1.0));
+        let h = face.hits(&r).unwrap();
+        let reflected = r.direction - 2.0 * r.direction.dot(&h.normal) * h.normal;
+        assert!(face.hits(&Ray::leaving(h.contact_point, reflected)).is_none());
+        assert!(face.hits(&Ray::leaving(h.contact_point, -reflected)).is_none());
+        assert!(Ray::leaving(h.contact_point, reflected).t_min < 1e-5);
+    }
+}
@@ -1,30 +1,34 @@
 use super::{Collision, Vec3, Ray};
 use num::{Float};
 
-pub trait Plane {
-    fn hits(&self, ray: &Ray) -> Option<Collision>;
+/// Something with a bounding box, which is all a `BoundingVolumeHierarchy` needs to find
+/// what overlaps a box. Things which rays can hit are `Plane`s as well.
+pub trait Bounded {
     fn min_extents(&self) -> Vec3;
     fn max_extents(&self) -> Vec3;
     fn translate(&self, t: Vec3) -> Self;
 }
 
+pub trait Plane: Bounded {
+    fn hits(&self, ray: &Ray) -> Option<Collision>;
 
-impl<T: Plane> Plane for Vec<T> {
-    fn hits(&self, ray: &Ray) -> Option<Collision> {
-        let mut plane = None;
-        for p in self {
-            plane = match p.hits(ray) {
-                None => plane,
-                Some(c) => match plane {
-                    None => Some(c),
-                    Some(c2) if c.distance < c2.distance => Some(c),
-                    _ => plane
-                }
-            }
-        }
-        plane
+    /// Whether the ray hits anything within its interval, such as between a point and a light.
+    /// Unlike `hits` this does not need to find the nearest hit, so it can stop as soon as it
+    /// finds any.
+    ///
+    /// There is no separate largest distance: a query such as `occluded(ray, max_distance)`
+    /// is written `occluded(&ray.with_interval(t_min, max_distance))`, which also lets the BVH
+    /// cull boxes beyond the end of the ray.
+    ///
+    /// # Arguments
+    /// * `ray` - The ray to test. Set its `t_max` to ignore hits beyond a distance.
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.hits(ray).is_some()
     }
+}
 
+
+impl<T: Bounded> Bounded for Vec<T> {
     fn min_extents(&self) -> Vec3 {
         let mut min = Vec3::new(f64::infinity(), f64::infinity(), f64::infinity());
         for f in self {
@@ -46,4 +50,25 @@ impl<T: Plane> Plane for Vec<T> {
             value.translate(t)
         }).collect()
     }
-}
\ No newline at end of file
+}
+
+impl<T: Plane> Plane for Vec<T> {
+    fn hits(&self, ray: &Ray) -> Option<Collision> {
+        let mut plane = None;
+        for p in self {
+            plane = match p.hits(ray) {
+                None => plane,
+                Some(c) => match plane {
+                    None => Some(c),
+                    Some(c2) if c.distance < c2.distance => Some(c),
+                    _ => plane
+                }
+            }
+        }
+        plane
+    }
+
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.iter().any(|p| p.occluded(ray))
+    }
+}
@@ -0,0 +1,495 @@
+use super::{Face, Vec3};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::Range;
+
+/// The units that the coordinates in a model are written in.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Units {
+    Micrometres,
+    Millimetres,
+    Centimetres,
+    Metres,
+    Inches,
+    Feet,
+}
+
+impl Units {
+    /// The size of one unit in metres
+    pub fn in_metres(self) -> f64 {
+        match self {
+            Units::Micrometres => 1e-6,
+            Units::Millimetres => 1e-3,
+            Units::Centimetres => 1e-2,
+            Units::Metres => 1.0,
+            Units::Inches => 0.0254,
+            Units::Feet => 0.3048,
+        }
+    }
+}
+
+/// How a loader treats problems in a file.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Strictness {
+    /// Any problem in the file is an error
+    Strict,
+    /// Bad records are skipped or repaired, and reported as warnings in the scene
+    Lenient,
+}
+
+/// A problem found while loading a file which did not stop it from being read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// The file the problem is in, if it is not the model file itself (e.g. a material library)
+    pub file: Option<String>,
+    /// The line the problem is on (starting from 1), for text formats
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(line: Option<usize>, message: String) -> Warning {
+        Warning { file: None, line, message }
+    }
+
+    /// Record which file this warning is about, returning the updated warning.
+    pub fn in_file(mut self, file: &str) -> Warning {
+        self.file = Some(file.to_owned());
+        self
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file, self.message),
+            (None, Some(line)) => write!(f, "line {}: {}", line, self.message),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The appearance of a surface, as described by the model file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    /// Diffuse colour, each channel in [0, 1]
+    pub diffuse: Vec3,
+    /// Specular colour, each channel in [0, 1]
+    pub specular: Vec3,
+    /// Light given off by the surface
+    pub emission: Vec3,
+    /// Specular exponent
+    pub shininess: f64,
+    /// 1 is fully opaque, 0 is fully transparent
+    pub opacity: f64,
+    /// Path to an image used for the diffuse colour, relative to the model
+    pub texture: Option<String>,
+}
+
+impl Material {
+    /// Create a material with the default appearance and a name.
+    pub fn named(name: &str) -> Material {
+        Material {
+            name: name.to_owned(),
+            ..Material::default()
+        }
+    }
+
+    /// Create a material with a name and diffuse colour.
+    pub fn with_colour(name: &str, colour: Vec3) -> Material {
+        Material {
+            name: name.to_owned(),
+            diffuse: colour,
+            ..Material::default()
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            name: String::new(),
+            diffuse: Vec3::new(20.0, 120.0, 220.0) / 255.0,
+            specular: Vec3::new(1.0, 1.0, 1.0),
+            emission: Vec3::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            opacity: 1.0,
+            texture: None,
+        }
+    }
+}
+
+/// A named set of faces within a mesh, such as an OBJ group.
+/// The faces of a group do not have to be next to each other, and a face can be in many groups.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    /// The indices of the faces in the group, in order and not overlapping
+    pub ranges: Vec<Range<usize>>,
+}
+
+impl Group {
+    /// Create a group from the indices of its faces. The indices must be in increasing order.
+    pub fn from_indices<I: IntoIterator<Item = usize>>(name: &str, indices: I) -> Group {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for i in indices {
+            match ranges.last_mut() {
+                Some(r) if r.end == i => r.end += 1,
+                _ => ranges.push(i..i + 1)
+            }
+        }
+        Group { name: name.to_owned(), ranges }
+    }
+
+    pub fn contains(&self, face: usize) -> bool {
+        self.ranges.iter().any(|r| r.contains(&face))
+    }
+
+    /// The indices of all the faces in the group.
+    pub fn faces(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ranges.iter().flat_map(|r| r.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|r| r.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// A named part of a model, such as an OBJ object or an STL solid.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub name: Option<String>,
+    pub faces: Vec<Face>,
+    /// Material for faces that do not have their own
+    pub material: Option<usize>,
+    /// Named subsets of the faces
+    pub groups: Vec<Group>,
+}
+
+impl Mesh {
+    pub fn new(name: Option<String>, faces: Vec<Face>) -> Mesh {
+        Mesh { name, faces, material: None, groups: Vec::new() }
+    }
+
+    /// Keep only some of the faces, updating the groups to match.
+    ///
+    /// # Arguments
+    /// * `keep` - whether to keep each face, by index
+    pub fn retain_faces<F: Fn(usize) -> bool>(&mut self, keep: F) {
+        // Where each kept face will end up
+        let mut new_index = Vec::with_capacity(self.faces.len());
+        let mut count = 0;
+        for i in 0..self.faces.len() {
+            if keep(i) {
+                new_index.push(Some(count));
+                count += 1;
+            } else {
+                new_index.push(None);
+            }
+        }
+
+        let faces = std::mem::take(&mut self.faces);
+        self.faces = faces.into_iter()
+                          .enumerate()
+                          .filter(|(i, _)| new_index[*i].is_some())
+                          .map(|(_, f)| f)
+                          .collect();
+        self.groups = self.groups.iter()
+                          .map(|g| Group::from_indices(&g.name, g.faces().filter_map(|i| new_index[i])))
+                          .filter(|g| !g.is_empty())
+                          .collect();
+    }
+
+    /// Use a material for the whole mesh, replacing any materials on its faces.
+    pub fn set_material(&mut self, material: usize) {
+        self.material = Some(material);
+        for f in self.faces.iter_mut() {
+            f.set_material(Some(material));
+        }
+    }
+
+    /// The name of the mesh for display. Unnamed meshes are shown as `<unnamed>`.
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<unnamed>")
+    }
+}
+
+/// Everything read from a model file: the parts, their materials and any other information
+/// the file held.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    /// Materials referred to (by index) from meshes and faces
+    pub materials: Vec<Material>,
+    pub units: Option<Units>,
+    /// Anything else of interest in the file, such as header text
+    pub metadata: BTreeMap<String, String>,
+    /// Problems which were skipped over or repaired while loading
+    pub warnings: Vec<Warning>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    /// Create a scene containing a single mesh.
+    pub fn from_mesh(mesh: Mesh) -> Scene {
+        Scene {
+            meshes: vec![mesh],
+            ..Scene::default()
+        }
+    }
+
+    /// All the faces in all the meshes.
+    pub fn faces(&self) -> impl Iterator<Item = &Face> {
+        self.meshes.iter().flat_map(|m| m.faces.iter())
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.meshes.iter().map(|m| m.faces.len()).sum()
+    }
+
+    /// Find a mesh by name.
+    pub fn mesh(&self, name: &str) -> Option<&Mesh> {
+        self.meshes.iter().find(|m| m.name.as_deref() == Some(name))
+    }
+
+    /// Find a mesh by name.
+    pub fn mesh_mut(&mut self, name: &str) -> Option<&mut Mesh> {
+        self.meshes.iter_mut().find(|m| m.name.as_deref() == Some(name))
+    }
+
+    /// Keep only the named parts of the scene. A part can be a whole mesh or a group within one.
+    ///
+    /// # Arguments
+    /// * `names` - the names of the meshes and groups to keep
+    pub fn select(&mut self, names: &[String]) {
+        for mesh in self.meshes.iter_mut() {
+            if names.iter().any(|n| mesh.name.as_ref() == Some(n)) {
+                continue;
+            }
+            let selected: Vec<Group> = mesh.groups.iter()
+                                           .filter(|g| names.contains(&g.name))
+                                           .cloned()
+                                           .collect();
+            mesh.retain_faces(|i| selected.iter().any(|g| g.contains(i)));
+        }
+        self.meshes.retain(|m| !m.faces.is_empty());
+    }
+
+    /// Remove the named parts of the scene. A part can be a whole mesh or a group within one.
+    ///
+    /// # Arguments
+    /// * `names` - the names of the meshes and groups to remove
+    pub fn hide(&mut self, names: &[String]) {
+        self.meshes.retain(|m| !names.iter().any(|n| m.name.as_ref() == Some(n)));
+        for mesh in self.meshes.iter_mut() {
+            let hidden: Vec<Group> = mesh.groups.iter()
+                                         .filter(|g| names.contains(&g.name))
+                                         .cloned()
+                                         .collect();
+            if !hidden.is_empty() {
+                mesh.retain_faces(|i| !hidden.iter().any(|g| g.contains(i)));
+            }
+        }
+        self.meshes.retain(|m| !m.faces.is_empty());
+    }
+
+    /// Whether there is a mesh or a group with this name.
+    pub fn has_part(&self, name: &str) -> bool {
+        self.meshes.iter().any(|m| m.name.as_deref() == Some(name) || m.groups.iter().any(|g| g.name == name))
+    }
+
+    /// Use a material for a named part of the scene, replacing any materials on its faces.
+    /// A part can be a whole mesh or a group within one.
+    ///
+    /// # Arguments
+    /// * `name` - the name of the mesh or group
+    /// * `material` - the index of the material
+    pub fn set_part_material(&mut self, name: &str, material: usize) {
+        for mesh in self.meshes.iter_mut() {
+            if mesh.name.as_deref() == Some(name) {
+                mesh.set_material(material);
+                continue;
+            }
+            let faces: Vec<usize> = mesh.groups.iter()
+                                        .filter(|g| g.name == name)
+                                        .flat_map(|g| g.faces())
+                                        .collect();
+            for i in faces {
+                mesh.faces[i].set_material(Some(material));
+            }
+        }
+    }
+
+    /// Add a material and return its index.
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Find the index of a material by name.
+    pub fn material_index(&self, name: &str) -> Option<usize> {
+        self.materials.iter().position(|m| m.name == name)
+    }
+
+    /// Flatten the scene into a single list of faces. Faces without a material of their own take
+    /// the material of their mesh. Each face is numbered by its place in the list.
+    pub fn into_faces(self) -> Vec<Face> {
+        let mut faces = Vec::with_capacity(self.face_count());
+        for mesh in self.meshes {
+            let material = mesh.material;
+            let first = faces.len();
+            faces.extend(mesh.faces.into_iter().enumerate().map(|(i, mut f)| {
+                if f.material().is_none() {
+                    f.set_material(material);
+                }
+                f.with_id(Some(first + i))
+            }));
+        }
+        faces
+    }
+}
+
+impl Display for Scene {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} faces in {} parts", self.face_count(), self.meshes.len())?;
+        for m in &self.meshes {
+            writeln!(f, "  {} ({} faces)", m.display_name(), m.faces.len())?;
+            for g in &m.groups {
+                writeln!(f, "    {} ({} faces)", g.name, g.len())?;
+            }
+        }
+        for (k, v) in &self.metadata {
+            writeln!(f, "  {}: {}", k, v)?;
+        }
+        if !self.warnings.is_empty() {
+            writeln!(f, "  {} warnings", self.warnings.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Group, Material, Mesh, Scene, Warning};
+    use crate::{Face, Vec3};
+
+    fn triangle() -> Face {
+        Face::from_points(Vec3::new(0.0, 0.0, 0.0),
+                          Vec3::new(1.0, 0.0, 0.0),
+                          Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn test_into_faces() {
+        let mut scene = Scene::new();
+        let red = scene.add_material(Material::with_colour("red", Vec3::new(1.0, 0.0, 0.0)));
+        let blue = scene.add_material(Material::with_colour("blue", Vec3::new(0.0, 0.0, 1.0)));
+
+        let mut a = Mesh::new(Some("a".to_owned()), vec![triangle(), triangle()]);
+        a.material = Some(red);
+        a.faces[1].set_material(Some(blue));
+        scene.meshes.push(a);
+        scene.meshes.push(Mesh::new(None, vec![triangle()]));
+
+        assert_eq!(scene.face_count(), 3);
+        assert!(scene.mesh("a").is_some());
+        assert!(scene.mesh("b").is_none());
+        assert_eq!(scene.material_index("blue"), Some(blue));
+
+        let faces = scene.into_faces();
+        let materials: Vec<Option<usize>> = faces.iter().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![Some(red), Some(blue), None]);
+        let ids: Vec<Option<usize>> = faces.iter().map(|f| f.id()).collect();
+        assert_eq!(ids, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_group_from_indices() {
+        let g = Group::from_indices("g", vec![0, 1, 2, 5, 7, 8]);
+        assert_eq!(g.ranges, vec![0..3, 5..6, 7..9]);
+        assert_eq!(g.len(), 6);
+        assert!(g.contains(5));
+        assert!(!g.contains(6));
+        assert!(Group::from_indices("empty", vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_select() {
+        let mut a = Mesh::new(Some("a".to_owned()), vec![triangle(); 4]);
+        a.groups.push(Group::from_indices("top", vec![0, 3]));
+        a.groups.push(Group::from_indices("bottom", vec![1, 2]));
+        a.groups.push(Group::from_indices("corner", vec![3]));
+        let b = Mesh::new(Some("b".to_owned()), vec![triangle(); 2]);
+        let c = Mesh::new(None, vec![triangle(); 2]);
+        let mut scene = Scene::new();
+        scene.meshes = vec![a, b, c];
+
+        scene.select(&["top".to_owned(), "b".to_owned()]);
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.face_count(), 4);
+        let a = scene.mesh("a").unwrap();
+        assert_eq!(a.groups, vec![Group::from_indices("top", vec![0, 1]),
+                                  Group::from_indices("corner", vec![1])]);
+        assert_eq!(scene.mesh("b").unwrap().faces.len(), 2);
+    }
+
+    #[test]
+    fn test_hide() {
+        let mut a = Mesh::new(Some("a".to_owned()), vec![triangle(); 4]);
+        a.groups.push(Group::from_indices("top", vec![0, 3]));
+        a.groups.push(Group::from_indices("bottom", vec![1, 2]));
+        let b = Mesh::new(Some("b".to_owned()), vec![triangle(); 2]);
+        let mut scene = Scene::new();
+        scene.meshes = vec![a, b];
+
+        scene.hide(&["top".to_owned(), "b".to_owned()]);
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.mesh("a").unwrap().groups, vec![Group::from_indices("bottom", vec![0, 1])]);
+
+        scene.hide(&["bottom".to_owned()]);
+        assert!(scene.meshes.is_empty());
+    }
+
+    #[test]
+    fn test_set_part_material() {
+        let mut a = Mesh::new(Some("a".to_owned()), vec![triangle(); 3]);
+        a.groups.push(Group::from_indices("top", vec![0, 2]));
+        let b = Mesh::new(Some("b".to_owned()), vec![triangle(); 2]);
+        let mut scene = Scene::new();
+        scene.meshes = vec![a, b];
+        assert!(scene.has_part("top") && scene.has_part("b"));
+        assert!(!scene.has_part("c"));
+
+        scene.set_part_material("top", 1);
+        scene.set_part_material("b", 2);
+        let materials: Vec<Option<usize>> = scene.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![Some(1), None, Some(1), Some(2), Some(2)]);
+        assert_eq!(scene.mesh("a").unwrap().material, None);
+        assert_eq!(scene.mesh("b").unwrap().material, Some(2));
+    }
+
+    #[test]
+    fn test_set_material() {
+        let mut mesh = Mesh::new(None, vec![triangle(), triangle()]);
+        mesh.faces[0].set_material(Some(3));
+        mesh.set_material(1);
+        assert!(mesh.faces.iter().all(|f| f.material() == Some(1)));
+    }
+
+    #[test]
+    fn test_warning_display() {
+        let w = Warning::new(Some(12), "bad vertex".to_owned());
+        assert_eq!(w.to_string(), "line 12: bad vertex");
+        assert_eq!(w.in_file("cube.mtl").to_string(), "cube.mtl:12: bad vertex");
+        assert_eq!(Warning::new(None, "truncated".to_owned()).to_string(), "truncated");
+    }
+}
@@ -0,0 +1,97 @@
+use super::Vec3;
+use nalgebra::{Matrix3, Rotation3};
+
+/// An affine transform of points: a linear part (rotation, scale, shear) followed by a translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub linear: Matrix3<f64>,
+    pub translation: Vec3,
+}
+
+impl Transform {
+    /// The transform which leaves points where they are.
+    pub fn identity() -> Transform {
+        Transform { linear: Matrix3::identity(), translation: Vec3::zeros() }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform { linear: Matrix3::identity(), translation }
+    }
+
+    /// Create a transform from 12 numbers in the row major order of a 4x3 matrix, as used by 3MF.
+    /// Points are row vectors multiplied on the left, so the last row is the translation.
+    pub fn from_rows(m: [f64; 12]) -> Transform {
+        Transform {
+            linear: Matrix3::new(m[0], m[3], m[6],
+                                 m[1], m[4], m[7],
+                                 m[2], m[5], m[8]),
+            translation: Vec3::new(m[9], m[10], m[11]),
+        }
+    }
+
+    /// Create a transform which rotates about the x, then y, then z axes and then translates.
+    ///
+    /// # Arguments
+    /// * `rotation` - The angles to rotate about each axis, in degrees
+    /// * `translation` - The translation after rotating
+    pub fn from_rotation_degrees(rotation: Vec3, translation: Vec3) -> Transform {
+        let r = rotation.map(f64::to_radians);
+        let x = Rotation3::from_axis_angle(&Vec3::x_axis(), r.x);
+        let y = Rotation3::from_axis_angle(&Vec3::y_axis(), r.y);
+        let z = Rotation3::from_axis_angle(&Vec3::z_axis(), r.z);
+        Transform { linear: (z * y * x).into_inner(), translation }
+    }
+
+    /// Transform a point.
+    pub fn apply(&self, point: Vec3) -> Vec3 {
+        self.linear * point + self.translation
+    }
+
+    /// The transform which applies this transform and then `other`.
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            linear: other.linear * self.linear,
+            translation: other.linear * self.translation + other.translation,
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transform, Vec3};
+
+    #[test]
+    fn test_from_rows() {
+        // Scale x by 2 and move up by 5
+        let t = Transform::from_rows([2.0, 0.0, 0.0,
+                                      0.0, 1.0, 0.0,
+                                      0.0, 0.0, 1.0,
+                                      0.0, 0.0, 5.0]);
+        assert_eq!(t.apply(Vec3::new(1.0, 1.0, 1.0)), Vec3::new(2.0, 1.0, 6.0));
+
+        // Rows of the linear part are where each axis goes
+        let swap = Transform::from_rows([0.0, 1.0, 0.0,
+                                         1.0, 0.0, 0.0,
+                                         0.0, 0.0, 1.0,
+                                         0.0, 0.0, 0.0]);
+        assert_eq!(swap.apply(Vec3::new(1.0, 2.0, 3.0)), Vec3::new(2.0, 1.0, 3.0));
+    }
+
+    #[test]
+    fn test_then() {
+        let rotate = Transform::from_rotation_degrees(Vec3::new(0.0, 0.0, 90.0), Vec3::zeros());
+        let up = Transform::from_translation(Vec3::new(0.0, 0.0, 1.0));
+        let p = rotate.then(&up).apply(Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Vec3::new(0.0, 1.0, 1.0)).norm() < 1e-12);
+        let p = up.then(&rotate).apply(Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Vec3::new(0.0, 1.0, 1.0)).norm() < 1e-12);
+        let p = rotate.then(&rotate).apply(Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Vec3::new(-1.0, 0.0, 0.0)).norm() < 1e-12);
+    }
+}
@@ -0,0 +1,226 @@
+use geometry::{Face, Vec3};
+use std::collections::{HashMap, HashSet};
+
+/// An edge between two vertices, with the smaller vertex index first.
+pub type Edge = (usize, usize);
+
+/// A triangle's use of an edge.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EdgeUse {
+    /// The index of the triangle
+    pub triangle: usize,
+    /// Whether the triangle goes along the edge from its first vertex to its second
+    pub forwards: bool,
+}
+
+/// Triangles which share their corners, so the connections between them can be followed.
+/// Faces in a `Scene` each have their own copy of their corners.
+#[derive(Clone, Debug, Default)]
+pub struct IndexedMesh {
+    pub positions: Vec<Vec3>,
+    /// The indices of the corners of each triangle, in order
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl IndexedMesh {
+    /// Join up faces whose corners are in exactly the same place.
+    ///
+    /// # Arguments
+    /// * `faces` - The faces, which become triangles in the same order
+    pub fn from_faces<'a, I: IntoIterator<Item = &'a Face>>(faces: I) -> IndexedMesh {
+        let mut mesh = IndexedMesh::default();
+        let mut index: HashMap<[u64; 3], usize> = HashMap::new();
+        for face in faces {
+            let mut triangle = [0; 3];
+            for (corner, v) in triangle.iter_mut().zip(face.vertices().iter()) {
+                // Adding 0 turns -0 into 0, so they are the same point
+                let key = [(v.x + 0.0).to_bits(), (v.y + 0.0).to_bits(), (v.z + 0.0).to_bits()];
+                let positions = &mut mesh.positions;
+                *corner = *index.entry(key).or_insert_with(|| {
+                    positions.push(*v);
+                    positions.len() - 1
+                });
+            }
+            mesh.triangles.push(triangle);
+        }
+        mesh
+    }
+
+    /// The positions of the corners of a triangle.
+    pub fn corners(&self, triangle: usize) -> [Vec3; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+
+    /// Twice the area of a triangle, in the direction of its normal.
+    pub fn cross(&self, triangle: usize) -> Vec3 {
+        let [a, b, c] = self.corners(triangle);
+        (b - a).cross(&(c - a))
+    }
+
+    /// Every edge and the triangles which use it. Edges from a vertex to itself (in triangles
+    /// which use a vertex twice) are left out.
+    pub fn edges(&self) -> HashMap<Edge, Vec<EdgeUse>> {
+        let mut edges: HashMap<Edge, Vec<EdgeUse>> = HashMap::new();
+        for (t, triangle) in self.triangles.iter().enumerate() {
+            for i in 0..3 {
+                let (from, to) = (triangle[i], triangle[(i + 1) % 3]);
+                if from == to {
+                    continue;
+                }
+                let edge = (from.min(to), from.max(to));
+                edges.entry(edge).or_default().push(EdgeUse { triangle: t, forwards: from < to });
+            }
+        }
+        edges
+    }
+
+    /// Whether two triangles have a corner in common.
+    pub fn share_vertex(&self, a: usize, b: usize) -> bool {
+        self.triangles[a].iter().any(|v| self.triangles[b].contains(v))
+    }
+
+    /// The triangles which use the same vertex twice, or whose area is no more than `min_area`.
+    pub fn degenerate_triangles(&self, min_area: f64) -> Vec<usize> {
+        (0..self.triangles.len()).filter(|&t| {
+            let [a, b, c] = self.triangles[t];
+            a == b || b == c || c == a || self.cross(t).norm() / 2.0 <= min_area
+        }).collect()
+    }
+
+    /// The triangles which use the same vertices as an earlier triangle, in either direction.
+    pub fn duplicate_triangles(&self) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        (0..self.triangles.len()).filter(|&t| {
+            let mut key = self.triangles[t];
+            key.sort_unstable();
+            !seen.insert(key)
+        }).collect()
+    }
+
+    /// Merge vertices which are within `tolerance` of each other, so that triangles which
+    /// nearly meet are joined. Each group of close vertices is moved to the first of them.
+    /// Returns the number of vertices removed.
+    ///
+    /// # Arguments
+    /// * `tolerance` - The largest distance between vertices which are merged
+    pub fn weld(&mut self, tolerance: f64) -> usize {
+        if tolerance <= 0.0 {
+            return 0;
+        }
+        // Vertices are put in a grid of cubes as big as the tolerance, so close vertices
+        // are always in the same or a neighbouring cube
+        let cell = |p: &Vec3| [(p.x / tolerance).floor() as i64,
+                               (p.y / tolerance).floor() as i64,
+                               (p.z / tolerance).floor() as i64];
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut new_index = Vec::with_capacity(self.positions.len());
+        for p in &self.positions {
+            let [x, y, z] = cell(p);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let mut near = grid.get(&[x + dx, y + dy, z + dz]).into_iter().flatten();
+                        if let Some(&i) = near.find(|&&i| (positions[i] - p).norm() <= tolerance) {
+                            found = Some(i);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+            new_index.push(found.unwrap_or_else(|| {
+                positions.push(*p);
+                grid.entry([x, y, z]).or_default().push(positions.len() - 1);
+                positions.len() - 1
+            }));
+        }
+
+        let removed = self.positions.len() - positions.len();
+        self.positions = positions;
+        for triangle in self.triangles.iter_mut() {
+            for v in triangle.iter_mut() {
+                *v = new_index[*v];
+            }
+        }
+        removed
+    }
+
+    /// Keep only some of the triangles. The vertices are not changed.
+    ///
+    /// # Arguments
+    /// * `keep` - whether to keep each triangle, by index
+    pub fn retain_triangles<F: Fn(usize) -> bool>(&mut self, keep: F) {
+        let triangles = std::mem::take(&mut self.triangles);
+        self.triangles = triangles.into_iter()
+                                  .enumerate()
+                                  .filter(|(i, _)| keep(*i))
+                                  .map(|(_, t)| t)
+                                  .collect();
+    }
+
+    /// Reverse the winding of a triangle, so it faces the other way.
+    pub fn flip(&mut self, triangle: usize) {
+        self.triangles[triangle].swap(1, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EdgeUse, IndexedMesh};
+    use geometry::{Face, Vec3};
+
+    #[test]
+    fn test_from_faces() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let d = Vec3::new(1.0, 1.0, -0.0);
+        let faces = vec![Face::from_points(a, b, c), Face::from_points(c, b, d)];
+        let mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [2, 1, 3]]);
+        assert_eq!(mesh.cross(0), Vec3::new(0.0, 0.0, 1.0));
+
+        let edges = mesh.edges();
+        assert_eq!(edges.len(), 5);
+        assert_eq!(edges[&(1, 2)], vec![EdgeUse { triangle: 0, forwards: true },
+                                        EdgeUse { triangle: 1, forwards: false }]);
+        assert!(mesh.share_vertex(0, 1));
+    }
+
+    #[test]
+    fn test_weld() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let faces = vec![Face::from_points(a, b, c),
+                         Face::from_points(c + Vec3::new(0.0, 0.001, 0.0), b - Vec3::new(0.0005, 0.0, 0.0), b + c)];
+        let mut mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.weld(0.0001), 0);
+        assert_eq!(mesh.weld(0.002), 2);
+        assert_eq!(mesh.positions, vec![a, b, c, b + c]);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [2, 1, 3]]);
+    }
+
+    #[test]
+    fn test_degenerate_and_duplicate() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let faces = vec![Face::from_points(a, b, c),
+                         Face::from_points(a, a, c),
+                         Face::from_points(a, b, b * 2.0),
+                         Face::from_points(b, a, c),
+                         Face::from_points(a, b, c)];
+        let mut mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(mesh.degenerate_triangles(1e-12), vec![1, 2]);
+        assert_eq!(mesh.duplicate_triangles(), vec![3, 4]);
+
+        mesh.retain_triangles(|t| t != 1 && t != 2);
+        mesh.flip(0);
+        assert_eq!(mesh.triangles, vec![[0, 2, 1], [1, 0, 2], [0, 1, 2]]);
+    }
+}
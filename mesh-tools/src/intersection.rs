@@ -0,0 +1,182 @@
+use super::IndexedMesh;
+use bvh::BoundingVolumeHierarchy;
+use geometry::{Bounded, Vec3};
+
+/// How close (relative to the size of the triangles) points must be to count as touching.
+const EPSILON: f64 = 1e-9;
+
+/// The bounding box of one triangle of an `IndexedMesh`, so that triangles which might
+/// touch can be found with a `BoundingVolumeHierarchy`.
+#[derive(Clone, Debug)]
+struct Indexed {
+    index: usize,
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounded for Indexed {
+    fn min_extents(&self) -> Vec3 {
+        self.min
+    }
+
+    fn max_extents(&self) -> Vec3 {
+        self.max
+    }
+
+    fn translate(&self, t: Vec3) -> Self {
+        Indexed { index: self.index, min: self.min + t, max: self.max + t }
+    }
+}
+
+/// Find every pair of triangles which cross or touch each other. Triangles which share a
+/// corner are expected to touch and are not checked, nor are triangles with no area.
+/// Each pair is returned once, with the smaller index first.
+///
+/// # Arguments
+/// * `mesh` - The triangles to check
+pub fn self_intersections(mesh: &IndexedMesh) -> Vec<(usize, usize)> {
+    let boxes: Vec<Indexed> = (0..mesh.triangles.len()).map(|index| {
+        let [a, b, c] = mesh.corners(index);
+        Indexed { index, min: a.inf(&b).inf(&c), max: a.sup(&b).sup(&c) }
+    }).collect();
+    let bvh = BoundingVolumeHierarchy::new(boxes.clone());
+
+    let mut pairs = Vec::new();
+    for b in &boxes {
+        let mut found: Vec<usize> = bvh.overlapping(&b.min, &b.max).iter()
+            .map(|other| other.index)
+            .filter(|&other| other > b.index && !mesh.share_vertex(b.index, other))
+            .filter(|&other| triangles_intersect(&mesh.corners(b.index), &mesh.corners(other)))
+            .collect();
+        found.sort_unstable();
+        pairs.extend(found.into_iter().map(|other| (b.index, other)));
+    }
+    pairs
+}
+
+/// Whether two triangles cross or touch. Triangles with no area never intersect anything.
+///
+/// # Arguments
+/// * `a` - The corners of the first triangle
+/// * `b` - The corners of the second triangle
+pub fn triangles_intersect(a: &[Vec3; 3], b: &[Vec3; 3]) -> bool {
+    let scale = a.iter().chain(b.iter())
+        .flat_map(|p| a.iter().chain(b.iter()).map(move |q| (p - q).norm()))
+        .fold(0.0, f64::max);
+    let eps = EPSILON * scale;
+    let normal_a = (a[1] - a[0]).cross(&(a[2] - a[0]));
+    let normal_b = (b[1] - b[0]).cross(&(b[2] - b[0]));
+    if normal_a.norm() <= eps * scale || normal_b.norm() <= eps * scale {
+        return false;
+    }
+    let normal_a = normal_a.normalize();
+
+    if b.iter().all(|p| normal_a.dot(&(p - a[0])).abs() <= eps) {
+        return coplanar_intersect(a, b, &normal_a, eps * scale);
+    }
+
+    // If the triangles cross, the ends of the line where they meet are on their edges
+    let edges = |t: &[Vec3; 3]| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])];
+    edges(a).iter().any(|(p, q)| segment_hits_triangle(p, q, b, eps)) ||
+        edges(b).iter().any(|(p, q)| segment_hits_triangle(p, q, a, eps))
+}
+
+/// Whether the segment from `p` to `q` touches the triangle `t`. Segments which lie in the
+/// plane of the triangle are treated as missing, as they are checked by the other edges.
+fn segment_hits_triangle(p: &Vec3, q: &Vec3, t: &[Vec3; 3], eps: f64) -> bool {
+    let normal = (t[1] - t[0]).cross(&(t[2] - t[0])).normalize();
+    let dp = normal.dot(&(p - t[0]));
+    let dq = normal.dot(&(q - t[0]));
+    if (dp > eps && dq > eps) || (dp < -eps && dq < -eps) || (dp.abs() <= eps && dq.abs() <= eps) {
+        return false;
+    }
+    let s = if dp.abs() <= eps { 0.0 } else if dq.abs() <= eps { 1.0 } else { dp / (dp - dq) };
+    let x = p + (q - p) * s;
+    (0..3).all(|i| {
+        let (from, to) = (t[i], t[(i + 1) % 3]);
+        // Distance of x inside this edge of the triangle
+        normal.cross(&(to - from)).normalize().dot(&(x - from)) >= -eps
+    })
+}
+
+/// Whether two triangles in the same plane overlap. `eps` is an area, like the results of
+/// `orient`.
+fn coplanar_intersect(a: &[Vec3; 3], b: &[Vec3; 3], normal: &Vec3, eps: f64) -> bool {
+    // Drop the axis the plane is most nearly facing
+    let axis = normal.iamax();
+    let flat = |p: &Vec3| match axis {
+        0 => (p.y, p.z),
+        1 => (p.z, p.x),
+        _ => (p.x, p.y)
+    };
+    let a = [flat(&a[0]), flat(&a[1]), flat(&a[2])];
+    let b = [flat(&b[0]), flat(&b[1]), flat(&b[2])];
+
+    let orient = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)
+    };
+    let inside = |t: &[(f64, f64); 3], p: (f64, f64)| {
+        let sign = orient(t[0], t[1], t[2]).signum();
+        (0..3).all(|i| orient(t[i], t[(i + 1) % 3], p) * sign >= -eps)
+    };
+    let crosses = |p: (f64, f64), q: (f64, f64), r: (f64, f64), s: (f64, f64)| {
+        let (d1, d2) = (orient(p, q, r), orient(p, q, s));
+        let (d3, d4) = (orient(r, s, p), orient(r, s, q));
+        d1 * d2 <= 0.0 && d3 * d4 <= 0.0 &&
+            // Edges along the same line only touch if their ends overlap, which is found below
+            !(d1.abs() <= eps && d2.abs() <= eps)
+    };
+
+    (0..3).any(|i| (0..3).any(|j| crosses(a[i], a[(i + 1) % 3], b[j], b[(j + 1) % 3]))) ||
+        a.iter().any(|&p| inside(&b, p)) ||
+        b.iter().any(|&p| inside(&a, p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{self_intersections, triangles_intersect};
+    use crate::IndexedMesh;
+    use geometry::{Face, Vec3};
+
+    fn triangle(a: (f64, f64, f64), b: (f64, f64, f64), c: (f64, f64, f64)) -> [Vec3; 3] {
+        [Vec3::new(a.0, a.1, a.2), Vec3::new(b.0, b.1, b.2), Vec3::new(c.0, c.1, c.2)]
+    }
+
+    #[test]
+    fn test_crossing() {
+        let a = triangle((0.0, 0.0, 0.0), (2.0, 0.0, 0.0), (0.0, 2.0, 0.0));
+        let through = triangle((0.5, 0.5, -1.0), (0.5, 0.5, 1.0), (1.5, -1.0, 0.0));
+        let above = triangle((0.5, 0.5, 1.0), (0.5, 0.5, 2.0), (1.5, -1.0, 1.0));
+        let beside = triangle((3.0, 0.0, -1.0), (3.0, 0.0, 1.0), (3.0, 1.0, 0.0));
+        assert!(triangles_intersect(&a, &through));
+        assert!(triangles_intersect(&through, &a));
+        assert!(!triangles_intersect(&a, &above));
+        assert!(!triangles_intersect(&a, &beside));
+    }
+
+    #[test]
+    fn test_coplanar() {
+        let a = triangle((0.0, 0.0, 0.0), (2.0, 0.0, 0.0), (0.0, 2.0, 0.0));
+        let overlapping = triangle((1.0, 1.0, 0.0), (-1.0, 0.5, 0.0), (1.0, -1.0, 0.0));
+        let inside = triangle((0.1, 0.1, 0.0), (0.5, 0.1, 0.0), (0.1, 0.5, 0.0));
+        let apart = triangle((2.0, 2.0, 0.0), (3.0, 2.0, 0.0), (2.0, 3.0, 0.0));
+        assert!(triangles_intersect(&a, &overlapping));
+        assert!(triangles_intersect(&a, &inside));
+        assert!(triangles_intersect(&inside, &a));
+        assert!(!triangles_intersect(&a, &apart));
+    }
+
+    #[test]
+    fn test_self_intersections() {
+        let p = |x, y, z| Vec3::new(x, y, z);
+        let faces = vec![
+            Face::from_points(p(0.0, 0.0, 0.0), p(2.0, 0.0, 0.0), p(0.0, 2.0, 0.0)),
+            // Shares a corner with the first, so is not checked
+            Face::from_points(p(0.0, 0.0, 0.0), p(1.0, 1.0, 1.0), p(1.0, 1.0, -1.0)),
+            Face::from_points(p(0.5, 0.5, -1.0), p(0.5, 0.5, 1.0), p(1.5, -1.0, 0.0)),
+            Face::from_points(p(5.0, 5.0, 5.0), p(6.0, 5.0, 5.0), p(5.0, 6.0, 5.0)),
+        ];
+        let mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(self_intersections(&mesh), vec![(0, 2), (1, 2)]);
+    }
+}
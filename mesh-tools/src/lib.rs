@@ -0,0 +1,16 @@
+//! Tools for checking, measuring, repairing and smoothing triangle meshes, for example before they
+//! are 3D printed.
+
+mod indexed;
+mod intersection;
+mod normals;
+mod repair;
+mod report;
+#[cfg(test)]
+mod test_shapes;
+
+pub use indexed::{Edge, EdgeUse, IndexedMesh};
+pub use intersection::{self_intersections, triangles_intersect};
+pub use normals::{smooth_groups, smooth_normals, smooth_scene_normals, NormalWeighting};
+pub use repair::{fill_holes, repair_mesh, repair_scene, unify_winding, RepairOptions, RepairSummary};
+pub use report::Report;
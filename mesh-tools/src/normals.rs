@@ -0,0 +1,201 @@
+use super::IndexedMesh;
+use geometry::{Face, Mesh, Scene, Vec3};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// How much each face around a vertex counts towards the normal at that vertex.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NormalWeighting {
+    /// By the area of the face, so large faces count the most
+    Area,
+    /// By the angle of the face's corner at the vertex, which does not depend on how the
+    /// surface is split into triangles
+    Angle,
+}
+
+impl FromStr for NormalWeighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "area" => Ok(NormalWeighting::Area),
+            "angle" => Ok(NormalWeighting::Angle),
+            _ => Err(format!("unknown normal weighting '{}', expected area or angle", s))
+        }
+    }
+}
+
+impl Display for NormalWeighting {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NormalWeighting::Area => write!(f, "area"),
+            NormalWeighting::Angle => write!(f, "angle"),
+        }
+    }
+}
+
+/// Replace the vertex normals of every part of a scene with smooth ones. See `smooth_normals`.
+///
+/// # Arguments
+/// * `scene` - The scene to change
+/// * `weighting` - How the normals of the faces around a vertex are combined
+/// * `crease_angle` - Faces meeting at more than this angle (in degrees) are not smoothed together
+pub fn smooth_scene_normals(scene: &mut Scene, weighting: NormalWeighting, crease_angle: f64) {
+    for mesh in scene.meshes.iter_mut() {
+        smooth_normals(mesh, weighting, crease_angle);
+    }
+}
+
+/// Replace the vertex normals of every face with the average of the normals of the faces
+/// which share that vertex, so that the surface is shaded smoothly. Only faces which meet
+/// the face at an angle no bigger than the crease angle are included, so sharp edges stay
+/// sharp. Any normals read from the model file are replaced. Faces with no area are left
+/// as they are.
+///
+/// # Arguments
+/// * `mesh` - The part to change
+/// * `weighting` - How the normals of the faces around a vertex are combined
+/// * `crease_angle` - Faces meeting at more than this angle (in degrees) are not smoothed together
+pub fn smooth_normals(mesh: &mut Mesh, weighting: NormalWeighting, crease_angle: f64) {
+    let groups = vec![Some(0); mesh.faces.len()];
+    smooth_groups(&mut mesh.faces, &groups, weighting, crease_angle);
+}
+
+/// Smooth the vertex normals of faces within smoothing groups, such as those of an OBJ file.
+/// This is `smooth_normals`, except that faces are only smoothed together with faces in the
+/// same group, and faces in no group are left as they are.
+///
+/// # Arguments
+/// * `faces` - The faces to change
+/// * `groups` - The smoothing group of each face, if it has one
+/// * `weighting` - How the normals of the faces around a vertex are combined
+/// * `crease_angle` - Faces meeting at more than this angle (in degrees) are not smoothed together
+pub fn smooth_groups(faces: &mut [Face], groups: &[Option<u32>], weighting: NormalWeighting, crease_angle: f64) {
+    let indexed = IndexedMesh::from_faces(faces.iter());
+    let min_cos = crease_angle.to_radians().cos();
+
+    let normals: Vec<Option<Vec3>> = (0..indexed.triangles.len())
+        .map(|t| groups[t].and(indexed.cross(t).try_normalize(0.0)))
+        .collect();
+    // The triangles around each vertex, and which of their corners it is
+    let mut around: Vec<Vec<(usize, usize)>> = vec![Vec::new(); indexed.positions.len()];
+    for (t, triangle) in indexed.triangles.iter().enumerate() {
+        if normals[t].is_some() {
+            for (corner, &v) in triangle.iter().enumerate() {
+                around[v].push((t, corner));
+            }
+        }
+    }
+
+    let weight = |t: usize, corner: usize| match weighting {
+        NormalWeighting::Area => indexed.cross(t).norm() / 2.0,
+        NormalWeighting::Angle => {
+            let p = indexed.corners(t);
+            let (from, to) = (p[(corner + 1) % 3] - p[corner], p[(corner + 2) % 3] - p[corner]);
+            from.angle(&to)
+        }
+    };
+
+    for (t, face) in faces.iter_mut().enumerate() {
+        let normal = match normals[t] {
+            Some(normal) => normal,
+            None => continue
+        };
+        let mut vertex_normals = [Vec3::zeros(); 3];
+        for (sum, &v) in vertex_normals.iter_mut().zip(indexed.triangles[t].iter()) {
+            for &(other, corner) in &around[v] {
+                let other_normal = normals[other].unwrap();
+                if groups[other] == groups[t] && other_normal.dot(&normal) >= min_cos {
+                    *sum += other_normal * weight(other, corner);
+                }
+            }
+        }
+        // The face itself always counts, so the sums can only vanish if its weight is zero.
+        // The normals are kept on the same side as the face's own normal from the file.
+        let side = if face.normal().dot(&normal) < 0.0 { -1.0 } else { 1.0 };
+        let vertex_normals = [0, 1, 2].map(|i| vertex_normals[i].try_normalize(0.0).unwrap_or(normal) * side);
+        *face = face.clone().with_vertex_normals(vertex_normals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{smooth_groups, smooth_normals, NormalWeighting};
+    use geometry::{Face, Mesh, Vec3};
+
+    /// Two triangles meeting along the y axis at a 90 degree ridge, the first one twice as
+    /// big as the second
+    fn ridge() -> Mesh {
+        let top = Vec3::new(0.0, 0.0, 0.0);
+        let bottom = Vec3::new(0.0, 1.0, 0.0);
+        Mesh::new(None, vec![
+            Face::from_points(top, bottom, Vec3::new(-2.0, 0.0, -2.0)),
+            Face::from_points(top, Vec3::new(1.0, 0.0, -1.0), bottom),
+        ])
+    }
+
+    #[test]
+    fn test_smooth() {
+        let mut mesh = ridge();
+        smooth_normals(&mut mesh, NormalWeighting::Angle, 100.0);
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let [top, bottom, far] = mesh.faces[0].vertex_normals();
+        // Both faces have a right angle at the top
+        assert!((top - up).norm() < 1e-12);
+        assert!(bottom.x < 0.0 && bottom.z > 0.0);
+        // Only used by one face
+        assert!((far - mesh.faces[0].normal()).norm() < 1e-12);
+        assert_eq!(mesh.faces[0].normal(), ridge().faces[0].normal());
+    }
+
+    #[test]
+    fn test_area_weighted() {
+        let mut mesh = ridge();
+        smooth_normals(&mut mesh, NormalWeighting::Area, 100.0);
+        let [top, _, _] = mesh.faces[1].vertex_normals();
+        // The bigger face (facing -x) pulls the normal towards it
+        assert!(top.x < 0.0);
+        assert!(top.z > 0.0);
+    }
+
+    #[test]
+    fn test_crease() {
+        let mut mesh = ridge();
+        smooth_normals(&mut mesh, NormalWeighting::Angle, 80.0);
+        for face in &mesh.faces {
+            for n in face.vertex_normals().iter() {
+                assert!((n - face.normal()).norm() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let flat = ridge();
+        let mut mesh = ridge();
+        smooth_groups(&mut mesh.faces, &[Some(1), Some(2)], NormalWeighting::Angle, 180.0);
+        for face in &mesh.faces {
+            for n in face.vertex_normals().iter() {
+                assert!((n - face.normal()).norm() < 1e-12);
+            }
+        }
+
+        // A face in no group keeps its normals
+        let mut mesh = ridge();
+        smooth_groups(&mut mesh.faces, &[Some(1), None], NormalWeighting::Angle, 180.0);
+        assert_eq!(mesh.faces[1].vertex_normals(), flat.faces[1].vertex_normals());
+
+        let mut mesh = ridge();
+        smooth_groups(&mut mesh.faces, &[Some(3), Some(3)], NormalWeighting::Angle, 180.0);
+        let mut smoothed = ridge();
+        smooth_normals(&mut smoothed, NormalWeighting::Angle, 180.0);
+        assert_eq!(mesh.faces[0].vertex_normals(), smoothed.faces[0].vertex_normals());
+    }
+
+    #[test]
+    fn test_parse_weighting() {
+        assert_eq!("Area".parse::<NormalWeighting>(), Ok(NormalWeighting::Area));
+        assert_eq!("angle".parse::<NormalWeighting>(), Ok(NormalWeighting::Angle));
+        assert!("volume".parse::<NormalWeighting>().is_err());
+    }
+}
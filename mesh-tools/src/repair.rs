@@ -0,0 +1,364 @@
+use super::report::DEGENERATE_AREA;
+use super::IndexedMesh;
+use geometry::{Face, Mesh, Scene, Vec3};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
+
+/// Which repairs to make to a model.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RepairOptions {
+    /// Merge vertices closer than this. Zero only joins vertices in exactly the same place.
+    pub weld_tolerance: f64,
+    /// Remove triangles with no area
+    pub remove_degenerate: bool,
+    /// Remove triangles with the same corners as another triangle
+    pub remove_duplicates: bool,
+    /// Make neighbouring triangles wind the same way, facing out of the model
+    pub fix_winding: bool,
+    /// Fill holes with at most this many edges. Zero leaves all holes open.
+    pub max_hole_edges: usize,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        RepairOptions {
+            weld_tolerance: 0.0,
+            remove_degenerate: true,
+            remove_duplicates: true,
+            fix_winding: true,
+            max_hole_edges: 16,
+        }
+    }
+}
+
+/// What was changed by a repair.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RepairSummary {
+    /// Vertices merged into another vertex
+    pub welded: usize,
+    /// Triangles with no area which were removed
+    pub degenerate: usize,
+    /// Repeated triangles which were removed
+    pub duplicate: usize,
+    /// Triangles turned to face the other way
+    pub flipped: usize,
+    /// Triangles which kept their winding, but had normals pointing the wrong way
+    pub normals: usize,
+    /// Holes which were closed
+    pub holes_filled: usize,
+}
+
+impl AddAssign for RepairSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.welded += other.welded;
+        self.degenerate += other.degenerate;
+        self.duplicate += other.duplicate;
+        self.flipped += other.flipped;
+        self.normals += other.normals;
+        self.holes_filled += other.holes_filled;
+    }
+}
+
+impl Display for RepairSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "welded {} vertices, removed {} degenerate and {} duplicate triangles, \
+                   flipped {} triangles, fixed {} normals and filled {} holes",
+               self.welded, self.degenerate, self.duplicate, self.flipped, self.normals, self.holes_filled)
+    }
+}
+
+/// Repair every part of a scene. Each part is repaired on its own. Faces which are not
+/// changed keep their normals and texture coordinates, and every face keeps its material and
+/// groups. Faces added to fill holes are not in any group.
+///
+/// # Arguments
+/// * `scene` - The scene to repair
+/// * `options` - Which repairs to make
+pub fn repair_scene(scene: &mut Scene, options: &RepairOptions) -> RepairSummary {
+    let mut summary = RepairSummary::default();
+    for mesh in scene.meshes.iter_mut() {
+        summary += repair_mesh(mesh, options);
+    }
+    summary
+}
+
+/// Repair one part of a model.
+///
+/// # Arguments
+/// * `mesh` - The part to repair
+/// * `options` - Which repairs to make
+pub fn repair_mesh(mesh: &mut Mesh, options: &RepairOptions) -> RepairSummary {
+    let mut summary = RepairSummary::default();
+    let mut indexed = IndexedMesh::from_faces(&mesh.faces);
+    // The face each triangle came from, or None for triangles which fill holes
+    let mut sources: Vec<Option<usize>> = (0..mesh.faces.len()).map(Some).collect();
+
+    summary.welded = indexed.weld(options.weld_tolerance);
+    let remove = |indexed: &mut IndexedMesh, sources: &mut Vec<Option<usize>>, found: Vec<usize>| {
+        let found: HashSet<usize> = found.into_iter().collect();
+        indexed.retain_triangles(|t| !found.contains(&t));
+        let mut t = 0;
+        sources.retain(|_| {
+            t += 1;
+            !found.contains(&(t - 1))
+        });
+        found.len()
+    };
+    if options.remove_degenerate {
+        let inf = f64::INFINITY;
+        let min = indexed.positions.iter().fold(Vec3::new(inf, inf, inf), |m, p| m.inf(p));
+        let max = indexed.positions.iter().fold(Vec3::new(-inf, -inf, -inf), |m, p| m.sup(p));
+        let size = if indexed.positions.is_empty() { 0.0 } else { (max - min).norm() };
+        let found = indexed.degenerate_triangles(DEGENERATE_AREA * size * size);
+        summary.degenerate = remove(&mut indexed, &mut sources, found);
+    }
+    if options.remove_duplicates {
+        let found = indexed.duplicate_triangles();
+        summary.duplicate = remove(&mut indexed, &mut sources, found);
+    }
+    if options.fix_winding {
+        summary.flipped = unify_winding(&mut indexed);
+    }
+    if options.max_hole_edges > 0 {
+        summary.holes_filled = fill_holes(&mut indexed, options.max_hole_edges);
+        sources.resize(indexed.triangles.len(), None);
+    }
+
+    // Faces are only rebuilt if they have changed, to keep their normals
+    let mut faces = Vec::with_capacity(indexed.triangles.len());
+    for (t, source) in sources.iter().enumerate() {
+        let corners = indexed.corners(t);
+        let face = match source.map(|s| &mesh.faces[s]) {
+            Some(face) if face.vertices() == corners => {
+                if face.normal().dot(&indexed.cross(t)) < 0.0 {
+                    summary.normals += 1;
+                    Face::from_points(corners[0], corners[1], corners[2]).with_material(face.material())
+                } else {
+                    face.clone()
+                }
+            },
+            Some(face) if face.vertices() == [corners[0], corners[2], corners[1]] => face.flipped(),
+            Some(face) => Face::from_points(corners[0], corners[1], corners[2]).with_material(face.material()),
+            None => Face::from_points(corners[0], corners[1], corners[2]).with_material(mesh.material)
+        };
+        faces.push(face);
+    }
+
+    let kept: HashSet<usize> = sources.iter().flatten().copied().collect();
+    mesh.retain_faces(|i| kept.contains(&i));
+    mesh.faces = faces;
+    summary
+}
+
+/// Make every triangle wind the same way as its neighbours, then turn each closed connected
+/// piece so that it faces out (encloses a positive volume). Open pieces have no inside, so they
+/// wind the way most of their triangles already did. Neighbours are only followed across edges
+/// shared by exactly two triangles. Returns the number of triangles flipped.
+///
+/// # Arguments
+/// * `mesh` - The mesh to change
+pub fn unify_winding(mesh: &mut IndexedMesh) -> usize {
+    let mut neighbours: Vec<Vec<(usize, bool)>> = vec![Vec::new(); mesh.triangles.len()];
+    for uses in mesh.edges().values() {
+        if let [first, second] = uses.as_slice() {
+            // The neighbour needs flipping (compared to this triangle) if both go the same way
+            let flip = first.forwards == second.forwards;
+            neighbours[first.triangle].push((second.triangle, flip));
+            neighbours[second.triangle].push((first.triangle, flip));
+        }
+    }
+
+    let mut flipped: Vec<Option<bool>> = vec![None; mesh.triangles.len()];
+    let mut flips = 0;
+    for seed in 0..mesh.triangles.len() {
+        if flipped[seed].is_some() {
+            continue;
+        }
+        // Breadth first search through one connected piece
+        let mut piece = Vec::new();
+        let mut queue = VecDeque::new();
+        flipped[seed] = Some(false);
+        queue.push_back(seed);
+        while let Some(t) = queue.pop_front() {
+            piece.push(t);
+            let flip = flipped[t] == Some(true);
+            for &(other, relative) in &neighbours[t] {
+                if flipped[other].is_none() {
+                    flipped[other] = Some(flip != relative);
+                    queue.push_back(other);
+                }
+            }
+        }
+
+        for &t in &piece {
+            if flipped[t] == Some(true) {
+                mesh.flip(t);
+            }
+        }
+        let closed = piece.iter().all(|&t| neighbours[t].len() == 3);
+        let turn = if closed {
+            let volume: f64 = piece.iter().map(|&t| {
+                let [a, b, c] = mesh.corners(t);
+                a.dot(&b.cross(&c))
+            }).sum();
+            volume < 0.0
+        } else {
+            2 * piece.iter().filter(|&&t| flipped[t] == Some(true)).count() > piece.len()
+        };
+        for &t in &piece {
+            if turn {
+                mesh.flip(t);
+            }
+            if turn != (flipped[t] == Some(true)) {
+                flips += 1;
+            }
+        }
+    }
+    flips
+}
+
+/// Close holes (loops of edges used by only one triangle) with new triangles which wind
+/// the same way as the triangles around them. Holes of three edges are filled with one
+/// triangle, and bigger holes with a fan of triangles around a new vertex in their middle.
+/// Holes which touch another hole at a vertex are left open. Returns the number filled.
+///
+/// # Arguments
+/// * `mesh` - The mesh to change
+/// * `max_edges` - Only fill holes with at most this many edges
+pub fn fill_holes(mesh: &mut IndexedMesh, max_edges: usize) -> usize {
+    // Each boundary edge, in the direction the triangle using it goes
+    let mut next: HashMap<usize, usize> = HashMap::new();
+    let mut branching = HashSet::new();
+    for (&(a, b), uses) in &mesh.edges() {
+        if let [only] = uses.as_slice() {
+            let (from, to) = if only.forwards { (a, b) } else { (b, a) };
+            if next.insert(from, to).is_some() {
+                branching.insert(from);
+            }
+        }
+    }
+
+    let mut starts: Vec<usize> = next.keys().copied().collect();
+    starts.sort_unstable();
+    let mut visited = HashSet::new();
+    let mut filled = 0;
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+        let mut hole = vec![start];
+        let mut v = start;
+        let closed = loop {
+            visited.insert(v);
+            if branching.contains(&v) {
+                break false;
+            }
+            match next.get(&v) {
+                Some(&n) if n == start => break true,
+                Some(&n) if !visited.contains(&n) => {
+                    hole.push(n);
+                    v = n;
+                },
+                _ => break false
+            }
+        };
+        if !closed || hole.len() > max_edges {
+            continue;
+        }
+
+        // The new triangles go around the hole the other way to the triangles beside it
+        if hole.len() == 3 {
+            mesh.triangles.push([hole[0], hole[2], hole[1]]);
+        } else {
+            let centre = hole.iter().map(|&v| mesh.positions[v]).sum::<Vec3>() / hole.len() as f64;
+            mesh.positions.push(centre);
+            let c = mesh.positions.len() - 1;
+            for i in 0..hole.len() {
+                mesh.triangles.push([c, hole[(i + 1) % hole.len()], hole[i]]);
+            }
+        }
+        filled += 1;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fill_holes, repair_scene, unify_winding, RepairOptions};
+    use crate::test_shapes::cube;
+    use crate::{IndexedMesh, Report};
+    use geometry::{Face, Group, Mesh, Scene, Vec3};
+
+    #[test]
+    fn test_unify_winding() {
+        // Inside out, with one triangle the right way round
+        let mut faces: Vec<Face> = cube().iter().map(|f| f.flipped()).collect();
+        faces[4] = faces[4].flipped();
+        let mut mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(unify_winding(&mut mesh), 11);
+        let report = Report::from_mesh(&mesh);
+        assert_eq!(report.inconsistent_edges, 0);
+        assert!(report.volume > 0.0);
+
+        // An open piece facing in keeps the winding of most of its triangles
+        let mut faces: Vec<Face> = cube()[..10].iter().map(|f| f.flipped()).collect();
+        faces[4] = faces[4].flipped();
+        let mut mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(unify_winding(&mut mesh), 1);
+        assert_eq!(Report::from_mesh(&mesh).inconsistent_edges, 0);
+        assert_eq!(mesh.corners(0), faces[0].vertices());
+    }
+
+    #[test]
+    fn test_fill_holes() {
+        let mut faces = cube();
+        // A square hole in the top and a triangular hole in the bottom
+        faces.remove(3);
+        faces.remove(2);
+        faces.remove(0);
+        let mut mesh = IndexedMesh::from_faces(&faces);
+        assert_eq!(fill_holes(&mut mesh, 3), 1);
+        assert_eq!(mesh.triangles.len(), 10);
+        assert_eq!(fill_holes(&mut mesh, 4), 1);
+        assert_eq!(mesh.triangles.len(), 14);
+        assert_eq!(mesh.positions.len(), 9);
+        assert!(Report::from_mesh(&mesh).is_printable());
+    }
+
+    #[test]
+    fn test_repair_scene() {
+        let faces = cube();
+        let mut broken = vec![faces[0].flipped()];
+        broken.extend(faces[1..].iter().map(|f| f.clone().with_material(Some(0))));
+        // A gap, a sliver and a repeated triangle
+        let gap = Vec3::new(0.0, 0.0, 0.0001);
+        broken[11] = Face::from_points(broken[11].vertices()[0] + gap, broken[11].vertices()[1],
+                                       broken[11].vertices()[2]);
+        broken.remove(6);
+        broken.push(Face::from_points(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.5, 0.0, 0.0),
+                                      Vec3::new(1.0, 0.0, 0.0)));
+        broken.push(broken[1].clone());
+        let mut mesh = Mesh::new(Some("cube".to_owned()), broken);
+        mesh.groups.push(Group::from_indices("top", vec![2, 3]));
+        mesh.groups.push(Group::from_indices("extra", vec![11, 12]));
+        let mut scene = Scene::from_mesh(mesh);
+        assert!(!Report::new(&scene).is_printable());
+
+        let options = RepairOptions { weld_tolerance: 0.001, ..RepairOptions::default() };
+        let summary = repair_scene(&mut scene, &options);
+        assert_eq!(summary.welded, 1);
+        assert_eq!(summary.degenerate, 1);
+        assert_eq!(summary.duplicate, 1);
+        assert_eq!(summary.flipped, 1);
+        assert_eq!(summary.holes_filled, 1);
+        assert!(Report::new(&scene).is_printable());
+
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.faces.len(), 12);
+        assert_eq!(mesh.faces[0].material(), None);
+        assert_eq!(mesh.faces[1].material(), Some(0));
+        assert_eq!(mesh.groups.len(), 1);
+        assert_eq!(mesh.groups[0].faces().collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
@@ -0,0 +1,311 @@
+use super::{self_intersections, IndexedMesh};
+use geometry::{Scene, Vec3};
+use std::fmt::{Display, Formatter};
+
+/// Triangles smaller than this (relative to the size of the whole model) have no area.
+pub(crate) const DEGENERATE_AREA: f64 = 1e-12;
+
+/// Statistics about a model, and the problems with it which stop it being 3D printed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub triangles: usize,
+    /// The number of distinct corner positions
+    pub vertices: usize,
+    /// The smallest corner of the bounding box
+    pub min: Vec3,
+    /// The largest corner of the bounding box
+    pub max: Vec3,
+    pub surface_area: f64,
+    /// The enclosed volume. This is negative if the model is inside out, and meaningless
+    /// if the model is not watertight.
+    pub volume: f64,
+    /// The centre of mass of the enclosed volume, or of the surface if there is no volume
+    pub centroid: Vec3,
+    /// Triangles with no area
+    pub degenerate: usize,
+    /// Triangles with the same corners as an earlier triangle
+    pub duplicate: usize,
+    /// Edges used by only one triangle
+    pub boundary_edges: usize,
+    /// Loops of boundary edges
+    pub holes: usize,
+    /// Edges used by more than two triangles
+    pub non_manifold_edges: usize,
+    /// Edges where the two triangles either side are wound in opposite directions
+    pub inconsistent_edges: usize,
+    /// Pairs of triangles which cross each other
+    pub self_intersections: usize,
+    /// The units of the model, if the file says
+    pub units: Option<String>,
+    /// The name and number of triangles of each part of the model
+    pub parts: Vec<(String, usize)>,
+}
+
+impl Report {
+    /// Measure and check a whole scene, treating all of its parts as one model.
+    ///
+    /// # Arguments
+    /// * `scene` - The scene to check
+    pub fn new(scene: &Scene) -> Report {
+        let mesh = IndexedMesh::from_faces(scene.faces());
+        let mut report = Report::from_mesh(&mesh);
+        report.units = scene.units.map(|u| format!("{:?}", u).to_lowercase());
+        report.parts = scene.meshes.iter()
+            .map(|m| (m.display_name().to_owned(), m.faces.len()))
+            .collect();
+        report
+    }
+
+    /// Measure and check a mesh.
+    ///
+    /// # Arguments
+    /// * `mesh` - The mesh to check
+    pub fn from_mesh(mesh: &IndexedMesh) -> Report {
+        let inf = f64::INFINITY;
+        let min = mesh.positions.iter().fold(Vec3::new(inf, inf, inf), |m, p| m.inf(p));
+        let max = mesh.positions.iter().fold(Vec3::new(-inf, -inf, -inf), |m, p| m.sup(p));
+        let size = if mesh.positions.is_empty() { 0.0 } else { (max - min).norm() };
+
+        let mut surface_area = 0.0;
+        let mut volume = 0.0;
+        let mut weighted_volume = Vec3::zeros();
+        let mut weighted_area = Vec3::zeros();
+        for t in 0..mesh.triangles.len() {
+            let [a, b, c] = mesh.corners(t);
+            let area = mesh.cross(t).norm() / 2.0;
+            // Signed volume of the tetrahedron from the origin to the triangle
+            let tetrahedron = a.dot(&b.cross(&c)) / 6.0;
+            surface_area += area;
+            volume += tetrahedron;
+            weighted_volume += (a + b + c) * (tetrahedron / 4.0);
+            weighted_area += (a + b + c) * (area / 3.0);
+        }
+        let centroid = if volume.abs() > DEGENERATE_AREA * size * size * size {
+            weighted_volume / volume
+        } else if surface_area > 0.0 {
+            weighted_area / surface_area
+        } else {
+            (min + max) / 2.0
+        };
+
+        let mut boundary = Vec::new();
+        let mut non_manifold_edges = 0;
+        let mut inconsistent_edges = 0;
+        for (edge, uses) in mesh.edges() {
+            match uses.as_slice() {
+                [_] => boundary.push(edge),
+                [first, second] if first.forwards == second.forwards => inconsistent_edges += 1,
+                [_, _] => (),
+                _ => non_manifold_edges += 1
+            }
+        }
+
+        Report {
+            triangles: mesh.triangles.len(),
+            vertices: mesh.positions.len(),
+            min,
+            max,
+            surface_area,
+            volume,
+            centroid,
+            degenerate: mesh.degenerate_triangles(DEGENERATE_AREA * size * size).len(),
+            duplicate: mesh.duplicate_triangles().len(),
+            boundary_edges: boundary.len(),
+            holes: count_loops(mesh.positions.len(), &boundary),
+            non_manifold_edges,
+            inconsistent_edges,
+            self_intersections: self_intersections(mesh).len(),
+            units: None,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Whether the surface is closed, with every edge shared by exactly two triangles.
+    pub fn is_watertight(&self) -> bool {
+        self.triangles > 0 && self.boundary_edges == 0 && self.non_manifold_edges == 0
+    }
+
+    /// Whether the model is a single clean, closed, outward facing surface which can be
+    /// sliced for 3D printing.
+    pub fn is_printable(&self) -> bool {
+        self.is_watertight() &&
+            self.degenerate == 0 &&
+            self.duplicate == 0 &&
+            self.inconsistent_edges == 0 &&
+            self.self_intersections == 0 &&
+            self.volume > 0.0
+    }
+
+    /// Write the report as a JSON object. Numbers which are not finite are written as null.
+    pub fn to_json(&self) -> String {
+        let vector = |v: &Vec3| format!("[{}, {}, {}]", number(v.x), number(v.y), number(v.z));
+        let parts: Vec<String> = self.parts.iter()
+            .map(|(name, triangles)| format!("{{\"name\": {}, \"triangles\": {}}}", string(name), triangles))
+            .collect();
+        let fields = vec![
+            ("triangles", self.triangles.to_string()),
+            ("vertices", self.vertices.to_string()),
+            ("min", vector(&self.min)),
+            ("max", vector(&self.max)),
+            ("surface_area", number(self.surface_area)),
+            ("volume", number(self.volume)),
+            ("centroid", vector(&self.centroid)),
+            ("degenerate_triangles", self.degenerate.to_string()),
+            ("duplicate_triangles", self.duplicate.to_string()),
+            ("boundary_edges", self.boundary_edges.to_string()),
+            ("holes", self.holes.to_string()),
+            ("non_manifold_edges", self.non_manifold_edges.to_string()),
+            ("inconsistent_edges", self.inconsistent_edges.to_string()),
+            ("self_intersections", self.self_intersections.to_string()),
+            ("watertight", self.is_watertight().to_string()),
+            ("printable", self.is_printable().to_string()),
+            ("units", self.units.as_deref().map_or("null".to_owned(), string)),
+            ("parts", format!("[{}]", parts.join(", "))),
+        ];
+        let fields: Vec<String> = fields.into_iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+/// Count the separate loops made by some edges.
+fn count_loops(vertices: usize, edges: &[(usize, usize)]) -> usize {
+    // Union-find over the vertices, counting each time two separate sets are joined
+    let mut parent: Vec<usize> = (0..vertices).collect();
+    fn root(parent: &mut [usize], mut v: usize) -> usize {
+        while parent[v] != v {
+            parent[v] = parent[parent[v]];
+            v = parent[v];
+        }
+        v
+    }
+    let mut used = vec![false; vertices];
+    let mut joins = 0;
+    for &(a, b) in edges {
+        used[a] = true;
+        used[b] = true;
+        let (a, b) = (root(&mut parent, a), root(&mut parent, b));
+        if a != b {
+            parent[a] = b;
+            joins += 1;
+        }
+    }
+    used.iter().filter(|&&u| u).count() - joins
+}
+
+fn number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{}", value)
+    } else {
+        "null".to_owned()
+    }
+}
+
+fn string(value: &str) -> String {
+    let mut s = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c)
+        }
+    }
+    s.push('"');
+    s
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} triangles, {} vertices", self.triangles, self.vertices)?;
+        if let Some(units) = &self.units {
+            writeln!(f, "Units: {}", units)?;
+        }
+        for (name, triangles) in &self.parts {
+            writeln!(f, "  {} ({} triangles)", name, triangles)?;
+        }
+        writeln!(f, "Bounding box: {} to {} (size {})", vector(&self.min), vector(&self.max),
+                 vector(&(self.max - self.min)))?;
+        writeln!(f, "Surface area: {:.6}", self.surface_area)?;
+        writeln!(f, "Volume: {:.6}", self.volume)?;
+        writeln!(f, "Centroid: {}", vector(&self.centroid))?;
+        writeln!(f, "Degenerate triangles: {}", self.degenerate)?;
+        writeln!(f, "Duplicate triangles: {}", self.duplicate)?;
+        writeln!(f, "Boundary edges: {} in {} holes", self.boundary_edges, self.holes)?;
+        writeln!(f, "Non-manifold edges: {}", self.non_manifold_edges)?;
+        writeln!(f, "Inconsistently wound edges: {}", self.inconsistent_edges)?;
+        writeln!(f, "Self-intersecting triangle pairs: {}", self.self_intersections)?;
+        writeln!(f, "Watertight: {}", if self.is_watertight() { "yes" } else { "no" })?;
+        writeln!(f, "Printable: {}", if self.is_printable() { "yes" } else { "no" })
+    }
+}
+
+fn vector(v: &Vec3) -> String {
+    format!("({:.6}, {:.6}, {:.6})", v.x, v.y, v.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::test_shapes::cube;
+    use crate::IndexedMesh;
+    use geometry::{Face, Mesh, Scene, Units, Vec3};
+
+    #[test]
+    fn test_cube() {
+        let mut scene = Scene::from_mesh(Mesh::new(Some("cube".to_owned()), cube()));
+        scene.units = Some(Units::Millimetres);
+        let report = Report::new(&scene);
+        assert_eq!(report.triangles, 12);
+        assert_eq!(report.vertices, 8);
+        assert_eq!(report.min, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(report.max, Vec3::new(1.0, 1.0, 1.0));
+        assert!((report.surface_area - 6.0).abs() < 1e-12);
+        assert!((report.volume - 1.0).abs() < 1e-12);
+        assert!((report.centroid - Vec3::new(0.5, 0.5, 0.5)).norm() < 1e-12);
+        assert_eq!(report.boundary_edges, 0);
+        assert_eq!(report.non_manifold_edges, 0);
+        assert_eq!(report.inconsistent_edges, 0);
+        assert_eq!(report.self_intersections, 0);
+        assert!(report.is_printable());
+        assert_eq!(report.units.as_deref(), Some("millimetres"));
+        assert_eq!(report.parts, vec![("cube".to_owned(), 12)]);
+    }
+
+    #[test]
+    fn test_problems() {
+        let mut faces = cube();
+        // Open one side, flip another, and add a duplicate and a triangle with no area
+        faces.remove(0);
+        let flipped = faces[2].vertices();
+        faces[2] = Face::from_points(flipped[0], flipped[2], flipped[1]);
+        faces.push(faces[5].clone());
+        let p = Vec3::new(3.0, 0.0, 0.0);
+        faces.push(Face::from_points(p, p, Vec3::new(4.0, 0.0, 0.0)));
+
+        let report = Report::from_mesh(&IndexedMesh::from_faces(&faces));
+        assert_eq!(report.triangles, 13);
+        assert_eq!(report.degenerate, 1);
+        assert_eq!(report.duplicate, 1);
+        assert_eq!(report.boundary_edges, 3);
+        assert_eq!(report.holes, 1);
+        // The duplicate's three edges are each used three times
+        assert_eq!(report.non_manifold_edges, 3);
+        assert!(report.inconsistent_edges > 0);
+        assert!(!report.is_watertight());
+        assert!(!report.is_printable());
+    }
+
+    #[test]
+    fn test_json() {
+        let scene = Scene::from_mesh(Mesh::new(Some("a \"part\"".to_owned()), cube()));
+        let json = Report::new(&scene).to_json();
+        assert!(json.starts_with("{\n  \"triangles\": 12,\n  \"vertices\": 8,\n  \"min\": [0, 0, 0],\n"));
+        assert!(json.contains("\"printable\": true,\n  \"units\": null,\n"));
+        assert!(json.ends_with("\"parts\": [{\"name\": \"a \\\"part\\\"\", \"triangles\": 12}]\n}\n"));
+    }
+}
@@ -0,0 +1,20 @@
+//! Meshes shared by the tests of several modules.
+
+use geometry::{Face, Vec3};
+
+/// A unit cube from (0, 0, 0) to (1, 1, 1) with its faces pointing out, two triangles per side
+pub fn cube() -> Vec<Face> {
+    let p = |x: f64, y: f64, z: f64| Vec3::new(x, y, z);
+    let quads = [
+        [p(0.0, 0.0, 0.0), p(0.0, 1.0, 0.0), p(1.0, 1.0, 0.0), p(1.0, 0.0, 0.0)],
+        [p(0.0, 0.0, 1.0), p(1.0, 0.0, 1.0), p(1.0, 1.0, 1.0), p(0.0, 1.0, 1.0)],
+        [p(0.0, 0.0, 0.0), p(1.0, 0.0, 0.0), p(1.0, 0.0, 1.0), p(0.0, 0.0, 1.0)],
+        [p(0.0, 1.0, 0.0), p(0.0, 1.0, 1.0), p(1.0, 1.0, 1.0), p(1.0, 1.0, 0.0)],
+        [p(0.0, 0.0, 0.0), p(0.0, 0.0, 1.0), p(0.0, 1.0, 1.0), p(0.0, 1.0, 0.0)],
+        [p(1.0, 0.0, 0.0), p(1.0, 1.0, 0.0), p(1.0, 1.0, 1.0), p(1.0, 0.0, 1.0)],
+    ];
+    quads.iter().flat_map(|[a, b, c, d]| vec![
+        Face::from_points(*a, *b, *c),
+        Face::from_points(*a, *c, *d)
+    ]).collect()
+}
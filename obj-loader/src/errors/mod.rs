@@ -1,6 +1,8 @@
 //! Errors for OBJ file reading
 
+use geometry::impl_load_error;
 use scanner_rust::ScannerError;
+use std::fmt::{Display, Formatter};
 use std::io::Error;
 use std::num::TryFromIntError;
 use std::str::Utf8Error;
@@ -21,12 +23,56 @@ pub enum ObjError {
     TooManyFacesError,
     /// Line started with an unknown command
     UnknownCommand(String),
-    /// File terminated when something else was expected
-    UnexpectedEndOfFile(String),
-    /// Expect a float, but did not get one
-    NotAFloat(String),
-    // Face without enough vertices to specify it (< 3)
-    NotEnoughVerticesInFace(usize)
+    /// The line ended when this value was expected
+    MissingValue(String),
+    /// A value could not be read as the expected type
+    InvalidValue { token: String, expected: String },
+    /// Face without enough vertices to specify it (< 3)
+    NotEnoughVerticesInFace(usize),
+    /// A face refers to a vertex, normal or texture coordinate which has not been defined
+    IndexOutOfRange { kind: &'static str, index: isize, count: usize },
+    /// A vertex has a weight (w) of 0, so it is infinitely far away
+    PointAtInfinity,
+    /// A face uses a vertex which could not be read (only in lenient mode)
+    UsesBadVertex(usize),
+    /// An error at a known position in a file. Lines and columns start at 1.
+    At { file: Option<String>, line: usize, column: usize, error: Box<ObjError> }
+}
+
+impl_load_error!(ObjError, IOError, ScanError);
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::UTF8Error(e) => write!(f, "OBJ file is not valid UTF-8: {}", e),
+            ObjError::IOError(e) => write!(f, "Could not read OBJ file: {}", e),
+            ObjError::ScanError(e) => write!(f, "Could not read OBJ file: {}", e),
+            ObjError::NoFacesFound => write!(f, "OBJ file has no faces"),
+            ObjError::TooManyFacesError => write!(f, "OBJ file has too many faces"),
+            ObjError::UnknownCommand(c) => write!(f, "unknown statement '{}'", c),
+            ObjError::MissingValue(expected) => write!(f, "expected {}, found the end of the line", expected),
+            ObjError::InvalidValue { token, expected } => write!(f, "expected {}, found '{}'", expected, token),
+            ObjError::NotEnoughVerticesInFace(n) => write!(f, "a face needs at least 3 vertices, found {}", n),
+            ObjError::IndexOutOfRange { kind, index, count } =>
+                write!(f, "{} index {} is out of range (there are {})", kind, index, count),
+            ObjError::PointAtInfinity => write!(f, "vertex has a weight of 0"),
+            ObjError::UsesBadVertex(v) => write!(f, "face uses vertex {} which could not be read", v),
+            ObjError::At { file, line, column, error } =>
+                write!(f, "{}:{}:{}: {}", file.as_deref().unwrap_or("<obj>"), line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::UTF8Error(e) => Some(e),
+            ObjError::IOError(e) => Some(e),
+            ObjError::ScanError(e) => Some(e),
+            ObjError::At { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
 }
 
 impl std::convert::From<ScannerError> for ObjError {
@@ -53,3 +99,32 @@ impl std::convert::From<TryFromIntError> for ObjError {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::ObjError;
+    use geometry::LoadError;
+
+    #[test]
+    fn test_display() {
+        let e = ObjError::InvalidValue { token: "1.0.0".to_owned(), expected: "vertex x".to_owned() };
+        let e = e.at(12, 3).in_file("cube.obj");
+        assert_eq!(e.to_string(), "cube.obj:12:3: expected vertex x, found '1.0.0'");
+
+        let e = ObjError::MissingValue("vertex z".to_owned()).at(2, 9);
+        assert_eq!(e.to_string(), "<obj>:2:9: expected vertex z, found the end of the line");
+    }
+
+    #[test]
+    fn test_position_kept() {
+        // The innermost position is the most precise, so it is not replaced
+        let e = ObjError::UnknownCommand("vp".to_owned()).at(3, 1).at(7, 7).in_file("a.obj").in_file("b.obj");
+        assert_eq!(e.to_string(), "a.obj:3:1: unknown statement 'vp'");
+    }
+
+    #[test]
+    fn test_into_warning() {
+        let w = ObjError::PointAtInfinity.at(5, 9).into_warning();
+        assert_eq!(w.line, Some(5));
+        assert_eq!(w.to_string(), "line 5: vertex has a weight of 0");
+    }
+}
@@ -1,4 +1,5 @@
-use geometry::{Vec3,Vec4};
+use geometry::{Strictness, Vec3, Vec4, Warning};
+use std::collections::HashSet;
 
 
 pub type ObjVertex  = Vec4;
@@ -25,11 +26,121 @@ pub struct ObjFace {
     pub cv: usize,
     pub cn: usize,
     pub ct: usize,
+    // Index into the material names, if a material is in use
+    pub material: Option<usize>,
+    // Smoothing group (0 if smoothing is off)
+    pub smoothing: u32,
+    // Index into the objects
+    pub object: usize,
+}
+
+/// Everything read from an OBJ file so far
+#[derive(Debug)]
+pub struct ObjData {
+    pub vertices: Vec<ObjVertex>,
+    pub normals: Vec<ObjNormal>,
+    pub textures: Vec<ObjParam>,
+    pub faces: Vec<ObjFace>,
+    /// Material library files named with mtllib
+    pub libraries: Vec<String>,
+    /// Material names in the order they were first used with usemtl
+    pub material_names: Vec<String>,
+    /// The material being applied to new faces
+    pub current_material: Option<usize>,
+    /// Object names. Faces before the first `o` statement go in the unnamed object 0.
+    pub objects: Vec<Option<String>>,
+    /// The object new faces are added to
+    pub current_object: usize,
+    /// Group names, with the (triangulated) faces in each
+    pub groups: Vec<(String, Vec<usize>)>,
+    /// The groups new faces are added to
+    pub current_groups: Vec<usize>,
+    /// The smoothing group new faces are added to (0 is off)
+    pub current_smoothing: u32,
+    /// The number of the line being read (starting from 1)
+    pub line: usize,
+    /// Whether bad lines are errors or are skipped
+    pub strictness: Strictness,
+    /// Problems found in lines which were skipped or repaired
+    pub warnings: Vec<Warning>,
+    /// Vertices (by 1 based index) which could not be read. Faces using these are skipped.
+    pub bad_vertices: HashSet<usize>,
+    /// Normals (by 1 based index) which could not be read. Faces using these compute their own.
+    pub bad_normals: HashSet<usize>,
+    /// Statements which are valid OBJ but are not used by Vitrum, and have been warned about
+    pub ignored: HashSet<String>,
+}
+
+impl ObjData {
+    /// Create empty data to read a file into.
+    ///
+    /// # Arguments
+    /// * `strictness` - Whether bad lines are errors or are skipped
+    pub fn new(strictness: Strictness) -> ObjData {
+        ObjData { strictness, ..ObjData::default() }
+    }
+}
+
+impl Default for ObjData {
+    fn default() -> ObjData {
+        ObjData {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            textures: Vec::new(),
+            faces: Vec::new(),
+            libraries: Vec::new(),
+            material_names: Vec::new(),
+            current_material: None,
+            objects: vec![None],
+            current_object: 0,
+            groups: Vec::new(),
+            current_groups: Vec::new(),
+            current_smoothing: 0,
+            line: 0,
+            strictness: Strictness::Strict,
+            warnings: Vec::new(),
+            bad_vertices: HashSet::new(),
+            bad_normals: HashSet::new(),
+            ignored: HashSet::new(),
+        }
+    }
 }
 
 /// Convert an ObjVertex to an Vec3.
-/// This panics if the point is at infinity (w == 0)
+/// The point must not be at infinity (w == 0), which is checked when vertices are read.
 pub fn from_homogenous(v: &ObjVertex) -> Vec3 {
-    //assert_relative_ne!(0.0, self.w, max_relative = 1.0);
     Vec3::new(v.x/v.w, v.y/v.w, v.z/v.w)
-}
\ No newline at end of file
+}
+/// The whitespace separated words of a line. This remembers where the most recent word started
+/// so that errors can point to it.
+pub struct Tokens<'a> {
+    line: &'a str,
+    words: std::str::SplitWhitespace<'a>,
+    column: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(line: &'a str) -> Tokens<'a> {
+        Tokens { line, words: line.split_whitespace(), column: 1 }
+    }
+
+    /// The column (starting at 1) of the most recent word, or the end of the line if there
+    /// were no more words.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.words.next();
+        self.column = match word {
+            // Words are slices of the line, so their offset is the difference of the pointers
+            Some(w) => self.line[..w.as_ptr() as usize - self.line.as_ptr() as usize].chars().count() + 1,
+            None => self.line.chars().count() + 1
+        };
+        word
+    }
+}
@@ -1,5 +1,10 @@
-//!  The stl-loader reads binary and ASCII STL files and converts them into `Faces`.
+//!  The obj-loader reads ASCII OBJ files (and their MTL material libraries) and converts them
+//!  into a `Scene`.
 
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
 use std::str;
 use std::vec::Vec;
 use scanner_rust::Scanner;
@@ -8,84 +13,219 @@ mod errors;
 pub  use errors::ObjError;
 
 mod helpers;
-use helpers::{ObjVertex, ObjNormal, ObjParam, ObjFace, from_homogenous};
+use helpers::{ObjVertex, ObjNormal, ObjParam, ObjFace, ObjData, Tokens, from_homogenous};
 
-pub use geometry::{Face, Vec3};
+mod materials;
+pub use materials::read_mtl;
 
-/// Read in and parse an ascii OBJ file
+use mesh_tools::{smooth_groups, NormalWeighting};
+
+pub use geometry::{Face, Group, LoadError, Material, Mesh, Scene, Strictness, Vec3, Warning};
+
+/// Statements which are valid OBJ but describe things Vitrum does not draw, such as free form
+/// curves and surfaces. These are skipped with a warning.
+const UNSUPPORTED_STATEMENTS: &[&str] = &[
+    "vp", "cstype", "deg", "bmat", "step", "curv", "curv2", "surf", "parm", "trim", "hole", "scrv",
+    "sp", "end", "con", "p", "mg", "lod", "usemap", "maplib", "bevel", "c_interp", "d_interp",
+    "shadow_obj", "trace_obj", "ctech", "stech", "call", "csh",
+];
+
+/// Read in and parse an ascii OBJ file. Material libraries are read from the same directory.
 ///
 /// # Arguments
 ///
 /// * `filename` - The path to the file to read. This must be either an ASCII OBJ file.
-pub fn read_obj_file(filename: &str) -> Result<Vec<Face>, ObjError> {
-    //  Check to make sure that it is not a binary file first
-    let mut scan = Scanner::scan_path(filename)?;
-
-    // Create lists to fill populate with data from the file
-    let mut vertices = Vec::new();
-    let mut normals = Vec::new();
-    let mut textures = Vec::new();
-    let mut faces = Vec::new();
+/// * `strictness` - Whether bad lines are errors or are skipped with a warning.
+pub fn read_obj_file(filename: &str, strictness: Strictness) -> Result<Scene, ObjError> {
+    let directory = Path::new(filename).parent().map(Path::to_path_buf).unwrap_or_default();
+    read_obj(File::open(filename)?, |library| fs::read(directory.join(library)), strictness)
+        .map_err(|e| e.in_file(filename))
+}
+
+/// Read in and parse ascii OBJ data from any source, such as a decompression stream.
+///
+/// # Arguments
+///
+/// * `reader` - The OBJ data to read.
+/// * `resolve` - Reads a file the OBJ refers to (e.g. a material library) given its name.
+/// * `strictness` - Whether bad lines are errors or are skipped with a warning.
+pub fn read_obj<R, F>(reader: R, resolve: F, strictness: Strictness) -> Result<Scene, ObjError>
+        where R: Read, F: Fn(&str) -> std::io::Result<Vec<u8>> {
+    let mut scan = Scanner::new(reader);
+
+    // Populate with data from the file
+    let mut data = ObjData::new(strictness);
     while let Some(line) = scan.next_line()? {
-        process_line(line.trim(), &mut vertices, &mut normals, &mut textures, &mut faces)?
+        data.line += 1;
+        process_line(&line, &mut data)?
     }
 
+    let mut scene = Scene::new();
+    scene.warnings = std::mem::take(&mut data.warnings);
+    scene.materials = read_materials(&data, resolve, &mut scene.warnings)?;
+
     // We now have the list of faces, but currently as indexes into other arrays.
-    // Convert them now into actual faces
-    let mut model = Vec::with_capacity(faces.len());
+    // Convert them now into actual faces, sorted into a mesh per object.
+    let mut meshes: Vec<Mesh> = data.objects.iter().map(|name| Mesh::new(name.clone(), Vec::new())).collect();
+    // The smoothing group of each face in each object, for faces without normals in the file
+    let mut smoothing: Vec<Vec<Option<u32>>> = vec![Vec::new(); meshes.len()];
+    // The object and position within the object of each face
+    let mut placement = Vec::with_capacity(data.faces.len());
+
+    for face in &data.faces {
+        let av = vertex(&data, face.av);
+        let bv = vertex(&data, face.bv);
+        let cv = vertex(&data, face.cv);
+
+        let f = if face.an > 0 && face.bn > 0 && face.cn > 0 {
+            let an = normal(&data, face.an);
+            let bn = normal(&data, face.bn);
+            let cn = normal(&data, face.cn);
+
+            Face::from_points_with_normals(av, bv, cv, an, bn, cn)
+        } else {
+            Face::from_points(av, bv, cv)
+        };
+        let has_normals = face.an > 0 && face.bn > 0 && face.cn > 0;
+        smoothing[face.object].push(Some(face.smoothing).filter(|s| *s != 0 && !has_normals));
+
+        let mesh = &mut meshes[face.object];
+        placement.push((face.object, mesh.faces.len()));
+        mesh.faces.push(f.with_material(face.material));
+    }
+
+    // Faces in the same smoothing group share the normals at their corners
+    for (mesh, groups) in meshes.iter_mut().zip(&smoothing) {
+        smooth_groups(&mut mesh.faces, groups, NormalWeighting::Area, 180.0);
+    }
 
-    for face in faces {
-        let av = get_element_from(face.av, &vertices[..]);
-        let bv = get_element_from(face.bv, &vertices[..]);
-        let cv = get_element_from(face.cv, &vertices[..]);
+    // A group can span several objects, so each object gets its own part of the group
+    for (name, members) in &data.groups {
+        for (m, mesh) in meshes.iter_mut().enumerate() {
+            let group = Group::from_indices(name, members.iter()
+                                                         .map(|i| placement[*i])
+                                                         .filter(|(object, _)| *object == m)
+                                                         .map(|(_, index)| index));
+            if !group.is_empty() {
+                mesh.groups.push(group);
+            }
+        }
+    }
 
-        if face.an > 0 && face.bn > 0 && face.cn > 0 {
-            let an = get_element_from(face.an, &normals[..]);
-            let bn = get_element_from(face.bn, &normals[..]);
-            let cn = get_element_from(face.cn, &normals[..]);
+    scene.meshes = meshes.into_iter().filter(|m| !m.faces.is_empty()).collect();
+    Ok(scene)
+}
 
-            model.push(Face::from_points_with_normals(from_homogenous(&av), from_homogenous(&bv), from_homogenous(&cv), an, bn, cn));
-        } else {
-            model.push(Face::from_points(from_homogenous(&av), from_homogenous(&bv), from_homogenous(&cv)));
+/// Build the materials used by the faces, in the order of `data.material_names`.
+/// Materials which are not defined in any library keep their name but get a default appearance.
+///
+/// # Arguments
+/// * `data` - The OBJ file contents
+/// * `resolve` - Reads a material library given its name.
+/// * `warnings` - Where to record problems with the libraries
+fn read_materials<F>(data: &ObjData, resolve: F, warnings: &mut Vec<Warning>) -> Result<Vec<Material>, ObjError>
+        where F: Fn(&str) -> std::io::Result<Vec<u8>> {
+    let mut defined = Vec::new();
+    for library in &data.libraries {
+        match resolve(library) {
+            Ok(bytes) => {
+                let (materials, problems) = read_mtl(&String::from_utf8_lossy(&bytes), data.strictness)
+                                                .map_err(|e| e.in_file(library))?;
+                defined.extend(materials);
+                warnings.extend(problems.into_iter().map(|w| w.in_file(library)));
+            },
+            // OBJ files are often shared without their materials. The geometry is still useful.
+            Err(e) => warnings.push(Warning::new(None, format!("Could not read material library {}: {}", library, e)))
         }
     }
 
-    Ok(model)
+    Ok(data.material_names.iter()
+        .map(|name| defined.iter()
+                           .find(|m| m.name == *name)
+                           .cloned()
+                           .unwrap_or_else(|| Material::named(name)))
+        .collect())
 }
 
-pub fn get_element_from<T: Copy>(index: usize, data: &[T]) -> T {
+/// Get an element by its 1 based OBJ index. Returns None if the index is 0 or too large.
+pub fn get_element_from<T: Copy>(index: usize, data: &[T]) -> Option<T> {
     if index > 0 {
         // 1 based index going forwards
-        data[index -  1]
+        data.get(index - 1).copied()
     } else {
-        panic!("Cannot have a negative index ({}) in a face after conversion!", index)
+        None
     }
 }
 
-fn process_line(line: &str, vertices: &mut Vec<ObjVertex>, normals: &mut Vec<ObjNormal>,
-                    textures: &mut Vec<ObjParam>, faces: &mut Vec<ObjFace>) -> Result<(), ObjError>{
-    if line.is_empty() || line.starts_with('#') {
+/// The position of a vertex used by a face. Face indices are checked when the face is read.
+fn vertex(data: &ObjData, index: usize) -> Vec3 {
+    from_homogenous(&get_element_from(index, &data.vertices[..]).expect("Vertex index checked when read"))
+}
+
+/// The normal used by a face. Face indices are checked when the face is read.
+fn normal(data: &ObjData, index: usize) -> Vec3 {
+    get_element_from(index, &data.normals[..]).expect("Normal index checked when read")
+}
+
+/// Read one line of an OBJ file. Errors are marked with the line (`data.line`) and the column of
+/// the word which caused them. In lenient mode bad lines are skipped with a warning instead.
+fn process_line(line: &str, data: &mut ObjData) -> Result<(), ObjError>{
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
         // Empty and comment lines can be ignored
         return Ok(());
     }
 
     // This line should have some interesting content.
     // Lines are read as words, split by spaces
-    let mut token_iter = line.split_whitespace();
+    let mut token_iter = Tokens::new(line);
+    let error = match process_statement(trimmed, &mut token_iter, data) {
+        Ok(()) => return Ok(()),
+        Err(e) => e.at(data.line, token_iter.column())
+    };
+    if data.strictness == Strictness::Strict {
+        return Err(error);
+    }
+
+    // Skip the line. Vertices, normals and texture coordinates are numbered by their position
+    // in the file, so they are replaced with a placeholder to keep later indices correct.
+    match trimmed.split_whitespace().next() {
+        Some("v") => {
+            data.vertices.push(ObjVertex::new(0.0, 0.0, 0.0, 1.0));
+            data.bad_vertices.insert(data.vertices.len());
+        },
+        Some("vn") => {
+            data.normals.push(ObjNormal::new(0.0, 0.0, 1.0));
+            data.bad_normals.insert(data.normals.len());
+        },
+        Some("vt") => data.textures.push(ObjParam { u: 0.0, v: 0.0, w: 0.0 }),
+        _ => ()
+    }
+    data.warnings.push(error.into_warning());
+    Ok(())
+}
 
+fn process_statement(line: &str, token_iter: &mut Tokens, data: &mut ObjData) -> Result<(), ObjError>{
     // Read the first token to determine the meaning of the line
     match token_iter.next() {
         Some(line_type) => match line_type{
-            "v" => process_vertex(&mut token_iter, vertices)?,
-            "vn" => process_normal(&mut token_iter, normals)?,
-            "vt" => process_texture(&mut token_iter, textures)?,
-            "f" => process_face(&mut token_iter, faces, vertices.len(), textures.len(), normals.len())?,
+            "v" => process_vertex(token_iter, &mut data.vertices)?,
+            "vn" => process_normal(token_iter, &mut data.normals)?,
+            "vt" => process_texture(token_iter, &mut data.textures)?,
+            "f" => process_face(token_iter, data)?,
             "l" => (), // we are ignoring lines for now
-            "g" => (), // we are ignoring group for now
-            "o" => (), // we are ignoring objects for now
-            "s" => (), // we are ignoring smooth shading instructions for now
-            "mtllib" => (), // we are ignoring materials files for now
-            "usemtl" => (), // we are ignoring materials for now
+            "g" => process_group(token_iter, data),
+            "o" => process_object(line, data),
+            "s" => process_smoothing(token_iter, data)?,
+            "mtllib" => data.libraries.extend(token_iter.map(String::from)),
+            "usemtl" => process_use_material(token_iter, data)?,
+            other if UNSUPPORTED_STATEMENTS.contains(&other) => {
+                // Only warn once for each kind of statement
+                if data.ignored.insert(other.to_owned()) {
+                    let message = format!("'{}' statements are not supported and have been skipped", other);
+                    data.warnings.push(Warning::new(Some(data.line), message));
+                }
+            },
             other => return Err(ObjError::UnknownCommand(other.to_string()))
         },
         None => return Ok(())
@@ -95,50 +235,124 @@ fn process_line(line: &str, vertices: &mut Vec<ObjVertex>, normals: &mut Vec<Obj
 
 }
 
-fn process_vertex(token_iter: &mut str::SplitWhitespace, vertices: &mut Vec<ObjVertex>) -> Result<(), ObjError> {
-    vertices.push(ObjVertex::new(
-        ensure(token_iter.next(), "vertex x")?,
-        ensure(token_iter.next(), "vertex y")?,
-        ensure(token_iter.next(), "vertex z")?,
-        maybe(token_iter.next(), 1.0, "vertex w")?
-        ));
+fn process_vertex(token_iter: &mut Tokens, vertices: &mut Vec<ObjVertex>) -> Result<(), ObjError> {
+    let vertex = ObjVertex::new(
+        ensure(token_iter.next(), "a number for vertex x")?,
+        ensure(token_iter.next(), "a number for vertex y")?,
+        ensure(token_iter.next(), "a number for vertex z")?,
+        maybe(token_iter.next(), 1.0, "a number for vertex w")?
+        );
+    if vertex.w == 0.0 {
+        return Err(ObjError::PointAtInfinity);
+    }
+    vertices.push(vertex);
 
     Ok(())
 }
 
-fn process_normal(token_iter: &mut str::SplitWhitespace, normals: &mut Vec<ObjNormal>) -> Result<(), ObjError> {
+fn process_normal(token_iter: &mut Tokens, normals: &mut Vec<ObjNormal>) -> Result<(), ObjError> {
     normals.push(ObjNormal::new(
-        ensure(token_iter.next(), "normal x")?,
-        ensure(token_iter.next(), "normal y")?,
-        ensure(token_iter.next(), "normal z")?,
+        ensure(token_iter.next(), "a number for normal x")?,
+        ensure(token_iter.next(), "a number for normal y")?,
+        ensure(token_iter.next(), "a number for normal z")?,
     ));
     Ok(())
 }
 
-fn process_texture(token_iter: &mut str::SplitWhitespace, textures: &mut Vec<ObjParam>) -> Result<(), ObjError> {
+fn process_texture(token_iter: &mut Tokens, textures: &mut Vec<ObjParam>) -> Result<(), ObjError> {
     textures.push(ObjParam {
-        u: ensure(token_iter.next(), "texture x")?,
-        v: maybe(token_iter.next(), 0.0, "texture y")?,
-        w: maybe(token_iter.next(), 0.0, "texture z")?,
+        u: ensure(token_iter.next(), "a number for texture u")?,
+        v: maybe(token_iter.next(), 0.0, "a number for texture v")?,
+        w: maybe(token_iter.next(), 0.0, "a number for texture w")?,
     });
 
     Ok(())
 }
 
-fn process_face(token_iter: &mut str::SplitWhitespace, faces: &mut Vec<ObjFace>,
-                vertex_size: usize, texture_size: usize, normal_size: usize)  -> Result<(), ObjError> {
-    let triples: Vec<(usize, usize, usize)> = token_iter.map(to_triple).map(|b| b.and_then(|a| Ok(to_positive_triple(a, vertex_size, texture_size, normal_size)))).collect::<Result<Vec<(usize, usize, usize)>, ObjError>>()?;
+fn process_use_material(token_iter: &mut Tokens, data: &mut ObjData) -> Result<(), ObjError> {
+    let name: String = ensure(token_iter.next(), "a material name")?;
+    let index = match data.material_names.iter().position(|n| *n == name) {
+        Some(i) => i,
+        None => {
+            data.material_names.push(name);
+            data.material_names.len() - 1
+        }
+    };
+    data.current_material = Some(index);
+    Ok(())
+}
+
+/// Start adding faces to a new object. If an object with the same name has already been seen,
+/// faces are added to that object instead.
+fn process_object(line: &str, data: &mut ObjData) {
+    // Object names can contain spaces
+    let name = line[1..].trim();
+    let name = if name.is_empty() { None } else { Some(name.to_owned()) };
+    data.current_object = match data.objects.iter().position(|o| *o == name) {
+        Some(i) => i,
+        None => {
+            data.objects.push(name);
+            data.objects.len() - 1
+        }
+    };
+}
+
+/// Set the groups new faces are added to. With no names, faces go into the `default` group.
+fn process_group(token_iter: &mut Tokens, data: &mut ObjData) {
+    let mut names: Vec<&str> = token_iter.collect();
+    if names.is_empty() {
+        names.push("default");
+    }
+
+    data.current_groups = names.iter().map(|name| {
+        match data.groups.iter().position(|(n, _)| n == name) {
+            Some(i) => i,
+            None => {
+                data.groups.push((name.to_string(), Vec::new()));
+                data.groups.len() - 1
+            }
+        }
+    }).collect();
+}
+
+/// Set the smoothing group for new faces. `off` and `0` turn smoothing off.
+fn process_smoothing(token_iter: &mut Tokens, data: &mut ObjData) -> Result<(), ObjError> {
+    data.current_smoothing = match token_iter.next() {
+        Some("off") => 0,
+        token => ensure(token, "a smoothing group number or off")?
+    };
+    Ok(())
+}
+
+fn process_face(token_iter: &mut Tokens, data: &mut ObjData)  -> Result<(), ObjError> {
+    let vertex_size = data.vertices.len();
+    let texture_size = data.textures.len();
+    let normal_size = data.normals.len();
+    let mut triples: Vec<(usize, usize, usize)> = token_iter.by_ref().map(to_triple).map(|b| b.and_then(|a| to_positive_triple(a, vertex_size, texture_size, normal_size))).collect::<Result<Vec<(usize, usize, usize)>, ObjError>>()?;
 
     if triples.len() < 3 {
         return Err(ObjError::NotEnoughVerticesInFace(triples.len()));
     }
 
+    // Only lenient mode has bad elements, which are placeholders for lines that were skipped
+    for triple in triples.iter_mut() {
+        if data.bad_vertices.contains(&triple.0) {
+            return Err(ObjError::UsesBadVertex(triple.0));
+        }
+        if data.bad_normals.contains(&triple.2) {
+            triple.2 = 0;
+        }
+    }
+
     for index in 1 .. triples.len() - 1 {
         let a = triples[0];
         let b = triples[index];
         let c = triples[index + 1];
 
-        faces.push(ObjFace {
+        for g in &data.current_groups {
+            data.groups[*g].1.push(data.faces.len());
+        }
+        data.faces.push(ObjFace {
             av: a.0,
             at: a.1,
             an: a.2,
@@ -148,6 +362,9 @@ fn process_face(token_iter: &mut str::SplitWhitespace, faces: &mut Vec<ObjFace>,
             cv: c.0,
             ct: c.1,
             cn: c.2,
+            material: data.current_material,
+            smoothing: data.current_smoothing,
+            object: data.current_object,
         });
     }
     Ok(())
@@ -155,42 +372,58 @@ fn process_face(token_iter: &mut str::SplitWhitespace, faces: &mut Vec<ObjFace>,
 
 fn to_triple(token: &str) -> Result<(isize, isize, isize), ObjError> {
     let mut parts = token.split('/');
-    let vertex: isize = ensure(parts.next(), "to_triple vertex")?;
-    let texture: isize = maybe(parts.next(),0, "to_triple texture")?;
-    let normal: isize = maybe(parts.next(),0, "to_triple normal")?;
-    assert!(parts.next().is_none());
+    let vertex: isize = ensure(parts.next(), "a vertex index")?;
+    let texture: isize = maybe(parts.next(),0, "a texture index")?;
+    let normal: isize = maybe(parts.next(),0, "a normal index")?;
+    if parts.next().is_some() {
+        return Err(ObjError::InvalidValue { token: token.to_owned(), expected: "indices in the form v/vt/vn".to_owned() });
+    }
     Ok((vertex,texture,normal))
 }
 
-fn to_positive_triple(fields: (isize, isize, isize), vertex_size: usize, texture_size: usize, normal_size: usize) -> (usize, usize, usize) {
-    let vertex = if fields.0 < 0 {
-        vertex_size as isize + fields.0 + 1
-    } else {
-        fields.0
-    };
-    let texture: isize = if fields.1 < 0 {
-        texture_size as isize + fields.1 + 1
-    } else {
-        fields.1
-    };
-    let normal: isize = if fields.2 < 0 {
-        normal_size as isize + fields.2 + 1
+/// Convert the indices of a face vertex to positive (1 based) indices, checking that they refer
+/// to elements that have been defined. Texture and normal indices of 0 mean they are not used.
+fn to_positive_triple(fields: (isize, isize, isize), vertex_size: usize, texture_size: usize, normal_size: usize) -> Result<(usize, usize, usize), ObjError> {
+    Ok((to_positive(fields.0, vertex_size, "vertex", true)?,
+        to_positive(fields.1, texture_size, "texture", false)?,
+        to_positive(fields.2, normal_size, "normal", false)?))
+}
+
+/// Convert a relative (negative) index to a positive one and check it is in range.
+///
+/// # Arguments
+/// * `index` - The index from the file
+/// * `size` - The number of elements defined so far
+/// * `kind` - What is being indexed, for error messages
+/// * `required` - Whether an index of 0 (unused) is an error
+fn to_positive(index: isize, size: usize, kind: &'static str, required: bool) -> Result<usize, ObjError> {
+    let out_of_range = || ObjError::IndexOutOfRange { kind, index, count: size };
+    let positive = if index < 0 {
+        // Relative indices count back from the most recent element
+        index.checked_neg()
+             .and_then(|back| usize::try_from(back).ok())
+             .and_then(|back| size.checked_sub(back - 1))
+             .ok_or_else(out_of_range)?
     } else {
-        fields.2
+        usize::try_from(index).map_err(|_| out_of_range())?
     };
-    (vertex as usize, texture as usize, normal as usize)
+    if positive > size || (positive == 0 && (required || index < 0)) {
+        return Err(out_of_range());
+    }
+    Ok(positive)
 }
 
 /// Read the next token as an <F>, returning an appropriate error if it fails.
 ///
 /// # Arguments
-/// * `scan` - The scanner to read.
+/// * `token` - The token to read.
+/// * `context` - What the token should be, used in error messages.
 fn ensure<F: str::FromStr>(token: Option<&str>, context: &str) -> Result<F, ObjError> {
     match token {
-        None => Err(ObjError::UnexpectedEndOfFile(String::from("Expected a value"))),
+        None => Err(ObjError::MissingValue(context.to_owned())),
         Some(s) => match s.parse() {
             Ok(v) => Ok(v),
-            Err(_) => {println!("Error!!"); Err(ObjError::NotAFloat(format!("Failed to ensure convert {} in {}", s, context)))}
+            Err(_) => Err(ObjError::InvalidValue { token: s.to_owned(), expected: context.to_owned() })
         }
     }
 }
@@ -198,15 +431,16 @@ fn ensure<F: str::FromStr>(token: Option<&str>, context: &str) -> Result<F, ObjE
 /// Read the next token as an <F> if there is one. Else return the default.
 ///
 /// # Arguments
-/// * `scan` - The scanner to read.
+/// * `token` - The token to read.
 /// * `default` - The value to return if there are no more values to read
+/// * `context` - What the token should be, used in error messages.
 fn maybe<F: str::FromStr>(token: Option<&str>, default: F, context: &str) -> Result<F, ObjError> {
     match token {
         None => Ok(default),
         Some("") => Ok(default),
         Some(s) => match s.parse() {
             Ok(v) => Ok(v),
-            Err(_) => Err(ObjError::NotAFloat(format!("Failed to maybe convert {} in {}", s, context)))
+            Err(_) => Err(ObjError::InvalidValue { token: s.to_owned(), expected: context.to_owned() })
         }
     }
 }
@@ -214,6 +448,24 @@ fn maybe<F: str::FromStr>(token: Option<&str>, default: F, context: &str) -> Res
 #[cfg(test)]
 mod tests {
 
+    use super::{Group, ObjData, Strictness, Vec3};
+    use super::helpers::{ObjNormal, ObjParam, ObjVertex};
+    use geometry::{Plane, Ray};
+
+    /// Data with some vertices, texture coordinates and normals already read, for faces to use.
+    fn defined(count: usize) -> ObjData {
+        ObjData {
+            vertices: vec![ObjVertex::new(0.0, 0.0, 0.0, 1.0); count],
+            textures: vec![ObjParam { u: 0.0, v: 0.0, w: 0.0 }; count],
+            normals: vec![ObjNormal::new(0.0, 0.0, 1.0); count],
+            ..ObjData::default()
+        }
+    }
+
+    fn no_files(_: &str) -> std::io::Result<Vec<u8>> {
+        Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+
     #[test]
     fn test_ensure() {
         assert!(super::ensure::<f32>(None, "test").is_err());
@@ -230,106 +482,254 @@ mod tests {
 
     #[test]
     fn test_vertex() {
-        let mut v = Vec::new();
-        let mut t = Vec::new();
-        let mut n = Vec::new();
-        let mut f = Vec::new();
-        assert!(super::process_line("v 1 2 3", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(v.len() == 1);
-        assert!(t.is_empty());
-        assert!(n.is_empty());
-        assert!(f.is_empty());
-        let d = &v[0];
-        assert_eq!(1.0, d.x);
-        assert_eq!(2.0, d.y);
-        assert_eq!(3.0, d.z);
-        assert_eq!(1.0, d.w);
+        let mut d = ObjData::default();
+        assert!(super::process_line("v 1 2 3", &mut d).is_ok());
+        assert!(d.vertices.len() == 1);
+        assert!(d.textures.is_empty());
+        assert!(d.normals.is_empty());
+        assert!(d.faces.is_empty());
+        let p = &d.vertices[0];
+        assert_eq!(1.0, p.x);
+        assert_eq!(2.0, p.y);
+        assert_eq!(3.0, p.z);
+        assert_eq!(1.0, p.w);
     }
 
     #[test]
     fn test_face() {
-        let mut v = Vec::new();
-        let mut t = Vec::new();
-        let mut n = Vec::new();
-        let mut f = Vec::new();
-        assert!(super::process_line("f 1 2 3", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(super::process_line("f 1//2 2//3 3//4", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(super::process_line("f 1/1 2/2 3/3", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(super::process_line("f 1/1/2 2/3/4 3/5/6", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(v.is_empty());
-        assert!(t.is_empty());
-        assert!(n.is_empty());
-        assert_eq!(4, f.len());
+        let mut d = defined(6);
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("f 1//2 2//3 3//4", &mut d).is_ok());
+        assert!(super::process_line("f 1/1 2/2 3/3", &mut d).is_ok());
+        assert!(super::process_line("f 1/1/2 2/3/4 3/5/6", &mut d).is_ok());
+        assert_eq!(d.vertices.len(), 6);
+        assert_eq!(d.textures.len(), 6);
+        assert_eq!(d.normals.len(), 6);
+        assert_eq!(4, d.faces.len());
     }
 
     #[test]
     fn test_normal() {
-        let mut v = Vec::new();
-        let mut t = Vec::new();
-        let mut n = Vec::new();
-        let mut f = Vec::new();
-        assert!(super::process_line("vn 1 2 3", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(v.is_empty());
-        assert!(t.is_empty());
-        assert!(n.len() == 1);
-        assert!(f.is_empty());
-        let d = &n[0];
-        assert_eq!(1.0, d.x);
-        assert_eq!(2.0, d.y);
-        assert_eq!(3.0, d.z);
+        let mut d = ObjData::default();
+        assert!(super::process_line("vn 1 2 3", &mut d).is_ok());
+        assert!(d.vertices.is_empty());
+        assert!(d.textures.is_empty());
+        assert!(d.normals.len() == 1);
+        assert!(d.faces.is_empty());
+        let p = &d.normals[0];
+        assert_eq!(1.0, p.x);
+        assert_eq!(2.0, p.y);
+        assert_eq!(3.0, p.z);
     }
 
     #[test]
     fn test_texture() {
-        let mut v = Vec::new();
-        let mut t = Vec::new();
-        let mut n = Vec::new();
-        let mut f = Vec::new();
-        assert!(super::process_line("vt 1", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(v.is_empty());
-        assert!(t.len() == 1);
-        assert!(n.is_empty());
-        assert!(f.is_empty());
-        let d = &t[0];
-        assert_eq!(1.0, d.u);
-        assert_eq!(0.0, d.v);
-        assert_eq!(0.0, d.w);
+        let mut d = ObjData::default();
+        assert!(super::process_line("vt 1", &mut d).is_ok());
+        assert!(d.vertices.is_empty());
+        assert!(d.textures.len() == 1);
+        assert!(d.normals.is_empty());
+        assert!(d.faces.is_empty());
+        let p = &d.textures[0];
+        assert_eq!(1.0, p.u);
+        assert_eq!(0.0, p.v);
+        assert_eq!(0.0, p.w);
+    }
+
+    #[test]
+    fn test_use_material() {
+        let mut d = defined(3);
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("usemtl red", &mut d).is_ok());
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("usemtl blue", &mut d).is_ok());
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("usemtl red", &mut d).is_ok());
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("usemtl", &mut d).is_err());
+        assert_eq!(d.material_names, vec!["red", "blue"]);
+        let materials: Vec<Option<usize>> = d.faces.iter().map(|f| f.material).collect();
+        assert_eq!(materials, vec![None, Some(0), Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn test_read_obj_materials() {
+        let obj = "mtllib colours.mtl missing.mtl
+                   v 0 0 0
+v 1 0 0
+v 0 1 0
+                   usemtl blue
+f 1 2 3
+                   usemtl undefined
+f 1 2 3
+";
+        let scene = super::read_obj(obj.as_bytes(), |name| match name {
+            "colours.mtl" => Ok(b"newmtl blue\nKd 0 0 1\n".to_vec()),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"))
+        }, Strictness::Strict).unwrap();
+
+        assert_eq!(scene.face_count(), 2);
+        assert_eq!(scene.materials.len(), 2);
+        assert_eq!(scene.materials[0].diffuse, super::Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.materials[1].name, "undefined");
+        let materials: Vec<Option<usize>> = scene.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![Some(0), Some(1)]);
+        assert_eq!(scene.warnings.len(), 1);
+        assert!(scene.warnings[0].message.contains("missing.mtl"));
+    }
+
+    #[test]
+    fn test_objects_and_groups() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+                   f 1 2 3\n\
+                   o Left Wheel\n\
+                   g tyre rubber\n\
+                   f 1 2 3 4\n\
+                   g hub\n\
+                   f 1 2 3\n\
+                   o Right Wheel\n\
+                   g tyre\n\
+                   f 1 2 3\n\
+                   o Left Wheel\n\
+                   g\n\
+                   f 1 2 3\n";
+        let scene = super::read_obj(obj.as_bytes(), no_files, Strictness::Strict).unwrap();
+        assert_eq!(scene.meshes.len(), 3);
+        assert_eq!(scene.face_count(), 6);
+
+        assert!(scene.meshes[0].name.is_none());
+        assert!(scene.meshes[0].groups.is_empty());
+
+        let left = scene.mesh("Left Wheel").unwrap();
+        assert_eq!(left.faces.len(), 4);
+        assert_eq!(left.groups, vec![Group::from_indices("tyre", vec![0, 1]),
+                                     Group::from_indices("rubber", vec![0, 1]),
+                                     Group::from_indices("hub", vec![2]),
+                                     Group::from_indices("default", vec![3])]);
+
+        let right = scene.mesh("Right Wheel").unwrap();
+        assert_eq!(right.groups, vec![Group::from_indices("tyre", vec![0])]);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        // Two triangles folded along the shared edge 1-2
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 1\n\
+                   s 1\n\
+                   f 1 2 3\n\
+                   f 2 4 3\n\
+                   s off\n\
+                   f 1 2 3\n";
+        let scene = super::read_obj(obj.as_bytes(), no_files, Strictness::Strict).unwrap();
+        let faces = &scene.meshes[0].faces;
+        assert_eq!(faces.len(), 3);
+
+        // Ray straight down onto the shared edge. The smooth normal is between the face normals.
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let smooth = faces[0].hits(&ray).unwrap().normal;
+        let flat = faces[2].hits(&ray).unwrap().normal;
+        assert!((flat - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-9);
+        assert!(smooth.z < 1.0 - 1e-3);
+        assert!(smooth.x < 0.0 && smooth.y < 0.0);
+
+        // A smoothing group of faces with no area has nothing to average
+        let obj = "v 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\ns 1\nf 1 2 3\nf 1 2 4\n";
+        let scene = super::read_obj(obj.as_bytes(), no_files, Strictness::Strict).unwrap();
+        let face = &scene.meshes[0].faces[1];
+        assert_eq!(face.vertex_normals(), [face.normal(); 3]);
+
+        let mut d = ObjData::default();
+        assert!(super::process_line("s 4", &mut d).is_ok());
+        assert_eq!(d.current_smoothing, 4);
+        assert!(super::process_line("s 0", &mut d).is_ok());
+        assert_eq!(d.current_smoothing, 0);
+        assert!(super::process_line("s sometimes", &mut d).is_err());
+    }
+
+    #[test]
+    fn test_error_position() {
+        let text = "# A bad vertex\nv 1 2 3\n  v 1 2.0.0 3\n";
+        let e = super::read_obj(text.as_bytes(), no_files, Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:3:7: expected a number for vertex y, found '2.0.0'");
+
+        let e = super::read_obj(&b"v 1 2 3\nvx 0.5\n"[..], no_files, Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:2:1: unknown statement 'vx'");
+
+        let e = super::read_obj(&b"v 1 2 3\nv 1 2 3\nf 1 2\n"[..], no_files, Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:3:6: a face needs at least 3 vertices, found 2");
+    }
+
+    #[test]
+    fn test_bad_indices() {
+        let mut d = defined(3);
+        assert!(super::process_line("f 1 2 3", &mut d).is_ok());
+        assert!(super::process_line("f -1 -2 -3", &mut d).is_ok());
+        assert!(super::process_line("f 0 1 2", &mut d).is_err());
+        assert!(super::process_line("f 1 2 4", &mut d).is_err());
+        assert!(super::process_line("f -4 1 2", &mut d).is_err());
+        assert!(super::process_line("f 1/4 2 3", &mut d).is_err());
+        assert!(super::process_line("f 1//-4 2 3", &mut d).is_err());
+        assert!(super::process_line("f 1/1/1/1 2 3", &mut d).is_err());
+        assert!(super::process_line("v 1 2 3 0", &mut d).is_err());
+        assert_eq!(d.faces.len(), 2);
+        assert_eq!(d.vertices.len(), 3);
+    }
+
+    #[test]
+    fn test_lenient() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                   v 1 1 0 0\n\
+                   vn 0 0 1\nvn 0 0 x\n\
+                   vp 0.5 0.5\nvp 0.1\n\
+                   cstype bezier\n\
+                   bogus\n\
+                   f 1 2 3\n\
+                   f 2 4 3\n\
+                   f 1//1 2//2 3//1\n\
+                   f 1 2 7\n\
+                   f 1/2/3/4 2 3\n";
+        assert!(super::read_obj(obj.as_bytes(), no_files, Strictness::Strict).is_err());
+
+        let scene = super::read_obj(obj.as_bytes(), no_files, Strictness::Lenient).unwrap();
+        // The face using the bad vertex 4 and the faces with bad indices are skipped. The face using
+        // the bad normal 2 computes its own normals.
+        assert_eq!(scene.face_count(), 2);
+        let lines: Vec<Option<usize>> = scene.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![Some(4), Some(6), Some(7), Some(9), Some(10), Some(12), Some(14), Some(15)]);
+        assert_eq!(scene.warnings[0].to_string(), "line 4: vertex has a weight of 0");
     }
 
     #[test]
     fn test_divide_face() {
-        let mut v = Vec::new();
-        let mut t = Vec::new();
-        let mut n = Vec::new();
-        let mut f = Vec::new();
-        assert!(super::process_line("f 1 2 3 4", &mut v, &mut n, &mut t, &mut f).is_ok());
-        assert!(v.is_empty());
-        assert!(t.is_empty());
-        assert!(n.is_empty());
-        assert_eq!(2, f.len());
+        let mut d = defined(4);
+        assert!(super::process_line("f 1 2 3 4", &mut d).is_ok());
+        assert_eq!(d.vertices.len(), 4);
+        assert_eq!(d.textures.len(), 4);
+        assert_eq!(d.normals.len(), 4);
+        assert_eq!(2, d.faces.len());
     }
 
     #[test]
     fn test_get_element_from() {
         let data = vec![1,2,3,4,5];
         for i in 1 .. 6  {
-            assert_eq!(super::get_element_from(i, &data[..]), i);
+            assert_eq!(super::get_element_from(i, &data[..]), Some(i));
         }
     }
 
     #[test]
-    #[should_panic]
-    fn test_get_element_from_panic() {
-        let data = vec![1,2,3,4,5];
-        super::get_element_from(0, &data[..]);
+    fn test_get_element_from_out_of_range() {
+        let data = [1,2,3,4,5];
+        assert_eq!(super::get_element_from(0, &data[..]), None);
+        assert_eq!(super::get_element_from(6, &data[..]), None);
     }
 
     #[test]
     fn test_read_triples() {
         let line = "1/2/3";
         assert_eq!(super::to_triple(line).unwrap(), (1,2,3));
-        assert_eq!(super::to_triple(line).and_then(|a| Ok(super::to_positive_triple(a, 5, 5, 5))).unwrap(), (1,2,3));
+        assert_eq!(super::to_triple(line).and_then(|a| super::to_positive_triple(a, 5, 5, 5)).unwrap(), (1,2,3));
         let line = "1/-2/3";
-        assert_eq!(super::to_triple(line).and_then(|a| Ok(super::to_positive_triple(a, 5, 5, 5))).unwrap(), (1,4,3));
+        assert_eq!(super::to_triple(line).and_then(|a| super::to_positive_triple(a, 5, 5, 5)).unwrap(), (1,4,3));
     }
 }
@@ -0,0 +1,120 @@
+//! Reading MTL material libraries
+
+use geometry::{LoadError, Material, Strictness, Vec3, Warning};
+use super::{ensure, maybe, ObjError};
+use super::helpers::Tokens;
+
+/// Read the materials defined in an MTL file.
+/// Only the parts of a material Vitrum can use are read, everything else is skipped.
+///
+/// # Arguments
+/// * `text` - The content of the MTL file
+/// * `strictness` - Whether bad lines are errors or are skipped with a warning
+///
+/// # Returns
+/// The materials, and warnings for any lines which were skipped.
+pub fn read_mtl(text: &str, strictness: Strictness) -> Result<(Vec<Material>, Vec<Warning>), ObjError> {
+    let mut materials: Vec<Material> = Vec::new();
+    let mut warnings: Vec<Warning> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let mut token_iter = Tokens::new(line);
+        let result = process_line(line.trim(), &mut token_iter, &mut materials);
+        if let Err(e) = result {
+            let e = e.at(number + 1, token_iter.column());
+            match strictness {
+                Strictness::Strict => return Err(e),
+                Strictness::Lenient => warnings.push(e.into_warning())
+            }
+        }
+    }
+
+    Ok((materials, warnings))
+}
+
+/// Read one (trimmed) line of an MTL file.
+fn process_line(line: &str, token_iter: &mut Tokens, materials: &mut Vec<Material>) -> Result<(), ObjError> {
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    let keyword = match token_iter.next() {
+        Some(k) => k,
+        None => return Ok(())
+    };
+
+    if keyword == "newmtl" {
+        // Material names can contain spaces
+        let name = line["newmtl".len()..].trim();
+        materials.push(Material::named(name));
+        return Ok(());
+    }
+
+    // Everything else modifies the most recent material
+    let material = match materials.last_mut() {
+        Some(m) => m,
+        None => return Ok(())
+    };
+    match keyword {
+        "Kd" => material.diffuse = read_colour(token_iter)?,
+        "Ks" => material.specular = read_colour(token_iter)?,
+        "Ke" => material.emission = read_colour(token_iter)?,
+        "Ns" => material.shininess = ensure(token_iter.next(), "a number for the specular exponent")?,
+        "d" => material.opacity = ensure(token_iter.next(), "a number for the opacity")?,
+        "Tr" => material.opacity = 1.0 - ensure::<f64>(token_iter.next(), "a number for the transparency")?,
+        // The file name is last, after any options
+        "map_Kd" => material.texture = token_iter.last().map(String::from),
+        _ => () // Ambient, illumination models, bump maps, etc are not used
+    }
+    Ok(())
+}
+
+/// Read an RGB colour. If only one value is given it is used for all channels.
+fn read_colour(token_iter: &mut Tokens) -> Result<Vec3, ObjError> {
+    let r = ensure(token_iter.next(), "a number for colour red")?;
+    let g = maybe(token_iter.next(), r, "a number for colour green")?;
+    let b = maybe(token_iter.next(), r, "a number for colour blue")?;
+    Ok(Vec3::new(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Strictness, Vec3};
+
+    #[test]
+    fn test_read_mtl() {
+        let text = "# Two materials\n\
+                    newmtl shiny red\n\
+                    Ka 0 0 0\n\
+                    Kd 1 0 0\n\
+                    Ks 0.5\n\
+                    Ns 90\n\
+                    illum 2\n\
+                    \n\
+                    newmtl glass\n\
+                    Tr 0.75\n\
+                    map_Kd -s 1 1 1 textures/glass.png\n";
+        let (materials, warnings) = super::read_mtl(text, Strictness::Strict).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(materials.len(), 2);
+
+        assert_eq!(materials[0].name, "shiny red");
+        assert_eq!(materials[0].diffuse, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(materials[0].specular, Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(materials[0].shininess, 90.0);
+
+        assert_eq!(materials[1].name, "glass");
+        assert_eq!(materials[1].opacity, 0.25);
+        assert_eq!(materials[1].texture, Some("textures/glass.png".to_owned()));
+    }
+
+    #[test]
+    fn test_bad_colour() {
+        let e = super::read_mtl("newmtl a\n  Kd 1 red", Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:2:8: expected a number for colour green, found 'red'");
+
+        let (materials, warnings) = super::read_mtl("newmtl a\n  Kd 1 red\nKs 0.5", Strictness::Lenient).unwrap();
+        assert_eq!(materials[0].specular, Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, Some(2));
+    }
+}
@@ -0,0 +1,94 @@
+//! Errors for OFF file reading
+
+use geometry::impl_load_error;
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use std::str::Utf8Error;
+
+/// An OFF error wraps all the different types of errors you can get back from reading
+/// an OFF file.
+#[derive(Debug)]
+pub enum OffError {
+    /// Error from converting bytes to UTF-8
+    UTF8Error(Utf8Error),
+    /// An error that came from IO
+    IOError(Error),
+    /// A variant of OFF which cannot be read (e.g. with more than 3 dimensions)
+    UnsupportedHeader(String),
+    /// A value was expected but the line ended
+    MissingValue(String),
+    /// A number could not be read
+    InvalidNumber { token: String, expected: String },
+    /// Faces need at least 3 vertices
+    NotEnoughVerticesInFace,
+    /// A face refers to a vertex which does not exist
+    IndexOutOfRange { index: usize, count: usize },
+    /// A face uses a vertex that could not be read (lenient mode only)
+    UsesBadVertex(usize),
+    /// File terminated when something else was expected
+    UnexpectedEndOfFile(String),
+    /// Found no faces
+    NoFacesFound,
+    /// An error at a known position in the file. Lines and columns start at 1.
+    At { file: Option<String>, line: usize, column: usize, error: Box<OffError> }
+}
+
+impl_load_error!(OffError, IOError);
+
+impl Display for OffError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OffError::UTF8Error(e) => write!(f, "OFF file is not valid UTF-8: {}", e),
+            OffError::IOError(e) => write!(f, "Could not read OFF file: {}", e),
+            OffError::UnsupportedHeader(header) => write!(f, "'{}' files are not supported", header),
+            OffError::MissingValue(expected) => write!(f, "expected {}, found the end of the line", expected),
+            OffError::InvalidNumber { token, expected } => write!(f, "expected {}, found '{}'", expected, token),
+            OffError::NotEnoughVerticesInFace => write!(f, "faces must have at least 3 vertices"),
+            OffError::IndexOutOfRange { index, count } =>
+                write!(f, "vertex {} does not exist, there are only {} vertices", index, count),
+            OffError::UsesBadVertex(index) => write!(f, "face uses vertex {} which could not be read", index),
+            OffError::UnexpectedEndOfFile(expected) => write!(f, "expected {}, found the end of the file", expected),
+            OffError::NoFacesFound => write!(f, "OFF file has no faces"),
+            OffError::At { file, line, column, error } =>
+                write!(f, "{}:{}:{}: {}", file.as_deref().unwrap_or("<off>"), line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for OffError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OffError::UTF8Error(e) => Some(e),
+            OffError::IOError(e) => Some(e),
+            OffError::At { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl std::convert::From<Error> for OffError {
+    fn from(error: Error) -> Self {
+        OffError::IOError(error)
+    }
+}
+
+impl std::convert::From<Utf8Error> for OffError {
+    fn from(error: Utf8Error) -> Self {
+        OffError::UTF8Error(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OffError;
+    use geometry::LoadError;
+
+    #[test]
+    fn test_display() {
+        let e = OffError::IndexOutOfRange { index: 8, count: 8 }.at(12, 5).in_file("cube.off");
+        assert_eq!(e.to_string(), "cube.off:12:5: vertex 8 does not exist, there are only 8 vertices");
+
+        let warning = OffError::NotEnoughVerticesInFace.at(3, 1).into_warning();
+        assert_eq!(warning.to_string(), "line 3: faces must have at least 3 vertices");
+    }
+}
@@ -0,0 +1,403 @@
+//! The off-loader reads Object File Format (OFF) files and converts them into a `Scene`.
+//!
+//! An OFF file is a header (`OFF`, optionally with prefixes such as `C`, `N` or `4`), a line
+//! with the number of vertices, faces and edges, then one line per vertex and one per face.
+//! A face line is the number of vertices in the face, their indices, and an optional colour.
+//! Faces with more than 3 vertices are split into triangles. `#` starts a comment.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::str::{self, FromStr};
+
+mod errors;
+
+pub use errors::OffError;
+pub use geometry::{Face, LoadError, Scene, Strictness, Vec3};
+use geometry::{Material, Mesh, Warning};
+
+/// Read in and parse an OFF file.
+///
+/// # Arguments
+///
+/// * `filename` - The path to the file to read.
+/// * `strictness` - Whether bad lines are errors or are skipped with a warning.
+pub fn read_off_file(filename: &str, strictness: Strictness) -> Result<Scene, OffError> {
+    println!("Reading OFF file {}", filename);
+    read_off(File::open(filename)?, strictness).map_err(|e| e.in_file(filename))
+}
+
+/// Read in and parse OFF data from any source, such as a decompression stream.
+///
+/// # Arguments
+///
+/// * `reader` - The OFF data to read.
+/// * `strictness` - Whether bad lines are errors or are skipped with a warning.
+pub fn read_off<R: Read>(mut reader: R, strictness: Strictness) -> Result<Scene, OffError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let text = str::from_utf8(&data)?;
+    let mut records = text.lines()
+                          .enumerate()
+                          .filter_map(|(i, line)| Record::new(i + 1, line));
+    let mut off = OffData::new(strictness);
+
+    // The header is optional, and the counts may follow it on the same line
+    let first = records.next().ok_or_else(|| OffError::UnexpectedEndOfFile("an OFF header".to_owned()))?;
+    let counts = match first.tokens.first() {
+        Some((column, keyword)) if keyword.ends_with("OFF") => {
+            off.homogeneous = read_header(keyword).map_err(|e| e.at(first.line, *column))?;
+            if first.tokens.len() > 1 {
+                first.skip(1)
+            } else {
+                records.next().ok_or_else(|| OffError::UnexpectedEndOfFile("the number of vertices and faces".to_owned()))?
+            }
+        },
+        _ => first
+    };
+    let mut values = counts.values();
+    let n_vertices: usize = values.number("the number of vertices").map_err(|e| e.at(counts.line, values.column()))?;
+    let n_faces: usize = values.number("the number of faces").map_err(|e| e.at(counts.line, values.column()))?;
+
+    for i in 0..n_vertices {
+        match records.next() {
+            Some(record) => off.process_vertex(&record)?,
+            None => return off.truncated(format!("vertex {} of {}", i + 1, n_vertices))
+        }
+    }
+    for i in 0..n_faces {
+        match records.next() {
+            Some(record) => off.process_face(&record)?,
+            None => return off.truncated(format!("face {} of {}", i + 1, n_faces))
+        }
+    }
+
+    off.into_scene()
+}
+
+/// Read the header keyword, returning whether the vertices have 4 (homogeneous) coordinates.
+///
+/// # Arguments
+/// * `keyword` - The first word of the file, which ends in `OFF`
+fn read_header(keyword: &str) -> Result<bool, OffError> {
+    // Prefixes come in the order [ST][C][N][4][n]
+    let mut prefix = &keyword[..keyword.len() - 3];
+    for p in &["ST", "C", "N"] {
+        prefix = prefix.strip_prefix(p).unwrap_or(prefix);
+    }
+    match prefix {
+        "" => Ok(false),
+        "4" => Ok(true),
+        // nOFF has a dimension line, and only 3 dimensions can be drawn
+        _ => Err(OffError::UnsupportedHeader(keyword.to_owned()))
+    }
+}
+
+/// A line of the file which is not blank or only a comment.
+struct Record<'a> {
+    line: usize,
+    /// The words on the line, and the column (starting from 1) each one starts in
+    tokens: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Record<'a> {
+    fn new(line: usize, text: &'a str) -> Option<Record<'a>> {
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    tokens.push((s + 1, &text[s..i]));
+                    start = None;
+                },
+                _ => ()
+            }
+        }
+        if tokens.is_empty() { None } else { Some(Record { line, tokens }) }
+    }
+
+    /// The same line without its first `n` words.
+    fn skip(&self, n: usize) -> Record<'a> {
+        Record { line: self.line, tokens: self.tokens[n..].to_vec() }
+    }
+
+    fn values(&self) -> Values<'_, 'a> {
+        Values { record: self, next: 0 }
+    }
+}
+
+/// Reads the words of a record one at a time.
+struct Values<'r, 'a> {
+    record: &'r Record<'a>,
+    next: usize,
+}
+
+impl<'r, 'a> Values<'r, 'a> {
+    /// Read the next word as a number.
+    ///
+    /// # Arguments
+    /// * `expected` - What the word is, for error messages
+    fn number<T: FromStr>(&mut self, expected: &str) -> Result<T, OffError> {
+        let (_, token) = self.record.tokens.get(self.next).ok_or_else(|| OffError::MissingValue(expected.to_owned()))?;
+        self.next += 1;
+        token.parse().map_err(|_| OffError::InvalidNumber { token: token.to_string(), expected: expected.to_owned() })
+    }
+
+    /// The column of the last word read, for error messages. Errors at the end of the line are
+    /// reported just after the last word.
+    fn column(&self) -> usize {
+        match self.record.tokens.get(self.next.saturating_sub(1)) {
+            Some((column, _)) => *column,
+            None => self.record.tokens.last().map(|(c, t)| c + t.len()).unwrap_or(1)
+        }
+    }
+
+    /// The words which have not been read.
+    fn rest(&self) -> &[(usize, &'a str)] {
+        &self.record.tokens[self.next.min(self.record.tokens.len())..]
+    }
+}
+
+/// Everything read from the file so far.
+struct OffData {
+    strictness: Strictness,
+    /// Whether vertices have a 4th (w) coordinate
+    homogeneous: bool,
+    vertices: Vec<Vec3>,
+    /// Vertices which could not be read and have a placeholder (lenient mode only)
+    bad_vertices: HashSet<usize>,
+    faces: Vec<Face>,
+    materials: Vec<Material>,
+    /// The material made for each face colour (RGBA)
+    palette: HashMap<[u8; 4], usize>,
+    warnings: Vec<Warning>,
+}
+
+impl OffData {
+    fn new(strictness: Strictness) -> OffData {
+        OffData {
+            strictness,
+            homogeneous: false,
+            vertices: Vec::new(),
+            bad_vertices: HashSet::new(),
+            faces: Vec::new(),
+            materials: Vec::new(),
+            palette: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Deal with an error in a line. Strict errors are returned, lenient ones become warnings.
+    fn fail(&mut self, error: OffError) -> Result<(), OffError> {
+        match self.strictness {
+            Strictness::Strict => Err(error),
+            Strictness::Lenient => {
+                self.warnings.push(error.into_warning());
+                Ok(())
+            }
+        }
+    }
+
+    /// Read a vertex line. Anything after the coordinates (normals, colours) is ignored.
+    fn process_vertex(&mut self, record: &Record) -> Result<(), OffError> {
+        let mut values = record.values();
+        let homogeneous = self.homogeneous;
+        let mut read = || -> Result<Vec3, OffError> {
+            let v = Vec3::new(values.number("a number for vertex x")?,
+                              values.number("a number for vertex y")?,
+                              values.number("a number for vertex z")?);
+            if homogeneous {
+                let w: f64 = values.number("a number for vertex w")?;
+                Ok(v / w)
+            } else {
+                Ok(v)
+            }
+        };
+        match read() {
+            Ok(v) => self.vertices.push(v),
+            Err(e) => {
+                let e = e.at(record.line, values.column());
+                self.bad_vertices.insert(self.vertices.len());
+                self.vertices.push(Vec3::zeros());
+                self.fail(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a face line, split it into triangles and give them the colour of the face.
+    fn process_face(&mut self, record: &Record) -> Result<(), OffError> {
+        let mut values = record.values();
+        let indices = match self.face_indices(&mut values) {
+            Ok(indices) => indices,
+            Err(e) => return self.fail(e.at(record.line, values.column()))
+        };
+        let material = match read_colour(values.rest()) {
+            Ok(colour) => colour.map(|c| self.material(c)),
+            Err(e) => return self.fail(e.at(record.line, values.rest()[0].0))
+        };
+
+        let vertex = |i: usize| self.vertices[indices[i]];
+        let triangles: Vec<Face> = (1..indices.len() - 1)
+            .map(|i| Face::from_points(vertex(0), vertex(i), vertex(i + 1)).with_material(material))
+            .collect();
+        self.faces.extend(triangles);
+        Ok(())
+    }
+
+    /// Read the vertex count and the indices of a face, checking they can be used.
+    fn face_indices(&self, values: &mut Values) -> Result<Vec<usize>, OffError> {
+        let n: usize = values.number("the number of vertices in the face")?;
+        if n < 3 {
+            return Err(OffError::NotEnoughVerticesInFace);
+        }
+        let mut indices = Vec::with_capacity(n);
+        for _ in 0..n {
+            let index: usize = values.number("a vertex index")?;
+            if index >= self.vertices.len() {
+                return Err(OffError::IndexOutOfRange { index, count: self.vertices.len() });
+            }
+            if self.bad_vertices.contains(&index) {
+                return Err(OffError::UsesBadVertex(index));
+            }
+            indices.push(index);
+        }
+        Ok(indices)
+    }
+
+    /// The material for a face colour, creating it the first time the colour is used.
+    fn material(&mut self, colour: [u8; 4]) -> usize {
+        let materials = &mut self.materials;
+        *self.palette.entry(colour).or_insert_with(|| {
+            let [r, g, b, a] = colour;
+            let name = if a == 255 {
+                format!("#{:02x}{:02x}{:02x}", r, g, b)
+            } else {
+                format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+            };
+            let mut m = Material::with_colour(&name, Vec3::new(r as f64, g as f64, b as f64) / 255.0);
+            m.opacity = a as f64 / 255.0;
+            materials.push(m);
+            materials.len() - 1
+        })
+    }
+
+    /// The file ended early. Lenient mode keeps what has been read.
+    fn truncated(mut self, expected: String) -> Result<Scene, OffError> {
+        self.fail(OffError::UnexpectedEndOfFile(expected))?;
+        self.into_scene()
+    }
+
+    fn into_scene(self) -> Result<Scene, OffError> {
+        if self.faces.is_empty() {
+            return Err(OffError::NoFacesFound);
+        }
+        let mut scene = Scene::from_mesh(Mesh::new(None, self.faces));
+        scene.materials = self.materials;
+        scene.warnings = self.warnings;
+        Ok(scene)
+    }
+}
+
+/// Read the colour at the end of a face line, as RGBA.
+///
+/// Colours are 3 or 4 integers from 0 to 255, or 3 or 4 decimals from 0 to 1. A single number is
+/// an index into a colour map, which is not supported, so the face has no colour.
+///
+/// # Arguments
+/// * `tokens` - The words after the vertex indices
+fn read_colour(tokens: &[(usize, &str)]) -> Result<Option<[u8; 4]>, OffError> {
+    if tokens.len() < 3 {
+        return Ok(None);
+    }
+    if tokens.len() > 4 {
+        return Err(OffError::InvalidNumber { token: tokens[4].1.to_owned(), expected: "the end of the face".to_owned() });
+    }
+    let decimal = tokens.iter().any(|(_, t)| t.contains('.') || t.contains('e') || t.contains('E'));
+    let mut colour = [255; 4];
+    for (c, (_, token)) in colour.iter_mut().zip(tokens) {
+        let value: f64 = token.parse().map_err(|_| OffError::InvalidNumber {
+            token: token.to_string(),
+            expected: "a number for the face colour".to_owned()
+        })?;
+        let value = if decimal { value * 255.0 } else { value };
+        *c = value.round().clamp(0.0, 255.0) as u8;
+    }
+    Ok(Some(colour))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OffError, Strictness, Vec3};
+
+    const SQUARE: &str = "OFF\n\
+                          # A square made of a quad and a triangle\n\
+                          5 2 0\n\
+                          0 0 0\n\
+                          1 0 0\n\
+                          1 1 0\n\
+                          0 1 0\n\
+                          0 2 0\n\
+                          4 0 1 2 3 255 0 0\n\
+                          3 3 2 4 0.0 0.0 1.0 0.5\n";
+
+    #[test]
+    fn test_read() {
+        let scene = super::read_off(SQUARE.as_bytes(), Strictness::Strict).unwrap();
+        assert_eq!(scene.face_count(), 3);
+        assert_eq!(scene.materials.len(), 2);
+        assert_eq!(scene.materials[0].name, "#ff0000");
+        assert_eq!(scene.materials[1].name, "#0000ff80");
+        assert_eq!(scene.materials[1].opacity, 128.0 / 255.0);
+        let materials: Vec<Option<usize>> = scene.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![Some(0), Some(0), Some(1)]);
+        assert_eq!(scene.faces().nth(1).unwrap().vertices(),
+                   [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_headers() {
+        // No header, and the counts on the header line
+        let plain = "3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        assert_eq!(super::read_off(plain.as_bytes(), Strictness::Strict).unwrap().face_count(), 1);
+        let same_line = "OFF 3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        assert_eq!(super::read_off(same_line.as_bytes(), Strictness::Strict).unwrap().face_count(), 1);
+
+        // Homogeneous coordinates are divided by w, and vertex colours and normals are ignored
+        let homogeneous = "CN4OFF\n3 1 0\n0 0 0 1 0 0 1 1 1 1\n2 0 0 2 0 0 1 1 1 1\n0 3 0 3 0 0 1 1 1 1\n3 0 1 2\n";
+        let scene = super::read_off(homogeneous.as_bytes(), Strictness::Strict).unwrap();
+        assert_eq!(scene.faces().next().unwrap().vertices()[1], Vec3::new(1.0, 0.0, 0.0));
+
+        match super::read_off("nOFF\n4\n".as_bytes(), Strictness::Strict) {
+            Err(OffError::At { line: 1, column: 1, error, .. }) =>
+                assert!(matches!(*error, OffError::UnsupportedHeader(_))),
+            other => panic!("Expected an unsupported header, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let bad_index = "OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+        let e = super::read_off(bad_index.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<off>:6:7: vertex 3 does not exist, there are only 3 vertices");
+
+        let bad_number = "OFF\n3 1 0\n0 0 0\n1 x 0\n0 1 0\n3 0 1 2\n";
+        let e = super::read_off(bad_number.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<off>:4:3: expected a number for vertex y, found 'x'");
+
+        let truncated = "OFF\n3 2 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        let e = super::read_off(truncated.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "expected face 2 of 2, found the end of the file");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let data = "OFF\n4 4 0\n0 0 0\n1 x 0\n0 1 0\n1 1 0\n3 0 1 2\n3 0 2 3\n2 0 1\n";
+        let scene = super::read_off(data.as_bytes(), Strictness::Lenient).unwrap();
+        assert_eq!(scene.face_count(), 1);
+        let lines: Vec<Option<usize>> = scene.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![Some(4), Some(7), Some(9), None]);
+    }
+}
@@ -1,64 +1,205 @@
 //! Functions for reading ASCII STL files
 
-use geometry::{Face, Vec3};
+use geometry::{Face, LoadError, Mesh, Scene, Strictness, Vec3, Warning};
 use scanner_rust::Scanner;
 use super::common::StlError;
 use num_traits::identities::Zero;
 
-/// Read an ASCII STL file into a Vec<Face>.
+/// Read ASCII STL data into a Scene. A file can have several solids one after the other.
+/// Each solid is a mesh, named after the solid.
 ///
 /// #Arguments
-/// * `filename` - the filepath of the ASCII formatted STL file to read.
-pub fn read_file_ascii(filename: &str) -> Result<Vec<Face>, StlError> {
-    println!("Reading STL file {}", filename);
-    let mut scan = Scanner::scan_path(filename)?;
+/// * `reader` - the ASCII formatted STL data to read.
+/// * `strictness` - In lenient mode bad facets are skipped with a warning.
+pub fn read_ascii<R: std::io::Read>(reader: R, strictness: Strictness) -> Result<Scene, StlError> {
+    let mut scan = Tokens::new(reader);
+    let mut scene = Scene::new();
 
-    let name = read_header_ascii(&mut scan)?;
-    println!("Reading solid with name {:?}", name);
-    let mut faces: Vec<Face> = Vec::new();
+    // There must be at least one solid
+    let mut name = read_header_ascii(&mut scan).map_err(|e| scan.locate(e))?;
     loop {
-        let result = read_body_ascii(&mut scan, &name)?;
-        if let Some(f) = result {
-            faces.push(f);
-        } else {
+        println!("Reading solid with name {:?}", name);
+        let faces = read_solid_ascii(&mut scan, &name, strictness, &mut scene.warnings)?;
+        scene.meshes.push(Mesh::new(name, faces));
+
+        // Anything after the end of a solid must be another solid
+        if scan.next()?.is_none() {
             break;
         }
+        scan.unread();
+        name = match read_header_ascii(&mut scan) {
+            Ok(name) => name,
+            Err(e) if strictness == Strictness::Strict => return Err(scan.locate(e)),
+            Err(e) => {
+                scene.warnings.push(scan.locate(e).into_warning());
+                break;
+            }
+        };
+    }
+    Ok(scene)
+}
+
+/// Read the facets of a solid, up to and including the `endsolid` line.
+///
+/// # Arguments
+/// * `scan` - scanner to read the data from, just after the header of the solid
+/// * `name` - the name of the solid being read
+/// * `strictness` - In lenient mode bad facets are skipped with a warning.
+/// * `warnings` - Where to record the problems found in lenient mode
+fn read_solid_ascii<T: std::io::Read>(scan: &mut Tokens<T>, name: &Option<String>, strictness: Strictness,
+                                      warnings: &mut Vec<Warning>) -> Result<Vec<Face>, StlError> {
+    let mut faces: Vec<Face> = Vec::new();
+    loop {
+        match read_body_ascii(scan, name) {
+            Ok(Some(f)) => faces.push(f),
+            Ok(None) => break,
+            Err(e @ StlError::IOError(_)) | Err(e @ StlError::ScanError(_)) => return Err(e),
+            Err(e) if strictness == Strictness::Strict => return Err(scan.locate(e)),
+            // The faces are all there, so the end of the solid can be let go
+            Err(e @ StlError::NoEndSolid) | Err(e @ StlError::MissmatchedSolidNames(..)) => {
+                warnings.push(scan.locate(e).into_warning());
+                break;
+            },
+            Err(e) => {
+                warnings.push(scan.locate(e).into_warning());
+                if !skip_facet(scan)? {
+                    break;
+                }
+            }
+        }
     }
     Ok(faces)
 }
 
-/// Read the header of the ASCII STL file and return the name of the solid
+/// Skip the rest of a facet which could not be read.
 ///
 /// # Arguments
-/// * `scan` - A scanner to the ascii file
-fn read_header_ascii<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<Option<String>, StlError> {
-    // The header is always the first line
-    let line = match scan.next_line()? {
-        None => return Err(StlError::MissingHeader),
-        Some(header) => header
-    };
+/// * `scan` - the scanner, just after the word which could not be read
+///
+/// # Returns
+/// Whether there is more of the solid to read.
+fn skip_facet<T: std::io::Read>(scan: &mut Tokens<T>) -> Result<bool, StlError> {
+    // The word which could not be read may be the end of this facet or the start of the next one
+    scan.unread();
+    while let Some(word) = scan.next()? {
+        match word.as_str() {
+            "endfacet" => return Ok(true),
+            "facet" | "endsolid" => {
+                scan.unread();
+                return Ok(true);
+            },
+            // The solid is missing its end, and the next one is starting
+            "solid" => {
+                scan.unread();
+                return Ok(false);
+            },
+            _ => ()
+        }
+    }
+    Ok(false)
+}
 
-    // The header is separated from the magic work "solid" by while space
-    let mut iter = line.split_whitespace();
-    // Confirm the file starts with "solid"
-    match iter.next() {
-        None => return Err(StlError::MissingHeader),
-        Some("solid") => (),
-        Some(_) => return Err(StlError::MissingHeader)
-    };
+/// Splits ASCII STL data into words, keeping track of where each word is so that errors can
+/// point to it.
+struct Tokens<R: std::io::Read> {
+    scan: Scanner<R>,
+    /// The line currently being read
+    line: String,
+    /// The byte offset in `line` of the first unread character
+    offset: usize,
+    /// The byte offset in `line` of the start of the last word read
+    start: usize,
+    /// The number of the current line (starting from 1)
+    line_number: usize,
+    /// The column of the last word read (starting from 1)
+    column: usize,
+}
 
-    // There might be a name of the solid, but it is optional
-    let name = match iter.next() {
-        Some(name) => Ok(Some(name.to_owned())),
-        None => Ok(None)
-    };
+impl<R: std::io::Read> Tokens<R> {
+    fn new(reader: R) -> Tokens<R> {
+        Tokens { scan: Scanner::new(reader), line: String::new(), offset: 0, start: 0, line_number: 0, column: 1 }
+    }
+
+    /// Move on to the next line. Returns false if there are no more lines.
+    fn advance(&mut self) -> Result<bool, StlError> {
+        match self.scan.next_line()? {
+            None => {
+                // Errors at the end of the file point just past the last line
+                self.column = self.line.chars().count() + 1;
+                Ok(false)
+            },
+            Some(line) => {
+                self.line = line;
+                self.offset = 0;
+                self.start = 0;
+                self.line_number += 1;
+                self.column = 1;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Read the rest of the current line, without white space at either end.
+    fn rest_of_line(&mut self) -> String {
+        let rest = &self.line[self.offset..];
+        let text = rest.trim();
+        if !text.is_empty() {
+            let start = self.offset + rest.len() - rest.trim_start().len();
+            self.column = self.line[..start].chars().count() + 1;
+            self.start = start;
+        }
+        self.offset = self.line.len();
+        text.to_owned()
+    }
 
-    // If there is anything else in the header - fail.
-    if iter.next().is_some() {
-        return Err(StlError::TooMuchInHeader);
+    /// Read the next word, moving on to later lines if needed.
+    fn next(&mut self) -> Result<Option<String>, StlError> {
+        loop {
+            let rest = &self.line[self.offset..];
+            let word = rest.trim_start();
+            if !word.is_empty() {
+                let start = self.offset + rest.len() - word.len();
+                let end = start + word.find(char::is_whitespace).unwrap_or(word.len());
+                self.column = self.line[..start].chars().count() + 1;
+                self.start = start;
+                self.offset = end;
+                return Ok(Some(self.line[start..end].to_owned()));
+            }
+            if !self.advance()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Go back to just before the last word read, so that it is read again.
+    fn unread(&mut self) {
+        self.offset = self.start;
+    }
+
+    /// Mark an error with the position of the last word read.
+    fn locate(&self, error: StlError) -> StlError {
+        error.at(self.line_number, self.column)
+    }
+}
+
+/// Read the header line of a solid and return the name of the solid
+///
+/// # Arguments
+/// * `scan` - A scanner to the ascii file, at the start of the solid
+fn read_header_ascii<T: std::io::Read>(scan: &mut Tokens<T>) -> Result<Option<String>, StlError> {
+    // Confirm the solid starts with "solid"
+    match scan.next()? {
+        Some(ref s) if s == "solid" => (),
+        _ => return Err(StlError::MissingHeader)
     };
 
-    name
+    // There might be a name of the solid, but it is optional. Names can contain spaces.
+    let name = scan.rest_of_line();
+    if name.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(name))
+    }
 }
 
 /// Read a single face from the body of an ASCII STL file
@@ -73,14 +214,15 @@ fn read_header_ascii<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<Option<S
 /// * Err - if there was a reading error
 /// * Ok(None) - if there were no more faces in the file and the end of file marker was reached
 /// * OK(Some(face)) - if there was a face read
-fn read_body_ascii<T: std::io::Read>(scan: &mut Scanner<T>, name: &Option<String>) -> Result<Option<Face>, StlError>  {
+fn read_body_ascii<T: std::io::Read>(scan: &mut Tokens<T>, name: &Option<String>) -> Result<Option<Face>, StlError>  {
     let result = scan.next()?;
     match result {
         None => Err(StlError::NoEndSolid),
         Some(s) => {
             if s == "endsolid" {
-                let end_name = scan.next()?;
-                if *name != end_name {
+                // The name is often left off the end of the solid
+                let end_name = Some(scan.rest_of_line()).filter(|n| !n.is_empty());
+                if end_name.is_some() && *name != end_name {
                     Err(StlError::MissmatchedSolidNames(name.clone(), end_name))
                 }else {
                     Ok(None)
@@ -106,7 +248,7 @@ fn read_body_ascii<T: std::io::Read>(scan: &mut Scanner<T>, name: &Option<String
                     }
                 ))
             } else {
-                Err(StlError::UnknownSymbol(s))
+                Err(StlError::UnknownSymbol { found: s, expected: String::from("facet or endsolid") })
             }
         }
     }
@@ -118,14 +260,14 @@ fn read_body_ascii<T: std::io::Read>(scan: &mut Scanner<T>, name: &Option<String
 ///
 /// * `scan` - The scanner to read from
 /// * `expected` - The string which the next token must match
-fn ensure_next<T: std::io::Read>(scan: &mut Scanner<T>, expected: String) -> Result<(), StlError> {
+fn ensure_next<T: std::io::Read>(scan: &mut Tokens<T>, expected: String) -> Result<(), StlError> {
     let result = scan.next()?;
     match result {
         None => Err(StlError::UnexpectedEndOfFile(expected)),
         Some(s) =>  if s == expected {
                         Ok(())
                     } else {
-                        Err(StlError::UnknownSymbol(s))
+                        Err(StlError::UnknownSymbol { found: s, expected })
                     }
     }
 }
@@ -134,11 +276,11 @@ fn ensure_next<T: std::io::Read>(scan: &mut Scanner<T>, expected: String) -> Res
 ///
 /// # Arguments
 /// * `scan` - The scanner to read.
-fn ensure_f32<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<f32, StlError> {
-    let result = scan.next_f32()?;
+fn ensure_f32<T: std::io::Read>(scan: &mut Tokens<T>) -> Result<f32, StlError> {
+    let result = scan.next()?;
     match result {
-        None => Err(StlError::UnexpectedEndOfFile(String::from("<float>"))),
-        Some(s) => Ok(s)
+        None => Err(StlError::UnexpectedEndOfFile(String::from("a number"))),
+        Some(s) => s.parse().map_err(|_| StlError::InvalidNumber(s))
     }
 }
 
@@ -146,7 +288,7 @@ fn ensure_f32<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<f32, StlError>
 ///
 /// #  Arguments
 /// * `scan` - The scanner to read from.
-pub fn read_vector3d<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<Vec3, StlError> {
+fn read_vector3d<T: std::io::Read>(scan: &mut Tokens<T>) -> Result<Vec3, StlError> {
     Ok(Vec3::new(
         ensure_f32(scan)? as f64,
         ensure_f32(scan)? as f64,
@@ -157,38 +299,89 @@ pub fn read_vector3d<T: std::io::Read>(scan: &mut Scanner<T>) -> Result<Vec3, St
 
 #[cfg(test)]
 mod tests {
-    use scanner_rust::Scanner;
+    use super::Tokens;
+    use geometry::Strictness;
 
     #[test]
     fn test_read_header() {
-        let mut scan = Scanner::scan_slice("solid dog");
+        let mut scan = Tokens::new(&b"solid dog"[..]);
         let name = super::read_header_ascii(&mut scan).unwrap().unwrap();
         assert_eq!(name, "dog");
 
-        let mut scan = Scanner::scan_slice("solid ");
+        let mut scan = Tokens::new(&b"solid "[..]);
         let name = super::read_header_ascii(&mut scan).unwrap();
         assert!(name.is_none());
 
-        let mut scan = Scanner::scan_slice("solid \n\tfacet normal");
+        let mut scan = Tokens::new(&b"solid \n\tfacet normal"[..]);
         let name = super::read_header_ascii(&mut scan).unwrap();
         assert!(name.is_none());
 
-        let mut scan = Scanner::scan_slice("");
+        let mut scan = Tokens::new(&b""[..]);
         let name = super::read_header_ascii(&mut scan);
         assert!(name.is_err());
 
-        let mut scan = Scanner::scan_slice("fish fish");
+        let mut scan = Tokens::new(&b"fish fish"[..]);
         let name = super::read_header_ascii(&mut scan);
         assert!(name.is_err());
 
-        let mut scan = Scanner::scan_slice("solid dog bowl");
-        let name = super::read_header_ascii(&mut scan);
-        assert!(name.is_err());
+        let mut scan = Tokens::new(&b"solid dog bowl  \n"[..]);
+        let name = super::read_header_ascii(&mut scan).unwrap().unwrap();
+        assert_eq!(name, "dog bowl");
+    }
+
+    #[test]
+    fn test_solids() {
+        let text = "solid left wheel\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    endsolid left wheel\n\
+                    solid\n\
+                    endsolid\n\
+                    solid  right wheel \n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    endsolid\n";
+        let scene = super::read_ascii(text.as_bytes(), Strictness::Strict).unwrap();
+        let parts: Vec<(Option<&str>, usize)> = scene.meshes.iter().map(|m| (m.name.as_deref(), m.faces.len())).collect();
+        assert_eq!(parts, vec![(Some("left wheel"), 1), (None, 0), (Some("right wheel"), 2)]);
+
+        let e = super::read_ascii(&b"solid a\nendsolid a\nendsolid b\n"[..], Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<stl>:3:1: expected 'solid' at the start of an ASCII STL solid");
+        let e = super::read_ascii(&b"solid a b\nendsolid a\n"[..], Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<stl>:2:10: solid a b ends with the name a");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let text = "solid cube\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 O 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n endloop\n\
+                    facet normal 0 0 1\n outer loop\n vertex 0 0 0\n vertex 1 0 0\n vertex 0 1 0\n endloop\n endfacet\n\
+                    endsolid cylinder\n";
+        assert!(super::read_ascii(text.as_bytes(), Strictness::Strict).is_err());
+
+        let scene = super::read_ascii(text.as_bytes(), Strictness::Lenient).unwrap();
+        assert_eq!(scene.face_count(), 2);
+        let lines: Vec<Option<usize>> = scene.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![Some(11), Some(20), Some(28)]);
+    }
+
+    #[test]
+    fn test_error_position() {
+        let text = "solid cube\n  facet normal 0 0 1\n    outer loop\n      vertex 0 O 0\n";
+        let e = super::read_ascii(text.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<stl>:4:16: expected a number, found 'O'");
+
+        let e = super::read_ascii(&b"solid cube\n  facet normal 0 0 1\n"[..], Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<stl>:2:21: expected outer, found the end of the file");
+
+        let e = super::read_ascii(&b"solid cube\nfacets"[..], Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<stl>:2:1: expected facet or endsolid, found 'facets'");
     }
 
     #[test]
     fn test_ensure_next() {
-        let mut scan = Scanner::scan_slice("dog fish cat");
+        let mut scan = Tokens::new(&b"dog fish cat"[..]);
         assert!(super::ensure_next(&mut scan, String::from("dog")).is_ok());
         assert!(super::ensure_next(&mut scan, String::from("crate")).is_err());
         assert!(super::ensure_next(&mut scan, String::from("cat")).is_ok());
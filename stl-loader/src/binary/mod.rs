@@ -1,23 +1,34 @@
-//! Functions for reading binary STL files.
+//! Functions for reading and writing binary STL files.
 
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
 use std::convert::TryInto;
 use num_traits::identities::Zero;
+use super::colour::{self, Colour5, ColourFormat};
 use super::common::StlError;
 
-use geometry::{Face, Vec3};
+use geometry::{Face, LoadError, Material, Mesh, Scene, Strictness, Vec3};
 
-/// Read a binary STL file and return the list of faces.
+/// The most faces to allocate space for before reading them. The face count in the header may
+/// be wrong, so it is not trusted for large allocations.
+const MAX_RESERVED_FACES: usize = 1 << 20;
+
+/// Read binary STL data and return a scene with all the faces in one mesh.
+/// The text of the header is kept in the scene metadata.
+/// Facet colours (see the `colour` module) become materials. A default colour in the header is
+/// the material of the mesh.
 /// Binary STL files are assumed to be written in little endian byte order.
 /// # Arguments
 ///
-/// * `file` - The file to read. This must be binary STL. The file pointer can be at any position.
-pub fn read_file_binary(file: &mut File) -> Result<Vec<Face>, StlError> {
-    // Skip the header and jump to position 80
-    file.seek(SeekFrom::Start(80))?;
+/// * `reader` - The data to read. This must be binary STL, starting from the header.
+/// * `strictness` - In lenient mode a file which ends early keeps the faces that were read.
+pub fn read_binary<R: Read>(mut reader: R, strictness: Strictness) -> Result<Scene, StlError> {
+    // The 80 byte header is free text, often padded with nulls
+    let mut header = [0; 80];
+    reader.read_exact(&mut header)?;
     let mut buff = [0;4];
-    file.read_exact(&mut buff)?;
+    reader.read_exact(&mut buff)?;
     let n_faces = u32::from_le_bytes(buff);
     let n_faces: usize = n_faces.try_into()?;
 
@@ -27,12 +38,33 @@ pub fn read_file_binary(file: &mut File) -> Result<Vec<Face>, StlError> {
         return Err(StlError::NoFacesFound)
     }
 
-    let mut faces: Vec<Face> = Vec::with_capacity(n_faces);
+    let mut faces: Vec<Face> = Vec::with_capacity(min(n_faces, MAX_RESERVED_FACES));
+    let mut warnings = Vec::new();
+
+    let format = ColourFormat::detect(&header);
+    let mut materials: Vec<Material> = Vec::new();
+    let default = colour::header_material(&header).map(|m| {
+        materials.push(m);
+        materials.len() - 1
+    });
+    // The material made for each facet colour
+    let mut palette: HashMap<Colour5, usize> = HashMap::new();
 
-    for _ in 1..n_faces {
+    for _ in 0..n_faces {
         // Each face is 12, 4 byte reals + a 2 byte uint16
         let mut face_buffer = [0; 12 * 4 + 2];
-        file.read_exact(&mut face_buffer)?;
+        match reader.read_exact(&mut face_buffer) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                let error = StlError::TruncatedBinary { expected_faces: n_faces, read_faces: faces.len() };
+                if strictness == Strictness::Strict || faces.is_empty() {
+                    return Err(error);
+                }
+                warnings.push(error.into_warning());
+                break;
+            },
+            Err(e) => return Err(StlError::IOError(e))
+        }
 
         let normal = read_vec(&face_buffer, 0);
         let f = if normal.is_zero() {
@@ -46,12 +78,86 @@ pub fn read_file_binary(file: &mut File) -> Result<Vec<Face>, StlError> {
                 read_vec(&face_buffer, 2),
                 read_vec(&face_buffer, 3))
         };
-        // We ignore the last 2 bytes for now as we don't need them for the shape
 
-        faces.push(f);
+        let attribute = u16::from_le_bytes([face_buffer[48], face_buffer[49]]);
+        let material = format.decode(attribute).map(|c| *palette.entry(c).or_insert_with(|| {
+            let rgb = colour::from_colour5(c) * 255.0;
+            let name = format!("#{:02x}{:02x}{:02x}", rgb.x.round() as u8, rgb.y.round() as u8, rgb.z.round() as u8);
+            materials.push(Material::with_colour(&name, colour::from_colour5(c)));
+            materials.len() - 1
+        }));
+
+        faces.push(f.with_material(material));
+    }
+
+    let mut mesh = Mesh::new(None, faces);
+    mesh.material = default;
+    let mut scene = Scene::from_mesh(mesh);
+    scene.materials = materials;
+    scene.warnings = warnings;
+    let header = String::from_utf8_lossy(colour::header_text(&header));
+    let header = header.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if !header.is_empty() {
+        scene.metadata.insert("header".to_owned(), header.to_owned());
+    }
+    Ok(scene)
+}
+
+/// Write a scene as binary STL, with the colours of the faces.
+///
+/// If all the meshes have the same material (which is in the scene) it is written as the default
+/// colour in the header, and facets use the Materialise convention. Otherwise the VisCAM convention is used.
+/// Colours are reduced to 5 bits per channel.
+///
+/// # Arguments
+/// * `writer` - Where to write the STL data
+/// * `scene` - The scene to write. All the meshes are written as one solid.
+pub fn write_binary<W: Write>(mut writer: W, scene: &Scene) -> Result<(), StlError> {
+    let diffuse = |material: usize| scene.materials.get(material).map(|m| m.diffuse);
+    // A shared material which is not in the scene has no colour to put in the header
+    let default = match scene.meshes.first().and_then(|m| m.material) {
+        Some(m) if scene.meshes.iter().all(|mesh| mesh.material == Some(m)) => diffuse(m).map(|c| (m, c)),
+        _ => None
+    };
+    let format = if default.is_some() { ColourFormat::Materialise } else { ColourFormat::VisCam };
+
+    let mut header = [0; 80];
+    let text = b"Binary STL written by Vitrum";
+    header[..text.len()].copy_from_slice(text);
+    if let Some((_, c)) = default {
+        colour::write_header_colour(&mut header, c);
     }
+    writer.write_all(&header)?;
+
+    let n_faces: u32 = scene.face_count().try_into()?;
+    writer.write_all(&n_faces.to_le_bytes())?;
+
+    for mesh in &scene.meshes {
+        for face in &mesh.faces {
+            let material = face.material().or(mesh.material);
+            // Faces with the default colour do not have their own
+            let face_colour = material.filter(|m| Some(*m) != default.map(|(d, _)| d))
+                                      .and_then(diffuse)
+                                      .map(colour::to_colour5);
 
-    Ok(faces)
+            let mut face_buffer = [0; 12 * 4 + 2];
+            let [a, b, c] = face.vertices();
+            for (i, v) in [face.normal(), a, b, c].iter().enumerate() {
+                write_vec(&mut face_buffer, i, v);
+            }
+            face_buffer[48..].copy_from_slice(&format.encode(face_colour).to_le_bytes());
+            writer.write_all(&face_buffer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write three f32s into a face buffer in little endian order. This is the inverse of `read_vec`.
+fn write_vec(buff: &mut [u8; 50], offset: usize, v: &Vec3) {
+    let offset = offset * 4 * 3;
+    for (i, x) in v.iter().enumerate() {
+        buff[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&(*x as f32).to_le_bytes());
+    }
 }
 
 /// This function reads three little endian f32s from the buff array with no padding.
@@ -66,3 +172,103 @@ fn read_vec(buff: &[u8;50], offset: usize) -> Vec3 {
               f32::from_le_bytes(buff[offset + 4..offset + 8].try_into().expect("Must be 4 bytes")) as f64,
               f32::from_le_bytes(buff[offset + 8..offset + 12].try_into().expect("Must be 4 bytes")) as f64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{StlError, Strictness};
+    use geometry::{Face, Material, Mesh, Scene, Vec3};
+
+    fn triangle() -> Face {
+        Face::from_points(Vec3::new(0.0, 0.0, 0.0),
+                          Vec3::new(1.0, 0.0, 0.0),
+                          Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn test_all_faces_read() {
+        let mut data = vec![0; 80];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[0; 100]);
+        let scene = super::read_binary(&data[..], Strictness::Strict).unwrap();
+        assert_eq!(scene.face_count(), 2);
+        assert!(scene.materials.is_empty());
+    }
+
+    #[test]
+    fn test_colours() {
+        // VisCAM: red in the high bits, bit 15 marks a valid colour
+        let mut data = vec![0; 80];
+        data.extend_from_slice(&3u32.to_le_bytes());
+        for attribute in &[0x8000u16 | 31 << 10, 0, 0x8000 | 31 << 10] {
+            data.extend_from_slice(&[0; 48]);
+            data.extend_from_slice(&attribute.to_le_bytes());
+        }
+        let scene = super::read_binary(&data[..], Strictness::Strict).unwrap();
+        assert_eq!(scene.materials.len(), 1);
+        assert_eq!(scene.materials[0].name, "#ff0000");
+        let materials: Vec<Option<usize>> = scene.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![Some(0), None, Some(0)]);
+    }
+
+    #[test]
+    fn test_write_colours() {
+        let mut scene = Scene::new();
+        let grey = scene.add_material(Material::with_colour("grey", Vec3::new(0.5, 0.5, 0.5)));
+        let blue = scene.add_material(Material::with_colour("blue", Vec3::new(0.0, 0.0, 1.0)));
+        let mut mesh = Mesh::new(None, vec![triangle(), triangle().with_material(Some(blue))]);
+        mesh.material = Some(grey);
+        scene.meshes.push(mesh);
+
+        // One material for all the meshes is written as the default colour in the header
+        let mut data = Vec::new();
+        super::write_binary(&mut data, &scene).unwrap();
+        assert_eq!(data.len(), 84 + 2 * 50);
+        let read = super::read_binary(&data[..], Strictness::Strict).unwrap();
+        assert_eq!(read.materials.len(), 2);
+        assert_eq!(read.meshes[0].material, Some(0));
+        assert_eq!(read.materials[0].diffuse, Vec3::new(128.0, 128.0, 128.0) / 255.0);
+        assert_eq!(read.materials[1].diffuse, Vec3::new(0.0, 0.0, 1.0));
+        let materials: Vec<Option<usize>> = read.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![None, Some(1)]);
+        assert_eq!(read.metadata["header"], "Binary STL written by Vitrum");
+        assert_eq!(read.faces().next().unwrap().vertices(), triangle().vertices());
+
+        // Without a shared material the colours are per face
+        scene.meshes[0].material = None;
+        let mut data = Vec::new();
+        super::write_binary(&mut data, &scene).unwrap();
+        let read = super::read_binary(&data[..], Strictness::Strict).unwrap();
+        assert_eq!(read.meshes[0].material, None);
+        let materials: Vec<Option<usize>> = read.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![None, Some(0)]);
+
+        // A shared material which is missing from the scene is not written as the default
+        scene.meshes[0].material = Some(5);
+        let mut data = Vec::new();
+        super::write_binary(&mut data, &scene).unwrap();
+        assert!(!data[..80].windows(6).any(|w| w == b"COLOR="));
+        let read = super::read_binary(&data[..], Strictness::Strict).unwrap();
+        assert_eq!(read.meshes[0].material, None);
+        assert_eq!(read.materials.len(), 1);
+        assert_eq!(read.materials[0].diffuse, Vec3::new(0.0, 0.0, 1.0));
+        let materials: Vec<Option<usize>> = read.faces().map(|f| f.material()).collect();
+        assert_eq!(materials, vec![None, Some(0)]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut data = vec![0; 80];
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(&[0; 50]);
+        data.extend_from_slice(&[0; 20]);
+        match super::read_binary(&data[..], Strictness::Strict) {
+            Err(StlError::TruncatedBinary { expected_faces: 3, read_faces: 1 }) => (),
+            other => panic!("Expected a truncation error, got {:?}", other)
+        }
+
+        let scene = super::read_binary(&data[..], Strictness::Lenient).unwrap();
+        assert_eq!(scene.face_count(), 1);
+        assert_eq!(scene.warnings.len(), 1);
+        assert!(super::read_binary(&data[..84 + 20], Strictness::Lenient).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Colours stored in binary STL files.
+//!
+//! There are two conventions for storing a colour in the 2 byte attribute of each facet:
+//! * VisCAM and SolidView store blue in bits 0-4, green in bits 5-9 and red in bits 10-14.
+//!   Bit 15 is set when the colour is valid.
+//! * Materialise Magics stores red in bits 0-4, green in bits 5-9 and blue in bits 10-14.
+//!   Bit 15 is set when the facet uses the default colour of the object instead. The default is
+//!   written in the header after `COLOR=` as 4 bytes (RGBA). The header may also have `MATERIAL=`
+//!   followed by the diffuse, specular and ambient colours (4 bytes each).
+//!
+//! Files with `COLOR=` or `MATERIAL=` in the header use the Materialise convention.
+
+use geometry::{Material, Vec3};
+
+/// The name given to the material described by the header of a file.
+pub const HEADER_MATERIAL: &str = "header";
+
+/// A colour with 5 bits (0 - 31) for each channel, in RGB order.
+pub type Colour5 = [u8; 3];
+
+/// The convention a file uses for facet colours.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColourFormat {
+    VisCam,
+    Materialise,
+}
+
+impl ColourFormat {
+    /// Work out which convention a file uses from its header.
+    pub fn detect(header: &[u8]) -> ColourFormat {
+        if find(header, b"COLOR=").is_some() || find(header, b"MATERIAL=").is_some() {
+            ColourFormat::Materialise
+        } else {
+            ColourFormat::VisCam
+        }
+    }
+
+    /// The colour of a facet, if it has its own colour.
+    ///
+    /// # Arguments
+    /// * `attribute` - The attribute word of the facet
+    pub fn decode(self, attribute: u16) -> Option<Colour5> {
+        let valid = attribute & 0x8000 != 0;
+        let low = (attribute & 0x1f) as u8;
+        let middle = ((attribute >> 5) & 0x1f) as u8;
+        let high = ((attribute >> 10) & 0x1f) as u8;
+        match self {
+            ColourFormat::VisCam if valid => Some([high, middle, low]),
+            ColourFormat::Materialise if !valid => Some([low, middle, high]),
+            _ => None
+        }
+    }
+
+    /// The attribute word for a facet.
+    ///
+    /// # Arguments
+    /// * `colour` - The colour of the facet, or None if it has no colour of its own
+    pub fn encode(self, colour: Option<Colour5>) -> u16 {
+        let pack = |low: u8, middle: u8, high: u8| (low as u16) | (middle as u16) << 5 | (high as u16) << 10;
+        match (self, colour) {
+            (ColourFormat::VisCam, Some([r, g, b])) => 0x8000 | pack(b, g, r),
+            (ColourFormat::VisCam, None) => 0,
+            (ColourFormat::Materialise, Some([r, g, b])) => pack(r, g, b),
+            (ColourFormat::Materialise, None) => 0x8000,
+        }
+    }
+}
+
+/// The default material given in a Materialise style header, if there is one.
+///
+/// # Arguments
+/// * `header` - The 80 byte header of the file
+pub fn header_material(header: &[u8]) -> Option<Material> {
+    let rgb = |at: usize| header.get(at..at + 4).map(|c| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / 255.0);
+
+    let colour = find(header, b"COLOR=").and_then(|i| rgb(i + 6));
+    let material = find(header, b"MATERIAL=").and_then(|i| match (rgb(i + 9), rgb(i + 13)) {
+        (Some(diffuse), Some(specular)) => Some((diffuse, specular)),
+        _ => None
+    });
+
+    match (colour, material) {
+        (None, None) => None,
+        (colour, material) => {
+            // COLOR= is the colour of the object, so it takes precedence over the diffuse colour
+            let diffuse = colour.or_else(|| material.map(|m| m.0)).expect("One of them is set");
+            let mut m = Material::with_colour(HEADER_MATERIAL, diffuse);
+            if let Some((_, specular)) = material {
+                m.specular = specular;
+            }
+            Some(m)
+        }
+    }
+}
+
+/// The part of the header which is text, before any colours.
+pub fn header_text(header: &[u8]) -> &[u8] {
+    let end = [find(header, b"COLOR="), find(header, b"MATERIAL=")].iter()
+                                                                   .filter_map(|i| *i)
+                                                                   .min()
+                                                                   .unwrap_or(header.len());
+    &header[..end]
+}
+
+/// Write a default colour into a header in the Materialise style.
+///
+/// # Arguments
+/// * `header` - The header to write to. The colour goes after any text.
+/// * `colour` - The colour, each channel in [0, 1]
+pub fn write_header_colour(header: &mut [u8; 80], colour: Vec3) {
+    let start = header.iter().position(|b| *b == 0).unwrap_or(70).min(70);
+    header[start..start + 6].copy_from_slice(b"COLOR=");
+    for (i, c) in [colour.x, colour.y, colour.z, 1.0].iter().enumerate() {
+        header[start + 6 + i] = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+}
+
+/// Reduce a colour to 5 bits per channel.
+pub fn to_colour5(colour: Vec3) -> Colour5 {
+    let channel = |c: f64| (c.clamp(0.0, 1.0) * 31.0).round() as u8;
+    [channel(colour.x), channel(colour.y), channel(colour.z)]
+}
+
+/// Expand a colour with 5 bits per channel to [0, 1] for each channel.
+pub fn from_colour5(colour: Colour5) -> Vec3 {
+    Vec3::new(colour[0] as f64, colour[1] as f64, colour[2] as f64) / 31.0
+}
+
+/// Find the first position of some bytes
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColourFormat, Vec3};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ColourFormat::detect(b"Exported from VisCAM"), ColourFormat::VisCam);
+        assert_eq!(ColourFormat::detect(b"Magics COLOR=\xff\x00\x00\xff"), ColourFormat::Materialise);
+        assert_eq!(ColourFormat::detect(b"MATERIAL=abcdabcdabcd"), ColourFormat::Materialise);
+    }
+
+    #[test]
+    fn test_decode() {
+        // Red is in the high bits for VisCAM and the low bits for Materialise
+        assert_eq!(ColourFormat::VisCam.decode(0x8000 | 31 << 10 | 2), Some([31, 0, 2]));
+        assert_eq!(ColourFormat::VisCam.decode(31 << 10), None);
+        assert_eq!(ColourFormat::Materialise.decode(31 | 4 << 5), Some([31, 4, 0]));
+        assert_eq!(ColourFormat::Materialise.decode(0x8000 | 31), None);
+
+        for format in &[ColourFormat::VisCam, ColourFormat::Materialise] {
+            assert_eq!(format.decode(format.encode(Some([1, 2, 3]))), Some([1, 2, 3]));
+            assert_eq!(format.decode(format.encode(None)), None);
+        }
+    }
+
+    #[test]
+    fn test_header_material() {
+        let mut header = [0; 80];
+        header[..4].copy_from_slice(b"part");
+        assert!(super::header_material(&header).is_none());
+        assert_eq!(super::header_text(&header), &header[..]);
+
+        super::write_header_colour(&mut header, Vec3::new(1.0, 0.0, 0.2));
+        let m = super::header_material(&header).unwrap();
+        assert_eq!(m.diffuse, Vec3::new(255.0, 0.0, 51.0) / 255.0);
+        assert_eq!(super::header_text(&header), b"part");
+
+        let mut header = [0; 80];
+        header[..21].copy_from_slice(b"MATERIAL=\x00\xff\x00\xff\x80\x80\x80\xff\x00\x00\x00\xff");
+        let m = super::header_material(&header).unwrap();
+        assert_eq!(m.diffuse, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(m.specular, Vec3::new(128.0, 128.0, 128.0) / 255.0);
+    }
+}
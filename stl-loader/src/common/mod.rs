@@ -1,6 +1,8 @@
 //! Errors for STL file reading
 
+use geometry::impl_load_error;
 use scanner_rust::ScannerError;
+use std::fmt::{Display, Formatter};
 use std::io::Error;
 use std::num::TryFromIntError;
 use std::str::Utf8Error;
@@ -17,8 +19,6 @@ pub enum StlError {
     ScanError(ScannerError),
     /// No header line in the ASCII STL file
     MissingHeader,
-    /// Trailing characters after the name in the header (solid) line of the ASCII STL file
-    TooMuchInHeader,
     /// File terminated with no endsolid
     NoEndSolid,
     /// Found no faces
@@ -26,11 +26,55 @@ pub enum StlError {
     /// There are more faces than can be allocated in memory (usize < u32)
     TooManyFacesError,
     /// Saw this text in a place where it was not expected
-    UnknownSymbol(String),
+    UnknownSymbol { found: String, expected: String },
+    /// A number could not be read
+    InvalidNumber(String),
     /// Solid names at start and end of the file were different
     MissmatchedSolidNames(Option<String>, Option<String>),
     /// File terminated when something else was expected
     UnexpectedEndOfFile(String),
+    /// A binary file ended before all the faces it declared were read
+    TruncatedBinary { expected_faces: usize, read_faces: usize },
+    /// An error at a known position in an ASCII file. Lines and columns start at 1.
+    At { file: Option<String>, line: usize, column: usize, error: Box<StlError> }
+}
+
+impl_load_error!(StlError, IOError, ScanError);
+
+impl Display for StlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StlError::UTF8Error(e) => write!(f, "STL file is not valid UTF-8: {}", e),
+            StlError::IOError(e) => write!(f, "Could not read STL file: {}", e),
+            StlError::ScanError(e) => write!(f, "Could not read STL file: {}", e),
+            StlError::MissingHeader => write!(f, "expected 'solid' at the start of an ASCII STL solid"),
+            StlError::NoEndSolid => write!(f, "STL file ended without 'endsolid'"),
+            StlError::NoFacesFound => write!(f, "STL file has no faces"),
+            StlError::TooManyFacesError => write!(f, "STL file has too many faces"),
+            StlError::UnknownSymbol { found, expected } => write!(f, "expected {}, found '{}'", expected, found),
+            StlError::InvalidNumber(token) => write!(f, "expected a number, found '{}'", token),
+            StlError::MissmatchedSolidNames(start, end) =>
+                write!(f, "solid {} ends with the name {}",
+                       start.as_deref().unwrap_or("<unnamed>"), end.as_deref().unwrap_or("<unnamed>")),
+            StlError::UnexpectedEndOfFile(expected) => write!(f, "expected {}, found the end of the file", expected),
+            StlError::TruncatedBinary { expected_faces, read_faces } =>
+                write!(f, "STL file ended after {} of {} faces", read_faces, expected_faces),
+            StlError::At { file, line, column, error } =>
+                write!(f, "{}:{}:{}: {}", file.as_deref().unwrap_or("<stl>"), line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for StlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StlError::UTF8Error(e) => Some(e),
+            StlError::IOError(e) => Some(e),
+            StlError::ScanError(e) => Some(e),
+            StlError::At { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
 }
 
 impl std::convert::From<ScannerError> for StlError {
@@ -57,3 +101,24 @@ impl std::convert::From<TryFromIntError> for StlError {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::StlError;
+    use geometry::LoadError;
+
+    #[test]
+    fn test_display() {
+        let e = StlError::InvalidNumber("1,5".to_owned()).at(4, 20).in_file("part.stl");
+        assert_eq!(e.to_string(), "part.stl:4:20: expected a number, found '1,5'");
+
+        let e = StlError::TruncatedBinary { expected_faces: 12, read_faces: 3 };
+        assert_eq!(e.to_string(), "STL file ended after 3 of 12 faces");
+    }
+
+    #[test]
+    fn test_into_warning() {
+        let w = StlError::InvalidNumber("1,5".to_owned()).at(4, 20).in_file("part.stl").into_warning();
+        assert_eq!(w.to_string(), "part.stl:4: expected a number, found '1,5'");
+    }
+}
@@ -1,35 +1,67 @@
-//!  The stl-loader reads binary and ASCII STL files and converts them into `Faces`.
+//!  The stl-loader reads binary and ASCII STL files and converts them into a `Scene`.
 
-use std::io::Read;
+use std::io::{BufWriter, Cursor, Read, Write};
 use std::fs::File;
 use std::str;
-use std::vec::Vec;
 
 mod ascii;
 mod binary;
+mod colour;
 mod common;
 
 pub use common::StlError;
-pub use geometry::{Face, Vec3};
+pub use geometry::{Face, LoadError, Scene, Strictness, Vec3};
 
 /// Read in and parse and STL file
 ///
 /// # Arguments
 ///
 /// * `filename` - The path to the file to read. This can be either an ASCII or binary STL.
-pub fn read_stl_file(filename: &str) -> Result<Vec<Face>, StlError> {
+/// * `strictness` - Whether bad facets are errors or are skipped with a warning.
+pub fn read_stl_file(filename: &str, strictness: Strictness) -> Result<Scene, StlError> {
+    println!("Reading STL file {}", filename);
+    read_stl(File::open(filename)?, strictness).map_err(|e| e.in_file(filename))
+}
+
+/// Read in and parse STL data from any source, such as a decompression stream.
+///
+/// # Arguments
+///
+/// * `reader` - The STL data. This can be either an ASCII or binary STL.
+/// * `strictness` - Whether bad facets are errors or are skipped with a warning.
+pub fn read_stl<R: Read>(mut reader: R, strictness: Strictness) -> Result<Scene, StlError> {
     //  Check to make sure that it is not a binary file first
-    let mut f = File::open(filename)?;
     let mut buf = [0;6];
-    f.read_exact(&mut buf)?;
-    let header = str::from_utf8(&buf)?;
-    if header == "solid " { // Technically the binary header could start like that. But it shouldn't.
-        ascii::read_file_ascii(filename)
+    reader.read_exact(&mut buf)?;
+    // Put the bytes used for the check back in front of the rest of the data
+    let reader = Cursor::new(buf).chain(reader);
+    if str::from_utf8(&buf) == Ok("solid ") { // Technically the binary header could start like that. But it shouldn't.
+        ascii::read_ascii(reader, strictness)
     } else {
-        binary::read_file_binary(&mut f)
+        binary::read_binary(reader, strictness)
     }
 }
 
+/// Write a scene to a binary STL file, including the colours of the faces.
+///
+/// # Arguments
+///
+/// * `filename` - The path to the file to write.
+/// * `scene` - The scene to write. All the meshes are written as one solid.
+pub fn write_stl_file(filename: &str, scene: &Scene) -> Result<(), StlError> {
+    write_stl(BufWriter::new(File::create(filename)?), scene)
+}
+
+/// Write a scene as binary STL, including the colours of the faces.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the STL data.
+/// * `scene` - The scene to write. All the meshes are written as one solid.
+pub fn write_stl<W: Write>(writer: W, scene: &Scene) -> Result<(), StlError> {
+    binary::write_binary(writer, scene)
+}
+
 #[cfg(test)]
 mod tests {
 
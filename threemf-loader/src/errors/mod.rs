@@ -0,0 +1,110 @@
+//! Errors for 3MF file reading
+
+use geometry::impl_load_error;
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use xml_tools::{AttributeError, MAX_PLACED_FACES, MAX_PLACEMENTS};
+
+/// A 3MF error wraps all the different types of errors you can get back from reading
+/// a 3MF file.
+#[derive(Debug)]
+pub enum ThreeMfError {
+    /// An error that came from IO
+    IOError(Error),
+    /// The file is not well formed XML
+    XmlError(quick_xml::Error),
+    /// The outermost element is not `<model>`
+    NotThreeMf(String),
+    /// A required element or attribute is missing
+    MissingValue(String),
+    /// A number could not be read
+    InvalidNumber { token: String, expected: String },
+    /// A colour is not in the form `#RRGGBB` or `#RRGGBBAA`
+    InvalidColour(String),
+    /// An id or property index was used which is not defined in the file
+    UnknownId { kind: &'static str, id: String },
+    /// A triangle refers to a vertex which does not exist
+    IndexOutOfRange { index: usize, count: usize },
+    /// A triangle uses a vertex that could not be read (lenient mode only)
+    UsesBadVertex(usize),
+    /// Objects are components of themselves, or are nested too deeply
+    TooMuchNesting(String),
+    /// Objects are placed too many times, counting copies of copies
+    TooManyPlacements,
+    /// Found no triangles
+    NoFacesFound,
+    /// An error at a known position in the file. Lines and columns start at 1.
+    At { file: Option<String>, line: usize, column: usize, error: Box<ThreeMfError> }
+}
+
+impl_load_error!(ThreeMfError, IOError);
+
+impl Display for ThreeMfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThreeMfError::IOError(e) => write!(f, "Could not read 3MF file: {}", e),
+            ThreeMfError::XmlError(e) => write!(f, "3MF file is not valid XML: {}", e),
+            ThreeMfError::NotThreeMf(root) => write!(f, "expected a <model> element, found <{}>", root),
+            ThreeMfError::MissingValue(expected) => write!(f, "expected {}", expected),
+            ThreeMfError::InvalidNumber { token, expected } => write!(f, "expected {}, found '{}'", expected, token),
+            ThreeMfError::InvalidColour(colour) => write!(f, "expected a colour like #RRGGBB, found '{}'", colour),
+            ThreeMfError::UnknownId { kind, id } => write!(f, "there is no {} with the id '{}'", kind, id),
+            ThreeMfError::IndexOutOfRange { index, count } =>
+                write!(f, "vertex {} does not exist, the object only has {} vertices", index, count),
+            ThreeMfError::UsesBadVertex(index) => write!(f, "triangle uses vertex {} which could not be read", index),
+            ThreeMfError::TooMuchNesting(id) => write!(f, "object '{}' has components nested too deeply", id),
+            ThreeMfError::TooManyPlacements =>
+                write!(f, "objects are placed more than {} times, or with more than {} triangles", MAX_PLACEMENTS, MAX_PLACED_FACES),
+            ThreeMfError::NoFacesFound => write!(f, "3MF file has no triangles"),
+            ThreeMfError::At { file, line, column, error } =>
+                write!(f, "{}:{}:{}: {}", file.as_deref().unwrap_or("<3mf>"), line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for ThreeMfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ThreeMfError::IOError(e) => Some(e),
+            ThreeMfError::XmlError(e) => Some(e),
+            ThreeMfError::At { error, .. } => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl std::convert::From<Error> for ThreeMfError {
+    fn from(error: Error) -> Self {
+        ThreeMfError::IOError(error)
+    }
+}
+
+impl std::convert::From<quick_xml::Error> for ThreeMfError {
+    fn from(error: quick_xml::Error) -> Self {
+        ThreeMfError::XmlError(error)
+    }
+}
+
+impl std::convert::From<AttributeError> for ThreeMfError {
+    fn from(error: AttributeError) -> Self {
+        match error {
+            AttributeError::Xml(e) => ThreeMfError::XmlError(e),
+            AttributeError::Missing(expected) => ThreeMfError::MissingValue(expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreeMfError;
+    use geometry::LoadError;
+
+    #[test]
+    fn test_display() {
+        let e = ThreeMfError::UnknownId { kind: "object", id: "2".to_owned() }.at(7, 9).in_file("part.3mf");
+        assert_eq!(e.to_string(), "part.3mf:7:9: there is no object with the id '2'");
+
+        let e = ThreeMfError::InvalidColour("red".to_owned());
+        assert_eq!(e.to_string(), "expected a colour like #RRGGBB, found 'red'");
+    }
+}
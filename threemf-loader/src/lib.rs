@@ -0,0 +1,595 @@
+//! The threemf-loader reads 3D Manufacturing Format (3MF) models and converts them into a
+//! `Scene`.
+//!
+//! A 3MF file is a zip archive. The file-loader extracts it and passes the model part
+//! (`3D/3dmodel.model`), which is XML, to this crate. The model has `<resources>`, which are
+//! objects and the materials and colours they use, and a `<build>` of items which place objects
+//! with a transform. Objects are either a mesh or made of components, which are other objects
+//! with a transform of their own. Each build item becomes a mesh in the scene.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::str::FromStr;
+
+use quick_xml::events::BytesStart;
+use xml_tools::{attribute, colour_name, read_elements, required, with_colour, Budget, Colour, ElementHandler, Location, ReadState};
+
+mod errors;
+
+pub use errors::ThreeMfError;
+pub use geometry::{Face, LoadError, Scene, Strictness, Vec3};
+use geometry::{Group, Material, Mesh, Transform, Units};
+
+/// How deeply components can be nested inside each other.
+const MAX_NESTING: usize = 16;
+
+/// Elements from 3MF extensions which Vitrum does not draw, such as textures and beam lattices.
+/// These are skipped with a warning.
+const UNSUPPORTED_ELEMENTS: &[&str] = &[
+    "texture2d", "texture2dgroup", "compositematerials", "multiproperties", "beamlattice", "slicestack",
+];
+
+/// Read in and parse a 3MF model part. The part must already be extracted from the 3MF archive.
+///
+/// # Arguments
+///
+/// * `filename` - The path to the model part to read.
+/// * `strictness` - Whether bad elements are errors or are skipped with a warning.
+pub fn read_3mf_file(filename: &str, strictness: Strictness) -> Result<Scene, ThreeMfError> {
+    println!("Reading 3MF model {}", filename);
+    read_3mf(&fs::read(filename)?, strictness).map_err(|e| e.in_file(filename))
+}
+
+/// Read in and parse a 3MF model part.
+///
+/// # Arguments
+///
+/// * `data` - The XML of the model part.
+/// * `strictness` - Whether bad elements are errors or are skipped with a warning.
+pub fn read_3mf(data: &[u8], strictness: Strictness) -> Result<Scene, ThreeMfError> {
+    let mut parser = ModelData::new(strictness);
+    read_elements(data, &mut parser)?;
+    parser.into_scene()
+}
+
+struct Object {
+    id: String,
+    name: Option<String>,
+    /// The property (group id and index) for triangles which do not have their own
+    property: Option<(String, usize)>,
+    vertices: Vec<Vec3>,
+    /// Vertices which could not be read and have a placeholder (lenient mode only)
+    bad_vertices: HashSet<usize>,
+    triangles: Vec<Triangle>,
+    components: Vec<Placement>,
+    location: Location,
+}
+
+struct Triangle {
+    vertices: [usize; 3],
+    /// The property group id and the index into it
+    property: Option<(String, usize)>,
+}
+
+/// A component of an object, or an item in the build.
+struct Placement {
+    id: String,
+    transform: Transform,
+    location: Location,
+}
+
+/// The properties which can be given to triangles.
+enum PropertyGroup {
+    /// Named materials with a display colour
+    Base(Vec<(String, Colour)>),
+    Colours(Vec<Colour>),
+}
+
+/// Everything read from the file so far.
+struct ModelData {
+    state: ReadState,
+    /// The name of the metadata being read
+    metadata_name: Option<String>,
+    units: Option<Units>,
+    metadata: BTreeMap<String, String>,
+    objects: Vec<Object>,
+    /// Property groups by id, and the id of the one being read
+    groups: HashMap<String, PropertyGroup>,
+    group: Option<String>,
+    items: Vec<Placement>,
+}
+
+impl ElementHandler for ModelData {
+    type Error = ThreeMfError;
+
+    fn state(&mut self) -> &mut ReadState {
+        &mut self.state
+    }
+
+    /// An element has started. Almost everything in a 3MF model is in the attributes.
+    fn start(&mut self, parent: Option<&str>, name: &str, element: &BytesStart) -> Result<(), ThreeMfError> {
+        match (parent, name) {
+            (None, "model") => if let Some(unit) = attribute(element, "unit")? {
+                self.units = units(&unit);
+                if self.units.is_none() {
+                    self.state.warn(format!("unknown unit '{}'", unit));
+                }
+            },
+            (None, other) => return Err(ThreeMfError::NotThreeMf(other.to_owned())),
+            (Some("model"), "metadata") => self.metadata_name = attribute(element, "name")?,
+            (Some("resources"), "basematerials") => self.start_group(element, PropertyGroup::Base(Vec::new()))?,
+            (Some("resources"), "colorgroup") => self.start_group(element, PropertyGroup::Colours(Vec::new()))?,
+            (Some("basematerials"), "base") => {
+                let name = attribute(element, "name")?.unwrap_or_default();
+                let colour = self.colour(&required(element, "displaycolor", "base")?)?;
+                if let Some(PropertyGroup::Base(materials)) = self.current_group() {
+                    materials.push((name, colour));
+                }
+            },
+            (Some("colorgroup"), "color") => {
+                let colour = self.colour(&required(element, "color", "color")?)?;
+                if let Some(PropertyGroup::Colours(colours)) = self.current_group() {
+                    colours.push(colour);
+                }
+            },
+            (Some("resources"), "object") => {
+                let object = Object {
+                    id: required(element, "id", "object")?,
+                    name: attribute(element, "name")?,
+                    property: property(element, "pid", "pindex")?,
+                    vertices: Vec::new(),
+                    bad_vertices: HashSet::new(),
+                    triangles: Vec::new(),
+                    components: Vec::new(),
+                    location: self.state.location,
+                };
+                self.objects.push(object);
+            },
+            (Some("vertices"), "vertex") => self.start_vertex(element)?,
+            (Some("triangles"), "triangle") => self.start_triangle(element)?,
+            (Some("components"), "component") | (Some("build"), "item") => {
+                let placement = Placement {
+                    id: required(element, "objectid", name)?,
+                    transform: match attribute(element, "transform")? {
+                        Some(t) => transform(&t)?,
+                        None => Transform::identity()
+                    },
+                    location: self.state.location,
+                };
+                if name == "item" {
+                    self.items.push(placement);
+                } else if let Some(object) = self.objects.last_mut() {
+                    object.components.push(placement);
+                }
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// The innermost open element has ended.
+    fn end(&mut self, parent: Option<&str>, name: &str) -> Result<(), ThreeMfError> {
+        match (parent, name) {
+            (Some("model"), "metadata") => if let Some(key) = self.metadata_name.take() {
+                self.metadata.insert(key, self.state.text.trim().to_owned());
+            },
+            (Some("resources"), "basematerials") | (Some("resources"), "colorgroup") => self.group = None,
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+impl ModelData {
+    fn new(strictness: Strictness) -> ModelData {
+        ModelData {
+            state: ReadState::new(strictness, UNSUPPORTED_ELEMENTS),
+            metadata_name: None,
+            // Millimetres are the default in 3MF
+            units: Some(Units::Millimetres),
+            metadata: BTreeMap::new(),
+            objects: Vec::new(),
+            groups: HashMap::new(),
+            group: None,
+            items: Vec::new(),
+        }
+    }
+
+    fn start_group(&mut self, element: &BytesStart, group: PropertyGroup) -> Result<(), ThreeMfError> {
+        let id = required(element, "id", "property group")?;
+        self.groups.insert(id.clone(), group);
+        self.group = Some(id);
+        Ok(())
+    }
+
+    fn current_group(&mut self) -> Option<&mut PropertyGroup> {
+        let groups = &mut self.groups;
+        self.group.as_ref().and_then(move |id| groups.get_mut(id))
+    }
+
+    /// Read a colour. Bad colours are white in lenient mode.
+    fn colour(&mut self, text: &str) -> Result<Colour, ThreeMfError> {
+        match colour(text) {
+            Some(c) => Ok(c),
+            None => {
+                self.state.fail(ThreeMfError::InvalidColour(text.to_owned()))?;
+                Ok([1.0; 4])
+            }
+        }
+    }
+
+    fn start_vertex(&mut self, element: &BytesStart) -> Result<(), ThreeMfError> {
+        let read = || -> Result<Vec3, ThreeMfError> {
+            Ok(Vec3::new(number(element, "x", "vertex")?,
+                         number(element, "y", "vertex")?,
+                         number(element, "z", "vertex")?))
+        };
+        let vertex = match read() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.state.fail(e)?;
+                None
+            }
+        };
+        if let Some(object) = self.objects.last_mut() {
+            if vertex.is_none() {
+                object.bad_vertices.insert(object.vertices.len());
+            }
+            object.vertices.push(vertex.unwrap_or_else(Vec3::zeros));
+        }
+        Ok(())
+    }
+
+    fn start_triangle(&mut self, element: &BytesStart) -> Result<(), ThreeMfError> {
+        let object = match self.objects.last() {
+            Some(object) => object,
+            None => return Ok(())
+        };
+        let read = || -> Result<Triangle, ThreeMfError> {
+            let vertices = [number(element, "v1", "triangle")?,
+                            number(element, "v2", "triangle")?,
+                            number(element, "v3", "triangle")?];
+            for i in &vertices {
+                if *i >= object.vertices.len() {
+                    return Err(ThreeMfError::IndexOutOfRange { index: *i, count: object.vertices.len() });
+                }
+                if object.bad_vertices.contains(i) {
+                    return Err(ThreeMfError::UsesBadVertex(*i));
+                }
+            }
+            // Triangles use the property group of their object unless they give their own
+            let property = match (property(element, "pid", "p1")?, attribute(element, "p1")?, &object.property) {
+                (Some(p), _, _) => Some(p),
+                (None, Some(index), Some((group, _))) => Some((group.clone(), parse(&index, "a number for p1")?)),
+                (None, _, _) => object.property.clone()
+            };
+            Ok(Triangle { vertices, property })
+        };
+        match read() {
+            Ok(triangle) => {
+                if let Some(object) = self.objects.last_mut() {
+                    object.triangles.push(triangle);
+                }
+                Ok(())
+            },
+            Err(e) => self.state.fail(e)
+        }
+    }
+
+    /// Add the faces of an object and its components to a list of faces.
+    ///
+    /// # Arguments
+    /// * `object` - The index of the object
+    /// * `transform` - Where the object is placed
+    /// * `depth` - How many objects this one is a component of
+    /// * `build` - The faces and groups made so far
+    fn place(&mut self, object: usize, transform: Transform, depth: usize, build: &mut Build) -> Result<(), ThreeMfError> {
+        let o = &self.objects[object];
+        if depth > MAX_NESTING {
+            return Err(o.location.error(ThreeMfError::TooMuchNesting(o.id.clone())));
+        }
+        if !build.budget.place(o.triangles.len()) {
+            return Err(o.location.error(ThreeMfError::TooManyPlacements));
+        }
+
+        let start = build.faces.len();
+        let mut errors = Vec::new();
+        for t in &o.triangles {
+            let [a, b, c] = t.vertices;
+            let vertex = |i: usize| transform.apply(o.vertices[i]);
+            let material = match &t.property {
+                Some(p) => match build.material(p, &self.groups) {
+                    Ok(m) => Some(m),
+                    Err(e) => {
+                        errors.push(e);
+                        None
+                    }
+                },
+                None => None
+            };
+            build.faces.push(Face::from_points(vertex(a), vertex(b), vertex(c)).with_material(material));
+        }
+        // Only report each bad property once for each object
+        let location = o.location;
+        errors.dedup_by_key(|e| e.to_string());
+        for e in errors {
+            self.state.fail_at(e, location)?;
+        }
+
+        let o = &self.objects[object];
+        if depth > 0 && build.faces.len() > start {
+            if let Some(name) = &o.name {
+                build.groups.push(Group::from_indices(name, start..build.faces.len()));
+            }
+        }
+        let components: Vec<(String, Transform, Location)> = o.components.iter()
+                                                              .map(|c| (c.id.clone(), c.transform.then(&transform), c.location))
+                                                              .collect();
+        for (id, transform, location) in components {
+            match self.objects.iter().position(|o| o.id == id) {
+                Some(inner) => self.place(inner, transform, depth + 1, build)?,
+                None => self.state.fail_at(ThreeMfError::UnknownId { kind: "object", id }, location)?
+            }
+        }
+        Ok(())
+    }
+
+    fn into_scene(mut self) -> Result<Scene, ThreeMfError> {
+        let items = std::mem::take(&mut self.items);
+        let mut build = Build {
+            faces: Vec::new(),
+            groups: Vec::new(),
+            scene: Scene::new(),
+            materials: HashMap::new(),
+            budget: Budget::default(),
+        };
+        for item in items {
+            let object = match self.objects.iter().position(|o| o.id == item.id) {
+                Some(object) => object,
+                None => {
+                    self.state.fail_at(ThreeMfError::UnknownId { kind: "object", id: item.id }, item.location)?;
+                    continue;
+                }
+            };
+            self.place(object, item.transform, 0, &mut build)?;
+
+            let o = &self.objects[object];
+            let name = o.name.clone().unwrap_or_else(|| format!("object {}", o.id));
+            let mut mesh = Mesh::new(Some(name), std::mem::take(&mut build.faces));
+            mesh.groups = std::mem::take(&mut build.groups);
+            build.scene.meshes.push(mesh);
+        }
+
+        let mut scene = build.scene;
+        if scene.face_count() == 0 {
+            return Err(ThreeMfError::NoFacesFound);
+        }
+        scene.units = self.units;
+        scene.metadata = self.metadata;
+        scene.warnings = self.state.warnings;
+        Ok(scene)
+    }
+}
+
+/// The scene being built from the build items.
+struct Build {
+    /// The faces and groups of the item being placed
+    faces: Vec<Face>,
+    groups: Vec<Group>,
+    scene: Scene,
+    /// The material made for each property
+    materials: HashMap<(String, usize), usize>,
+    budget: Budget,
+}
+
+impl Build {
+    /// The material for a property, creating it the first time the property is used.
+    ///
+    /// # Arguments
+    /// * `property` - The property group id and index into the group
+    /// * `groups` - The property groups, by id
+    fn material(&mut self, property: &(String, usize), groups: &HashMap<String, PropertyGroup>) -> Result<usize, ThreeMfError> {
+        if let Some(m) = self.materials.get(property) {
+            return Ok(*m);
+        }
+        let (id, index) = property;
+        let unknown = || ThreeMfError::UnknownId { kind: "property", id: format!("{}:{}", id, index) };
+        let material = match groups.get(id).ok_or_else(unknown)? {
+            PropertyGroup::Base(materials) => {
+                let (name, colour) = materials.get(*index).ok_or_else(unknown)?;
+                with_colour(Material::named(name), *colour)
+            },
+            PropertyGroup::Colours(colours) => {
+                let colour = colours.get(*index).ok_or_else(unknown)?;
+                with_colour(Material::named(&colour_name(colour)), *colour)
+            }
+        };
+        let m = self.scene.add_material(material);
+        self.materials.insert(property.clone(), m);
+        Ok(m)
+    }
+}
+
+/// Parse a colour in the form `#RRGGBB` or `#RRGGBBAA`.
+fn colour(text: &str) -> Option<Colour> {
+    let hex = text.strip_prefix('#')?;
+    if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+        return None;
+    }
+    let mut colour = [1.0; 4];
+    for (i, c) in colour.iter_mut().take(hex.len() / 2).enumerate() {
+        *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()? as f64 / 255.0;
+    }
+    Some(colour)
+}
+
+/// Parse a transform of 12 numbers (the rows of a 4x3 matrix).
+fn transform(text: &str) -> Result<Transform, ThreeMfError> {
+    let numbers: Vec<f64> = text.split_whitespace()
+                                .map(|n| parse(n, "a number in the transform"))
+                                .collect::<Result<_, _>>()?;
+    let mut m = [0.0; 12];
+    if numbers.len() != m.len() {
+        return Err(ThreeMfError::MissingValue("12 numbers in the transform".to_owned()));
+    }
+    m.copy_from_slice(&numbers);
+    Ok(Transform::from_rows(m))
+}
+
+/// The units named by the `unit` attribute.
+fn units(unit: &str) -> Option<Units> {
+    match unit {
+        "micron" => Some(Units::Micrometres),
+        "millimeter" => Some(Units::Millimetres),
+        "centimeter" => Some(Units::Centimetres),
+        "meter" => Some(Units::Metres),
+        "inch" => Some(Units::Inches),
+        "foot" => Some(Units::Feet),
+        _ => None
+    }
+}
+
+fn parse<T: FromStr>(token: &str, expected: &str) -> Result<T, ThreeMfError> {
+    token.parse().map_err(|_| ThreeMfError::InvalidNumber { token: token.to_owned(), expected: expected.to_owned() })
+}
+
+/// An attribute which an element must have, as a number.
+fn number<T: FromStr>(element: &BytesStart, name: &str, element_name: &str) -> Result<T, ThreeMfError> {
+    parse(&required(element, name, element_name)?, &format!("a number for {}", name))
+}
+
+/// A property (group id and index) given by a pair of attributes, if the element has one.
+///
+/// # Arguments
+/// * `element` - The element with the attributes
+/// * `group` - The attribute with the property group id
+/// * `index` - The attribute with the index into the group. This is 0 if it is missing.
+fn property(element: &BytesStart, group: &str, index: &str) -> Result<Option<(String, usize)>, ThreeMfError> {
+    match attribute(element, group)? {
+        Some(id) => {
+            let index = match attribute(element, index)? {
+                Some(i) => parse(&i, &format!("a number for {}", index))?,
+                None => 0
+            };
+            Ok(Some((id, index)))
+        },
+        None => Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Strictness, ThreeMfError, Vec3};
+    use geometry::Units;
+
+    /// A triangle, used as a component of an assembly placed twice.
+    const ASSEMBLY: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="inch" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02"
+       xmlns:m="http://schemas.microsoft.com/3dmanufacturing/material/2015/02">
+  <metadata name="Title">Test</metadata>
+  <resources>
+    <basematerials id="1">
+      <base name="Red" displaycolor="#FF0000" />
+      <base name="Glass" displaycolor="#FFFFFF80" />
+    </basematerials>
+    <m:colorgroup id="5">
+      <m:color color="#00FF00" />
+    </m:colorgroup>
+    <object id="2" type="model" name="Triangle" pid="1" pindex="1">
+      <mesh>
+        <vertices>
+          <vertex x="0" y="0" z="0" />
+          <vertex x="1" y="0" z="0" />
+          <vertex x="0" y="1" z="0" />
+        </vertices>
+        <triangles>
+          <triangle v1="0" v2="1" v3="2" />
+          <triangle v1="0" v2="2" v3="1" p1="0" />
+          <triangle v1="1" v2="2" v3="0" pid="5" p1="0" />
+        </triangles>
+      </mesh>
+    </object>
+    <object id="3" type="model" name="Assembly">
+      <components>
+        <component objectid="2" transform="1 0 0 0 1 0 0 0 1 0 0 5" />
+      </components>
+    </object>
+  </resources>
+  <build>
+    <item objectid="3" transform="1 0 0 0 1 0 0 0 1 10 0 0" />
+    <item objectid="2" />
+  </build>
+</model>
+"##;
+
+    #[test]
+    fn test_read() {
+        let scene = super::read_3mf(ASSEMBLY.as_bytes(), Strictness::Strict).unwrap();
+        assert_eq!(scene.units, Some(Units::Inches));
+        assert_eq!(scene.metadata["Title"], "Test");
+        assert_eq!(scene.meshes.len(), 2);
+        assert_eq!(scene.face_count(), 6);
+
+        // The component is moved up, then the item is moved along
+        let assembly = scene.mesh("Assembly").unwrap();
+        assert_eq!(assembly.faces[0].vertices()[1], Vec3::new(11.0, 0.0, 5.0));
+        assert_eq!(assembly.groups.len(), 1);
+        assert_eq!(assembly.groups[0].name, "Triangle");
+
+        let names: Vec<&str> = scene.mesh("Triangle").unwrap().faces.iter()
+                                    .map(|f| scene.materials[f.material().unwrap()].name.as_str())
+                                    .collect();
+        assert_eq!(names, vec!["Glass", "Red", "#00ff00"]);
+        assert_eq!(scene.materials.len(), 3);
+        let glass = scene.material_index("Glass").unwrap();
+        assert_eq!(scene.materials[glass].opacity, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn test_colour() {
+        assert_eq!(super::colour("#FF000080"), Some([1.0, 0.0, 0.0, 128.0 / 255.0]));
+        assert_eq!(super::colour("#00ff00"), Some([0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(super::colour("red"), None);
+        assert_eq!(super::colour("#FF00"), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        match super::read_3mf(b"<amf></amf>", Strictness::Strict) {
+            Err(ThreeMfError::At { line: 1, column: 1, error, .. }) =>
+                assert!(matches!(*error, ThreeMfError::NotThreeMf(_))),
+            other => panic!("Expected not 3MF, got {:?}", other)
+        }
+
+        let bad_index = ASSEMBLY.replace(r#"v3="0""#, r#"v3="3""#);
+        let e = super::read_3mf(bad_index.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<3mf>:23:11: vertex 3 does not exist, the object only has 3 vertices");
+
+        let scene = super::read_3mf(bad_index.as_bytes(), Strictness::Lenient).unwrap();
+        assert_eq!(scene.face_count(), 4);
+        assert_eq!(scene.warnings.len(), 1);
+
+        let bad_item = ASSEMBLY.replace(r#"<item objectid="2" />"#, r#"<item objectid="7" />"#);
+        let e = super::read_3mf(bad_item.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<3mf>:35:5: there is no object with the id '7'");
+
+        let bad_property = ASSEMBLY.replace(r#"pid="5" p1="0""#, r#"pid="5" p1="4""#);
+        let e = super::read_3mf(bad_property.as_bytes(), Strictness::Strict).unwrap_err();
+        assert_eq!(e.to_string(), "<3mf>:13:5: there is no property with the id '5:4'");
+    }
+
+    #[test]
+    fn test_too_many_placements() {
+        // Each object has 10 of the one before as components, so the triangle is placed millions of times
+        let mut objects = String::new();
+        for id in 4..10 {
+            let components = format!(r#"<component objectid="{}" />"#, id - 1).repeat(10);
+            objects += &format!(r#"<object id="{}"><components>{}</components></object>"#, id, components);
+        }
+        let data = ASSEMBLY.replace("</resources>", &(objects + "</resources>"))
+                           .replace(r#"<item objectid="2" />"#, r#"<item objectid="9" />"#);
+        let e = super::read_3mf(data.as_bytes(), Strictness::Lenient).unwrap_err();
+        assert!(e.to_string().ends_with("objects are placed more than 100000 times, or with more than 10000000 triangles"),
+                "{}", e);
+    }
+}
@@ -0,0 +1,615 @@
+use geometry::{CollisionDirection, Material, Plane, Ray, Vec3};
+use super::bsdf::{Bsdf, Frame};
+use super::camera::Pinhole;
+use super::environment::Environment;
+use super::film::Film;
+use super::light::{shadow_ray, Light};
+use super::path::{MisHeuristic, PathSettings};
+use super::sampler::Sampler;
+
+/// What a vertex of a path is.
+enum Kind<'a> {
+    /// The camera's pinhole
+    Camera,
+    /// A point on a light, where a light subpath starts or a camera subpath ends
+    Light(&'a Light),
+    /// A point on the model, where light is scattered
+    Surface {
+        bsdf: Box<dyn Bsdf>,
+        frame: Frame,
+        /// The direction back along the subpath, in the local frame
+        wo: Vec3,
+        /// The light given off by the surface towards the front
+        emission: Vec3,
+        /// The light the surface is part of, if it gives off light
+        light: Option<&'a Light>,
+    },
+    /// Where a camera subpath leaves the model, with the environment's light from there
+    Environment(Vec3),
+}
+
+/// A point on a subpath from the camera or from a light.
+struct Vertex<'a> {
+    kind: Kind<'a>,
+    point: Vec3,
+    /// The direction the surface faces, or zero for points which are not on a surface
+    normal: Vec3,
+    /// How much of what is carried along the subpath reaches this vertex, divided by how
+    /// likely the subpath was
+    beta: Vec3,
+    /// Whether the BSDF picked a perfectly specular direction here
+    delta: bool,
+    /// How likely the vertex was to be picked from the one before it on its subpath, per
+    /// unit area
+    pdf_fwd: f64,
+    /// How likely the vertex would have been picked from the one after it, if the subpath
+    /// had been traced from the other end, per unit area
+    pdf_rev: f64,
+}
+
+/// Everything needed to trace and connect paths.
+struct Context<'a, T: Plane> {
+    model: &'a T,
+    materials: &'a [Material],
+    lights: &'a [Light],
+    environment: Option<&'a Environment>,
+    camera: &'a Pinhole,
+    settings: &'a PathSettings,
+}
+
+impl<T: Plane> Context<'_, T> {
+    /// How likely each light is to be picked to start a light subpath.
+    fn pick_pdf(&self) -> f64 {
+        1.0 / self.lights.len().max(1) as f64
+    }
+
+    /// Whether nothing in the model is in the way between two points.
+    fn visible(&self, from: Vec3, to: Vec3) -> bool {
+        let direction = to - from;
+        let distance = direction.norm();
+        !self.model.occluded(&shadow_ray(from, direction, distance))
+    }
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: Kind<'a>, point: Vec3, normal: Vec3, beta: Vec3) -> Vertex<'a> {
+        Vertex { kind, point, normal, beta, delta: false, pdf_fwd: 0.0, pdf_rev: 0.0 }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.normal != Vec3::zeros()
+    }
+
+    /// The light this vertex is on, if it gives off light which could have been sampled.
+    fn light(&self) -> Option<&'a Light> {
+        match self.kind {
+            Kind::Light(light) => Some(light),
+            Kind::Surface { light, .. } => light,
+            _ => None
+        }
+    }
+
+    fn is_delta_light(&self) -> bool {
+        matches!(self.kind, Kind::Light(light) if light.is_point())
+    }
+
+    /// Turn how likely a direction from this vertex was, per unit solid angle, into how
+    /// likely `next` was, per unit area.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if let Kind::Environment(_) = next.kind {
+            return pdf;
+        }
+        let to_next = next.point - self.point;
+        let distance2 = to_next.norm_squared();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        let pdf = if next.is_on_surface() { pdf * next.normal.dot(&to_next).abs() / distance2.sqrt() } else { pdf };
+        pdf / distance2
+    }
+
+    /// The direction from this vertex to another, in the local frame of a surface.
+    fn local_towards(frame: &Frame, from: Vec3, to: Vec3) -> Vec3 {
+        frame.to_local(&(to - from).normalize())
+    }
+
+    /// How much light going between the vertex before this one on its subpath and `next`
+    /// is scattered here. Vertices on light subpaths carry importance back from the camera.
+    fn f(&self, next: &Vertex, from_light: bool) -> Vec3 {
+        match &self.kind {
+            Kind::Surface { bsdf, frame, wo, .. } => {
+                let wi = Vertex::local_towards(frame, self.point, next.point);
+                let f = bsdf.eval(wo, &wi);
+                if from_light { f * bsdf.importance_scale(wo, &wi) } else { f }
+            },
+            _ => Vec3::zeros()
+        }
+    }
+
+    /// How likely `next` was to be picked from this vertex, per unit area, if `prev` was
+    /// the vertex before this one.
+    fn pdf(&self, camera: &Pinhole, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = (next.point - self.point).normalize();
+        let pdf = match &self.kind {
+            Kind::Light(_) => return self.pdf_light(next),
+            Kind::Camera => camera.pdf_direction(&direction),
+            Kind::Surface { bsdf, frame, .. } => match prev {
+                Some(prev) => {
+                    let wo = Vertex::local_towards(frame, self.point, prev.point);
+                    bsdf.pdf(&wo, &frame.to_local(&direction))
+                },
+                None => 0.0
+            },
+            Kind::Environment(_) => 0.0
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// How likely `next` was to be picked, per unit area, as the first bounce of a light
+    /// subpath starting at this vertex.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let light = match self.light() {
+            Some(light) => light,
+            None => return 0.0
+        };
+        let to_next = next.point - self.point;
+        let distance2 = to_next.norm_squared();
+        let direction = to_next / distance2.sqrt();
+        let (_, pdf_direction) = light.pdf_emission(self.normal, &direction);
+        let pdf = pdf_direction / distance2;
+        if next.is_on_surface() { pdf * next.normal.dot(&direction).abs() } else { pdf }
+    }
+
+    /// How likely this vertex was to be picked as the start of a light subpath, per unit area.
+    fn pdf_light_origin(&self, pick_pdf: f64) -> f64 {
+        match self.light() {
+            Some(light) => light.pdf_emission(self.normal, &self.normal).0 * pick_pdf,
+            None => 0.0
+        }
+    }
+
+    /// The light given off by this vertex towards another.
+    fn le(&self, to: &Vertex) -> Vec3 {
+        let emission = match &self.kind {
+            Kind::Environment(radiance) => return *radiance,
+            Kind::Light(light) if !light.is_point() => light.emission,
+            Kind::Surface { emission, .. } => *emission,
+            _ => return Vec3::zeros()
+        };
+        if self.normal.dot(&(to.point - self.point)) > 0.0 { emission } else { Vec3::zeros() }
+    }
+}
+
+/// Follow a subpath from its first vertex, adding the vertices it bounces off to `path`.
+///
+/// # Arguments
+/// * `context` - The scene
+/// * `ray` - The ray leaving the last vertex of `path`
+/// * `beta` - What the ray carries, divided by how likely it was
+/// * `pdf` - How likely the ray's direction was, per unit solid angle
+/// * `sampler` - Where the random numbers come from
+/// * `max_vertices` - The most vertices to add
+/// * `from_light` - Whether the subpath starts at a light
+/// * `path` - The subpath so far
+#[allow(clippy::too_many_arguments)]
+fn random_walk<'a, T: Plane>(context: &Context<'a, T>, ray: Ray, beta: Vec3, pdf: f64, sampler: &mut dyn Sampler,
+                             max_vertices: usize, from_light: bool, path: &mut Vec<Vertex<'a>>) {
+    let mut ray = ray;
+    let mut beta = beta;
+    let mut pdf_fwd = pdf;
+    let start = path.len();
+    while path.len() - start < max_vertices && beta != Vec3::zeros() {
+        let hit = context.model.hits(&ray);
+        let hit_distance = hit.as_ref().map_or(f64::INFINITY, |c| c.distance);
+
+        // Lights which are not part of the model end camera subpaths, and absorb light
+        let light_hit = context.lights.iter()
+                                      .filter_map(|l| l.intersect(&ray).map(|(t, normal)| (l, t, normal)))
+                                      .filter(|(_, t, _)| *t < hit_distance)
+                                      .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((light, t, normal)) = light_hit {
+            if !from_light {
+                let mut vertex = Vertex::new(Kind::Light(light), ray.at(t), normal, beta);
+                vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+                path.push(vertex);
+            }
+            break;
+        }
+
+        let c = match hit {
+            Some(c) => c,
+            None => {
+                if let (false, Some(environment)) = (from_light, context.environment) {
+                    let radiance = environment.radiance(&ray.direction);
+                    let mut vertex = Vertex::new(Kind::Environment(radiance), ray.origin + ray.direction, Vec3::zeros(), beta);
+                    vertex.pdf_fwd = pdf_fwd;
+                    path.push(vertex);
+                }
+                break;
+            }
+        };
+
+        let default_material = Material::default();
+        let material = c.material.and_then(|m| context.materials.get(m)).unwrap_or(&default_material);
+        let bsdf = context.settings.bsdf.create(material, context.settings.roughness);
+        let wo_world = -ray.direction;
+        let normal = c.normal.normalize();
+        let normal = if !bsdf.transmits() && normal.dot(&wo_world) < 0.0 { -normal } else { normal };
+        let frame = Frame::from_normal(normal);
+        let wo = frame.to_local(&wo_world);
+        let (emission, light) = if material.emission != Vec3::zeros() && c.direction == CollisionDirection::FrontFace {
+            (material.emission, c.material.and_then(|m| context.lights.iter().find(|l| l.material == Some(m))))
+        } else {
+            (Vec3::zeros(), None)
+        };
+
+        let sample = bsdf.sample(&wo, sampler.next_2d(), sampler.next_1d());
+        let scale = sample.map_or(1.0, |s| bsdf.importance_scale(&wo, &s.wi));
+        let pdf_rev = sample.map_or(0.0, |s| bsdf.pdf(&s.wi, &wo));
+
+        let mut vertex = Vertex::new(Kind::Surface { bsdf, frame, wo, emission, light }, c.contact_point, normal, beta);
+        vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+        if path.len() - start >= max_vertices {
+            break;
+        }
+
+        let sample = match sample {
+            Some(s) if s.pdf > 0.0 => s,
+            _ => break
+        };
+        let f = if from_light { sample.f * scale } else { sample.f };
+        beta = beta.component_mul(&(f * (sample.wi.z.abs() / sample.pdf)));
+        // Specular bounces can only be found by following them, so have no density to compare
+        let specular = sample.specular;
+        let pdf_rev = if specular { 0.0 } else { pdf_rev };
+        pdf_fwd = if specular { 0.0 } else { sample.pdf };
+        ray = Ray::leaving(c.contact_point, frame.to_world(&sample.wi));
+
+        let n = path.len();
+        let (before, current) = path.split_at_mut(n - 1);
+        current[0].delta = specular;
+        before[n - 2].pdf_rev = current[0].convert_density(pdf_rev, &before[n - 2]);
+    }
+}
+
+/// Join the first `s` vertices of the light subpath to the first `t` of the camera subpath,
+/// giving the light that path carries to the camera, weighted by multiple importance
+/// sampling. Paths which only use the camera's vertex land somewhere else in the image, so
+/// also give where.
+fn connect<T: Plane>(context: &Context<T>, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize,
+                     sampler: &mut dyn Sampler) -> (Vec3, Option<(f64, f64)>) {
+    let pt = &camera_path[t - 1];
+    if t > 1 && s != 0 && matches!(pt.kind, Kind::Light(_) | Kind::Environment(_)) {
+        return (Vec3::zeros(), None);
+    }
+
+    // The light or camera vertex picked to connect to, for the strategies which pick one
+    let mut sampled = None;
+    let mut raster = None;
+    let light = if s == 0 {
+        // The camera subpath found a light by itself
+        match pt.kind {
+            Kind::Camera => Vec3::zeros(),
+            _ => pt.le(&camera_path[t - 2]).component_mul(&pt.beta)
+        }
+    } else if t == 1 {
+        // Light tracing: join the light subpath to the camera
+        let qs = &light_path[s - 1];
+        match context.camera.raster(qs.point) {
+            Some(position) if context.visible(qs.point, context.camera.position) => {
+                let to_camera = context.camera.position - qs.point;
+                let distance2 = to_camera.norm_squared();
+                let direction = -to_camera / distance2.sqrt();
+                let we = context.camera.importance(&direction) * direction.dot(&context.camera.forwards) / distance2;
+                let camera = Vertex::new(Kind::Camera, context.camera.position, Vec3::zeros(), Vec3::repeat(we));
+                let mut light = qs.beta.component_mul(&qs.f(&camera, true)).component_mul(&camera.beta);
+                if qs.is_on_surface() {
+                    light *= qs.normal.dot(&direction).abs();
+                }
+                raster = Some(position);
+                sampled = Some(camera);
+                light
+            },
+            _ => Vec3::zeros()
+        }
+    } else if s == 1 {
+        // Next event estimation: join the camera subpath to a new point on a light
+        let count = context.lights.len();
+        let pick = ((sampler.next_1d() * count as f64) as usize).min(count - 1);
+        let light = &context.lights[pick];
+        let sample = light.sample(pt.point, sampler.next_2d());
+        if !light.shines_towards(&sample, pt.point) || !context.visible(pt.point, sample.point) {
+            return (Vec3::zeros(), None);
+        }
+        let to_light = sample.point - pt.point;
+        let distance2 = to_light.norm_squared();
+        let direction = to_light / distance2.sqrt();
+        // Point lights fall off with the square of the distance
+        let (emission, pdf) = match sample.normal {
+            Some(normal) => (light.emission, light.pdf(pt.point, sample.point, normal)),
+            None => (light.emission / distance2, 1.0)
+        };
+        if pdf == 0.0 {
+            return (Vec3::zeros(), None);
+        }
+        let mut vertex = Vertex::new(Kind::Light(light), sample.point, sample.normal.unwrap_or_else(Vec3::zeros),
+                                     emission / (pdf * context.pick_pdf()));
+        vertex.pdf_fwd = vertex.pdf_light_origin(context.pick_pdf());
+        let mut light = pt.beta.component_mul(&pt.f(&vertex, false)).component_mul(&vertex.beta);
+        if pt.is_on_surface() {
+            light *= pt.normal.dot(&direction).abs();
+        }
+        sampled = Some(vertex);
+        light
+    } else {
+        // Join the ends of the two subpaths
+        let qs = &light_path[s - 1];
+        let light = qs.beta.component_mul(&qs.f(pt, true)).component_mul(&pt.f(qs, false)).component_mul(&pt.beta);
+        if light == Vec3::zeros() || !context.visible(qs.point, pt.point) {
+            return (Vec3::zeros(), None);
+        }
+        let to_pt = pt.point - qs.point;
+        let distance2 = to_pt.norm_squared();
+        let direction = to_pt / distance2.sqrt();
+        light * (qs.normal.dot(&direction).abs() * pt.normal.dot(&direction).abs() / distance2)
+    };
+
+    if light == Vec3::zeros() {
+        return (light, None);
+    }
+    (light * mis_weight(context, light_path, camera_path, sampled.as_ref(), s, t), raster)
+}
+
+/// How much a path made by joining `s` light vertices to `t` camera vertices counts, given
+/// the other ways the same path could have been made.
+fn mis_weight<T: Plane>(context: &Context<T>, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>,
+                        s: usize, t: usize) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+    // The vertices of the path, with the one picked for the connection in place
+    let mut lights: Vec<&Vertex> = light_path[..s].iter().collect();
+    let mut cameras: Vec<&Vertex> = camera_path[..t].iter().collect();
+    match sampled {
+        Some(v) if s == 1 => lights[0] = v,
+        Some(v) if t == 1 => cameras[0] = v,
+        _ => ()
+    }
+    let pt = cameras[t - 1];
+    let pt_minus = if t > 1 { Some(cameras[t - 2]) } else { None };
+    let qs = if s > 0 { Some(lights[s - 1]) } else { None };
+    let qs_minus = if s > 1 { Some(lights[s - 2]) } else { None };
+
+    // Light from the environment, or from surfaces which are not lights, can only be found
+    // by the camera subpath
+    if s == 0 && pt.light().is_none() {
+        return 1.0;
+    }
+
+    // How likely each vertex is each way, and whether it is specular
+    let mut camera_pdfs: Vec<(f64, f64, bool)> = cameras.iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+    let mut light_pdfs: Vec<(f64, f64, bool)> = lights.iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+
+    // The vertices at the connection are joined directly, so are not specular
+    camera_pdfs[t - 1].2 = false;
+    camera_pdfs[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(context.camera, qs_minus, pt),
+        None => pt.pdf_light_origin(context.pick_pdf())
+    };
+    if let Some(pt_minus) = pt_minus {
+        camera_pdfs[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(context.camera, Some(qs), pt_minus),
+            None => pt.pdf_light(pt_minus)
+        };
+    }
+    if let Some(qs) = qs {
+        light_pdfs[s - 1].2 = false;
+        light_pdfs[s - 1].1 = pt.pdf(context.camera, pt_minus, qs);
+    }
+    if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+        light_pdfs[s - 2].1 = qs.pdf(context.camera, Some(pt), qs_minus);
+    }
+
+    // Zero probabilities come from specular vertices, which are skipped
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let heuristic = |ratio: f64| match context.settings.heuristic {
+        MisHeuristic::Balance => ratio,
+        MisHeuristic::Power => ratio * ratio,
+    };
+
+    // The ways which use fewer camera vertices and more light ones, then the other way round
+    let mut total = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+            total += heuristic(ratio);
+        }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        let delta_light = if i > 0 { light_pdfs[i - 1].2 } else { lights[0].is_delta_light() };
+        if !light_pdfs[i].2 && !delta_light {
+            total += heuristic(ratio);
+        }
+    }
+    1.0 / (1.0 + total)
+}
+
+/// Trace a subpath from the camera and one from a light, and join every vertex of one to
+/// every vertex of the other. Each join is another way of making a path from the light to
+/// the camera, and multiple importance sampling weights each by how well it finds that
+/// path. Paths from the lights can find light focused by glass or mirrors (caustics),
+/// which paths from the camera almost never do.
+///
+/// Returns the light through the camera ray's pixel. Paths joined straight to the camera
+/// land anywhere in the image, so are splatted onto the film instead. Light from the
+/// environment is only found by camera subpaths.
+///
+/// # Arguments
+/// * `ray` - The ray from the camera
+/// * `model` - The model
+/// * `materials` - The materials used by the model
+/// * `lights` - The lights
+/// * `environment` - Light from far away, if there is any
+/// * `camera` - The camera the ray came from
+/// * `sampler` - Where the random numbers come from
+/// * `settings` - How to trace. Paths bounce at most `max_depth` times.
+/// * `film` - Where to splat light traced to the camera
+#[allow(clippy::too_many_arguments)]
+pub fn trace<T: Plane>(ray: &Ray, model: &T, materials: &[Material], lights: &[Light],
+                       environment: Option<&Environment>, camera: &Pinhole, sampler: &mut dyn Sampler,
+                       settings: &PathSettings, film: &mut Film) -> Vec3 {
+    let context = Context { model, materials, lights, environment, camera, settings };
+    let max_depth = settings.max_depth as usize;
+
+    let mut camera_path = vec![Vertex::new(Kind::Camera, camera.position, Vec3::zeros(), Vec3::repeat(1.0))];
+    let pdf = camera.pdf_direction(&ray.direction);
+    random_walk(&context, ray.clone(), Vec3::repeat(1.0), pdf, sampler, max_depth + 1, false, &mut camera_path);
+
+    let mut light_path = Vec::new();
+    if !lights.is_empty() {
+        let pick = ((sampler.next_1d() * lights.len() as f64) as usize).min(lights.len() - 1);
+        let light = &lights[pick];
+        let emitted = light.sample_emission(sampler.next_2d(), sampler.next_2d());
+        if emitted.pdf_position > 0.0 && emitted.pdf_direction > 0.0 {
+            let normal = emitted.normal.unwrap_or_else(Vec3::zeros);
+            let mut vertex = Vertex::new(Kind::Light(light), emitted.point, normal, light.emission);
+            vertex.pdf_fwd = emitted.pdf_position * context.pick_pdf();
+            light_path.push(vertex);
+            let cos = emitted.normal.map_or(1.0, |n| n.dot(&emitted.direction).abs());
+            let beta = light.emission * (cos / (context.pick_pdf() * emitted.pdf_position * emitted.pdf_direction));
+            random_walk(&context, Ray::leaving(emitted.point, emitted.direction), beta, emitted.pdf_direction,
+                        sampler, max_depth, true, &mut light_path);
+        }
+    }
+
+    let mut radiance = Vec3::zeros();
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            let depth = s + t;
+            if (s == 1 && t == 1) || depth < 2 || depth - 2 > max_depth {
+                continue;
+            }
+            let (light, raster) = connect(&context, &light_path, &camera_path, s, t, sampler);
+            match raster {
+                Some((x, y)) => film.add_splat(x, y, light),
+                None => radiance += light
+            }
+        }
+    }
+    radiance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trace;
+    use crate::bsdf::BsdfKind;
+    use crate::environment::Environment;
+    use crate::film::{Film, Filter};
+    use crate::light::Light;
+    use crate::path::{self, MisHeuristic, PathSettings};
+    use crate::sampler::SamplerKind;
+    use crate::test_scene::{camera, settings, square, MIDDLE};
+    use geometry::{Face, Material, Vec3};
+    use std::f64::consts::PI;
+
+    /// Render the camera's view, returning the middle of it
+    fn render(model: &Vec<Face>, materials: &[Material], lights: &[Light], environment: Option<&Environment>,
+              samples: usize, settings: &PathSettings) -> Vec3 {
+        let camera = camera();
+        let mut film = Film::new(4, 4, Filter::Box).with_splat_scale(1.0 / samples as f64);
+        let mut sampler = SamplerKind::Sobol.create(samples);
+        for y in 0..4 {
+            for x in 0..4 {
+                for i in 0..samples {
+                    sampler.start_sample(x, y, i);
+                    let (dx, dy) = sampler.next_2d();
+                    let (px, py) = (x as f64 + dx, y as f64 + dy);
+                    let ray = camera.ray(px, py);
+                    let colour = trace(&ray, model, materials, lights, environment, &camera, sampler.as_mut(),
+                                       settings, &mut film);
+                    film.add_sample(px, py, colour);
+                }
+            }
+        }
+        MIDDLE.iter().map(|(x, y)| film.pixel(*x, *y)).sum::<Vec3>() / 4.0
+    }
+
+    /// The middle of the camera's view, from the path tracer
+    fn path_traced(model: &Vec<Face>, materials: &[Material], lights: &[Light], samples: usize,
+                   settings: &PathSettings) -> Vec3 {
+        let camera = camera();
+        let mut sampler = SamplerKind::Sobol.create(samples);
+        let mut total = Vec3::zeros();
+        for (x, y) in &MIDDLE {
+            for i in 0..samples {
+                sampler.start_sample(*x, *y, i);
+                let (dx, dy) = sampler.next_2d();
+                let ray = camera.ray(*x as f64 + dx, *y as f64 + dy);
+                total += path::trace(&ray, model, materials, lights, None, sampler.as_mut(), settings);
+            }
+        }
+        total / (4 * samples) as f64
+    }
+
+    #[test]
+    fn test_matches_path_tracer() {
+        // A disc light over a grey floor, seen by the camera. Light traced from the light
+        // and splatted should add up to the same as tracing from the camera.
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        let lights = vec![Light::disc(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.5)];
+        let model = square(0.0, 0);
+        let expected = path_traced(&model, &materials, &lights, 1024, &settings(MisHeuristic::Power, BsdfKind::Lambertian)).x;
+        for heuristic in &[MisHeuristic::Balance, MisHeuristic::Power] {
+            let radiance = render(&model, &materials, &lights, None, 256, &settings(*heuristic, BsdfKind::Lambertian));
+            assert!((radiance.x - expected).abs() < 0.03 * expected, "{:?} {} {}", heuristic, radiance.x, expected);
+        }
+    }
+
+    #[test]
+    fn test_through_glass() {
+        // The floor lit through a sheet of smooth glass, which the path tracer can only see
+        // through by following bounces and the bidirectional one by tracing from the light
+        let mut glass = Material::named("glass");
+        glass.opacity = 0.0;
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5)), glass];
+        let lights = vec![Light::disc(Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0), 1.0)];
+        let mut model = square(0.0, 0);
+        model.extend(square(2.0, 1));
+        let settings = settings(MisHeuristic::Power, BsdfKind::Model);
+        let expected = path_traced(&model, &materials, &lights, 4096, &settings).x;
+        let radiance = render(&model, &materials, &lights, None, 512, &settings);
+        assert!(expected > 0.0);
+        assert!((radiance.x - expected).abs() < 0.05 * expected, "{} {}", radiance.x, expected);
+    }
+
+    #[test]
+    fn test_white_environment() {
+        // The environment is only found by the camera subpaths
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        let sky = Environment::new(2, 2, vec![Vec3::repeat(1.0); 4]);
+        let radiance = render(&square(0.0, 0), &materials, &[], Some(&sky), 64,
+                              &settings(MisHeuristic::Power, BsdfKind::Lambertian));
+        assert!((radiance.x - 0.5).abs() < 0.03, "{}", radiance.x);
+    }
+
+    #[test]
+    fn test_point_light() {
+        // Point lights can only be found by light sampling and light tracing
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        let lights = vec![Light::point(Vec3::new(0.0, 0.0, 0.5)).with_emission(Vec3::repeat(0.25))];
+        let radiance = render(&square(0.0, 0), &materials, &lights, None, 64,
+                              &settings(MisHeuristic::Balance, BsdfKind::Lambertian));
+        // albedo / pi * intensity * cos / distance squared, averaged over the floor seen
+        let n = 100;
+        let expected = (0..n * n).map(|i| {
+            let (x, y) = (((i % n) as f64 + 0.5) / n as f64 - 0.5, ((i / n) as f64 + 0.5) / n as f64 - 0.5);
+            let distance2 = x * x + y * y + 0.25;
+            0.5 / PI * 0.25 * 0.5 / distance2.powf(1.5)
+        }).sum::<f64>() / (n * n) as f64;
+        assert!((radiance.x - expected).abs() < 0.03 * expected, "{} {}", radiance.x, expected);
+    }
+}
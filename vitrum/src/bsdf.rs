@@ -0,0 +1,888 @@
+use geometry::{Material, Vec3};
+use std::f64::consts::PI;
+
+use enum_from_str::ParseEnumVariantError;
+use enum_from_str_derive::FromStr;
+
+/// Directions at a point on a surface, with the normal as z. BSDFs work with directions
+/// in this frame, pointing away from the surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    pub s: Vec3,
+    pub t: Vec3,
+    pub n: Vec3,
+}
+
+impl Frame {
+    /// A frame around a normal, which must be normalised.
+    pub fn from_normal(n: Vec3) -> Frame {
+        let other = if n.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let s = n.cross(&other).normalize();
+        Frame { s, t: n.cross(&s), n }
+    }
+
+    pub fn to_local(self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(self, v: &Vec3) -> Vec3 {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+/// A direction picked by a BSDF.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BsdfSample {
+    /// The direction light arrives from, in the local frame
+    pub wi: Vec3,
+    /// The value of the BSDF for the pair of directions
+    pub f: Vec3,
+    /// How likely the direction was to be picked, per unit solid angle. For perfectly
+    /// specular directions it is the chance of picking that kind of reflection instead.
+    pub pdf: f64,
+    /// Whether the direction is the only one possible, as in a mirror
+    pub specular: bool,
+}
+
+/// How light is scattered by a surface.
+///
+/// All directions are in the surface's local `Frame` and point away from the surface: `wo`
+/// towards the viewer and `wi` towards where the light comes from. Values do not include
+/// the cosine of the angle between `wi` and the normal.
+pub trait Bsdf: std::fmt::Debug {
+    /// How much of the light arriving from `wi` leaves towards `wo`. Perfectly specular
+    /// reflection and transmission are only found by `sample`, so give zero here.
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3;
+
+    /// How likely `sample` is to pick `wi` given `wo`, per unit solid angle.
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64;
+
+    /// Pick a direction for the light to come from, in proportion to how much it adds.
+    /// Returns `None` if no light can be scattered towards `wo`.
+    ///
+    /// # Arguments
+    /// * `wo` - The direction towards the viewer
+    /// * `u` - A point in the unit square, from a `Sampler`
+    /// * `lobe` - A number in [0, 1) for choosing between kinds of reflection
+    fn sample(&self, wo: &Vec3, u: (f64, f64), lobe: f64) -> Option<BsdfSample>;
+
+    /// Whether light can go through the surface. Surfaces which only reflect need their
+    /// normal facing the viewer, but ones which let light through need it facing out.
+    fn transmits(&self) -> bool {
+        false
+    }
+
+    /// Whether the surface only scatters light in perfectly specular directions, so light
+    /// can only be found by following `sample`.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// How much more the BSDF is when following light from its source rather than back from
+    /// the viewer. Refraction squeezes radiance into a smaller solid angle, which changes it
+    /// by the square of the ratio of refractive indices, but the amount of light is the same.
+    fn importance_scale(&self, _wo: &Vec3, _wi: &Vec3) -> f64 {
+        1.0
+    }
+}
+
+/// The kinds of surface, for picking one on the command line.
+#[derive(Debug, Copy, Clone, PartialEq, FromStr)]
+pub enum BsdfKind {
+    // A principled material from each of the model's materials, or glass if it is see through
+    Model,
+    Lambertian,
+    OrenNayar,
+    Principled,
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Glass,
+}
+
+impl BsdfKind {
+    /// Make the BSDF for a surface.
+    ///
+    /// # Arguments
+    /// * `material` - The surface's material, which gives the colour
+    /// * `roughness` - How rough to make the surface in [0, 1], instead of the material's own
+    ///   roughness or the default for the kind
+    pub fn create(&self, material: &Material, roughness: Option<f64>) -> Box<dyn Bsdf> {
+        let principled = Principled::from_material(material);
+        let roughness = roughness.unwrap_or(match self {
+            BsdfKind::Model | BsdfKind::Principled => principled.roughness,
+            BsdfKind::Glass => 0.0,
+            _ => 0.3,
+        });
+        match self {
+            BsdfKind::Model if material.opacity < 1.0 => Box::new(Dielectric::new(1.5, roughness)),
+            BsdfKind::Model | BsdfKind::Principled => Box::new(Principled { roughness, ..principled }),
+            BsdfKind::Lambertian => Box::new(Lambertian { albedo: material.diffuse }),
+            // Roughness 1 is about as rough as real matte surfaces get
+            BsdfKind::OrenNayar => Box::new(OrenNayar::new(material.diffuse, 40.0 * roughness)),
+            BsdfKind::Gold => Box::new(Conductor::gold(roughness)),
+            BsdfKind::Silver => Box::new(Conductor::silver(roughness)),
+            BsdfKind::Copper => Box::new(Conductor::copper(roughness)),
+            BsdfKind::Aluminium => Box::new(Conductor::aluminium(roughness)),
+            BsdfKind::Glass => Box::new(Dielectric::new(1.5, roughness)),
+        }
+    }
+}
+
+fn same_hemisphere(a: &Vec3, b: &Vec3) -> bool {
+    a.z * b.z > 0.0
+}
+
+fn reflect(wo: &Vec3, n: &Vec3) -> Vec3 {
+    -wo + n * (2.0 * wo.dot(n))
+}
+
+/// Bend `wo` through a surface with normal `n`, where `eta` is the refractive index of the
+/// side `n` points away from over the side it points to. Returns the direction and the
+/// ratio of refractive indices actually crossed, or `None` for total internal reflection.
+fn refract(wo: &Vec3, n: &Vec3, eta: f64) -> Option<(Vec3, f64)> {
+    let (mut n, mut eta, mut cos_o) = (*n, eta, wo.dot(n));
+    if cos_o < 0.0 {
+        eta = 1.0 / eta;
+        cos_o = -cos_o;
+        n = -n;
+    }
+    let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-wo / eta + n * (cos_o / eta - cos_t), eta))
+}
+
+/// Cosine weighted directions around the normal, which is what a matte surface reflects.
+pub fn sample_cosine(u: (f64, f64)) -> Vec3 {
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+/// The fraction of light reflected at the boundary between two clear materials. The rest
+/// goes through.
+///
+/// # Arguments
+/// * `cos_i` - The cosine of the angle to the normal, negative from inside
+/// * `eta` - The refractive index inside over the one outside
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// The fraction of light reflected by a metal, for each channel.
+///
+/// # Arguments
+/// * `cos_i` - The cosine of the angle to the normal
+/// * `eta` - The real part of the metal's refractive index
+/// * `k` - The imaginary part (how strongly light is absorbed)
+pub fn fresnel_conductor(cos_i: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        (r_p + r_s) / 2.0
+    };
+    Vec3::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+/// Schlick's approximation to the Fresnel reflectance.
+///
+/// # Arguments
+/// * `f0` - The reflectance looking straight at the surface
+/// * `cos_i` - The cosine of the angle to the normal
+pub fn fresnel_schlick(f0: &Vec3, cos_i: f64) -> Vec3 {
+    let m = (1.0 - cos_i).clamp(0.0, 1.0);
+    f0 + (Vec3::repeat(1.0) - f0) * (m * m * m * m * m)
+}
+
+/// The GGX (Trowbridge-Reitz) spread of microfacet normals, which gives a sharp highlight
+/// with a long tail.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    /// The same spread in every direction, from a perceptual roughness in [0, 1].
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        Ggx { alpha_x: alpha, alpha_y: alpha }
+    }
+
+    /// Whether the surface is so smooth it is treated as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    /// The density of microfacets facing along `h`.
+    pub fn d(&self, h: &Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (h.x / self.alpha_x).powi(2) + (h.y / self.alpha_y).powi(2) + h.z * h.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY;
+        }
+        let alpha2_tan2 = ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / (w.z * w.z);
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// The fraction of microfacets which can be seen from `w`.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets which can be seen from both `wo` and `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// The density of microfacets facing along `h` which can be seen from `w`.
+    pub fn visible_d(&self, w: &Vec3, h: &Vec3) -> f64 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) * w.dot(h).abs() * self.d(h) / w.z.abs()
+    }
+
+    /// Pick a microfacet normal which can be seen from `w`, with density `visible_d`.
+    /// From Heitz's "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, w: &Vec3, u: (f64, f64)) -> Vec3 {
+        // Stretch the view so the microfacets are a hemisphere
+        let mut wh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let length2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if length2 > 0.0 { Vec3::new(-wh.y, wh.x, 0.0) / length2.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t2 = wh.cross(&t1);
+
+        // A point on the part of the disc that can be seen
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+/// A matte surface which scatters light evenly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lambertian {
+    pub albedo: Vec3,
+}
+
+impl Bsdf for Lambertian {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        self.albedo / PI
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 { 0.0 } else { wi.z / PI }
+    }
+
+    fn sample(&self, wo: &Vec3, u: (f64, f64), _lobe: f64) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wi = sample_cosine(u);
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf: self.pdf(wo, &wi), specular: false })
+    }
+}
+
+/// A rough matte surface, such as clay or the moon, which looks flatter than a Lambertian
+/// one. From Oren and Nayar's qualitative model.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OrenNayar {
+    pub albedo: Vec3,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    /// # Arguments
+    /// * `albedo` - The fraction of light reflected, per channel
+    /// * `sigma` - How rough the surface is, as the spread of facet angles in degrees
+    pub fn new(albedo: Vec3, sigma: f64) -> OrenNayar {
+        let sigma2 = sigma.to_radians().powi(2);
+        OrenNayar {
+            albedo,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Bsdf for OrenNayar {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        // alpha is the larger of the two angles to the normal, beta the smaller
+        let (sin_alpha, tan_beta) = if wi.z > wo.z { (sin_o, sin_i / wi.z) } else { (sin_i, sin_o / wo.z) };
+        self.albedo / PI * (self.a + self.b * max_cos * sin_alpha * tan_beta)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 { 0.0 } else { wi.z / PI }
+    }
+
+    fn sample(&self, wo: &Vec3, u: (f64, f64), _lobe: f64) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wi = sample_cosine(u);
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf: self.pdf(wo, &wi), specular: false })
+    }
+}
+
+/// A metal, which reflects light tinted by its colour and absorbs the rest.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Conductor {
+    /// The real part of the refractive index, per channel
+    pub eta: Vec3,
+    /// The imaginary part of the refractive index, per channel
+    pub k: Vec3,
+    pub distribution: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f64) -> Conductor {
+        Conductor { eta, k, distribution: Ggx::from_roughness(roughness) }
+    }
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147), roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837), roughness)
+    }
+}
+
+impl Bsdf for Conductor {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        let h = (wo + wi).normalize();
+        let f = fresnel_conductor(wi.dot(&h), &self.eta, &self.k);
+        f * (self.distribution.d(&h) * self.distribution.g(wo, wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        self.distribution.visible_d(wo, &h) / (4.0 * wo.dot(&h))
+    }
+
+    fn sample(&self, wo: &Vec3, u: (f64, f64), _lobe: f64) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            let f = fresnel_conductor(wi.z, &self.eta, &self.k) / wi.z;
+            return Some(BsdfSample { wi, f, pdf: 1.0, specular: true });
+        }
+        let h = self.distribution.sample_visible(wo, u);
+        let wi = reflect(wo, &h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf: self.pdf(wo, &wi), specular: false })
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+}
+
+/// A clear material such as glass or water, which reflects some light and lets the rest
+/// through, bending it. The normal points out of the material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Dielectric {
+    /// The refractive index inside over the one outside
+    pub eta: f64,
+    pub distribution: Ggx,
+}
+
+impl Dielectric {
+    pub fn new(eta: f64, roughness: f64) -> Dielectric {
+        Dielectric { eta, distribution: Ggx::from_roughness(roughness) }
+    }
+
+    /// The microfacet normal which takes `wo` to `wi`, facing out, and the ratio of
+    /// refractive indices crossed (1 for reflection). `None` if there is no such microfacet.
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<(Vec3, f64)> {
+        let reflect = same_hemisphere(wo, wi);
+        let etap = if reflect { 1.0 } else if wo.z > 0.0 { self.eta } else { 1.0 / self.eta };
+        let wm = wi * etap + wo;
+        if wi.z == 0.0 || wo.z == 0.0 || wm.norm_squared() == 0.0 {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+        // Microfacets facing away from either direction cannot be used
+        if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if self.distribution.is_smooth() {
+            return Vec3::zeros();
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return Vec3::zeros()
+        };
+        let f = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let dg = self.distribution.d(&wm) * self.distribution.g(wo, wi);
+        if etap == 1.0 {
+            Vec3::repeat(dg * f / (4.0 * wi.z * wo.z).abs())
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2) * wi.z * wo.z;
+            // Light is squeezed into a smaller solid angle as it enters a denser material
+            let t = dg * (1.0 - f) * (wi.dot(&wm) * wo.dot(&wm) / denominator).abs() / (etap * etap);
+            Vec3::repeat(t)
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0
+        };
+        let r = fresnel_dielectric(wo.dot(&wm), self.eta);
+        if etap == 1.0 {
+            self.distribution.visible_d(wo, &wm) / (4.0 * wo.dot(&wm).abs()) * r
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / etap).powi(2);
+            self.distribution.visible_d(wo, &wm) * wi.dot(&wm).abs() / denominator * (1.0 - r)
+        }
+    }
+
+    fn sample(&self, wo: &Vec3, u: (f64, f64), lobe: f64) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            let r = fresnel_dielectric(wo.z, self.eta);
+            return if lobe < r {
+                let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                Some(BsdfSample { wi, f: Vec3::repeat(r / wi.z.abs()), pdf: r, specular: true })
+            } else {
+                let (wi, etap) = refract(wo, &Vec3::new(0.0, 0.0, 1.0), self.eta)?;
+                let f = (1.0 - r) / wi.z.abs() / (etap * etap);
+                Some(BsdfSample { wi, f: Vec3::repeat(f), pdf: 1.0 - r, specular: true })
+            };
+        }
+
+        let wm = self.distribution.sample_visible(wo, u);
+        let r = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let wi = if lobe < r {
+            let wi = reflect(wo, &wm);
+            if !same_hemisphere(wo, &wi) {
+                return None;
+            }
+            wi
+        } else {
+            let (wi, _) = refract(wo, &wm, self.eta)?;
+            if same_hemisphere(wo, &wi) || wi.z == 0.0 {
+                return None;
+            }
+            wi
+        };
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf, specular: false })
+    }
+
+    fn transmits(&self) -> bool {
+        true
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+
+    fn importance_scale(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if same_hemisphere(wo, wi) {
+            1.0
+        } else {
+            let etap = if wo.z > 0.0 { self.eta } else { 1.0 / self.eta };
+            etap * etap
+        }
+    }
+}
+
+/// A single material which can be anything from matte plastic to polished metal, after
+/// Burley's "Physically Based Shading at Disney". Made of a diffuse base, a GGX specular
+/// layer and a clear coat on top.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Principled {
+    pub base_colour: Vec3,
+    /// 0 for a dielectric, 1 for a metal tinted by the base colour
+    pub metallic: f64,
+    /// 0 is polished, 1 is completely rough
+    pub roughness: f64,
+    /// How much a dielectric reflects looking straight at it. 0.5 is 4%, as for most plastics.
+    pub specular: f64,
+    /// How much of a clear varnish layer there is
+    pub clearcoat: f64,
+    /// 0 is a satin clear coat, 1 is a gloss one
+    pub clearcoat_gloss: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_colour: Vec3::repeat(0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+        }
+    }
+}
+
+/// The GTR1 distribution used by the clear coat, which has an even longer tail than GGX.
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+impl Principled {
+    /// A principled material close to how a model file's material looks.
+    pub fn from_material(material: &Material) -> Principled {
+        // A Phong exponent of n has about the same highlight as a GGX alpha of
+        // sqrt(2 / (n + 2)), and roughness is the square root of alpha
+        let alpha = (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt();
+        Principled {
+            base_colour: material.diffuse,
+            roughness: alpha.sqrt(),
+            specular: (material.specular.x + material.specular.y + material.specular.z) / 6.0,
+            ..Principled::default()
+        }
+    }
+
+    fn specular_distribution(&self) -> Ggx {
+        // Perfectly smooth highlights would need a separate specular path
+        Ggx::from_roughness(self.roughness.max(0.03))
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 + (0.001 - 0.1) * self.clearcoat_gloss
+    }
+
+    /// The chance of sampling the diffuse, specular and clear coat layers.
+    fn lobe_weights(&self) -> (f64, f64, f64) {
+        let diffuse = 1.0 - self.metallic;
+        let specular = 1.0;
+        let clearcoat = 0.25 * self.clearcoat;
+        let total = diffuse + specular + clearcoat;
+        (diffuse / total, specular / total, clearcoat / total)
+    }
+}
+
+impl Bsdf for Principled {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(&h);
+        let schlick_weight = |c: f64| (1.0 - c).clamp(0.0, 1.0).powi(5);
+
+        // Diffuse, with the retro-reflection of rough surfaces at grazing angles
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+        let diffuse = self.base_colour * (fd * (1.0 - self.metallic) / PI);
+
+        let f0 = Vec3::repeat(0.08 * self.specular) * (1.0 - self.metallic) + self.base_colour * self.metallic;
+        let distribution = self.specular_distribution();
+        let specular = fresnel_schlick(&f0, cos_d)
+                     * (distribution.d(&h) * distribution.g(wo, wi) / (4.0 * wo.z * wi.z));
+
+        let clearcoat = if self.clearcoat > 0.0 {
+            let f = 0.04 + 0.96 * schlick_weight(cos_d);
+            let g = Ggx { alpha_x: 0.25, alpha_y: 0.25 }.g(wo, wi);
+            0.25 * self.clearcoat * f * gtr1(h.z, self.clearcoat_alpha()) * g / (4.0 * wo.z * wi.z)
+        } else {
+            0.0
+        };
+        diffuse + specular + Vec3::repeat(clearcoat)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        let (diffuse, specular, clearcoat) = self.lobe_weights();
+        diffuse * wi.z / PI
+            + specular * self.specular_distribution().visible_d(wo, &h) / (4.0 * wo.dot(&h))
+            + clearcoat * gtr1(h.z, self.clearcoat_alpha()) * h.z / (4.0 * wo.dot(&h))
+    }
+
+    fn sample(&self, wo: &Vec3, u: (f64, f64), lobe: f64) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let (diffuse, specular, _) = self.lobe_weights();
+        let wi = if lobe < diffuse {
+            sample_cosine(u)
+        } else if lobe < diffuse + specular {
+            reflect(wo, &self.specular_distribution().sample_visible(wo, u))
+        } else {
+            let a2 = self.clearcoat_alpha().powi(2);
+            let cos_h = ((1.0 - a2.powf(1.0 - u.0)) / (1.0 - a2)).max(0.0).sqrt();
+            let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.1;
+            reflect(wo, &Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h))
+        };
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf: self.pdf(wo, &wi), specular: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direction(theta: f64, phi: f64) -> Vec3 {
+        Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    /// The integral of a function over the whole sphere, on a fine grid.
+    fn integrate<F: Fn(&Vec3) -> f64>(f: F) -> f64 {
+        let (n_theta, n_phi) = (400, 400);
+        let mut total = 0.0;
+        for i in 0..n_theta {
+            let theta = PI * (i as f64 + 0.5) / n_theta as f64;
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                total += f(&direction(theta, phi)) * theta.sin();
+            }
+        }
+        total * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64)
+    }
+
+    fn grid(n: usize) -> impl Iterator<Item = (f64, f64)> {
+        (0..n * n).map(move |i| (((i % n) as f64 + 0.5) / n as f64, ((i / n) as f64 + 0.5) / n as f64))
+    }
+
+    #[test]
+    fn test_frame() {
+        let frame = Frame::from_normal(Vec3::new(0.0, 0.6, 0.8));
+        let v = Vec3::new(0.3, -0.2, 0.9);
+        assert!((frame.to_world(&frame.to_local(&v)) - v).norm() < 1e-12);
+        assert!((frame.to_local(&frame.n) - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_fresnel() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        // Total internal reflection leaving glass at a shallow angle
+        assert_eq!(fresnel_dielectric(-0.2, 1.5), 1.0);
+        // A conductor which absorbs nothing is a dielectric
+        let f = fresnel_conductor(0.6, &Vec3::repeat(1.5), &Vec3::zeros());
+        assert!((f.x - fresnel_dielectric(0.6, 1.5)).abs() < 1e-12);
+        assert!(fresnel_conductor(1.0, &Conductor::gold(0.0).eta, &Conductor::gold(0.0).k).x > 0.9);
+        assert_eq!(fresnel_schlick(&Vec3::repeat(0.04), 0.0), Vec3::repeat(1.0));
+    }
+
+    #[test]
+    fn test_sample_matches_eval() {
+        let wo = direction(0.7, 0.3);
+        let bsdfs: Vec<Box<dyn Bsdf>> = vec![
+            Box::new(Lambertian { albedo: Vec3::repeat(0.5) }),
+            Box::new(OrenNayar::new(Vec3::repeat(0.5), 20.0)),
+            Box::new(Conductor::copper(0.4)),
+            Box::new(Dielectric::new(1.5, 0.3)),
+            Box::new(Principled { clearcoat: 1.0, metallic: 0.3, ..Principled::default() }),
+        ];
+        for bsdf in &bsdfs {
+            let mut found = 0;
+            for (i, u) in grid(20).enumerate() {
+                if let Some(s) = bsdf.sample(&wo, u, (i % 7) as f64 / 7.0) {
+                    assert!(!s.specular);
+                    assert!(s.pdf > 0.0);
+                    assert!((s.pdf - bsdf.pdf(&wo, &s.wi)).abs() < 1e-9 * s.pdf, "{:?}", bsdf);
+                    assert!((s.f - bsdf.eval(&wo, &s.wi)).norm() < 1e-9 * (1.0 + s.f.norm()), "{:?}", bsdf);
+                    found += 1;
+                }
+            }
+            assert!(found > 300, "{:?}", bsdf);
+        }
+    }
+
+    #[test]
+    fn test_pdfs_integrate_to_one() {
+        let wo = direction(0.5, 1.0);
+        let bsdfs: Vec<Box<dyn Bsdf>> = vec![
+            Box::new(OrenNayar::new(Vec3::repeat(0.5), 20.0)),
+            Box::new(Conductor::gold(0.5)),
+            Box::new(Dielectric::new(1.5, 0.5)),
+            Box::new(Principled { clearcoat: 1.0, clearcoat_gloss: 0.0, ..Principled::default() }),
+        ];
+        for bsdf in &bsdfs {
+            let total = integrate(|wi| bsdf.pdf(&wo, wi));
+            // Some sampled directions go below the surface and are thrown away
+            assert!(total > 0.9 && total < 1.01, "{:?} {}", bsdf, total);
+        }
+        // From inside the glass as well
+        let inside = Vec3::new(wo.x, wo.y, -wo.z);
+        let total = integrate(|wi| Dielectric::new(1.5, 0.5).pdf(&inside, wi));
+        assert!(total > 0.9 && total < 1.01, "{}", total);
+    }
+
+    #[test]
+    fn test_energy() {
+        // White surfaces reflect about all the light, estimated by sampling. Burley's diffuse
+        // term is not exactly energy conserving, so the principled material can go slightly over.
+        let wo = direction(0.4, 0.0);
+        let bsdfs: Vec<Box<dyn Bsdf>> = vec![
+            Box::new(Lambertian { albedo: Vec3::repeat(1.0) }),
+            Box::new(OrenNayar::new(Vec3::repeat(1.0), 30.0)),
+            Box::new(Conductor::new(Vec3::repeat(0.0), Vec3::repeat(1e6), 0.3)),
+            Box::new(Principled { base_colour: Vec3::repeat(1.0), ..Principled::default() }),
+        ];
+        for bsdf in &bsdfs {
+            let n = 64;
+            let total: f64 = grid(n).enumerate()
+                                    .filter_map(|(i, u)| bsdf.sample(&wo, u, (i % 11) as f64 / 11.0))
+                                    .map(|s| s.f.x * s.wi.z / s.pdf)
+                                    .sum::<f64>() / (n * n) as f64;
+            assert!(total > 0.8 && total < 1.1, "{:?} {}", bsdf, total);
+        }
+    }
+
+    #[test]
+    fn test_reciprocity() {
+        let a = direction(0.3, 0.2);
+        let b = direction(1.1, 2.5);
+        let bsdfs: Vec<Box<dyn Bsdf>> = vec![
+            Box::new(OrenNayar::new(Vec3::repeat(0.5), 20.0)),
+            Box::new(Conductor::silver(0.3)),
+            Box::new(Principled { clearcoat: 0.5, ..Principled::default() }),
+        ];
+        for bsdf in &bsdfs {
+            assert!((bsdf.eval(&a, &b) - bsdf.eval(&b, &a)).norm() < 1e-12, "{:?}", bsdf);
+        }
+        // Rough glass is only reciprocal once importance is not squeezed going through it
+        let glass = Dielectric::new(1.5, 0.4);
+        let below = -direction(0.25, 0.2);
+        for (wo, wi) in &[(a, below), (below, a)] {
+            let forwards = glass.eval(wo, wi) * glass.importance_scale(wo, wi);
+            assert!(forwards.x > 0.0);
+            assert!((forwards - glass.eval(wi, wo)).norm() < 1e-12);
+        }
+        assert_eq!(glass.importance_scale(&a, &b), 1.0);
+    }
+
+    #[test]
+    fn test_smooth() {
+        let wo = direction(0.0, 0.0);
+        let glass = Dielectric::new(1.5, 0.0);
+        let reflected = glass.sample(&wo, (0.5, 0.5), 0.01).unwrap();
+        assert!(reflected.specular);
+        assert!((reflected.pdf - 0.04).abs() < 1e-12);
+        assert!((reflected.wi - wo).norm() < 1e-12);
+
+        let through = glass.sample(&wo, (0.5, 0.5), 0.5).unwrap();
+        assert!((through.wi - Vec3::new(0.0, 0.0, -1.0)).norm() < 1e-12);
+        assert!((through.pdf - 0.96).abs() < 1e-12);
+        assert_eq!(glass.eval(&wo, &through.wi), Vec3::zeros());
+
+        // Refraction bends towards the normal going in
+        let slanted = direction(0.8, 0.0);
+        let bent = glass.sample(&slanted, (0.5, 0.5), 0.99).unwrap();
+        assert!((bent.wi.x + slanted.x / 1.5).abs() < 1e-12);
+
+        assert!(glass.is_specular());
+        assert!(!Dielectric::new(1.5, 0.2).is_specular());
+        assert!(!Lambertian { albedo: Vec3::repeat(0.5) }.is_specular());
+
+        let mirror = Conductor::aluminium(0.0).sample(&slanted, (0.5, 0.5), 0.5).unwrap();
+        assert!(mirror.specular);
+        assert!((mirror.wi - Vec3::new(-slanted.x, -slanted.y, slanted.z)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_from_material() {
+        let mut material = Material::with_colour("red", Vec3::new(1.0, 0.0, 0.0));
+        material.shininess = 1000.0;
+        let principled = Principled::from_material(&material);
+        assert_eq!(principled.base_colour, Vec3::new(1.0, 0.0, 0.0));
+        assert!(principled.roughness < 0.3);
+        assert_eq!(principled.specular, 0.5);
+
+        assert!(BsdfKind::Model.create(&material, None).eval(&Vec3::new(0.0, 0.0, 1.0), &Vec3::new(0.0, 0.0, 1.0)).x > 0.0);
+        material.opacity = 0.5;
+        assert!(BsdfKind::Model.create(&material, None).transmits());
+        assert!(!BsdfKind::Gold.create(&material, Some(0.2)).transmits());
+        for kind in &[BsdfKind::Model, BsdfKind::Lambertian, BsdfKind::OrenNayar, BsdfKind::Principled,
+                      BsdfKind::Gold, BsdfKind::Silver, BsdfKind::Copper, BsdfKind::Aluminium, BsdfKind::Glass] {
+            assert_eq!(format!("{:?}", kind).parse::<BsdfKind>().unwrap(), *kind);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use geometry::{Ray, Vec3};
+
+/// The view through a pinhole camera onto an image, for sending rays out through pixels and
+/// finding where points in the scene land on the image.
+///
+/// The image is a rectangle one unit in front of the pinhole. Pixel (x, y) covers the part of
+/// it from (x, y) to (x + 1, y + 1), with x going right and y going down.
+#[derive(Debug, Copy, Clone)]
+pub struct Pinhole {
+    pub position: Vec3,
+    /// The direction the camera faces, normalised
+    pub forwards: Vec3,
+    top_left: Vec3,
+    right_step: Vec3,
+    down_step: Vec3,
+    width: usize,
+    height: usize,
+}
+
+impl Pinhole {
+    /// Make the view of a camera.
+    ///
+    /// # Arguments
+    /// * `position` - Where the pinhole is
+    /// * `forwards` - The direction the camera faces
+    /// * `up` - The direction which is up in the image, at right angles to `forwards`
+    /// * `y_fov` - The angle from the top of the image to the bottom, in radians
+    /// * `width` - The width of the image in pixels
+    /// * `height` - The height of the image in pixels
+    pub fn new(position: Vec3, forwards: Vec3, up: Vec3, y_fov: f64, width: usize, height: usize) -> Pinhole {
+        let forwards = forwards.normalize();
+        let left = forwards.cross(&up).normalize();
+
+        let y_dist_up = f64::tan(y_fov / 2.0);
+        let x_dist_left = y_dist_up * width as f64 / height as f64;
+
+        let top_center = position + forwards + y_dist_up * up;
+        Pinhole {
+            position,
+            forwards,
+            top_left: top_center + left * x_dist_left,
+            right_step: left * x_dist_left / (width as f64 / -2.0),
+            down_step: up * y_dist_up / (height as f64 / -2.0),
+            width,
+            height,
+        }
+    }
+
+    /// The ray from the pinhole through a point on the image, in pixels.
+    pub fn ray(&self, x: f64, y: f64) -> Ray {
+        let point = self.top_left + self.right_step * x + self.down_step * y;
+        Ray::new(self.position, point - self.position)
+    }
+
+    /// Where on the image a point is seen, in pixels, or `None` if it is outside the view.
+    pub fn raster(&self, point: Vec3) -> Option<(f64, f64)> {
+        let to_point = point - self.position;
+        let cos = to_point.dot(&self.forwards);
+        if cos <= 0.0 {
+            return None;
+        }
+        let on_image = self.position + to_point / cos - self.top_left;
+        let x = on_image.dot(&self.right_step) / self.right_step.norm_squared();
+        let y = on_image.dot(&self.down_step) / self.down_step.norm_squared();
+        if (0.0..self.width as f64).contains(&x) && (0.0..self.height as f64).contains(&y) {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// The area of the image, one unit in front of the pinhole.
+    fn image_area(&self) -> f64 {
+        self.right_step.norm() * self.width as f64 * self.down_step.norm() * self.height as f64
+    }
+
+    /// How likely a ray through a random point on the image is to go in a direction, per
+    /// unit solid angle. The direction must be normalised.
+    pub fn pdf_direction(&self, direction: &Vec3) -> f64 {
+        match self.raster(self.position + direction) {
+            Some(_) => 1.0 / (self.image_area() * direction.dot(&self.forwards).powi(3)),
+            None => 0.0
+        }
+    }
+
+    /// How much light arriving at the pinhole from a direction counts towards the image.
+    /// Multiplied by the cosine of the angle to `forwards`, it adds up to 1 over the view.
+    /// The direction must be normalised.
+    pub fn importance(&self, direction: &Vec3) -> f64 {
+        match self.raster(self.position + direction) {
+            Some(_) => 1.0 / (self.image_area() * direction.dot(&self.forwards).powi(4)),
+            None => 0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pinhole;
+    use geometry::Vec3;
+    use std::f64::consts::PI;
+
+    fn camera() -> Pinhole {
+        Pinhole::new(Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0), PI / 2.0, 40, 30)
+    }
+
+    #[test]
+    fn test_ray_and_raster() {
+        let camera = camera();
+        let centre = camera.ray(20.0, 15.0);
+        assert!((centre.direction - Vec3::new(0.0, 0.0, 1.0)).norm() < 1e-12);
+        // The top of the image is 45 degrees up
+        let top = camera.ray(20.0, 0.0);
+        assert!((top.direction.y - top.direction.z).abs() < 1e-12);
+
+        for &(x, y) in &[(0.5, 0.5), (20.0, 15.0), (39.2, 3.7)] {
+            let point = camera.ray(x, y).at(7.0);
+            let (rx, ry) = camera.raster(point).unwrap();
+            assert!((rx - x).abs() < 1e-9 && (ry - y).abs() < 1e-9);
+        }
+        assert_eq!(camera.raster(Vec3::new(1.0, 2.0, -5.0)), None);
+        assert_eq!(camera.raster(Vec3::new(1.0, 20.0, 4.0)), None);
+    }
+
+    #[test]
+    fn test_importance() {
+        // Add up over the sphere of directions
+        let camera = camera();
+        let n = 800;
+        let (mut total_pdf, mut total_importance) = (0.0, 0.0);
+        for i in 0..n {
+            let theta = (i as f64 + 0.5) / n as f64 * PI;
+            for j in 0..2 * n {
+                let phi = (j as f64 + 0.5) / n as f64 * PI;
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                let solid_angle = theta.sin() * (PI / n as f64) * (PI / n as f64);
+                total_pdf += camera.pdf_direction(&direction) * solid_angle;
+                total_importance += camera.importance(&direction) * direction.z * solid_angle;
+            }
+        }
+        assert!((total_pdf - 1.0).abs() < 0.01, "{}", total_pdf);
+        assert!((total_importance - 1.0).abs() < 0.01, "{}", total_importance);
+        assert_eq!(camera.importance(&Vec3::new(0.0, 0.0, -1.0)), 0.0);
+    }
+}
@@ -0,0 +1,131 @@
+//! Renderers which show one thing about the surface each ray hits, rather than how it is lit,
+//! for finding out whether a model which looks wrong is down to the loader, the normals or
+//! the BVH. Rays which miss the model are black.
+
+use bvh::BoundingVolumeHierarchy;
+use geometry::{CollisionDirection, Face, Plane, Ray, Vec3};
+use super::sampler::{hash, to_unit};
+
+/// A normal as a colour, with each axis going from 0 for -1 to 1 for +1.
+fn normal_colour(normal: &Vec3) -> Vec3 {
+    (normal.normalize() + Vec3::repeat(1.0)) / 2.0
+}
+
+/// The normal interpolated from the corners of the face hit, which is what shading uses.
+pub fn shading_normal<T: Plane>(ray: &Ray, model: &T) -> Vec3 {
+    model.hits(ray).map_or(Vec3::zeros(), |c| normal_colour(&c.normal))
+}
+
+/// The normal of the flat face hit.
+pub fn geometric_normal<T: Plane>(ray: &Ray, model: &T) -> Vec3 {
+    model.hits(ray).map_or(Vec3::zeros(), |c| normal_colour(&c.geometric_normal))
+}
+
+/// Green where the front of a face is seen and red where the back is, showing faces wound
+/// the wrong way.
+pub fn facing<T: Plane>(ray: &Ray, model: &T) -> Vec3 {
+    match model.hits(ray).map(|c| c.direction) {
+        Some(CollisionDirection::FrontFace) => Vec3::new(0.0, 1.0, 0.0),
+        Some(CollisionDirection::BackFace) => Vec3::new(1.0, 0.0, 0.0),
+        None => Vec3::zeros()
+    }
+}
+
+/// How far away the surface is, from white up close to black at `far`.
+pub fn depth<T: Plane>(ray: &Ray, model: &T, far: f64) -> Vec3 {
+    model.hits(ray).map_or(Vec3::zeros(), |c| Vec3::repeat((1.0 - c.distance / far).max(0.0)))
+}
+
+/// How near the point is to each corner of the face, as red, green and blue. Edges between
+/// faces show up where a colour fades out.
+pub fn barycentric<T: Plane>(ray: &Ray, model: &T) -> Vec3 {
+    model.hits(ray).map_or(Vec3::zeros(), |c| c.barycentric)
+}
+
+/// A different colour for every face, from its number. Faces without a number are white.
+pub fn face_id<T: Plane>(ray: &Ray, model: &T) -> Vec3 {
+    match model.hits(ray) {
+        Some(c) => match c.face {
+            Some(id) => {
+                let h = hash(&[id as u64]);
+                Vec3::new(to_unit(h), to_unit(hash(&[h, 1])), to_unit(hash(&[h, 2])))
+            },
+            None => Vec3::repeat(1.0)
+        },
+        None => Vec3::zeros()
+    }
+}
+
+/// A heat map of how many boxes and faces of the BVH are tested to find what the ray hits,
+/// going from blue through green to red at `full_cost` tests or more. Misses are coloured
+/// too, as they can still be slow.
+pub fn bvh_cost(ray: &Ray, model: &BoundingVolumeHierarchy<Face>, full_cost: f64) -> Vec3 {
+    let heat = (model.traversal_cost(ray) as f64 / full_cost).min(1.0);
+    if heat < 0.5 {
+        Vec3::new(0.0, 2.0 * heat, 1.0 - 2.0 * heat)
+    } else {
+        Vec3::new(2.0 * heat - 1.0, 2.0 - 2.0 * heat, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{barycentric, bvh_cost, depth, face_id, facing, geometric_normal, shading_normal};
+    use bvh::BoundingVolumeHierarchy;
+    use geometry::{Face, Ray, Vec3};
+
+    /// A triangle facing up, with its corner normals leaning outwards
+    fn triangle() -> Vec<Face> {
+        let (a, b, c) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        vec![Face::from_points_with_normals(a, b, c, Vec3::new(-1.0, -1.0, 1.0), Vec3::new(1.0, 0.0, 1.0),
+                                            Vec3::new(0.0, 1.0, 1.0)).with_id(Some(3))]
+    }
+
+    fn down(x: f64, y: f64) -> Ray {
+        Ray::new(Vec3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    fn up(x: f64, y: f64) -> Ray {
+        Ray::new(Vec3::new(x, y, -1.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn test_normals() {
+        let model = triangle();
+        assert_eq!(geometric_normal(&down(0.5, 0.5), &model), Vec3::new(0.5, 0.5, 1.0));
+        let at_corner = shading_normal(&down(0.0, 0.0), &model);
+        assert!((at_corner - (Vec3::new(-1.0, -1.0, 1.0).normalize() + Vec3::repeat(1.0)) / 2.0).norm() < 1e-9);
+        assert_eq!(shading_normal(&down(5.0, 5.0), &model), Vec3::zeros());
+    }
+
+    #[test]
+    fn test_facing_and_depth() {
+        let model = triangle();
+        assert_eq!(facing(&down(0.5, 0.5), &model), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(facing(&up(0.5, 0.5), &model), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(depth(&down(0.5, 0.5), &model, 4.0), Vec3::repeat(0.75));
+        assert_eq!(depth(&down(0.5, 0.5), &model, 0.5), Vec3::zeros());
+    }
+
+    #[test]
+    fn test_barycentric_and_face_id() {
+        let model = triangle();
+        let b = barycentric(&down(1.0, 0.5), &model);
+        assert!((b - Vec3::new(0.25, 0.5, 0.25)).norm() < 1e-9);
+
+        let colour = face_id(&down(0.5, 0.5), &model);
+        assert_eq!(colour, face_id(&down(1.0, 0.2), &model));
+        let other = vec![model[0].clone().with_id(Some(4))];
+        assert_ne!(colour, face_id(&down(0.5, 0.5), &other));
+        let unnumbered = vec![model[0].clone().with_id(None)];
+        assert_eq!(face_id(&down(0.5, 0.5), &unnumbered), Vec3::repeat(1.0));
+    }
+
+    #[test]
+    fn test_bvh_cost() {
+        let bvh = BoundingVolumeHierarchy::new(triangle());
+        assert_eq!(bvh_cost(&down(0.5, 0.5), &bvh, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(bvh_cost(&down(0.5, 0.5), &bvh, 2.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(bvh_cost(&down(0.5, 0.5), &BoundingVolumeHierarchy::empty(), 2.0), Vec3::new(0.0, 0.0, 1.0));
+    }
+}
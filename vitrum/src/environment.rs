@@ -0,0 +1,531 @@
+use geometry::Vec3;
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Errors that can be returned from reading an environment map.
+#[derive(Debug)]
+pub enum EnvironmentError {
+    /// The file could not be opened or read
+    IOError(std::io::Error),
+    /// The file extension is not .hdr or .pfm
+    UnknownFileType,
+    /// The file is not a valid image of its type
+    InvalidImage(String),
+}
+
+impl Display for EnvironmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvironmentError::IOError(e) => write!(f, "Could not read environment map: {}", e),
+            EnvironmentError::UnknownFileType => write!(f, "Environment maps must be .hdr or .pfm files"),
+            EnvironmentError::InvalidImage(e) => write!(f, "Invalid environment map: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnvironmentError::IOError(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<std::io::Error> for EnvironmentError {
+    fn from(e: std::io::Error) -> Self {
+        EnvironmentError::IOError(e)
+    }
+}
+
+fn invalid<T>(message: &str) -> Result<T, EnvironmentError> {
+    Err(EnvironmentError::InvalidImage(message.to_owned()))
+}
+
+/// How bright a colour looks, using the Rec. 709 weights.
+pub fn luminance(colour: &Vec3) -> f64 {
+    0.2126 * colour.x + 0.7152 * colour.y + 0.0722 * colour.z
+}
+
+/// A direction and the light coming from it, picked from an environment map.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EnvironmentSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    /// How likely the direction was to be picked, per unit solid angle
+    pub pdf: f64,
+}
+
+/// Light coming from every direction far away, read from an equirectangular (latitude /
+/// longitude) image. The top row of the image is straight up (+y) and the middle column
+/// looks along +z.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    width: usize,
+    height: usize,
+    /// Rows from top to bottom
+    pixels: Vec<Vec3>,
+    /// How far the map is turned about the up axis, as a fraction of a turn
+    rotation: f64,
+    intensity: f64,
+    /// The running total of the weight of each row, for picking a row
+    row_cdf: Vec<f64>,
+    /// The running total of the weight of each pixel within its row
+    pixel_cdf: Vec<f64>,
+}
+
+impl Environment {
+    /// Create an environment from its pixels.
+    ///
+    /// # Arguments
+    /// * `width` - The width of the image in pixels
+    /// * `height` - The height of the image in pixels
+    /// * `pixels` - The colour of each pixel, row by row from the top
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Environment {
+        let mut environment = Environment {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            row_cdf: Vec::with_capacity(height),
+            pixel_cdf: Vec::with_capacity(width * height),
+        };
+
+        // Pixels are picked in proportion to how bright they are and how much of the sphere
+        // they cover, which shrinks towards the poles
+        let mut total = 0.0;
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let mut row = 0.0;
+            for x in 0..width {
+                row += luminance(&environment.pixels[y * width + x]).max(0.0) * sin_theta;
+                environment.pixel_cdf.push(row);
+            }
+            total += row;
+            environment.row_cdf.push(total);
+        }
+        environment
+    }
+
+    /// Read an environment map from a Radiance `.hdr` or `.pfm` file.
+    pub fn load(path: &str) -> Result<Environment, EnvironmentError> {
+        let extension = Path::new(path).extension()
+                                       .and_then(|e| e.to_str())
+                                       .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => Environment::from_hdr(&std::fs::read(path)?),
+            Some("pfm") => Environment::from_pfm(&std::fs::read(path)?),
+            _ => Err(EnvironmentError::UnknownFileType)
+        }
+    }
+
+    /// Turn the map about the up axis, returning the updated environment.
+    ///
+    /// # Arguments
+    /// * `degrees` - How far to turn it, anticlockwise looking down
+    pub fn with_rotation(mut self, degrees: f64) -> Environment {
+        self.rotation = degrees / 360.0;
+        self
+    }
+
+    /// Scale how bright the map is, returning the updated environment.
+    pub fn with_intensity(mut self, intensity: f64) -> Environment {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Where a direction is in the image, each coordinate in [0, 1).
+    fn to_image(&self, direction: &Vec3) -> (f64, f64) {
+        let d = direction.normalize();
+        let u = (d.x.atan2(d.z) / (2.0 * PI) + 0.5 - self.rotation).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// The direction a point in the image is in.
+    fn to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = (u - 0.5 + self.rotation) * 2.0 * PI;
+        let theta = v * PI;
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos())
+    }
+
+    fn pixel_at(&self, u: f64, v: f64) -> (usize, usize) {
+        (((u * self.width as f64) as usize).min(self.width - 1),
+         ((v * self.height as f64) as usize).min(self.height - 1))
+    }
+
+    /// The light coming from a direction.
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let (u, v) = self.to_image(direction);
+        let (x, y) = self.pixel_at(u, v);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    /// How likely `sample` is to pick a direction, per unit solid angle.
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let total = *self.row_cdf.last().unwrap_or(&0.0);
+        if total <= 0.0 {
+            return 1.0 / (4.0 * PI);
+        }
+        let (u, v) = self.to_image(direction);
+        let (x, y) = self.pixel_at(u, v);
+        let before = if x == 0 { 0.0 } else { self.pixel_cdf[y * self.width + x - 1] };
+        let weight = self.pixel_cdf[y * self.width + x] - before;
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // From per unit of image area to per unit solid angle
+        (weight / total) * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Pick a direction to send a ray to, favouring the bright parts of the map. A map with
+    /// no light at all is sampled evenly.
+    ///
+    /// # Arguments
+    /// * `u` - A point in the unit square, from a `Sampler`
+    pub fn sample(&self, u: (f64, f64)) -> EnvironmentSample {
+        let total = *self.row_cdf.last().unwrap_or(&0.0);
+        if total <= 0.0 {
+            let z = 1.0 - 2.0 * u.0;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let angle = 2.0 * PI * u.1;
+            let direction = Vec3::new(r * angle.cos(), z, r * angle.sin());
+            return EnvironmentSample { direction, radiance: self.radiance(&direction), pdf: 1.0 / (4.0 * PI) };
+        }
+
+        // Pick a row, then a pixel in it, then a point in the pixel
+        let pick = |cdf: &[f64], target: f64| -> (usize, f64) {
+            let i = cdf.iter().position(|&c| target < c).unwrap_or(cdf.len() - 1);
+            let before = if i == 0 { 0.0 } else { cdf[i - 1] };
+            let within = if cdf[i] > before { (target - before) / (cdf[i] - before) } else { 0.5 };
+            (i, within.clamp(0.0, 1.0))
+        };
+        let (y, within_y) = pick(&self.row_cdf, u.0 * total);
+        let row = &self.pixel_cdf[y * self.width..(y + 1) * self.width];
+        let (x, within_x) = pick(row, u.1 * row[self.width - 1]);
+
+        let direction = self.to_direction((x as f64 + within_x) / self.width as f64,
+                                          (y as f64 + within_y) / self.height as f64);
+        EnvironmentSample { direction, radiance: self.radiance(&direction), pdf: self.pdf(&direction) }
+    }
+
+    /// Read a Radiance RGBE (`.hdr`) image, with or without run length encoding.
+    pub fn from_hdr(data: &[u8]) -> Result<Environment, EnvironmentError> {
+        // The header is lines of text ending with a blank line, then the size
+        let mut pos = 0;
+        let next_line = |pos: &mut usize| -> Option<String> {
+            let start = *pos;
+            let end = start + data.get(start..)?.iter().position(|&b| b == b'\n')?;
+            *pos = end + 1;
+            Some(String::from_utf8_lossy(&data[start..end]).trim().to_owned())
+        };
+        match next_line(&mut pos) {
+            Some(ref magic) if magic.starts_with("#?") => {},
+            _ => return invalid("missing #? at the start of the header")
+        }
+        loop {
+            match next_line(&mut pos) {
+                None => return invalid("header does not end"),
+                Some(line) if line.is_empty() => break,
+                Some(line) => {
+                    if let Some(format) = line.strip_prefix("FORMAT=") {
+                        if format != "32-bit_rle_rgbe" {
+                            return invalid(&format!("unsupported format {}", format));
+                        }
+                    }
+                }
+            }
+        }
+        let size = match next_line(&mut pos) {
+            Some(size) => size,
+            None => return invalid("missing image size")
+        };
+        let (width, height, flip) = match size.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [y, h, "+X", w] if *y == "-Y" || *y == "+Y" => match (h.parse::<usize>(), w.parse::<usize>()) {
+                (Ok(h), Ok(w)) => (w, h, *y == "+Y"),
+                _ => return invalid(&format!("bad image size {}", size))
+            },
+            _ => return invalid(&format!("unsupported image size {}", size))
+        };
+        if width == 0 || height == 0 {
+            return invalid("image is empty");
+        }
+        // Check the size against the data left before making room for the pixels. Run length
+        // encoded channels take at least 2 bytes for every 127 pixels.
+        let smallest_scanline = if (8..0x8000).contains(&width) { Some(4 + 8 * width.div_ceil(127)) } else { width.checked_mul(4) };
+        match smallest_scanline.and_then(|bytes| bytes.checked_mul(height)) {
+            Some(bytes) if bytes <= data.len() - pos => {},
+            _ => return invalid(&format!("not enough pixel data for a {}x{} image", width, height))
+        }
+
+        let mut rgbe = vec![[0u8; 4]; width * height];
+        for y in 0..height {
+            let scanline = &mut rgbe[y * width..(y + 1) * width];
+            pos = read_hdr_scanline(data, pos, scanline)?;
+        }
+
+        let mut pixels: Vec<Vec3> = rgbe.iter().map(|[r, g, b, e]| {
+            if *e == 0 {
+                Vec3::zeros()
+            } else {
+                let scale = 2f64.powi(*e as i32 - (128 + 8));
+                Vec3::new(*r as f64, *g as f64, *b as f64) * scale
+            }
+        }).collect();
+        if flip {
+            flip_rows(&mut pixels, width);
+        }
+        Ok(Environment::new(width, height, pixels))
+    }
+
+    /// Read a PFM (portable float map) image, either colour (`PF`) or grey (`Pf`).
+    pub fn from_pfm(data: &[u8]) -> Result<Environment, EnvironmentError> {
+        // Three whitespace separated header fields, then a single whitespace character
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return invalid("header ends early");
+            }
+            fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+        pos += 1;
+
+        let channels = match fields[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            other => return invalid(&format!("unknown PFM type {}", other))
+        };
+        let (width, height, scale) = match (fields[1].parse::<usize>(), fields[2].parse::<usize>(), fields[3].parse::<f64>()) {
+            (Ok(w), Ok(h), Ok(s)) if w > 0 && h > 0 && s != 0.0 => (w, h, s),
+            _ => return invalid("bad size or scale")
+        };
+        let floats = data.get(pos..).unwrap_or(&[]);
+        match width.checked_mul(height).and_then(|pixels| pixels.checked_mul(channels * 4)) {
+            Some(bytes) if bytes <= floats.len() => {},
+            _ => return invalid("not enough pixel data")
+        }
+
+        // A negative scale means little endian. Rows go from the bottom up.
+        let value = |i: usize| {
+            let bytes = [floats[i * 4], floats[i * 4 + 1], floats[i * 4 + 2], floats[i * 4 + 3]];
+            if scale < 0.0 { f32::from_le_bytes(bytes) as f64 } else { f32::from_be_bytes(bytes) as f64 }
+        };
+        let mut pixels: Vec<Vec3> = (0..width * height).map(|p| {
+            if channels == 3 {
+                Vec3::new(value(p * 3), value(p * 3 + 1), value(p * 3 + 2))
+            } else {
+                Vec3::repeat(value(p))
+            }
+        }).collect();
+        flip_rows(&mut pixels, width);
+        Ok(Environment::new(width, height, pixels))
+    }
+}
+
+fn flip_rows(pixels: &mut [Vec3], width: usize) {
+    let height = pixels.len() / width;
+    for y in 0..height / 2 {
+        for x in 0..width {
+            pixels.swap(y * width + x, (height - 1 - y) * width + x);
+        }
+    }
+}
+
+/// Read one scanline of an RGBE image starting at `pos`, returning where the next one starts.
+fn read_hdr_scanline(data: &[u8], pos: usize, scanline: &mut [[u8; 4]]) -> Result<usize, EnvironmentError> {
+    let width = scanline.len();
+    let byte = |i: usize| data.get(i).copied().ok_or_else(|| EnvironmentError::InvalidImage("pixel data ends early".to_owned()));
+    let mut pos = pos;
+
+    // Run length encoded scanlines start with 2, 2 and the width
+    let encoded = (8..0x8000).contains(&width)
+               && data.len() >= pos + 4
+               && data[pos] == 2 && data[pos + 1] == 2
+               && ((data[pos + 2] as usize) << 8 | data[pos + 3] as usize) == width;
+    if encoded {
+        pos += 4;
+        // Each channel is stored separately, as runs of one value or of different values
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = byte(pos)? as usize;
+                pos += 1;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = byte(pos)?;
+                    pos += 1;
+                    if x + count > width {
+                        return invalid("run goes past the end of the scanline");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 || x + count > width {
+                        return invalid("bad run length");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[channel] = byte(pos)?;
+                        pos += 1;
+                    }
+                    x += count;
+                }
+            }
+        }
+    } else {
+        // Flat pixels, where 1, 1, 1, n repeats the previous pixel n times
+        let mut x = 0;
+        let mut shift = 0;
+        while x < width {
+            let pixel = [byte(pos)?, byte(pos + 1)?, byte(pos + 2)?, byte(pos + 3)?];
+            pos += 4;
+            if pixel[..3] == [1, 1, 1] && x > 0 {
+                let count = (pixel[3] as usize) << shift;
+                if x + count > width {
+                    return invalid("run goes past the end of the scanline");
+                }
+                for i in x..x + count {
+                    scanline[i] = scanline[x - 1];
+                }
+                x += count;
+                shift += 8;
+            } else {
+                scanline[x] = pixel;
+                x += 1;
+                shift = 0;
+            }
+        }
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{luminance, Environment};
+    use geometry::Vec3;
+    use std::f64::consts::PI;
+
+    fn hdr_header(width: usize, height: usize) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+    #[test]
+    fn test_flat_hdr() {
+        let mut data = hdr_header(2, 2);
+        // 1.0 is stored as 128 with exponent 129, 0.5 as 128 with exponent 128
+        data.extend_from_slice(&[128, 0, 0, 129, 0, 128, 0, 128, 0, 0, 0, 0, 1, 1, 1, 1]);
+        let environment = Environment::from_hdr(&data).unwrap();
+        assert_eq!(environment.pixels, vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.5, 0.0),
+                                            Vec3::zeros(), Vec3::zeros()]);
+    }
+
+    #[test]
+    fn test_rle_hdr() {
+        let mut data = hdr_header(8, 1);
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        // Red: a run of 8, green: 8 different values, blue: two runs of 4, exponent: run of 8
+        data.extend_from_slice(&[128 + 8, 128]);
+        data.push(8);
+        data.extend_from_slice(&[0, 16, 32, 48, 64, 80, 96, 112]);
+        data.extend_from_slice(&[128 + 4, 0, 128 + 4, 64]);
+        data.extend_from_slice(&[128 + 8, 129]);
+        let environment = Environment::from_hdr(&data).unwrap();
+        assert_eq!(environment.pixels[0], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(environment.pixels[7], Vec3::new(1.0, 112.0 / 128.0, 0.5));
+
+        assert!(Environment::from_hdr(b"P6\n").is_err());
+        assert!(Environment::from_hdr(&hdr_header(8, 1)).is_err());
+    }
+
+    #[test]
+    fn test_hdr_too_big() {
+        // Sizes the data cannot hold are rejected before room is made for the pixels
+        let mut data = hdr_header(30000, 1_000_000_000);
+        data.extend_from_slice(&[2, 2, 0x75, 0x30]);
+        let e = Environment::from_hdr(&data).err().unwrap();
+        assert!(e.to_string().contains("not enough pixel data for a 30000x1000000000 image"), "{}", e);
+        assert!(Environment::from_hdr(&hdr_header(usize::MAX, 2)).is_err());
+    }
+
+    #[test]
+    fn test_pfm() {
+        let mut data = b"PF\n1 2\n-1.0\n".to_vec();
+        for v in &[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        let environment = Environment::from_pfm(&data).unwrap();
+        // The bottom row comes first
+        assert_eq!(environment.pixels, vec![Vec3::new(4.0, 5.0, 6.0), Vec3::new(1.0, 2.0, 3.0)]);
+
+        let mut data = b"Pf 1 1 1.0 ".to_vec();
+        data.extend_from_slice(&0.25f32.to_be_bytes());
+        assert_eq!(Environment::from_pfm(&data).unwrap().pixels, vec![Vec3::repeat(0.25)]);
+        assert!(Environment::from_pfm(b"PF\n1 1\n1.0\n").is_err());
+        assert!(Environment::from_pfm(format!("PF\n{} 2\n1.0\n", usize::MAX / 2).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_directions() {
+        // Bright only in the third column of four, which looks along +x
+        let mut pixels = vec![Vec3::zeros(); 8];
+        pixels[2] = Vec3::repeat(1.0);
+        pixels[6] = Vec3::repeat(1.0);
+        let environment = Environment::new(4, 2, pixels).with_intensity(2.0);
+        assert_eq!(environment.radiance(&Vec3::new(1.0, 0.1, 0.1)), Vec3::repeat(2.0));
+        assert_eq!(environment.radiance(&Vec3::new(-1.0, 0.1, 0.0)), Vec3::zeros());
+
+        let turned = environment.clone().with_rotation(90.0);
+        assert_eq!(turned.radiance(&Vec3::new(1.0, 0.1, -0.1)), Vec3::repeat(2.0));
+        assert_eq!(turned.radiance(&Vec3::new(1.0, 0.1, 0.1)), Vec3::zeros());
+
+        for &(u, v) in &[(0.1, 0.2), (0.7, 0.9), (0.5, 0.5)] {
+            let d = turned.to_direction(u, v);
+            let (u2, v2) = turned.to_image(&d);
+            assert!((u - u2).abs() < 1e-12 && (v - v2).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_importance_sampling() {
+        let mut pixels: Vec<Vec3> = (0..32).map(|i| Vec3::repeat(1.0 + (i % 5) as f64)).collect();
+        pixels[9] = Vec3::repeat(20.0);
+        let environment = Environment::new(8, 4, pixels);
+        let n = 64;
+        let mut inverse_pdf = 0.0;
+        let mut estimate = 0.0;
+        let mut hits = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let s = environment.sample(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+                inverse_pdf += 1.0 / s.pdf;
+                estimate += luminance(&s.radiance) / s.pdf;
+                let (u, v) = environment.to_image(&s.direction);
+                if environment.pixel_at(u, v) == (1, 1) {
+                    hits += 1;
+                }
+            }
+        }
+        // The samples cover the whole sphere
+        assert!((inverse_pdf / (n * n) as f64 - 4.0 * PI).abs() < 0.05 * 4.0 * PI);
+        // and add up to the light from all of it
+        let exact: f64 = (0..32).map(|p| {
+            let (y0, y1) = ((p / 8) as f64 * PI / 4.0, (p / 8 + 1) as f64 * PI / 4.0);
+            luminance(&environment.pixels[p]) * (2.0 * PI / 8.0) * (y0.cos() - y1.cos())
+        }).sum();
+        assert!((estimate / (n * n) as f64 - exact).abs() < 0.05 * exact);
+        // The brightest pixel gets picked far more often than its size alone would give
+        assert!(hits > n * n / 8);
+    }
+}
@@ -0,0 +1,225 @@
+use geometry::Vec3;
+
+use enum_from_str::ParseEnumVariantError;
+use enum_from_str_derive::FromStr;
+
+/// How samples are spread over the pixels around them when the image is built.
+#[derive(Debug, Copy, Clone, PartialEq, FromStr)]
+pub enum Filter {
+    // Each sample only counts towards the pixel it is in
+    Box,
+    // Samples count less the further they are from the pixel centre, out to one pixel
+    Tent,
+    // A Gaussian bell curve out to one and a half pixels. Soft, with no ringing.
+    Gaussian,
+    // The Mitchell-Netravali cubic (B = C = 1/3) out to two pixels. Sharp, with slight ringing.
+    Mitchell,
+}
+
+impl Filter {
+    /// How far (in pixels) from a pixel's centre a sample can be and still count towards it.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// How much a sample counts towards a pixel, given how far it is from the pixel's centre.
+    ///
+    /// # Arguments
+    /// * `dx` - The horizontal distance in pixels
+    /// * `dy` - The vertical distance in pixels
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let r = self.radius();
+        let d = d.abs();
+        if d > r {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d / r,
+            Filter::Gaussian => {
+                // Shifted down so it reaches zero at the radius
+                let alpha = 2.0;
+                (-alpha * d * d).exp() - (-alpha * r * r).exp()
+            },
+            Filter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let weight = if d < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * d * d * d
+                        + (-18.0 + 12.0 * b + 6.0 * c) * d * d
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * d * d * d
+                        + (6.0 * b + 30.0 * c) * d * d
+                        + (-12.0 * b - 48.0 * c) * d
+                        + (8.0 * b + 24.0 * c)
+                };
+                weight / 6.0
+            },
+        }
+    }
+}
+
+/// An image built up from samples at any point in it. Each pixel is the weighted average of
+/// the samples near it, plus any light splatted straight onto it. Pixel (x, y) covers the
+/// area from (x, y) to (x + 1, y + 1).
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sums: Vec<Vec3>,
+    weights: Vec<f64>,
+    splats: Vec<Vec3>,
+    splat_scale: f64,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            sums: vec![Vec3::zeros(); width * height],
+            weights: vec![0.0; width * height],
+            splats: vec![Vec3::zeros(); width * height],
+            splat_scale: 1.0,
+        }
+    }
+
+    /// Set how much each splat counts, returning the updated film. Light traced from the
+    /// lights is splatted once for every sample, so this is one over the samples per pixel.
+    pub fn with_splat_scale(mut self, splat_scale: f64) -> Film {
+        self.splat_scale = splat_scale;
+        self
+    }
+
+    /// Add a sample to every pixel it counts towards.
+    ///
+    /// # Arguments
+    /// * `x` - Where across the image the sample is, in pixels
+    /// * `y` - Where down the image the sample is, in pixels
+    /// * `colour` - The colour of the sample
+    pub fn add_sample(&mut self, x: f64, y: f64, colour: Vec3) {
+        let r = self.filter.radius();
+        // The pixels whose centres (at +0.5) are within the radius
+        let first_x = (x - 0.5 - r).ceil().max(0.0) as usize;
+        let first_y = (y - 0.5 - r).ceil().max(0.0) as usize;
+        let last_x = ((x - 0.5 + r).floor() as isize).min(self.width as isize - 1);
+        let last_y = ((y - 0.5 + r).floor() as isize).min(self.height as isize - 1);
+        for py in first_y as isize..=last_y {
+            for px in first_x as isize..=last_x {
+                let weight = self.filter.weight(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                let i = py as usize * self.width + px as usize;
+                self.sums[i] += weight * colour;
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    /// Add light to the pixel a point is in, without averaging it with the samples. For
+    /// light found by following paths from the lights to the camera, which can land anywhere
+    /// in the image.
+    ///
+    /// # Arguments
+    /// * `x` - Where across the image the light lands, in pixels
+    /// * `y` - Where down the image the light lands, in pixels
+    /// * `colour` - The light
+    pub fn add_splat(&mut self, x: f64, y: f64, colour: Vec3) {
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return;
+        }
+        self.splats[y as usize * self.width + x as usize] += colour;
+    }
+
+    /// The colour of a pixel. Pixels with no samples near them are black, apart from any
+    /// splats.
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        let i = y * self.width + x;
+        let splat = self.splats[i] * self.splat_scale;
+        if self.weights[i] == 0.0 {
+            splat
+        } else {
+            self.sums[i] / self.weights[i] + splat
+        }
+    }
+
+    /// Write the image out as 8 bit RGBA, clamping each channel to [0, 1].
+    pub fn write_rgba(&self, data: &mut [u8]) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let colour = self.pixel(x, y);
+                let i = (y * self.width + x) * 4;
+                data[i    ] = (colour.x.clamp(0.0, 1.0) * 255.0) as u8;
+                data[i + 1] = (colour.y.clamp(0.0, 1.0) * 255.0) as u8;
+                data[i + 2] = (colour.z.clamp(0.0, 1.0) * 255.0) as u8;
+                data[i + 3] = 255;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Film, Filter};
+    use geometry::Vec3;
+
+    #[test]
+    fn test_filters() {
+        for filter in &[Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+            assert!(filter.weight(0.0, 0.0) > 0.0);
+            assert_eq!(filter.weight(filter.radius() + 0.01, 0.0), 0.0);
+            assert!(filter.weight(0.3, 0.1) <= filter.weight(0.0, 0.0));
+        }
+        assert_eq!(Filter::Tent.weight(0.5, 0.0), 0.5);
+        // Mitchell-Netravali goes slightly negative
+        assert!(Filter::Mitchell.weight(1.5, 0.0) < 0.0);
+        for filter in &[Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+            assert_eq!(format!("{:?}", filter).parse::<Filter>().unwrap(), *filter);
+        }
+    }
+
+    #[test]
+    fn test_box_film() {
+        let mut film = Film::new(2, 1, Filter::Box);
+        film.add_sample(0.25, 0.5, Vec3::new(1.0, 0.0, 0.0));
+        film.add_sample(0.75, 0.5, Vec3::new(0.0, 1.0, 0.0));
+        film.add_sample(1.5, 0.5, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(film.pixel(0, 0), Vec3::new(0.5, 0.5, 0.0));
+        assert_eq!(film.pixel(1, 0), Vec3::new(0.0, 0.0, 1.0));
+
+        let mut data = vec![0; 8];
+        film.write_rgba(&mut data);
+        assert_eq!(data, vec![127, 127, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_tent_film() {
+        let mut film = Film::new(3, 1, Filter::Tent);
+        film.add_sample(1.0, 0.5, Vec3::new(1.0, 1.0, 1.0));
+        // On the edge between the first two pixels, so it counts for both but not the third
+        assert_eq!(film.pixel(0, 0), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(1, 0), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(2, 0), Vec3::zeros());
+    }
+
+    #[test]
+    fn test_splats() {
+        let mut film = Film::new(2, 2, Filter::Box).with_splat_scale(0.5);
+        film.add_sample(0.5, 0.5, Vec3::new(0.2, 0.2, 0.2));
+        film.add_splat(0.9, 0.1, Vec3::new(1.0, 0.0, 0.0));
+        film.add_splat(1.5, 1.5, Vec3::new(0.0, 1.0, 0.0));
+        film.add_splat(1.5, 1.5, Vec3::new(0.0, 1.0, 0.0));
+        film.add_splat(2.5, 1.5, Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(0, 0), Vec3::new(0.7, 0.2, 0.2));
+        assert_eq!(film.pixel(1, 1), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(film.pixel(1, 0), Vec3::zeros());
+    }
+}
@@ -0,0 +1,76 @@
+//! The `vitrum info` command, which measures a model and checks whether it can be 3D
+//! printed instead of rendering it.
+
+use argparse::{ArgumentParser, StoreOption, StoreTrue};
+
+use geometry::Strictness;
+
+use mesh_tools::Report;
+
+/// Run the info command, and return the exit code.
+///
+/// # Arguments
+/// * `args` - The command line arguments, starting with the name of the command
+pub fn run(args: Vec<String>) -> i32 {
+    let mut filename: Option<String> = None;
+    let mut lenient = false;
+    let mut json_filename: Option<String> = None;
+    let mut check = false;
+
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Print statistics about a model and check whether it can be 3D printed");
+        ap.refer(&mut filename)
+            .add_option(&["-f", "--file"], StoreOption, "File to parse")
+            .required();
+        ap.refer(&mut lenient).add_option(
+            &["--lenient"],
+            StoreTrue,
+            "Skip (or repair) bad parts of the model file with a warning, instead of failing.",
+        );
+        ap.refer(&mut json_filename).add_option(
+            &["--json"],
+            StoreOption,
+            "Also write the report as JSON to this file.",
+        );
+        ap.refer(&mut check).add_option(
+            &["--check"],
+            StoreTrue,
+            "Exit with status 2 if the model is not printable (watertight, consistently wound and not self-intersecting).",
+        );
+        if let Err(code) = ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr()) {
+            return code;
+        }
+    }
+
+    let filename = filename.unwrap();
+    let mut registry = file_loader::LoaderRegistry::default();
+    if lenient {
+        registry.set_strictness(Strictness::Lenient);
+    }
+    let scene = match registry.load_file(&filename) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    for warning in &scene.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let report = Report::new(&scene);
+    print!("{}", report);
+    if let Some(path) = &json_filename {
+        if let Err(e) = std::fs::write(path, report.to_json()) {
+            eprintln!("Could not write report to {}: {}", path, e);
+            return 1;
+        }
+    }
+
+    if check && !report.is_printable() {
+        2
+    } else {
+        0
+    }
+}
@@ -1,16 +1,18 @@
-use geometry::{Ray, Collision, Plane};
+use geometry::{Ray, Collision, Plane, Material, Vec3};
+use super::environment::Environment;
+use super::shading::surface_colour;
 
 fn lambert(ray: &Ray, collision: &Collision) -> f64 {
 //   println!("{:?}", collision.normal);
     1.0 - (collision.normal.dot(&ray.direction))
 }
 
-pub fn trace<T:Plane>(ray: &Ray, model: &T) -> f64 {
+pub fn trace<T:Plane>(ray: &Ray, model: &T, materials: &[Material], environment: Option<&Environment>) -> Vec3 {
      // println!("{:?}", ray);
      let hit = model.hits(&ray);
      if let Some(c) = hit {
-        lambert(&ray, &c)
+        lambert(&ray, &c) * surface_colour(materials, &c)
      } else {
-        0.0
+        environment.map_or(Vec3::zeros(), |e| e.radiance(&ray.direction))
      }
-}
\ No newline at end of file
+}
@@ -0,0 +1,541 @@
+use geometry::{Ray, Scene, Vec3};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// The shape of a light.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// A single point, giving sharp shadows
+    Point(Vec3),
+    /// A parallelogram with one corner at `corner` and sides `u` and `v`. It shines from the
+    /// side `u` x `v` points to.
+    Rectangle { corner: Vec3, u: Vec3, v: Vec3 },
+    /// A flat disc shining from the side its normal points to
+    Disc { centre: Vec3, normal: Vec3, radius: f64 },
+    /// A ball shining outwards
+    Sphere { centre: Vec3, radius: f64 },
+    /// Triangles shining from their front sides, with the running total of their areas for
+    /// picking one in proportion to its area
+    Mesh { triangles: Vec<[Vec3; 3]>, cumulative_area: Vec<f64> },
+}
+
+/// How much shorter than the distance to a light a shadow ray is, so that the light's own
+/// surface does not block it.
+pub const SHADOW_GAP: f64 = 1e-6;
+
+/// A shadow ray from a point on a surface towards a light, which stops just short of it.
+///
+/// # Arguments
+/// * `point` - The point on the surface
+/// * `direction` - The direction of the light
+/// * `distance` - How far away the light is. Lights at infinity block nothing.
+pub fn shadow_ray(point: Vec3, direction: Vec3, distance: f64) -> Ray {
+    let ray = Ray::leaving(point, direction);
+    let t_min = ray.t_min;
+    let t_max = if distance.is_finite() { distance * (1.0 - SHADOW_GAP) } else { distance };
+    ray.with_interval(t_min, t_max)
+}
+
+/// Something giving off light, which can be sampled for shadow rays.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub shape: Shape,
+    /// The light given off, per channel
+    pub emission: Vec3,
+    /// For mesh lights made from the model, the material of their faces
+    pub material: Option<usize>,
+}
+
+/// A point on a light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightSample {
+    pub point: Vec3,
+    /// The direction the light's surface faces at the point. Point lights have none.
+    pub normal: Option<Vec3>,
+    /// How likely the point was to be picked, per unit area. 1 for point lights.
+    pub pdf: f64,
+}
+
+/// Light leaving a light, for following the light's path from it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EmissionSample {
+    pub point: Vec3,
+    /// The direction the light's surface faces at the point. Point lights have none.
+    pub normal: Option<Vec3>,
+    /// The direction the light leaves in
+    pub direction: Vec3,
+    /// How likely the point was to be picked, per unit area. 1 for point lights.
+    pub pdf_position: f64,
+    /// How likely the direction was to be picked, per unit solid angle
+    pub pdf_direction: f64,
+}
+
+/// Two directions at right angles to each other and to `n`, which must be normalised.
+fn basis(n: Vec3) -> (Vec3, Vec3) {
+    let other = if n.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let s = n.cross(&other).normalize();
+    (s, n.cross(&s))
+}
+
+/// A direction picked evenly from all of them.
+fn uniform_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let angle = 2.0 * PI * u.1;
+    Vec3::new(r * angle.cos(), r * angle.sin(), z)
+}
+
+impl Light {
+    fn new(shape: Shape) -> Light {
+        Light { shape, emission: Vec3::repeat(1.0), material: None }
+    }
+
+    pub fn point(position: Vec3) -> Light {
+        Light::new(Shape::Point(position))
+    }
+
+    pub fn rectangle(corner: Vec3, u: Vec3, v: Vec3) -> Light {
+        Light::new(Shape::Rectangle { corner, u, v })
+    }
+
+    pub fn disc(centre: Vec3, normal: Vec3, radius: f64) -> Light {
+        Light::new(Shape::Disc { centre, normal: normal.normalize(), radius })
+    }
+
+    pub fn sphere(centre: Vec3, radius: f64) -> Light {
+        Light::new(Shape::Sphere { centre, radius })
+    }
+
+    /// A light made of triangles. Triangles with no area are left out, and if none are left
+    /// there is no light.
+    pub fn mesh(triangles: Vec<[Vec3; 3]>) -> Option<Light> {
+        let area = |t: &[Vec3; 3]| (t[1] - t[0]).cross(&(t[2] - t[0])).norm() / 2.0;
+        let triangles: Vec<[Vec3; 3]> = triangles.into_iter().filter(|t| area(t) > 0.0).collect();
+        if triangles.is_empty() {
+            return None;
+        }
+        let cumulative_area = triangles.iter()
+                                       .scan(0.0, |total, t| { *total += area(t); Some(*total) })
+                                       .collect();
+        Some(Light::new(Shape::Mesh { triangles, cumulative_area }))
+    }
+
+    /// Set the light given off, returning the updated light.
+    pub fn with_emission(mut self, emission: Vec3) -> Light {
+        self.emission = emission;
+        self
+    }
+
+    /// Set the material the light was made from, returning the updated light.
+    pub fn with_material(mut self, material: usize) -> Light {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn is_point(&self) -> bool {
+        matches!(self.shape, Shape::Point(_))
+    }
+
+    /// The area of the light's surface. Point lights have none.
+    pub fn area(&self) -> f64 {
+        match &self.shape {
+            Shape::Point(_) => 0.0,
+            Shape::Rectangle { u, v, .. } => u.cross(v).norm(),
+            Shape::Disc { radius, .. } => PI * radius * radius,
+            Shape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            Shape::Mesh { cumulative_area, .. } => *cumulative_area.last().unwrap(),
+        }
+    }
+
+    /// Pick a point on the light to send a shadow ray to. Spheres only pick points on the
+    /// half facing `from`, as the rest cannot be seen from there.
+    ///
+    /// # Arguments
+    /// * `from` - The point being lit
+    /// * `u` - A point in the unit square, from a `Sampler`
+    pub fn sample(&self, from: Vec3, u: (f64, f64)) -> LightSample {
+        match &self.shape {
+            Shape::Point(position) => LightSample { point: *position, normal: None, pdf: 1.0 },
+            Shape::Rectangle { corner, u: side_u, v: side_v } => LightSample {
+                point: corner + side_u * u.0 + side_v * u.1,
+                normal: Some(side_u.cross(side_v).normalize()),
+                pdf: 1.0 / self.area(),
+            },
+            Shape::Disc { centre, normal, radius } => {
+                let (s, t) = basis(*normal);
+                let r = radius * u.0.sqrt();
+                let angle = 2.0 * PI * u.1;
+                LightSample {
+                    point: centre + r * (angle.cos() * s + angle.sin() * t),
+                    normal: Some(*normal),
+                    pdf: 1.0 / self.area(),
+                }
+            },
+            Shape::Sphere { centre, radius } => {
+                let to_from = from - centre;
+                // Inside the sphere all of it can be seen
+                let (axis, z, pdf) = if to_from.norm() > *radius {
+                    (to_from.normalize(), u.0, 2.0 / self.area())
+                } else {
+                    (Vec3::new(0.0, 0.0, 1.0), 1.0 - 2.0 * u.0, 1.0 / self.area())
+                };
+                let (s, t) = basis(axis);
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let angle = 2.0 * PI * u.1;
+                let normal = z * axis + r * (angle.cos() * s + angle.sin() * t);
+                LightSample { point: centre + normal * *radius, normal: Some(normal), pdf }
+            },
+            Shape::Mesh { triangles, cumulative_area } => {
+                // The first coordinate picks the triangle, and what is left of it is reused
+                let total = self.area();
+                let target = u.0 * total;
+                let i = cumulative_area.iter().position(|&a| target < a).unwrap_or(triangles.len() - 1);
+                let before = if i == 0 { 0.0 } else { cumulative_area[i - 1] };
+                let within = ((target - before) / (cumulative_area[i] - before)).clamp(0.0, 1.0);
+
+                let [a, b, c] = triangles[i];
+                let su = within.sqrt();
+                LightSample {
+                    point: a * (1.0 - su) + b * (u.1 * su) + c * (su * (1.0 - u.1)),
+                    normal: Some((b - a).cross(&(c - a)).normalize()),
+                    pdf: 1.0 / total,
+                }
+            },
+        }
+    }
+
+    /// How likely `sample` is to pick a point on the light, per unit solid angle seen from
+    /// `from`. Zero for point lights, which can only be sampled.
+    ///
+    /// # Arguments
+    /// * `from` - The point being lit
+    /// * `point` - The point on the light
+    /// * `normal` - The direction the light's surface faces at the point
+    pub fn pdf(&self, from: Vec3, point: Vec3, normal: Vec3) -> f64 {
+        let pdf_area = match &self.shape {
+            Shape::Point(_) => return 0.0,
+            Shape::Sphere { centre, radius } if (from - centre).norm() > *radius => 2.0 / self.area(),
+            _ => 1.0 / self.area()
+        };
+        let to_from = from - point;
+        let distance2 = to_from.norm_squared();
+        let cos = normal.dot(&to_from).abs() / distance2.sqrt();
+        if cos == 0.0 {
+            return 0.0;
+        }
+        pdf_area * distance2 / cos
+    }
+
+    /// Pick a point on the light and a direction for light to leave it in. Area lights send
+    /// more light out straight from their surface than at a glancing angle.
+    ///
+    /// # Arguments
+    /// * `u_point` - A point in the unit square for picking the point
+    /// * `u_direction` - A point in the unit square for picking the direction
+    pub fn sample_emission(&self, u_point: (f64, f64), u_direction: (f64, f64)) -> EmissionSample {
+        let sample = match &self.shape {
+            // Light leaves every part of a sphere, not just the half facing one point
+            Shape::Sphere { centre, radius } => {
+                let normal = uniform_sphere(u_point);
+                LightSample { point: centre + normal * *radius, normal: Some(normal), pdf: 1.0 / self.area() }
+            },
+            _ => self.sample(Vec3::zeros(), u_point)
+        };
+        let direction = match sample.normal {
+            None => uniform_sphere(u_direction),
+            Some(normal) => {
+                let (s, t) = basis(normal);
+                let r = u_direction.0.sqrt();
+                let angle = 2.0 * PI * u_direction.1;
+                s * (r * angle.cos()) + t * (r * angle.sin()) + normal * (1.0 - u_direction.0).max(0.0).sqrt()
+            }
+        };
+        let (pdf_position, pdf_direction) = self.pdf_emission(sample.normal.unwrap_or_else(Vec3::zeros), &direction);
+        EmissionSample { point: sample.point, normal: sample.normal, direction, pdf_position, pdf_direction }
+    }
+
+    /// How likely `sample_emission` is to pick a point on the light, per unit area, and a
+    /// direction from it, per unit solid angle.
+    ///
+    /// # Arguments
+    /// * `normal` - The direction the light's surface faces at the point
+    /// * `direction` - The direction light leaves in, which must be normalised
+    pub fn pdf_emission(&self, normal: Vec3, direction: &Vec3) -> (f64, f64) {
+        match self.shape {
+            Shape::Point(_) => (1.0, 1.0 / (4.0 * PI)),
+            _ => (1.0 / self.area(), normal.dot(direction).max(0.0) / PI)
+        }
+    }
+
+    /// Where a ray first hits the light, as a distance along it. Only lights which are
+    /// not part of the model can be hit. Point and mesh lights give `None`.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        let on_plane = |point: &Vec3, normal: &Vec3| {
+            let denominator = normal.dot(&ray.direction);
+            if denominator == 0.0 {
+                return None;
+            }
+            let t = normal.dot(&(point - ray.origin)) / denominator;
+            if ray.contains(t) { Some(t) } else { None }
+        };
+        match &self.shape {
+            Shape::Rectangle { corner, u, v } => {
+                let normal = u.cross(v);
+                let t = on_plane(corner, &normal)?;
+                let local = ray.at(t) - corner;
+                let n2 = normal.norm_squared();
+                let a = local.cross(v).dot(&normal) / n2;
+                let b = u.cross(&local).dot(&normal) / n2;
+                if (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b) {
+                    Some((t, normal.normalize()))
+                } else {
+                    None
+                }
+            },
+            Shape::Disc { centre, normal, radius } => {
+                let t = on_plane(centre, normal)?;
+                if (ray.at(t) - centre).norm() <= *radius { Some((t, *normal)) } else { None }
+            },
+            Shape::Sphere { centre, radius } => {
+                let oc = ray.origin - centre;
+                let b = oc.dot(&ray.direction);
+                let discriminant = b * b - (oc.norm_squared() - radius * radius);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                let t = [-b - root, -b + root].iter().copied().find(|&t| ray.contains(t))?;
+                Some((t, (ray.at(t) - centre) / *radius))
+            },
+            Shape::Point(_) | Shape::Mesh { .. } => None
+        }
+    }
+
+    /// Whether the light at a sampled point shines towards another point.
+    pub fn shines_towards(&self, sample: &LightSample, to: Vec3) -> bool {
+        match sample.normal {
+            Some(normal) => normal.dot(&(to - sample.point)) > 0.0,
+            None => true
+        }
+    }
+}
+
+/// Parse a list of numbers written as `a,b,c`.
+fn parse_numbers(s: &str) -> Result<Vec<f64>, String> {
+    s.split(',')
+     .map(|n| n.trim().parse::<f64>().map_err(|_| format!("'{}' is not a number", n)))
+     .collect()
+}
+
+fn parse_vector(s: &str) -> Result<Vec3, String> {
+    match parse_numbers(s)?.as_slice() {
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("'{}' is not a vector, expected X,Y,Z", s))
+    }
+}
+
+fn parse_number(s: &str) -> Result<f64, String> {
+    s.trim().parse::<f64>().map_err(|_| format!("'{}' is not a number", s))
+}
+
+impl FromStr for Light {
+    type Err = String;
+
+    /// Read a light written as one of
+    /// `point:X,Y,Z`, `rect:X,Y,Z:UX,UY,UZ:VX,VY,VZ`, `disc:X,Y,Z:NX,NY,NZ:RADIUS` or
+    /// `sphere:X,Y,Z:RADIUS`, optionally followed by `@R,G,B` for the light given off.
+    /// Lights other than points must have some area.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (shape, emission) = match s.rfind('@') {
+            Some(i) => (&s[..i], Some(parse_vector(&s[i + 1..])?)),
+            None => (s, None)
+        };
+        let parts: Vec<&str> = shape.split(':').collect();
+        let light = match (parts[0].to_lowercase().as_str(), &parts[1..]) {
+            ("point", [p]) => Light::point(parse_vector(p)?),
+            ("rect", [c, u, v]) => Light::rectangle(parse_vector(c)?, parse_vector(u)?, parse_vector(v)?),
+            ("disc", [c, n, r]) => Light::disc(parse_vector(c)?, parse_vector(n)?, parse_number(r)?),
+            ("sphere", [c, r]) => Light::sphere(parse_vector(c)?, parse_number(r)?),
+            _ => return Err(format!("unknown light '{}', expected point:X,Y,Z, rect:X,Y,Z:UX,UY,UZ:VX,VY,VZ, \
+                                     disc:X,Y,Z:NX,NY,NZ:RADIUS or sphere:X,Y,Z:RADIUS", s))
+        };
+        // Samples on a light are picked with a likelihood of one over its area
+        let has_area = match &light.shape {
+            Shape::Point(_) => true,
+            Shape::Disc { radius, .. } | Shape::Sphere { radius, .. } => *radius > 0.0,
+            _ => light.area() > 0.0
+        };
+        if !has_area {
+            return Err(format!("light '{}' has no area", s));
+        }
+        Ok(match emission {
+            Some(e) => light.with_emission(e),
+            None => light
+        })
+    }
+}
+
+/// The faces of a scene whose materials give off light, as one light per material.
+pub fn emissive_lights(scene: &Scene) -> Vec<Light> {
+    let mut by_material: BTreeMap<usize, Vec<[Vec3; 3]>> = BTreeMap::new();
+    for mesh in &scene.meshes {
+        for face in &mesh.faces {
+            if let Some(m) = face.material().or(mesh.material) {
+                if scene.materials.get(m).map(|m| m.emission != Vec3::zeros()) == Some(true) {
+                    by_material.entry(m).or_default().push(face.vertices());
+                }
+            }
+        }
+    }
+    by_material.into_iter()
+               .filter_map(|(m, triangles)| {
+                   Light::mesh(triangles).map(|l| l.with_emission(scene.materials[m].emission).with_material(m))
+               })
+               .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emissive_lights, Light, Shape};
+    use geometry::{Face, Material, Mesh, Ray, Scene, Vec3};
+    use std::f64::consts::PI;
+
+    fn grid() -> Vec<(f64, f64)> {
+        (0..100).map(|i| ((i % 10) as f64 / 10.0 + 0.05, (i / 10) as f64 / 10.0 + 0.05)).collect()
+    }
+
+    #[test]
+    fn test_samples_on_shapes() {
+        let from = Vec3::new(0.0, 0.0, 10.0);
+        let rect = Light::rectangle(Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 3.0, 0.0));
+        let disc = Light::disc(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, -2.0), 0.5);
+        let sphere = Light::sphere(Vec3::new(1.0, 2.0, 3.0), 2.0);
+        for u in grid() {
+            let s = rect.sample(from, u);
+            assert!(s.point.x >= 0.0 && s.point.x <= 2.0 && s.point.y >= 0.0 && s.point.y <= 3.0 && s.point.z == 0.0);
+            assert_eq!(s.normal, Some(Vec3::new(0.0, 0.0, 1.0)));
+            assert!(rect.shines_towards(&s, from));
+
+            let s = disc.sample(from, u);
+            assert!((s.point - Vec3::new(1.0, 1.0, 1.0)).norm() <= 0.5 + 1e-12);
+            assert!((s.point.z - 1.0).abs() < 1e-12);
+            assert!(!disc.shines_towards(&s, from));
+
+            let s = sphere.sample(from, u);
+            assert!(((s.point - Vec3::new(1.0, 2.0, 3.0)).norm() - 2.0).abs() < 1e-12);
+            // Only the half facing the point being lit
+            assert!(s.normal.unwrap().dot(&(from - Vec3::new(1.0, 2.0, 3.0))) >= 0.0);
+        }
+        assert_eq!(rect.area(), 6.0);
+        assert_eq!(rect.sample(from, (0.5, 0.5)).pdf, 1.0 / 6.0);
+    }
+
+    #[test]
+    fn test_mesh_light() {
+        let big = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)];
+        let small = [Vec3::new(10.0, 0.0, 0.0), Vec3::new(11.0, 0.0, 0.0), Vec3::new(10.0, 1.0, 0.0)];
+        let flat = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)];
+        let light = Light::mesh(vec![big, small, flat]).unwrap();
+        assert_eq!(light.area(), 3.5);
+        if let Shape::Mesh { triangles, .. } = &light.shape {
+            assert_eq!(triangles.len(), 2);
+        }
+
+        // Triangles are picked in proportion to their area
+        let on_small = (0..1000).filter(|&i| {
+            light.sample(Vec3::zeros(), ((i as f64 + 0.5) / 1000.0, 0.5)).point.x >= 10.0
+        }).count();
+        assert!((on_small as i32 - 143).abs() <= 1);
+        for u in grid() {
+            let p = light.sample(Vec3::zeros(), u).point;
+            let inside = if p.x >= 10.0 { p.x - 10.0 + p.y <= 1.0 + 1e-12 } else { p.x / 3.0 + p.y / 2.0 <= 1.0 + 1e-12 };
+            assert!(inside && p.x >= 0.0 && p.y >= 0.0);
+        }
+        assert_eq!(Light::mesh(vec![flat]), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("point:1,2,3".parse::<Light>(), Ok(Light::point(Vec3::new(1.0, 2.0, 3.0))));
+        assert_eq!("sphere:0,0,0:2@5,5,5".parse::<Light>(),
+                   Ok(Light::sphere(Vec3::zeros(), 2.0).with_emission(Vec3::repeat(5.0))));
+        assert!("rect:0,0,0:1,0,0:0,1,0".parse::<Light>().is_ok());
+        assert!("disc:0,0,0:0,0,1".parse::<Light>().is_err());
+        assert!("cone:0,0,0".parse::<Light>().is_err());
+
+        assert!("disc:0,0,0:0,0,1:0".parse::<Light>().is_err());
+        assert!("sphere:0,0,0:0".parse::<Light>().is_err());
+        assert!("sphere:0,0,0:-1".parse::<Light>().is_err());
+        assert!("rect:0,0,0:1,0,0:2,0,0".parse::<Light>().is_err());
+    }
+
+    #[test]
+    fn test_emissive_lights() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(1.0, 0.0, 0.0);
+        let c = Vec3::new(0.0, 1.0, 0.0);
+        let mut scene = Scene::new();
+        let plain = scene.add_material(Material::named("plain"));
+        let mut glowing = Material::named("glowing");
+        glowing.emission = Vec3::new(2.0, 2.0, 1.0);
+        let glowing = scene.add_material(glowing);
+        let mut lamp = Mesh::new(None, vec![Face::from_points(a, b, c), Face::from_points(a, c, b).with_material(Some(plain))]);
+        lamp.material = Some(glowing);
+        scene.meshes.push(lamp);
+
+        let lights = emissive_lights(&scene);
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights[0].emission, Vec3::new(2.0, 2.0, 1.0));
+        assert_eq!(lights[0].area(), 0.5);
+        assert_eq!(lights[0].material, Some(glowing));
+    }
+
+    #[test]
+    fn test_intersect_and_pdf() {
+        let down = |x: f64, y: f64| Ray::new(Vec3::new(x, y, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let rect = Light::rectangle(Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 3.0, 0.0));
+        assert_eq!(rect.intersect(&down(2.5, 2.0)).map(|h| h.0), Some(10.0));
+        assert_eq!(rect.intersect(&down(0.2, 2.0)), None);
+        let disc = Light::disc(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0), 1.0);
+        assert_eq!(disc.intersect(&down(0.5, 0.5)), Some((9.0, Vec3::new(0.0, 0.0, 1.0))));
+        assert_eq!(disc.intersect(&down(0.8, 0.8)), None);
+        let sphere = Light::sphere(Vec3::new(0.0, 0.0, 0.0), 2.0);
+        assert_eq!(sphere.intersect(&down(0.0, 0.0)), Some((8.0, Vec3::new(0.0, 0.0, 1.0))));
+        assert_eq!(Light::point(Vec3::zeros()).intersect(&down(0.0, 0.0)), None);
+
+        // Straight above the middle of a 6 unit area light, 10 away
+        let from = Vec3::new(1.5, 1.5, 10.0);
+        let pdf = rect.pdf(from, Vec3::new(1.5, 1.5, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((pdf - 100.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_emission() {
+        let disc = Light::disc(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0), 2.0);
+        let sphere = Light::sphere(Vec3::new(1.0, 0.0, 0.0), 1.0);
+        let point = Light::point(Vec3::new(0.0, 3.0, 0.0));
+        for (u, v) in grid().into_iter().zip(grid().into_iter().rev()) {
+            let e = disc.sample_emission(u, v);
+            assert!((e.direction.norm() - 1.0).abs() < 1e-9);
+            // Light leaves the front, more of it straight out
+            assert!(e.direction.z >= 0.0);
+            assert!((e.pdf_direction - e.direction.z / PI).abs() < 1e-9);
+            assert!((e.pdf_position - 1.0 / (4.0 * PI)).abs() < 1e-9);
+            assert_eq!(disc.pdf_emission(e.normal.unwrap(), &e.direction), (e.pdf_position, e.pdf_direction));
+
+            // Any point on a sphere, not only the half facing the origin
+            let e = sphere.sample_emission(u, v);
+            assert!(((e.point - Vec3::new(1.0, 0.0, 0.0)).norm() - 1.0).abs() < 1e-9);
+            assert!(e.normal.unwrap().dot(&e.direction) >= 0.0);
+            assert!((e.pdf_position - 1.0 / (4.0 * PI)).abs() < 1e-9);
+
+            let e = point.sample_emission(u, v);
+            assert_eq!(e.point, Vec3::new(0.0, 3.0, 0.0));
+            assert_eq!(e.normal, None);
+            assert_eq!((e.pdf_position, e.pdf_direction), (1.0, 1.0 / (4.0 * PI)));
+        }
+        let below = grid().into_iter().filter(|u| sphere.sample_emission(*u, (0.5, 0.5)).point.z < 0.0).count();
+        assert_eq!(below, 50);
+    }
+}
@@ -1,11 +1,13 @@
-use argparse::{ArgumentParser, Store, StoreOption, StoreTrue};
+use argparse::{ArgumentParser, List, Store, StoreOption, StoreTrue};
 
-use geometry::{Face, Plane, Ray, Vec3};
+use geometry::{Bounded, Face, Material, Ray, Scene, Strictness, Vec3};
 
 use nalgebra::{Rotation3, Unit};
 
 use bvh::BoundingVolumeHierarchy;
 
+use mesh_tools::{repair_scene, smooth_scene_normals, NormalWeighting, RepairOptions};
+
 use std::f64;
 use std::fs::File;
 use std::io::BufWriter;
@@ -15,9 +17,33 @@ use std::vec::Vec;
 mod stack;
 use stack::stack;
 
+mod bdpt;
+mod bsdf;
+mod camera;
+mod debug;
+mod environment;
+mod film;
+mod info;
 mod lambert;
+mod light;
+mod occlusion;
+mod path;
+mod photon;
+mod sampler;
+mod shading;
+#[cfg(test)]
+mod test_scene;
 mod whitted;
 
+use bsdf::BsdfKind;
+use camera::Pinhole;
+use film::{Film, Filter};
+use environment::Environment;
+use light::Light;
+use path::{MisHeuristic, PathSettings};
+use photon::{PhotonMaps, PhotonSettings};
+use sampler::{Sampler, SamplerKind};
+
 use enum_from_str::ParseEnumVariantError;
 use enum_from_str_derive::FromStr;
 
@@ -33,19 +59,54 @@ use winit_input_helper::WinitInputHelper;
 enum Renderer {
     Whitted,
     Lambert,
+    Path,
+    Bdpt,
+    PhotonMap,
+    AmbientOcclusion,
+    ShadingNormal,
+    GeometricNormal,
+    Facing,
+    Depth,
+    Barycentric,
+    FaceId,
+    BvhCost,
 }
 
 #[derive(Debug)]
 struct World {
     pub camera: Camera,
     pub model: BoundingVolumeHierarchy<Face>,
+    pub materials: Vec<Material>,
+    pub lights: Vec<Light>,
+    /// Light from far away in every direction, also seen behind the model
+    pub environment: Option<Environment>,
     pub renderer: RenderSetup,
 }
 
 #[derive(Debug, Copy, Clone)]
 struct RenderSetup {
     pub algorithm: Renderer,
-    pub max_depth: u8
+    pub max_depth: u8,
+    /// Rays per pixel
+    pub samples: usize,
+    /// How the samples are combined into pixels
+    pub filter: Filter,
+    /// Where the samples go
+    pub sampler: SamplerKind,
+    /// Shadow rays sent to each light with an area, for each ray which hits something
+    pub shadow_samples: usize,
+    /// How the path tracer weights light and BSDF samples
+    pub mis: MisHeuristic,
+    /// What surfaces are made of for the path tracer
+    pub bsdf: BsdfKind,
+    /// A roughness for every surface, instead of the materials' own
+    pub roughness: Option<f64>,
+    /// How the photon mapper sends out and gathers photons
+    pub photon: PhotonSettings,
+    /// Rays sent out from each surface seen for ambient occlusion
+    pub ao_samples: usize,
+    /// How far away the model can be and still shade a surface with ambient occlusion
+    pub ao_distance: f64,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -64,50 +125,118 @@ impl World {
         //print!("Rendering start - ");
         let y_fov: f64 = deg_to_rad(90.0);
 
-        let left = self.camera.forwards.cross(&self.camera.up).normalize();
-
-        let y_dist_up: f64 = f64::tan(y_fov / 2.0);
-        let x_dist_left: f64 = y_dist_up * x_res as f64 / y_res as f64;
-
-        let right_step = left * x_dist_left / (x_res as f64 / -2.0);
-        let down_step = self.camera.up * y_dist_up / (y_res as f64 / -2.0);
-
-        let top_center = self.camera.position + self.camera.forwards + y_dist_up * self.camera.up;
-
-        let top_left = top_center + left * x_dist_left;
-
-        let light = vec![Vec3::new(-100.0, 0.0, 0.0)];
+        let pinhole = Pinhole::new(self.camera.position, self.camera.forwards, self.camera.up, y_fov, x_res, y_res);
 
         let ambient_intensity = 0.2; // Ia
         let diffuse_reflection_constant = 0.9; // kd
         let specular_reflection_constant = 0.9; // ks
         let transmission_coefficient = 0.0; // kt
 
-        let colour = Vec3::new(20.0, 120.0, 220.0); // white
-
-        for y in 0..y_res {
-            for x in 0..x_res {
-                let point = top_left + right_step * (x as f64) + down_step * (y as f64);
-                let ray = Ray::new(self.camera.position, point - self.camera.position);
-                let i = match self.renderer.algorithm {
-                    Renderer::Lambert => lambert::trace(&ray, &self.model),
-                    Renderer::Whitted => whitted::trace(
-                        &ray,
-                        &self.model,
-                        &light,
-                        ambient_intensity,
-                        diffuse_reflection_constant,
-                        specular_reflection_constant,
-                        transmission_coefficient,
-                        self.renderer.max_depth,
-                    ),
-                };
-                data[(y * x_res * 4) + (x * 4)    ] = (i * colour.x) as u8;
-                data[(y * x_res * 4) + (x * 4) + 1] = (i * colour.y) as u8;
-                data[(y * x_res * 4) + (x * 4) + 2] = (i * colour.z) as u8;
-                data[(y * x_res * 4) + (x * 4) + 3] = 255;
+        // The far side of the model is black in the depth view
+        let far = (self.model.max_extents() - self.model.min_extents()).norm()
+            + (self.camera.position - (self.model.min_extents() + self.model.max_extents()) / 2.0).norm();
+        // A well balanced BVH tests a few boxes for each level it has
+        let full_cost = 4.0 * (self.model.size().max(2) as f64).log2();
+
+        let path_settings = PathSettings {
+            max_depth: self.renderer.max_depth,
+            heuristic: self.renderer.mis,
+            bsdf: self.renderer.bsdf,
+            roughness: self.renderer.roughness,
+        };
+
+        let trace = |ray: &Ray, sampler: &mut dyn Sampler, film: &mut Film, maps: Option<&PhotonMaps>| match self.renderer.algorithm {
+            Renderer::Lambert => lambert::trace(ray, &self.model, &self.materials, self.environment.as_ref()),
+            Renderer::Whitted => whitted::trace(
+                ray,
+                &self.model,
+                &self.materials,
+                &self.lights,
+                self.environment.as_ref(),
+                sampler,
+                self.renderer.shadow_samples,
+                ambient_intensity,
+                diffuse_reflection_constant,
+                specular_reflection_constant,
+                transmission_coefficient,
+                self.renderer.max_depth,
+            ),
+            Renderer::Path => path::trace(
+                ray,
+                &self.model,
+                &self.materials,
+                &self.lights,
+                self.environment.as_ref(),
+                sampler,
+                &path_settings,
+            ),
+            Renderer::Bdpt => bdpt::trace(
+                ray,
+                &self.model,
+                &self.materials,
+                &self.lights,
+                self.environment.as_ref(),
+                &pinhole,
+                sampler,
+                &path_settings,
+                film,
+            ),
+            Renderer::PhotonMap => photon::trace(
+                ray,
+                &self.model,
+                &self.materials,
+                &self.lights,
+                self.environment.as_ref(),
+                maps.expect("photon maps are built before tracing"),
+                sampler,
+                &path_settings,
+            ),
+            Renderer::AmbientOcclusion => occlusion::trace(
+                ray,
+                &self.model,
+                sampler,
+                self.renderer.ao_samples,
+                self.renderer.ao_distance,
+            ),
+            Renderer::ShadingNormal => debug::shading_normal(ray, &self.model),
+            Renderer::GeometricNormal => debug::geometric_normal(ray, &self.model),
+            Renderer::Facing => debug::facing(ray, &self.model),
+            Renderer::Depth => debug::depth(ray, &self.model, far),
+            Renderer::Barycentric => debug::barycentric(ray, &self.model),
+            Renderer::FaceId => debug::face_id(ray, &self.model),
+            Renderer::BvhCost => debug::bvh_cost(ray, &self.model, full_cost),
+        };
+
+        // A single sample goes through the pixel centre
+        let samples = self.renderer.samples.max(1);
+        let mut sampler = self.renderer.sampler.create(samples);
+
+        // Light traced to the camera from every sample is splatted onto the film
+        let mut film = Film::new(x_res, y_res, self.renderer.filter).with_splat_scale(1.0 / samples as f64);
+        let mut maps = None;
+        for s in 0..samples {
+            // Photons are sent out once, or again for every pass when progressive
+            if self.renderer.algorithm == Renderer::PhotonMap && (maps.is_none() || self.renderer.photon.progressive) {
+                maps = Some(PhotonMaps::build(&self.model, &self.materials, &self.lights, &path_settings,
+                                              &self.renderer.photon, s));
+            }
+            if s == 0 {
+                if let Some(maps) = &maps {
+                    println!("Photon maps have {} caustic and {} global photons", maps.caustic.len(), maps.global.len());
+                }
+            }
+            for y in 0..y_res {
+                for x in 0..x_res {
+                    sampler.start_sample(x, y, s);
+                    let (dx, dy) = if samples == 1 { (0.5, 0.5) } else { sampler.next_2d() };
+                    let (px, py) = (x as f64 + dx, y as f64 + dy);
+                    let ray = pinhole.ray(px, py);
+                    let colour = trace(&ray, sampler.as_mut(), &mut film, maps.as_ref());
+                    film.add_sample(px, py, colour);
+                }
             }
         }
+        film.write_rgba(data);
 
         //println!("complete");
     }
@@ -148,23 +277,161 @@ impl World {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("info") {
+        let mut args = args;
+        let command = args.remove(1);
+        args[0] = format!("{} {}", args[0], command);
+        std::process::exit(info::run(args));
+    }
+
     let mut filename: Option<String> = None;
     let mut show_window = false;
+    let mut lenient = false;
     let mut output_filename = String::from("image.png");
     let mut algorithm = Renderer::Lambert;
+    let mut samples: usize = 1;
+    let mut filter = Filter::Box;
+    let mut sampler = SamplerKind::Stratified;
+    let mut light_specs: Vec<String> = Vec::new();
+    let mut shadow_samples: usize = 16;
+    let mut environment_filename: Option<String> = None;
+    let mut environment_rotation = 0.0;
+    let mut environment_intensity = 1.0;
+    let mut mis = MisHeuristic::Power;
+    let mut bsdf = BsdfKind::Model;
+    let mut roughness: Option<f64> = None;
+    let mut max_depth: u8 = 3;
+    let mut photons: usize = 200000;
+    let mut photon_nearest: usize = 100;
+    let mut photon_radius: Option<f64> = None;
+    let mut gather_rays: usize = 16;
+    let mut progressive = false;
+    let mut ao_samples: usize = 16;
+    let mut ao_distance: Option<f64> = None;
+    let mut selected: Vec<String> = Vec::new();
+    let mut hidden: Vec<String> = Vec::new();
+    let mut colours: Vec<String> = Vec::new();
+    let mut export_filename: Option<String> = None;
+    let mut repair = false;
+    let mut repair_options = RepairOptions::default();
+    let mut smooth = false;
+    let mut crease_angle = 60.0;
+    let mut weighting = NormalWeighting::Angle;
     let x_res = 1024;
     let y_res = 768;
 
-    let max_depth = 3;
-
-
     {
         let mut ap = ArgumentParser::new();
-        ap.set_description("Render output to image.png");
+        ap.set_description("Render output to image.png. Use `vitrum info` to check a model instead.");
         ap.refer(&mut algorithm).add_option(
             &["-a", "--algorithm"],
             Store,
-            "Rendering algorithm to use. Options are: Lambert (default), Whitted.",
+            "Rendering algorithm to use. Options are: Lambert (default), Whitted, Path, Bdpt, PhotonMap, \
+             AmbientOcclusion, or for checking the model ShadingNormal, GeometricNormal, Facing, Depth, \
+             Barycentric, FaceId, BvhCost.",
+        );
+        ap.refer(&mut samples).add_option(
+            &["-s", "--samples"],
+            Store,
+            "Rays per pixel, spread over the pixel to smooth jagged edges (default 1).",
+        );
+        ap.refer(&mut filter).add_option(
+            &["--filter"],
+            Store,
+            "How samples are combined into pixels. Options are: Box (default), Tent, Gaussian, Mitchell.",
+        );
+        ap.refer(&mut sampler).add_option(
+            &["--sampler"],
+            Store,
+            "Where samples go. Options are: Independent, Stratified (default), Halton, Sobol, BlueNoise.",
+        );
+        ap.refer(&mut light_specs).add_option(
+            &["--light"],
+            List,
+            "Lights, each written as point:X,Y,Z, rect:X,Y,Z:UX,UY,UZ:VX,VY,VZ, disc:X,Y,Z:NX,NY,NZ:RADIUS \
+             or sphere:X,Y,Z:RADIUS, optionally followed by @R,G,B for its brightness. Faces with an \
+             emissive material are lights too. Default is a point light at -100,0,0.",
+        );
+        ap.refer(&mut shadow_samples).add_option(
+            &["--shadow-samples"],
+            Store,
+            "Shadow rays sent to each light with an area, giving soft shadows (default 16).",
+        );
+        ap.refer(&mut environment_filename).add_option(
+            &["--environment"],
+            StoreOption,
+            "Light the model with an equirectangular HDR image (.hdr or .pfm), also used as the background.",
+        );
+        ap.refer(&mut environment_rotation).add_option(
+            &["--environment-rotation"],
+            Store,
+            "Turn the environment about the up axis by this many degrees (default 0).",
+        );
+        ap.refer(&mut environment_intensity).add_option(
+            &["--environment-intensity"],
+            Store,
+            "Scale the brightness of the environment (default 1).",
+        );
+        ap.refer(&mut max_depth).add_option(
+            &["--max-depth"],
+            Store,
+            "Most times a ray can bounce for the Whitted, Path, Bdpt and PhotonMap renderers (default 3).",
+        );
+        ap.refer(&mut mis).add_option(
+            &["--mis"],
+            Store,
+            "How the Path and Bdpt renderers weight the different ways they can find the same light. \
+             Options are: Balance, Power (default).",
+        );
+        ap.refer(&mut bsdf).add_option(
+            &["--bsdf"],
+            Store,
+            "What surfaces are made of for the Path, Bdpt and PhotonMap renderers. Options are: Model (default, from the \
+             model's materials), Lambertian, OrenNayar, Principled, Gold, Silver, Copper, Aluminium, Glass.",
+        );
+        ap.refer(&mut roughness).add_option(
+            &["--roughness"],
+            StoreOption,
+            "Roughness of every surface for the Path, Bdpt and PhotonMap renderers, from 0 (polished) to 1.",
+        );
+        ap.refer(&mut photons).add_option(
+            &["--photons"],
+            Store,
+            "Photons sent out from the lights by the PhotonMap renderer, for each progressive pass (default 200000).",
+        );
+        ap.refer(&mut photon_nearest).add_option(
+            &["--photon-nearest"],
+            Store,
+            "How many of the nearest photons make up each estimate of the light (default 100).",
+        );
+        ap.refer(&mut photon_radius).add_option(
+            &["--photon-radius"],
+            StoreOption,
+            "Furthest a photon can be from a point and count, or the starting distance when progressive \
+             (default a fiftieth of the size of the model).",
+        );
+        ap.refer(&mut gather_rays).add_option(
+            &["--gather-rays"],
+            Store,
+            "Final gather rays sent out for each point seen, or 0 to use the global photon map directly (default 16).",
+        );
+        ap.refer(&mut progressive).add_option(
+            &["--progressive"],
+            StoreTrue,
+            "Send out new photons for every sample, gathering them from a shrinking distance so caustics come out \
+             sharp as samples are added.",
+        );
+        ap.refer(&mut ao_samples).add_option(
+            &["--ao-samples"],
+            Store,
+            "Rays sent out from each surface seen by the AmbientOcclusion renderer (default 16).",
+        );
+        ap.refer(&mut ao_distance).add_option(
+            &["--ao-distance"],
+            StoreOption,
+            "Furthest away the model still darkens a surface with ambient occlusion (default a tenth of the \
+             size of the model).",
         );
         ap.refer(&mut filename)
             .add_option(&["-f", "--file"], StoreOption, "File to parse")
@@ -174,6 +441,61 @@ fn main() {
             Store,
             "Output image filename. Format  will always be PNG.",
         );
+        ap.refer(&mut selected).add_option(
+            &["--select"],
+            List,
+            "Names of the parts (objects or groups) of the model to render. Default is all of them.",
+        );
+        ap.refer(&mut hidden).add_option(
+            &["--hide"],
+            List,
+            "Names of parts (objects or groups) of the model not to render.",
+        );
+        ap.refer(&mut colours).add_option(
+            &["--colour"],
+            List,
+            "Colours for parts (objects or groups) of the model, each written as NAME=RRGGBB (hex).",
+        );
+        ap.refer(&mut export_filename).add_option(
+            &["--export"],
+            StoreOption,
+            "Save the (selected and coloured) model to this file before rendering. Must be STL.",
+        );
+        ap.refer(&mut lenient).add_option(
+            &["--lenient"],
+            StoreTrue,
+            "Skip (or repair) bad parts of the model file with a warning, instead of failing.",
+        );
+        ap.refer(&mut repair).add_option(
+            &["--repair"],
+            StoreTrue,
+            "Remove degenerate and duplicate triangles, fix the winding and fill small holes before exporting or rendering.",
+        );
+        ap.refer(&mut repair_options.weld_tolerance).add_option(
+            &["--weld"],
+            Store,
+            "When repairing, also merge vertices closer than this distance. Default is 0 (only identical vertices).",
+        );
+        ap.refer(&mut repair_options.max_hole_edges).add_option(
+            &["--max-hole"],
+            Store,
+            "When repairing, only fill holes with at most this many edges (default 16, 0 to fill none).",
+        );
+        ap.refer(&mut smooth).add_option(
+            &["--smooth"],
+            StoreTrue,
+            "Replace the vertex normals with smooth ones computed from the faces around each vertex.",
+        );
+        ap.refer(&mut crease_angle).add_option(
+            &["--crease"],
+            Store,
+            "When smoothing, faces meeting at more than this angle in degrees keep a sharp edge (default 60).",
+        );
+        ap.refer(&mut weighting).add_option(
+            &["--normal-weighting"],
+            Store,
+            "When smoothing, weight the faces around a vertex by their Angle (default) or Area.",
+        );
         ap.refer(&mut show_window).add_option(
             &["-w", "--window"],
             StoreTrue,
@@ -186,9 +508,74 @@ fn main() {
 
     println!("You have selected the file {} to open", filename);
 
-    let model = file_loader::load_file(&filename).unwrap();
+    let mut registry = file_loader::LoaderRegistry::default();
+    if lenient {
+        registry.set_strictness(Strictness::Lenient);
+    }
+    let mut scene = match registry.load_file(&filename) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for warning in &scene.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    print!("{}", scene);
+
+    if !selected.is_empty() {
+        scene.select(&selected);
+    }
+    scene.hide(&hidden);
+    for spec in &colours {
+        colour_part(&mut scene, spec);
+    }
+    if repair {
+        let summary = repair_scene(&mut scene, &repair_options);
+        println!("Repaired model: {}", summary);
+    }
+    if smooth {
+        smooth_scene_normals(&mut scene, weighting, crease_angle);
+    }
+    if let Some(path) = &export_filename {
+        match file_loader::save_file(path, &scene) {
+            Ok(()) => println!("Saved model to {}", path),
+            Err(e) => eprintln!("Could not save model to {}: {}", path, e)
+        }
+    }
 
-    let model = BoundingVolumeHierarchy::new(model);
+    let mut lights = Vec::new();
+    for spec in &light_specs {
+        match spec.parse::<Light>() {
+            Ok(light) => lights.push(light),
+            Err(e) => println!("Ignoring light {}: {}", spec, e)
+        }
+    }
+    lights.extend(light::emissive_lights(&scene));
+    let environment = match environment_filename {
+        Some(path) => match Environment::load(&path) {
+            Ok(e) => Some(e.with_rotation(environment_rotation).with_intensity(environment_intensity)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
+    let environment = match environment {
+        // A path traced model with nothing to light it is lit by a plain white sky
+        None if lights.is_empty() && matches!(algorithm, Renderer::Path | Renderer::Bdpt | Renderer::PhotonMap) => {
+            Some(Environment::new(1, 1, vec![Vec3::repeat(1.0)]))
+        },
+        e => e
+    };
+    if lights.is_empty() && environment.is_none() {
+        lights.push(Light::point(Vec3::new(-100.0, 0.0, 0.0)));
+    }
+
+    let materials = std::mem::take(&mut scene.materials);
+    let model = BoundingVolumeHierarchy::new(scene.into_faces());
     //let model = stack(model);
     println!(
         "BVH has {} faces with extents {} {}",
@@ -221,9 +608,28 @@ fn main() {
         },
         renderer: RenderSetup {
             algorithm,
-            max_depth
+            max_depth,
+            samples,
+            filter,
+            sampler,
+            shadow_samples,
+            mis,
+            bsdf,
+            roughness,
+            photon: PhotonSettings {
+                photons,
+                nearest: photon_nearest,
+                radius: photon_radius,
+                gather_rays,
+                progressive,
+            },
+            ao_samples,
+            ao_distance: ao_distance.unwrap_or(dist / 10.0),
         },
         model,
+        materials,
+        lights,
+        environment,
     };
 
     if !show_window {
@@ -337,6 +743,34 @@ fn main() {
     });
 }
 
+/// Give a named part (object or group) of the scene its own colour.
+///
+/// # Arguments
+/// * `scene` - the scene containing the part
+/// * `spec` - the part and colour, written as NAME=RRGGBB
+fn colour_part(scene: &mut Scene, spec: &str) {
+    let parsed = spec.rfind('=').and_then(|i| {
+        let hex = &spec[i + 1..];
+        let channel = |c: usize| hex.get(c..c + 2).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Some((&spec[..i], Vec3::new(r as f64, g as f64, b as f64) / 255.0)),
+            _ => None
+        }
+    });
+
+    match parsed {
+        None => println!("Ignoring colour {}. Colours must be written as NAME=RRGGBB", spec),
+        Some((name, colour)) => {
+            if !scene.has_part(name) {
+                println!("Cannot colour {}. There is no part with that name", name);
+                return;
+            }
+            let material = scene.add_material(Material::with_colour(name, colour));
+            scene.set_part_material(name, material);
+        }
+    }
+}
+
 /// Convert xy screen coordinates to a unit sphere mapped to the screen for arc ball
 fn screen_to_sphere(x: f64, y: f64, x_res: f64, y_res: f64) -> Vec3 {
 
@@ -0,0 +1,78 @@
+use geometry::{Plane, Ray, Vec3};
+use super::bsdf::{sample_cosine, Frame};
+use super::sampler::Sampler;
+
+/// How open the surface seen along a ray is: the fraction of rays sent out over the
+/// hemisphere around its normal, weighted by cosine, which go at least `max_distance`
+/// without hitting the model. Creases and holes come out dark whatever the lighting.
+///
+/// # Arguments
+/// * `ray` - The ray from the camera
+/// * `model` - The model
+/// * `sampler` - Where the directions of the rays come from
+/// * `samples` - How many rays to send out from the surface (at least one is sent)
+/// * `max_distance` - How far away the model stops shading a point
+pub fn trace<T: Plane>(ray: &Ray, model: &T, sampler: &mut dyn Sampler, samples: usize, max_distance: f64) -> Vec3 {
+    let c = match model.hits(ray) {
+        Some(c) => c,
+        None => return Vec3::zeros()
+    };
+    let normal = c.normal.normalize();
+    let normal = if normal.dot(&ray.direction) > 0.0 { -normal } else { normal };
+    let frame = Frame::from_normal(normal);
+    let samples = samples.max(1);
+    let open = (0..samples).filter(|_| {
+        let direction = frame.to_world(&sample_cosine(sampler.next_2d()));
+        let ray = Ray::leaving(c.contact_point, direction);
+        let t_min = ray.t_min;
+        !model.occluded(&ray.with_interval(t_min, max_distance))
+    }).count();
+    Vec3::repeat(open as f64 / samples as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trace;
+    use crate::sampler::SamplerKind;
+    use crate::test_scene::floor;
+    use geometry::{Face, Ray, Vec3};
+
+    /// A wall standing on the floor along the y axis, a little way to the right of it
+    fn wall(x: f64, height: f64) -> Vec<Face> {
+        vec![Face::from_points(Vec3::new(x, -100.0, 0.0), Vec3::new(x, 100.0, 0.0), Vec3::new(x, 0.0, height))]
+    }
+
+    fn occlusion(model: &Vec<Face>, max_distance: f64) -> f64 {
+        let mut sampler = SamplerKind::Sobol.create(1);
+        sampler.start_sample(0, 0, 0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        trace(&ray, model, sampler.as_mut(), 4096, max_distance).x
+    }
+
+    #[test]
+    fn test_open_floor() {
+        assert_eq!(occlusion(&floor(), 10.0), 1.0);
+        let mut sampler = SamplerKind::Sobol.create(1);
+        let missed = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(trace(&missed, &floor(), sampler.as_mut(), 16, 10.0), Vec3::zeros());
+
+        // No samples still sends one ray out, rather than leaving everything black
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(trace(&ray, &floor(), sampler.as_mut(), 0, 10.0), Vec3::repeat(1.0));
+    }
+
+    #[test]
+    fn test_wall() {
+        // A wall right next to the point hides half of the sky
+        let mut model = floor();
+        model.extend(wall(0.001, 50.0));
+        let open = occlusion(&model, 1000.0);
+        assert!((open - 0.5).abs() < 0.02, "{}", open);
+
+        // Further off than the largest distance, it does not count
+        let mut model = floor();
+        model.extend(wall(2.0, 50.0));
+        assert_eq!(occlusion(&model, 1.0), 1.0);
+        assert!(occlusion(&model, 1000.0) < 1.0);
+    }
+}
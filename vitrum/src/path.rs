@@ -0,0 +1,315 @@
+use geometry::{CollisionDirection, Material, Plane, Ray, Vec3};
+use super::bsdf::{Bsdf, BsdfKind, Frame};
+use super::environment::Environment;
+use super::light::{shadow_ray, Light};
+use super::sampler::Sampler;
+
+use enum_from_str::ParseEnumVariantError;
+use enum_from_str_derive::FromStr;
+
+/// How the light found by sampling the lights and by following the BSDF is combined, so
+/// each counts most where it is least noisy.
+#[derive(Debug, Copy, Clone, PartialEq, FromStr)]
+pub enum MisHeuristic {
+    // Weight each way of finding the light by how likely it was to find it
+    Balance,
+    // As balance, but with the likelihoods squared, which favours the better way more
+    Power,
+}
+
+impl MisHeuristic {
+    /// How much a sample found one way counts.
+    ///
+    /// # Arguments
+    /// * `pdf` - How likely the way used was to find it
+    /// * `other_pdf` - How likely the other way was to find it
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b <= 0.0 { 0.0 } else { a / (a + b) }
+    }
+}
+
+/// How the path tracer should run.
+#[derive(Debug, Copy, Clone)]
+pub struct PathSettings {
+    /// The most surfaces a path can bounce off
+    pub max_depth: u8,
+    pub heuristic: MisHeuristic,
+    /// What the surfaces are made of
+    pub bsdf: BsdfKind,
+    /// A roughness for every surface, instead of the materials' own
+    pub roughness: Option<f64>,
+}
+
+/// The lights that can be sampled directly: the lights, then the environment if there is one.
+struct LightChoice<'a> {
+    lights: &'a [Light],
+    environment: Option<&'a Environment>,
+}
+
+impl LightChoice<'_> {
+    fn count(&self) -> usize {
+        self.lights.len() + self.environment.map_or(0, |_| 1)
+    }
+
+    /// How likely each light is to be picked.
+    fn pick_pdf(&self) -> f64 {
+        1.0 / self.count().max(1) as f64
+    }
+}
+
+/// Follow a path of light back from the camera, bouncing off surfaces in directions picked by
+/// their BSDFs. At each bounce a light is also sampled directly (next event estimation), and
+/// light which either could have found is weighted by multiple importance sampling.
+///
+/// # Arguments
+/// * `ray` - The ray from the camera
+/// * `model` - The model
+/// * `materials` - The materials used by the model
+/// * `lights` - The lights
+/// * `environment` - Light from far away, if there is any
+/// * `sampler` - Where the random numbers come from
+/// * `settings` - How to trace
+pub fn trace<T: Plane>(ray: &Ray, model: &T, materials: &[Material], lights: &[Light],
+                       environment: Option<&Environment>, sampler: &mut dyn Sampler,
+                       settings: &PathSettings) -> Vec3 {
+    let choice = LightChoice { lights, environment };
+    let mut radiance = Vec3::zeros();
+    // How much of the light arriving along the current ray reaches the camera
+    let mut throughput = Vec3::repeat(1.0);
+    let mut ray = ray.clone();
+    // Light found by a ray from the camera or a perfect mirror could not have been sampled
+    let mut specular_bounce = true;
+    let mut bsdf_pdf = 0.0;
+
+    for depth in 0..settings.max_depth {
+        let hit = model.hits(&ray);
+        let hit_distance = hit.as_ref().map_or(f64::INFINITY, |c| c.distance);
+
+        // Lights which are not part of the model
+        let light_hit = lights.iter()
+                              .filter_map(|l| l.intersect(&ray).map(|(t, normal)| (l, t, normal)))
+                              .filter(|(_, t, _)| *t < hit_distance)
+                              .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((light, t, normal)) = light_hit {
+            let point = ray.at(t);
+            if normal.dot(&(ray.origin - point)) > 0.0 {
+                let weight = if specular_bounce {
+                    1.0
+                } else {
+                    settings.heuristic.weight(bsdf_pdf, choice.pick_pdf() * light.pdf(ray.origin, point, normal))
+                };
+                radiance += throughput.component_mul(&light.emission) * weight;
+            }
+            break;
+        }
+
+        let c = match hit {
+            Some(c) => c,
+            None => {
+                if let Some(environment) = environment {
+                    let weight = if specular_bounce {
+                        1.0
+                    } else {
+                        settings.heuristic.weight(bsdf_pdf, choice.pick_pdf() * environment.pdf(&ray.direction))
+                    };
+                    radiance += throughput.component_mul(&environment.radiance(&ray.direction)) * weight;
+                }
+                break;
+            }
+        };
+
+        let default_material = Material::default();
+        let material = c.material.and_then(|m| materials.get(m)).unwrap_or(&default_material);
+        let point = c.contact_point;
+
+        // Surfaces which glow, from their front
+        if material.emission != Vec3::zeros() && c.direction == CollisionDirection::FrontFace {
+            let light = c.material.and_then(|m| lights.iter().find(|l| l.material == Some(m)));
+            let weight = match light {
+                Some(light) if !specular_bounce => {
+                    settings.heuristic.weight(bsdf_pdf, choice.pick_pdf() * light.pdf(ray.origin, point, c.normal))
+                },
+                _ => 1.0
+            };
+            radiance += throughput.component_mul(&material.emission) * weight;
+        }
+
+        let bsdf = settings.bsdf.create(material, settings.roughness);
+        let wo_world = -ray.direction;
+        let normal = c.normal.normalize();
+        let normal = if !bsdf.transmits() && normal.dot(&wo_world) < 0.0 { -normal } else { normal };
+        let frame = Frame::from_normal(normal);
+        let wo = frame.to_local(&wo_world);
+
+        radiance += throughput.component_mul(&sample_light(&choice, model, bsdf.as_ref(), &frame, point, &wo, sampler, settings));
+
+        let sample = match bsdf.sample(&wo, sampler.next_2d(), sampler.next_1d()) {
+            Some(s) if s.pdf > 0.0 => s,
+            _ => break
+        };
+        throughput = throughput.component_mul(&(sample.f * (sample.wi.z.abs() / sample.pdf)));
+        specular_bounce = sample.specular;
+        bsdf_pdf = sample.pdf;
+        ray = Ray::leaving(point, frame.to_world(&sample.wi));
+
+        // Russian roulette: end paths which can add little, making up for it in the others
+        if depth >= 3 {
+            let survive = throughput.max().min(0.95);
+            if sampler.next_1d() >= survive {
+                break;
+            }
+            throughput /= survive;
+        }
+    }
+    radiance
+}
+
+/// The light reaching a point directly from one light picked at random, weighted against
+/// finding the same light by following the BSDF.
+#[allow(clippy::too_many_arguments)]
+fn sample_light<T: Plane>(choice: &LightChoice, model: &T, bsdf: &dyn Bsdf, frame: &Frame, point: Vec3,
+                          wo: &Vec3, sampler: &mut dyn Sampler, settings: &PathSettings) -> Vec3 {
+    let count = choice.count();
+    if count == 0 {
+        return Vec3::zeros();
+    }
+    let pick = ((sampler.next_1d() * count as f64) as usize).min(count - 1);
+    let u = sampler.next_2d();
+
+    // Where the light comes from, how much there is, how likely the light sample was and
+    // how far away it is
+    let (direction, emitted, light_pdf, distance) = match choice.lights.get(pick) {
+        Some(light) => {
+            let sample = light.sample(point, u);
+            if !light.shines_towards(&sample, point) {
+                return Vec3::zeros();
+            }
+            let to_light = sample.point - point;
+            let distance = to_light.norm();
+            let direction = to_light / distance;
+            match sample.normal {
+                Some(normal) => (direction, light.emission, light.pdf(point, sample.point, normal), distance),
+                // Point lights fall off with the square of the distance, and can only be
+                // found this way
+                None => (direction, light.emission / (distance * distance), 0.0, distance),
+            }
+        },
+        None => {
+            let environment = choice.environment.unwrap();
+            let sample = environment.sample(u);
+            (sample.direction, sample.radiance, sample.pdf, f64::INFINITY)
+        }
+    };
+
+    let wi = frame.to_local(&direction);
+    let f = bsdf.eval(wo, &wi);
+    if f == Vec3::zeros() || emitted == Vec3::zeros() {
+        return Vec3::zeros();
+    }
+    if model.occluded(&shadow_ray(point, direction, distance)) {
+        return Vec3::zeros();
+    }
+
+    let contribution = f.component_mul(&emitted) * wi.z.abs();
+    if light_pdf == 0.0 {
+        contribution / choice.pick_pdf()
+    } else {
+        let pdf = choice.pick_pdf() * light_pdf;
+        contribution * (settings.heuristic.weight(pdf, bsdf.pdf(wo, &wi)) / pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trace, MisHeuristic, PathSettings};
+    use crate::bsdf::BsdfKind;
+    use crate::environment::Environment;
+    use crate::light::Light;
+    use crate::sampler::{Sampler, SamplerKind};
+    use crate::test_scene::floor;
+    use geometry::{Face, Material, Ray, Vec3};
+    use std::f64::consts::PI;
+
+    fn settings(heuristic: MisHeuristic) -> PathSettings {
+        PathSettings { max_depth: 5, heuristic, bsdf: BsdfKind::Lambertian, roughness: None }
+    }
+
+    fn average<F: FnMut(&mut dyn Sampler) -> Vec3>(samples: usize, mut f: F) -> Vec3 {
+        let mut sampler = SamplerKind::Sobol.create(samples);
+        let mut total = Vec3::zeros();
+        for i in 0..samples {
+            sampler.start_sample(0, 0, i);
+            total += f(sampler.as_mut());
+        }
+        total / samples as f64
+    }
+
+    #[test]
+    fn test_mis_weights() {
+        assert_eq!(MisHeuristic::Balance.weight(1.0, 3.0), 0.25);
+        assert_eq!(MisHeuristic::Power.weight(1.0, 3.0), 0.1);
+        assert_eq!(MisHeuristic::Power.weight(2.0, 0.0), 1.0);
+        assert_eq!(MisHeuristic::Balance.weight(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_parse_heuristic() {
+        assert_eq!("Balance".parse::<MisHeuristic>().unwrap(), MisHeuristic::Balance);
+        assert_eq!("Power".parse::<MisHeuristic>().unwrap(), MisHeuristic::Power);
+        assert!("power".parse::<MisHeuristic>().is_err());
+    }
+
+    #[test]
+    fn test_point_light() {
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        let model: Vec<Face> = floor().into_iter().map(|f| f.with_material(Some(0))).collect();
+        let lights = vec![Light::point(Vec3::new(3.0, 0.0, 4.0)).with_emission(Vec3::repeat(25.0))];
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let radiance = average(4, |s| trace(&ray, &model, &materials, &lights, None, s, &settings(MisHeuristic::Power)));
+        // albedo / pi * intensity * cos / distance squared
+        let expected = 0.5 / PI * 25.0 * 0.8 / 25.0;
+        assert!((radiance.x - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_white_environment() {
+        // A grey floor under an even sky reflects its albedo of the sky
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        let model: Vec<Face> = floor().into_iter().map(|f| f.with_material(Some(0))).collect();
+        let sky = Environment::new(2, 2, vec![Vec3::repeat(1.0); 4]);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.3, 0.0, -1.0));
+        for heuristic in &[MisHeuristic::Balance, MisHeuristic::Power] {
+            let radiance = average(256, |s| trace(&ray, &model, &materials, &[], Some(&sky), s, &settings(*heuristic)));
+            assert!((radiance.x - 0.5).abs() < 0.02, "{:?} {}", heuristic, radiance.x);
+        }
+    }
+
+    #[test]
+    fn test_area_light() {
+        // A small disc light above a white floor, found both by light and BSDF sampling
+        let materials = vec![Material::with_colour("white", Vec3::repeat(1.0))];
+        let model: Vec<Face> = floor().into_iter().map(|f| f.with_material(Some(0))).collect();
+        let radius = 0.5;
+        let lights = vec![Light::disc(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0), radius)];
+        let ray = Ray::new(Vec3::new(1.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, -1.0));
+        // The light from a disc straight above is pi * sin^2 of the angle it covers
+        let expected = 1.0 / PI * PI * (radius * radius) / (radius * radius + 4.0);
+        let radiance = average(256, |s| trace(&ray, &model, &materials, &lights, None, s, &settings(MisHeuristic::Power)));
+        assert!((radiance.x - expected).abs() < 0.02 * expected, "{} {}", radiance.x, expected);
+    }
+
+    #[test]
+    fn test_emissive_surface() {
+        // Looking straight at a glowing face shows its emission
+        let mut glow = Material::named("glow");
+        glow.emission = Vec3::new(2.0, 1.0, 0.5);
+        let model: Vec<Face> = floor().into_iter().map(|f| f.with_material(Some(0))).collect();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let radiance = average(1, |s| trace(&ray, &model, &[glow.clone()], &[], None, s, &settings(MisHeuristic::Power)));
+        assert_eq!(radiance, Vec3::new(2.0, 1.0, 0.5));
+    }
+}
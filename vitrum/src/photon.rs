@@ -0,0 +1,546 @@
+use geometry::{Collision, CollisionDirection, Material, Plane, Ray, Vec3};
+use super::bsdf::{Bsdf, Frame};
+use super::environment::Environment;
+use super::light::{shadow_ray, Light};
+use super::path::PathSettings;
+use super::sampler::{Sampler, SamplerKind};
+
+use std::f64::consts::PI;
+use std::ops::Range;
+
+/// How quickly progressive photon mapping shrinks its radius, between 0 and 1. Smaller
+/// values shrink it faster, so the bias goes sooner but the noise later.
+const PROGRESSIVE_ALPHA: f64 = 0.7;
+
+/// A packet of light which has landed on a surface.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Photon {
+    pub position: Vec3,
+    /// The direction back towards where the light came from, normalised
+    pub direction: Vec3,
+    /// The light carried
+    pub power: Vec3,
+    /// Whether the light bounced off a surface which is not specular on the way
+    pub indirect: bool,
+}
+
+/// Photons in a kd-tree, for finding the ones near a point.
+///
+/// The tree is kept in the list of photons. Each part of the list is split at its middle
+/// photon: those before it are on one side of it along its axis, and those after on the
+/// other.
+#[derive(Debug)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    pub fn new(photons: Vec<Photon>) -> PhotonMap {
+        let mut photons = photons;
+        let mut axes = vec![0; photons.len()];
+        PhotonMap::build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Arrange photons into a tree, splitting across the axis they are most spread along.
+    fn build(photons: &mut [Photon], axes: &mut [usize]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let (min, max) = photons.iter().fold((photons[0].position, photons[0].position), |(min, max), p| {
+            (min.inf(&p.position), max.sup(&p.position))
+        });
+        let axis = (max - min).imax();
+        let middle = photons.len() / 2;
+        photons.select_nth_unstable_by(middle, |a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap());
+        axes[middle] = axis;
+
+        let (before, after) = photons.split_at_mut(middle);
+        let (axes_before, axes_after) = axes.split_at_mut(middle);
+        PhotonMap::build(before, axes_before);
+        PhotonMap::build(&mut after[1..], &mut axes_after[1..]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// The photons nearest a point, closest first, with their squared distances from it.
+    ///
+    /// # Arguments
+    /// * `point` - Where to look around
+    /// * `count` - The most photons to find
+    /// * `max_distance` - How far away photons can be
+    pub fn nearest(&self, point: Vec3, count: usize, max_distance: f64) -> Vec<(f64, &Photon)> {
+        let mut found = Vec::new();
+        self.search(0..self.photons.len(), point, count, max_distance * max_distance, &mut found);
+        found.into_iter().map(|(d2, i)| (d2, &self.photons[i])).collect()
+    }
+
+    fn search(&self, range: Range<usize>, point: Vec3, count: usize, max_distance2: f64, found: &mut Vec<(f64, usize)>) {
+        if range.is_empty() || count == 0 {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let photon = &self.photons[middle];
+        let axis = self.axes[middle];
+        let offset = point[axis] - photon.position[axis];
+        let (near, far) = if offset < 0.0 {
+            (range.start..middle, middle + 1..range.end)
+        } else {
+            (middle + 1..range.end, range.start..middle)
+        };
+
+        // How far away photons can be and still be one of the nearest so far
+        let limit = |found: &Vec<(f64, usize)>| if found.len() < count { max_distance2 } else { found[count - 1].0 };
+
+        self.search(near, point, count, max_distance2, found);
+        let distance2 = (photon.position - point).norm_squared();
+        if distance2 <= limit(found) {
+            let i = found.iter().position(|(d2, _)| *d2 > distance2).unwrap_or(found.len());
+            found.insert(i, (distance2, middle));
+            found.truncate(count);
+        }
+        if offset * offset <= limit(found) {
+            self.search(far, point, count, max_distance2, found);
+        }
+    }
+}
+
+/// How the photon maps are made and used.
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonSettings {
+    /// Photons sent out from the lights for each pair of maps
+    pub photons: usize,
+    /// How many of the nearest photons make up each estimate
+    pub nearest: usize,
+    /// How far away photons can be to count, or for progressive maps the starting distance.
+    /// `None` is a fiftieth of the size of the model.
+    pub radius: Option<f64>,
+    /// Rays sent out from each point seen to find the light reaching it from other surfaces.
+    /// With none, the global map is used straight away at the point instead.
+    pub gather_rays: usize,
+    /// Whether to make new maps for every pass, using the photons within a shrinking
+    /// distance, so the blur (and bias) they give goes away as passes are added
+    pub progressive: bool,
+}
+
+/// The distance photons are gathered from in a pass of progressive photon mapping. Each
+/// pass shrinks the area by (i + alpha) / (i + 1), slowly enough that the number of photons
+/// in it still grows (Knaus and Zwicker's probabilistic progressive photon mapping).
+///
+/// # Arguments
+/// * `radius` - The distance for the first pass
+/// * `pass` - Which pass it is, from 0
+pub fn pass_radius(radius: f64, pass: usize) -> f64 {
+    let area = (1..=pass).fold(radius * radius, |area, i| area * (i as f64 + PROGRESSIVE_ALPHA) / (i as f64 + 1.0));
+    area.sqrt()
+}
+
+/// The scattering at a point on the model.
+struct Surface {
+    bsdf: Box<dyn Bsdf>,
+    /// The frame, facing back along the ray unless light goes through the surface
+    frame: Frame,
+    /// The direction back along the ray, in the frame
+    wo: Vec3,
+    /// The light given off towards the ray
+    emission: Vec3,
+}
+
+impl Surface {
+    fn at(c: &Collision, ray: &Ray, materials: &[Material], settings: &PathSettings) -> Surface {
+        let default_material = Material::default();
+        let material = c.material.and_then(|m| materials.get(m)).unwrap_or(&default_material);
+        let bsdf = settings.bsdf.create(material, settings.roughness);
+        let wo_world = -ray.direction;
+        let normal = c.normal.normalize();
+        let normal = if !bsdf.transmits() && normal.dot(&wo_world) < 0.0 { -normal } else { normal };
+        let frame = Frame::from_normal(normal);
+        let emission = if c.direction == CollisionDirection::FrontFace { material.emission } else { Vec3::zeros() };
+        Surface { bsdf, frame, wo: frame.to_local(&wo_world), emission }
+    }
+}
+
+/// Photons which have landed on the model, sent out from the lights.
+#[derive(Debug)]
+pub struct PhotonMaps {
+    /// Photons which only bounced off specular surfaces on the way from the light, which
+    /// gather into sharp caustics
+    pub caustic: PhotonMap,
+    /// Every photon landing on a surface which is not specular
+    pub global: PhotonMap,
+    /// How far away photons count
+    radius: f64,
+    /// How many of the nearest photons count
+    nearest: usize,
+    gather_rays: usize,
+}
+
+impl PhotonMaps {
+    /// Send photons out from the lights and store where they land. The environment does not
+    /// send out photons, so only lights its light reaches directly.
+    ///
+    /// # Arguments
+    /// * `model` - The model
+    /// * `materials` - The materials used by the model
+    /// * `lights` - The lights
+    /// * `settings` - What the surfaces are made of, and how many times photons bounce
+    /// * `photon_settings` - How many photons to send and how to use them
+    /// * `pass` - Which pass the maps are for, giving different photons for each
+    pub fn build<T: Plane>(model: &T, materials: &[Material], lights: &[Light], settings: &PathSettings,
+                           photon_settings: &PhotonSettings, pass: usize) -> PhotonMaps {
+        let mut caustic = Vec::new();
+        let mut global = Vec::new();
+        let mut sampler = SamplerKind::Halton.create(photon_settings.photons);
+        let pick_pdf = 1.0 / lights.len().max(1) as f64;
+        for i in 0..photon_settings.photons {
+            if lights.is_empty() {
+                break;
+            }
+            // Every pass carries on along the sequence, so its photons go somewhere new
+            sampler.start_sample(0, 0, pass * photon_settings.photons + i);
+            let pick = ((sampler.next_1d() * lights.len() as f64) as usize).min(lights.len() - 1);
+            let light = &lights[pick];
+            let emitted = light.sample_emission(sampler.next_2d(), sampler.next_2d());
+            if emitted.pdf_position <= 0.0 || emitted.pdf_direction <= 0.0 {
+                continue;
+            }
+            let cos = emitted.normal.map_or(1.0, |n| n.dot(&emitted.direction).abs());
+            let power = light.emission * (cos / (pick_pdf * emitted.pdf_position * emitted.pdf_direction
+                                                 * photon_settings.photons as f64));
+
+            let mut ray = Ray::leaving(emitted.point, emitted.direction);
+            let mut power = power;
+            let mut indirect = false;
+            for bounce in 0..settings.max_depth {
+                let c = match model.hits(&ray) {
+                    Some(c) => c,
+                    None => break
+                };
+                let surface = Surface::at(&c, &ray, materials, settings);
+                if !surface.bsdf.is_specular() {
+                    let photon = Photon { position: c.contact_point, direction: -ray.direction, power, indirect };
+                    if bounce > 0 && !indirect {
+                        caustic.push(photon);
+                    }
+                    global.push(photon);
+                }
+
+                let sample = match surface.bsdf.sample(&surface.wo, sampler.next_2d(), sampler.next_1d()) {
+                    Some(s) if s.pdf > 0.0 => s,
+                    _ => break
+                };
+                let f = sample.f * surface.bsdf.importance_scale(&surface.wo, &sample.wi);
+                let scattered = power.component_mul(&(f * (sample.wi.z.abs() / sample.pdf)));
+                // Russian roulette keeps each photon about as bright, ending some instead of
+                // dimming all of them
+                let survive = (scattered.max() / power.max()).min(1.0);
+                if sampler.next_1d() >= survive {
+                    break;
+                }
+                power = scattered / survive;
+                indirect |= !surface.bsdf.is_specular();
+                ray = Ray::leaving(c.contact_point, surface.frame.to_world(&sample.wi));
+            }
+        }
+
+        let radius = photon_settings.radius.unwrap_or_else(|| (model.max_extents() - model.min_extents()).norm() / 50.0);
+        let (radius, nearest) = if photon_settings.progressive {
+            // Every photon within the radius counts
+            (pass_radius(radius, pass), usize::MAX)
+        } else {
+            (radius, photon_settings.nearest)
+        };
+        PhotonMaps {
+            caustic: PhotonMap::new(caustic),
+            global: PhotonMap::new(global),
+            radius,
+            nearest,
+            gather_rays: photon_settings.gather_rays,
+        }
+    }
+
+    /// The light leaving a surface towards the viewer, estimated from the density of the
+    /// photons near it.
+    ///
+    /// # Arguments
+    /// * `map` - The photons to use
+    /// * `surface` - The surface
+    /// * `point` - The point on the surface
+    /// * `only_indirect` - Whether to only use photons which bounced off a surface which is
+    ///   not specular, for when direct light and caustics are found some other way
+    fn estimate(&self, map: &PhotonMap, surface: &Surface, point: Vec3, only_indirect: bool) -> Vec3 {
+        if map.is_empty() {
+            return Vec3::zeros();
+        }
+        let found = map.nearest(point, self.nearest, self.radius);
+        // The photons are spread over the disc out to the furthest one used
+        let radius2 = if found.len() == self.nearest { found[found.len() - 1].0 } else { self.radius * self.radius };
+        if found.is_empty() || radius2 <= 0.0 {
+            return Vec3::zeros();
+        }
+        let total = found.iter()
+                         .filter(|(_, p)| p.indirect || !only_indirect)
+                         .map(|(_, p)| surface.bsdf.eval(&surface.wo, &surface.frame.to_local(&p.direction)).component_mul(&p.power))
+                         .fold(Vec3::zeros(), |total, l| total + l);
+        total / (PI * radius2)
+    }
+}
+
+/// The light reaching a point straight from each light, and from the environment.
+fn direct_light<T: Plane>(model: &T, lights: &[Light], environment: Option<&Environment>, surface: &Surface,
+                          point: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut total = Vec3::zeros();
+    let mut add = |direction: Vec3, distance: f64, emitted: Vec3| {
+        let wi = surface.frame.to_local(&direction);
+        let f = surface.bsdf.eval(&surface.wo, &wi);
+        if f == Vec3::zeros() {
+            return;
+        }
+        if !model.occluded(&shadow_ray(point, direction, distance)) {
+            total += f.component_mul(&emitted) * wi.z.abs();
+        }
+    };
+    for light in lights {
+        let sample = light.sample(point, sampler.next_2d());
+        if !light.shines_towards(&sample, point) {
+            continue;
+        }
+        let to_light = sample.point - point;
+        let distance = to_light.norm();
+        let direction = to_light / distance;
+        match sample.normal {
+            Some(normal) => {
+                let pdf = light.pdf(point, sample.point, normal);
+                if pdf > 0.0 {
+                    add(direction, distance, light.emission / pdf);
+                }
+            },
+            // Point lights fall off with the square of the distance
+            None => add(direction, distance, light.emission / (distance * distance))
+        }
+    }
+    if let Some(environment) = environment {
+        let sample = environment.sample(sampler.next_2d());
+        if sample.pdf > 0.0 {
+            add(sample.direction, f64::INFINITY, sample.radiance / sample.pdf);
+        }
+    }
+    total
+}
+
+/// The light reaching a point from other surfaces, found by sending rays out from it and
+/// looking up the global photon map where they land (final gathering).
+fn gather<T: Plane>(model: &T, materials: &[Material], maps: &PhotonMaps, surface: &Surface, point: Vec3,
+                    sampler: &mut dyn Sampler, settings: &PathSettings) -> Vec3 {
+    let mut total = Vec3::zeros();
+    for _ in 0..maps.gather_rays {
+        let sample = match surface.bsdf.sample(&surface.wo, sampler.next_2d(), sampler.next_1d()) {
+            Some(s) if s.pdf > 0.0 => s,
+            _ => continue
+        };
+        let mut weight = sample.f * (sample.wi.z.abs() / sample.pdf);
+        let mut ray = Ray::leaving(point, surface.frame.to_world(&sample.wi));
+        // Go on through specular surfaces, where there are no photons, to the next surface
+        for _ in 0..settings.max_depth {
+            let c = match model.hits(&ray) {
+                Some(c) => c,
+                None => break
+            };
+            let hit = Surface::at(&c, &ray, materials, settings);
+            if !hit.bsdf.is_specular() {
+                total += weight.component_mul(&maps.estimate(&maps.global, &hit, c.contact_point, false));
+                break;
+            }
+            let sample = match hit.bsdf.sample(&hit.wo, sampler.next_2d(), sampler.next_1d()) {
+                Some(s) if s.pdf > 0.0 => s,
+                _ => break
+            };
+            weight = weight.component_mul(&(sample.f * (sample.wi.z.abs() / sample.pdf)));
+            ray = Ray::leaving(c.contact_point, hit.frame.to_world(&sample.wi));
+        }
+    }
+    total / maps.gather_rays as f64
+}
+
+/// Follow a ray from the camera through specular surfaces to the first surface photons
+/// land on. There the light is made up of the light straight from the lights, the caustic
+/// photons near the point and the light from other surfaces, either by final gathering or
+/// from the global photon map.
+///
+/// # Arguments
+/// * `ray` - The ray from the camera
+/// * `model` - The model
+/// * `materials` - The materials used by the model
+/// * `lights` - The lights
+/// * `environment` - Light from far away, if there is any
+/// * `maps` - The photons sent out from the lights
+/// * `sampler` - Where the random numbers come from
+/// * `settings` - What the surfaces are made of, and how many specular surfaces to go through
+#[allow(clippy::too_many_arguments)]
+pub fn trace<T: Plane>(ray: &Ray, model: &T, materials: &[Material], lights: &[Light],
+                       environment: Option<&Environment>, maps: &PhotonMaps, sampler: &mut dyn Sampler,
+                       settings: &PathSettings) -> Vec3 {
+    let mut radiance = Vec3::zeros();
+    let mut throughput = Vec3::repeat(1.0);
+    let mut ray = ray.clone();
+    for _ in 0..settings.max_depth {
+        let hit = model.hits(&ray);
+        let hit_distance = hit.as_ref().map_or(f64::INFINITY, |c| c.distance);
+
+        // Lights which are not part of the model
+        let light_hit = lights.iter()
+                              .filter_map(|l| l.intersect(&ray).map(|(t, normal)| (l, t, normal)))
+                              .filter(|(_, t, _)| *t < hit_distance)
+                              .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((light, _, normal)) = light_hit {
+            if normal.dot(&ray.direction) < 0.0 {
+                radiance += throughput.component_mul(&light.emission);
+            }
+            break;
+        }
+
+        let c = match hit {
+            Some(c) => c,
+            None => {
+                if let Some(environment) = environment {
+                    radiance += throughput.component_mul(&environment.radiance(&ray.direction));
+                }
+                break;
+            }
+        };
+        let surface = Surface::at(&c, &ray, materials, settings);
+        radiance += throughput.component_mul(&surface.emission);
+
+        if surface.bsdf.is_specular() {
+            let sample = match surface.bsdf.sample(&surface.wo, sampler.next_2d(), sampler.next_1d()) {
+                Some(s) if s.pdf > 0.0 => s,
+                _ => break
+            };
+            throughput = throughput.component_mul(&(sample.f * (sample.wi.z.abs() / sample.pdf)));
+            ray = Ray::leaving(c.contact_point, surface.frame.to_world(&sample.wi));
+            continue;
+        }
+
+        let point = c.contact_point;
+        let indirect = if maps.gather_rays > 0 {
+            gather(model, materials, maps, &surface, point, sampler, settings)
+        } else {
+            maps.estimate(&maps.global, &surface, point, true)
+        };
+        let reflected = direct_light(model, lights, environment, &surface, point, sampler)
+                      + maps.estimate(&maps.caustic, &surface, point, false)
+                      + indirect;
+        radiance += throughput.component_mul(&reflected);
+        break;
+    }
+    radiance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pass_radius, trace, Photon, PhotonMap, PhotonMaps, PhotonSettings};
+    use crate::bsdf::BsdfKind;
+    use crate::light::Light;
+    use crate::path::{self, MisHeuristic};
+    use crate::sampler::SamplerKind;
+    use crate::test_scene::{camera, settings, square, MIDDLE};
+    use geometry::{Face, Material, Vec3};
+
+    fn photon_settings(gather_rays: usize, progressive: bool) -> PhotonSettings {
+        PhotonSettings { photons: 20000, nearest: 100, radius: Some(0.3), gather_rays, progressive }
+    }
+
+    /// The middle of the view of a camera looking down at the floor, from the path tracer
+    /// and from photon mapping
+    fn render(model: &Vec<Face>, materials: &[Material], lights: &[Light], photon_settings: &PhotonSettings,
+              passes: usize) -> (f64, f64) {
+        let camera = camera();
+        let settings = settings(MisHeuristic::Power, BsdfKind::Model);
+        let samples = 1024;
+        let mut sampler = SamplerKind::Sobol.create(samples);
+        let (mut expected, mut radiance) = (Vec3::zeros(), Vec3::zeros());
+        let mut maps = None;
+        for i in 0..samples {
+            if maps.is_none() || (photon_settings.progressive && i % (samples / passes) == 0) {
+                maps = Some(PhotonMaps::build(model, materials, lights, &settings, photon_settings, i * passes / samples));
+            }
+            for (x, y) in &MIDDLE {
+                sampler.start_sample(*x, *y, i);
+                let (dx, dy) = sampler.next_2d();
+                let ray = camera.ray(*x as f64 + dx, *y as f64 + dy);
+                expected += path::trace(&ray, model, materials, lights, None, sampler.as_mut(), &settings);
+                radiance += trace(&ray, model, materials, lights, None, maps.as_ref().unwrap(), sampler.as_mut(), &settings);
+            }
+        }
+        (radiance.x / (4 * samples) as f64, expected.x / (4 * samples) as f64)
+    }
+
+    #[test]
+    fn test_nearest() {
+        let photons: Vec<Photon> = (0..500).map(|i| {
+            let position = Vec3::new((i * 37 % 101) as f64, (i * 61 % 103) as f64, (i % 7) as f64) / 10.0;
+            Photon { position, direction: Vec3::new(0.0, 0.0, 1.0), power: Vec3::repeat(i as f64), indirect: false }
+        }).collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), 500);
+        for point in &[Vec3::new(5.0, 5.0, 0.3), Vec3::new(0.0, 0.0, 0.0), Vec3::new(12.0, -1.0, 0.5)] {
+            let mut expected: Vec<f64> = photons.iter().map(|p| (p.position - point).norm_squared()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found: Vec<f64> = map.nearest(*point, 20, 100.0).iter().map(|(d2, _)| *d2).collect();
+            assert_eq!(found, expected[..20].to_vec());
+
+            let within: Vec<f64> = map.nearest(*point, usize::MAX, 2.0).iter().map(|(d2, _)| *d2).collect();
+            assert_eq!(within, expected.iter().copied().filter(|d2| *d2 <= 4.0).collect::<Vec<f64>>());
+        }
+        assert!(PhotonMap::new(Vec::new()).nearest(Vec3::zeros(), 10, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_pass_radius() {
+        assert_eq!(pass_radius(2.0, 0), 2.0);
+        let radii: Vec<f64> = (0..100).map(|i| pass_radius(2.0, i)).collect();
+        assert!(radii.windows(2).all(|w| w[1] < w[0]));
+        // Slowly enough that the photons in the area (passes times area) keep growing
+        assert!(radii.iter().enumerate().all(|(i, r)| (i + 1) as f64 * r * r > 4.0 - 1e-12));
+        assert!(radii[99] < 0.6 * radii[0]);
+    }
+
+    #[test]
+    fn test_between_floor_and_ceiling() {
+        // A floor lit from above, and a ceiling lit by the light bouncing off it
+        let mut materials = vec![Material::with_colour("grey", Vec3::repeat(0.5))];
+        materials[0].specular = Vec3::zeros();
+        let lights = vec![Light::disc(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.25)];
+        let mut model = square(0.0, 0);
+        model.extend(square(3.0, 0).iter().map(Face::flipped));
+        for gather_rays in &[0, 4] {
+            let (radiance, expected) = render(&model, &materials, &lights, &photon_settings(*gather_rays, false), 1);
+            assert!((radiance - expected).abs() < 0.03 * expected, "{} {} {}", gather_rays, radiance, expected);
+        }
+    }
+
+    #[test]
+    fn test_caustic() {
+        // The floor lit through a sheet of smooth glass, so all the light reaching it is a
+        // caustic
+        let mut glass = Material::named("glass");
+        glass.opacity = 0.0;
+        let materials = vec![Material::with_colour("grey", Vec3::repeat(0.5)), glass];
+        let lights = vec![Light::disc(Vec3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0), 1.0)];
+        let mut model = square(0.0, 0);
+        model.extend(square(2.0, 1));
+        for progressive in &[false, true] {
+            let (radiance, expected) = render(&model, &materials, &lights, &photon_settings(0, *progressive), 8);
+            assert!(expected > 0.0);
+            assert!((radiance - expected).abs() < 0.03 * expected, "{} {} {}", progressive, radiance, expected);
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::sync::OnceLock;
+
+use enum_from_str::ParseEnumVariantError;
+use enum_from_str_derive::FromStr;
+
+/// A source of sample points in [0, 1) for everything random in a render: where in a pixel
+/// a ray goes, where on a light a shadow ray ends, which way a path bounces.
+///
+/// The points for a sample depend only on the pixel, the sample's index and how many
+/// points have been drawn since `start_sample`, so a render is the same whatever order (or
+/// on however many threads) the pixels are done in.
+pub trait Sampler {
+    /// Start drawing points for a sample of a pixel.
+    ///
+    /// # Arguments
+    /// * `x` - The pixel's column
+    /// * `y` - The pixel's row
+    /// * `index` - Which of the pixel's samples this is
+    fn start_sample(&mut self, x: usize, y: usize, index: usize);
+
+    /// The next number for the current sample.
+    fn next_1d(&mut self) -> f64;
+
+    /// The next point in the unit square for the current sample.
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+/// The kinds of sampler, for picking one on the command line.
+#[derive(Debug, Copy, Clone, PartialEq, FromStr)]
+pub enum SamplerKind {
+    // Every point is independent of the others
+    Independent,
+    // The samples of a pixel are spread over a grid with one cell for each, at a random place in it
+    Stratified,
+    // The Halton sequence, rotated by a different amount in each pixel
+    Halton,
+    // The Sobol sequence with Owen scrambling, shuffled differently in each pixel
+    Sobol,
+    // The Sobol sequence, rotated in each pixel by a blue noise mask so the error left is
+    // spread out evenly over the image instead of clumping
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// Make a sampler of this kind.
+    ///
+    /// # Arguments
+    /// * `samples` - How many samples each pixel has
+    pub fn create(&self, samples: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new()),
+            SamplerKind::Sobol => Box::new(SobolSampler::new()),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new()),
+        }
+    }
+}
+
+/// Mix some numbers into a well spread 64 bit hash.
+pub fn hash(values: &[u64]) -> u64 {
+    let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+    for v in values {
+        // The SplitMix64 finaliser
+        h = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    h
+}
+
+/// A number in [0, 1) from the top bits of a hash.
+pub fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A number in [0, 1) from the bits of a 32 bit fixed point fraction.
+fn from_fixed(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+/// Where a point is in its pixel, dimension and sample, for seeding.
+#[derive(Copy, Clone, Debug, Default)]
+struct Position {
+    x: u64,
+    y: u64,
+    index: u64,
+    dimension: u64,
+}
+
+impl Position {
+    fn start(&mut self, x: usize, y: usize, index: usize) {
+        *self = Position { x: x as u64, y: y as u64, index: index as u64, dimension: 0 };
+    }
+
+    /// Move on to the next dimension, returning the one just used.
+    fn advance(&mut self) -> u64 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+}
+
+pub struct IndependentSampler {
+    rng: SmallRng,
+}
+
+impl IndependentSampler {
+    pub fn new() -> IndependentSampler {
+        IndependentSampler { rng: SmallRng::seed_from_u64(0) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.rng = SmallRng::seed_from_u64(hash(&[x as u64, y as u64, index as u64]));
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Shuffle the numbers 0 to `length` - 1, returning where `i` goes. Different seeds give
+/// different orders. From Kensler's "Correlated Multi-Jittered Sampling".
+fn permute(i: u32, length: u32, seed: u32) -> u32 {
+    let mut i = i;
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    // Shuffle in the next power of two up, until the result is in range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    ((i as u64 + seed as u64) % length as u64) as u32
+}
+
+pub struct StratifiedSampler {
+    samples: usize,
+    /// The columns and rows of the grid for 2D points, which has exactly one cell per sample
+    grid: (usize, usize),
+    position: Position,
+    rng: SmallRng,
+}
+
+impl StratifiedSampler {
+    /// # Arguments
+    /// * `samples` - How many samples each pixel has, which is how many strata there are
+    pub fn new(samples: usize) -> StratifiedSampler {
+        let samples = samples.max(1);
+        // The squarest grid with the right number of cells, which is a single row for primes
+        let rows = (1..=samples).take_while(|r| r * r <= samples)
+                                .filter(|r| samples / r * r == samples)
+                                .last()
+                                .unwrap_or(1);
+        StratifiedSampler {
+            samples,
+            grid: (samples / rows, rows),
+            position: Position::default(),
+            rng: SmallRng::seed_from_u64(0),
+        }
+    }
+
+    /// Which stratum the current sample is in, shuffled differently in every dimension so
+    /// the dimensions are not correlated.
+    fn stratum(&mut self) -> usize {
+        let p = self.position;
+        let dimension = self.position.advance();
+        let seed = hash(&[p.x, p.y, dimension]) as u32;
+        permute((p.index % self.samples as u64) as u32, self.samples as u32, seed) as usize
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.position.start(x, y, index);
+        self.rng = SmallRng::seed_from_u64(hash(&[x as u64, y as u64, index as u64]));
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let stratum = self.stratum();
+        (stratum as f64 + self.rng.gen::<f64>()) / self.samples as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let (columns, rows) = self.grid;
+        let stratum = self.stratum();
+        (((stratum % columns) as f64 + self.rng.gen::<f64>()) / columns as f64,
+         ((stratum / columns) as f64 + self.rng.gen::<f64>()) / rows as f64)
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Mirror the digits of `index` in the given base about the point, so 1, 2, 3, ... in base
+/// 2 become 0.5, 0.25, 0.75, ...
+fn radical_inverse(base: u64, index: u64) -> f64 {
+    let mut index = index;
+    let mut result = 0.0;
+    let mut scale = 1.0 / base as f64;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+pub struct HaltonSampler {
+    position: Position,
+}
+
+impl HaltonSampler {
+    pub fn new() -> HaltonSampler {
+        HaltonSampler { position: Position::default() }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.position.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let p = self.position;
+        let dimension = self.position.advance();
+        let rotation = hash(&[p.x, p.y, dimension]);
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => (radical_inverse(base, p.index) + to_unit(rotation)).fract(),
+            // Past the table the bases are too big to spread a few samples out anyway
+            None => to_unit(hash(&[rotation, p.index])),
+        }
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+/// The first two dimensions of the Sobol sequence, as 32 bit fractions.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut x = 0;
+    let mut y = 0;
+    // The first dimension's direction numbers are the powers of two, the second's come from
+    // the polynomial x + 1
+    let mut v: u32 = 1 << 31;
+    for bit in 0..32 {
+        if index & (1 << bit) != 0 {
+            x ^= 1 << (31 - bit);
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+/// Owen scramble the bits of a fraction, so that each bit is flipped depending on the bits
+/// above it. From Burley's "Practical Hash-based Owen Scrambling".
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+pub struct SobolSampler {
+    position: Position,
+}
+
+impl SobolSampler {
+    pub fn new() -> SobolSampler {
+        SobolSampler { position: Position::default() }
+    }
+
+    /// A scrambled point from the first two dimensions of the sequence. Each call uses a
+    /// different shuffle of the samples, so that further dimensions are not correlated.
+    fn point(&mut self) -> (f64, f64) {
+        let p = self.position;
+        let dimension = self.position.advance();
+        let seed = hash(&[p.x, p.y, dimension]);
+        let index = owen_scramble(p.index as u32, seed as u32);
+        let (x, y) = sobol_2d(index);
+        (from_fixed(owen_scramble(x, (seed >> 32) as u32)),
+         from_fixed(owen_scramble(y, hash(&[seed]) as u32)))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.position.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.point().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        self.point()
+    }
+}
+
+/// The width and height of the blue noise mask.
+const MASK_SIZE: usize = 32;
+
+/// A tile of numbers in [0, 1) where neighbouring values are as different as possible, so
+/// that thresholding it at any level gives evenly spread points. Made with Ulichney's
+/// void-and-cluster method.
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = MASK_SIZE * MASK_SIZE;
+        // How much each point crowds the points around it, by offset, wrapping at the edges
+        let sigma: f64 = 1.5;
+        let kernel: Vec<f64> = (0..n).map(|i| {
+            let (dx, dy) = (i % MASK_SIZE, i / MASK_SIZE);
+            let dx = dx.min(MASK_SIZE - dx) as f64;
+            let dy = dy.min(MASK_SIZE - dy) as f64;
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        }).collect();
+        let offset = |a: usize, b: usize| {
+            let dx = (a % MASK_SIZE + MASK_SIZE - b % MASK_SIZE) % MASK_SIZE;
+            let dy = (a / MASK_SIZE + MASK_SIZE - b / MASK_SIZE) % MASK_SIZE;
+            dy * MASK_SIZE + dx
+        };
+        let mut on = vec![false; n];
+        let mut energy = vec![0.0; n];
+        let set = |on: &mut [bool], energy: &mut [f64], i: usize, value: bool| {
+            on[i] = value;
+            let sign = if value { 1.0 } else { -1.0 };
+            for (j, e) in energy.iter_mut().enumerate() {
+                *e += sign * kernel[offset(j, i)];
+            }
+        };
+        let tightest_cluster = |on: &[bool], energy: &[f64]| {
+            (0..n).filter(|&i| on[i]).max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+        };
+        let largest_void = |on: &[bool], energy: &[f64]| {
+            (0..n).filter(|&i| !on[i]).min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+        };
+
+        // Start with a tenth of the points on at random, then move the most crowded point to
+        // the emptiest space until that stops changing anything
+        let mut rng = SmallRng::seed_from_u64(0);
+        let initial = n / 10;
+        let mut count = 0;
+        while count < initial {
+            let i = rng.gen_range(0, n);
+            if !on[i] {
+                set(&mut on, &mut energy, i, true);
+                count += 1;
+            }
+        }
+        loop {
+            let cluster = tightest_cluster(&on, &energy);
+            set(&mut on, &mut energy, cluster, false);
+            let void = largest_void(&on, &energy);
+            set(&mut on, &mut energy, void, true);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Rank the starting points by taking the most crowded away first, then fill in the
+        // rest from the emptiest space
+        let mut rank = vec![0; n];
+        let (mut removing_on, mut removing_energy) = (on.clone(), energy.clone());
+        for r in (0..initial).rev() {
+            let cluster = tightest_cluster(&removing_on, &removing_energy);
+            set(&mut removing_on, &mut removing_energy, cluster, false);
+            rank[cluster] = r;
+        }
+        for r in initial..n {
+            let void = largest_void(&on, &energy);
+            set(&mut on, &mut energy, void, true);
+            rank[void] = r;
+        }
+        rank.into_iter().map(|r| (r as f64 + 0.5) / n as f64).collect()
+    })
+}
+
+pub struct BlueNoiseSampler {
+    position: Position,
+    mask: &'static [f64],
+}
+
+impl BlueNoiseSampler {
+    pub fn new() -> BlueNoiseSampler {
+        BlueNoiseSampler { position: Position::default(), mask: blue_noise_mask() }
+    }
+
+    /// How far to rotate a value in a pixel. Each dimension and axis reads the mask from
+    /// a different place, so they are not correlated.
+    fn rotation(&self, dimension: u64, axis: u64) -> f64 {
+        let p = self.position;
+        let step = (2 * dimension + axis) as f64;
+        // The R2 sequence spreads out the places the mask is read from
+        let dx = (step * 0.754_877_666_246_692_7 * MASK_SIZE as f64) as u64;
+        let dy = (step * 0.569_840_290_998_053_2 * MASK_SIZE as f64) as u64;
+        let x = ((p.x + dx) % MASK_SIZE as u64) as usize;
+        let y = ((p.y + dy) % MASK_SIZE as u64) as usize;
+        self.mask[y * MASK_SIZE + x]
+    }
+
+    fn point(&mut self) -> (f64, f64) {
+        let dimension = self.position.advance();
+        // The same shuffle in every pixel, so the rotations are all that differ between them
+        let index = owen_scramble(self.position.index as u32, hash(&[dimension]) as u32);
+        let (x, y) = sobol_2d(index);
+        ((from_fixed(x) + self.rotation(dimension, 0)).fract(),
+         (from_fixed(y) + self.rotation(dimension, 1)).fract())
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.position.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.point().0
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        self.point()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 5] = [SamplerKind::Independent, SamplerKind::Stratified,
+                                     SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise];
+
+    #[test]
+    fn test_range_and_repeatable() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.create(16);
+            let mut first = Vec::new();
+            for index in 0..16 {
+                sampler.start_sample(3, 7, index);
+                let (a, b) = sampler.next_2d();
+                let c = sampler.next_1d();
+                for v in &[a, b, c] {
+                    assert!((0.0..1.0).contains(v), "{:?} gave {}", kind, v);
+                }
+                first.push((a, b, c));
+            }
+            // Doing another pixel in between, or going backwards, changes nothing
+            let mut other = kind.create(16);
+            for index in (0..16).rev() {
+                other.start_sample(4, 7, index);
+                other.next_2d();
+                other.start_sample(3, 7, index);
+                let (a, b) = other.next_2d();
+                assert_eq!((a, b, other.next_1d()), first[index], "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stratified_covers_strata() {
+        for kind in &[SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut sampler = kind.create(16);
+            let mut cells = vec![0; 16];
+            let mut strata = vec![0; 16];
+            for index in 0..16 {
+                sampler.start_sample(1, 2, index);
+                let (x, y) = sampler.next_2d();
+                cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                strata[(sampler.next_1d() * 16.0) as usize] += 1;
+            }
+            assert_eq!(cells, vec![1; 16], "{:?}", kind);
+            assert_eq!(strata, vec![1; 16], "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_stratified_grid() {
+        // Every cell is used whether or not the number of samples is square
+        for &(samples, columns, rows) in &[(6, 3, 2), (7, 7, 1), (12, 4, 3)] {
+            let mut sampler = SamplerKind::Stratified.create(samples);
+            let mut cells = vec![0; samples];
+            for index in 0..samples {
+                sampler.start_sample(1, 2, index);
+                let (x, y) = sampler.next_2d();
+                cells[(y * rows as f64) as usize * columns + (x * columns as f64) as usize] += 1;
+            }
+            assert_eq!(cells, vec![1; samples], "{}", samples);
+        }
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert!((radical_inverse(3, 5) - (2.0 / 3.0 + 1.0 / 9.0)).abs() < 1e-12);
+        assert_eq!(sobol_2d(0), (0, 0));
+        assert_eq!(sobol_2d(1), (1 << 31, 1 << 31));
+        assert_eq!(sobol_2d(2), (1 << 30, 3 << 30));
+        assert_eq!(sobol_2d(3), (3 << 30, 1 << 30));
+    }
+
+    #[test]
+    fn test_permute() {
+        for &length in &[1, 5, 16, 100] {
+            let mut seen: Vec<u32> = (0..length).map(|i| permute(i, length, 1234)).collect();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..length).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn test_blue_noise_mask() {
+        let mask = blue_noise_mask();
+        let mut ranks: Vec<usize> = mask.iter().map(|v| (v * mask.len() as f64) as usize).collect();
+        ranks.sort_unstable();
+        assert_eq!(ranks, (0..mask.len()).collect::<Vec<usize>>());
+        // The darkest tenth of the mask has no two points next to each other
+        let dark = |x: usize, y: usize| mask[(y % MASK_SIZE) * MASK_SIZE + x % MASK_SIZE] < 0.1;
+        for y in 0..MASK_SIZE {
+            for x in 0..MASK_SIZE {
+                if dark(x, y) {
+                    assert!(!dark(x + 1, y) && !dark(x, y + 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        for kind in &[SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton,
+                      SamplerKind::Sobol, SamplerKind::BlueNoise] {
+            assert_eq!(format!("{:?}", kind).parse::<SamplerKind>().unwrap(), *kind);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use geometry::{Collision, Material, Vec3};
+
+/// The diffuse colour of the surface hit in a collision.
+/// Surfaces without a material use the default material colour.
+pub fn surface_colour(materials: &[Material], collision: &Collision) -> Vec3 {
+    match collision.material.and_then(|m| materials.get(m)) {
+        Some(m) => m.diffuse,
+        None => Material::default().diffuse
+    }
+}
+
+/// The light given off by the surface hit in a collision.
+pub fn surface_emission(materials: &[Material], collision: &Collision) -> Vec3 {
+    match collision.material.and_then(|m| materials.get(m)) {
+        Some(m) => m.emission,
+        None => Material::default().emission
+    }
+}
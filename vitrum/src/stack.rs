@@ -1,8 +1,8 @@
-use geometry::{Plane, Vec3};
+use geometry::{Bounded, Vec3};
 use bvh::BoundingVolumeHierarchy;
 use num::{Float, Signed};
 
-pub fn stack<T: Plane>(model: BoundingVolumeHierarchy<T>) -> BoundingVolumeHierarchy<T> {
+pub fn stack<T: Bounded>(model: BoundingVolumeHierarchy<T>) -> BoundingVolumeHierarchy<T> {
     let min_extents = model.min_extents();
     let max_extents = model.max_extents();
     let size = (max_extents - min_extents).abs().min();
@@ -10,7 +10,7 @@ pub fn stack<T: Plane>(model: BoundingVolumeHierarchy<T>) -> BoundingVolumeHiera
     pyramid(min_extents, min_extents + (max_extents - min_extents) * factor, size, &model, min_extents)
 }
 
-fn pyramid<T: Plane>(min: Vec3, max: Vec3, size: f64,  model: &BoundingVolumeHierarchy<T>, model_min: Vec3) -> BoundingVolumeHierarchy<T> {
+fn pyramid<T: Bounded>(min: Vec3, max: Vec3, size: f64,  model: &BoundingVolumeHierarchy<T>, model_min: Vec3) -> BoundingVolumeHierarchy<T> {
     let current_size = (max - min).abs().min();
 
     if current_size <= 2.0 * size {
@@ -0,0 +1,32 @@
+//! Scenes shared by the tests of the renderers, which mostly look down at a floor.
+
+use super::bsdf::BsdfKind;
+use super::camera::Pinhole;
+use super::path::{MisHeuristic, PathSettings};
+use geometry::{Face, Vec3};
+use std::f64::consts::PI;
+
+/// The pixels in the middle of the camera's view, which only see the floor.
+pub const MIDDLE: [(usize, usize); 4] = [(1, 1), (2, 1), (1, 2), (2, 2)];
+
+/// A triangle lying on the ground, with the origin well inside it
+pub fn floor() -> Vec<Face> {
+    vec![Face::from_points(Vec3::new(-100.0, -100.0, 0.0), Vec3::new(100.0, -100.0, 0.0), Vec3::new(0.0, 100.0, 0.0))]
+}
+
+/// A square 200 across at a height, facing up
+pub fn square(height: f64, material: usize) -> Vec<Face> {
+    let corner = |x: f64, y: f64| Vec3::new(x, y, height);
+    vec![Face::from_points(corner(-100.0, -100.0), corner(100.0, -100.0), corner(100.0, 100.0)),
+         Face::from_points(corner(-100.0, -100.0), corner(100.0, 100.0), corner(-100.0, 100.0))]
+        .into_iter().map(|f| f.with_material(Some(material))).collect()
+}
+
+pub fn settings(heuristic: MisHeuristic, bsdf: BsdfKind) -> PathSettings {
+    PathSettings { max_depth: 5, heuristic, bsdf, roughness: None }
+}
+
+/// A 4 by 4 pixel camera one unit above the floor, looking straight down at it
+pub fn camera() -> Pinhole {
+    Pinhole::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), PI / 2.0, 4, 4)
+}
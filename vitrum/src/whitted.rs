@@ -1,65 +1,94 @@
-use geometry::{Plane, Ray, Vec3, CollisionDirection};
+use geometry::{Plane, Ray, Vec3, Material};
+use super::environment::Environment;
+use super::light::{shadow_ray, Light};
+use super::sampler::Sampler;
+use super::shading::{surface_colour, surface_emission};
 
-pub fn trace<T:Plane>(ray: &Ray, model: &T, lights: &[Vec3],
+pub fn trace<T:Plane>(ray: &Ray, model: &T, materials: &[Material], lights: &[Light],
+    environment: Option<&Environment>, sampler: &mut dyn Sampler, shadow_samples: usize,
     ambient_intensity: f64, diffuse_reflection_constant: f64,
     specular_reflection_constant: f64, transmission_coefficient: f64,
-    max_depth: u8) -> f64 {
-        trace_down(ray, model, lights,
+    max_depth: u8) -> Vec3 {
+        trace_down(ray, model, materials, lights, environment, sampler, shadow_samples,
             ambient_intensity, diffuse_reflection_constant,
             specular_reflection_constant, transmission_coefficient,
             max_depth)
 }
 
-fn trace_down<T:Plane>(ray: &Ray, model: &T, lights: &[Vec3],
-    i_a: f64, k_d: f64, k_s: f64, k_t: f64, depth: u8) -> f64 {
+fn trace_down<T:Plane>(ray: &Ray, model: &T, materials: &[Material], lights: &[Light],
+    environment: Option<&Environment>, sampler: &mut dyn Sampler, shadow_samples: usize,
+    i_a: f64, k_d: f64, k_s: f64, k_t: f64, depth: u8) -> Vec3 {
         if depth == 0 {
-            return 0.0;
+            return Vec3::zeros();
         }
         let hit = model.hits(ray);
         match hit {
             Some(c) => {
                 // Ambient Light
-                let mut total_i = i_a;
+                let mut total_i = Vec3::repeat(i_a);
 
-                let contact_shift_factor = match c.direction {
-                    CollisionDirection::BackFace => -0.00001,
-                    CollisionDirection::FrontFace => 0.00001
-                };
-                let contact = c.contact_point + contact_shift_factor * c.normal;
+                let contact = c.contact_point;
                 let normal = c.normal;
 
-                // Direct diffuse illumination
+                // Direct diffuse illumination. Lights with an area are sampled with several
+                // shadow rays, and the fraction which get through gives a soft shadow.
                 {
-                    let mut total_diffuse = 0.0;
+                    let mut total_diffuse = Vec3::zeros();
                     for light in lights {
-                        let light_dir = *light - contact;
-                        let light_t = light_dir.norm();
-                        let light_dir = light_dir.normalize();
-                        let light_ray = Ray::new(contact, light_dir);
-                        let hit = model.hits(&light_ray);
-                        total_diffuse += match hit {
-                            Some(c) if c.distance < light_t => 0.0,
-                            _ => normal.dot(&light_dir)
+                        let samples = if light.is_point() { 1 } else { shadow_samples.max(1) };
+                        let mut light_diffuse = Vec3::zeros();
+                        for _ in 0..samples {
+                            let sample = light.sample(contact, sampler.next_2d());
+                            if !light.shines_towards(&sample, contact) {
+                                continue;
+                            }
+                            let light_dir = sample.point - contact;
+                            let light_t = light_dir.norm();
+                            let light_dir = light_dir.normalize();
+                            if !model.occluded(&shadow_ray(contact, light_dir, light_t)) {
+                                light_diffuse += light.emission * normal.dot(&light_dir);
+                            }
                         }
+                        total_diffuse += light_diffuse / samples as f64;
+                    }
+                    // The environment is sampled towards its bright parts. Dividing by pi
+                    // makes a plain white environment as bright as a point light overhead.
+                    if let Some(environment) = environment {
+                        let samples = shadow_samples.max(1);
+                        let mut environment_diffuse = Vec3::zeros();
+                        for _ in 0..samples {
+                            let sample = environment.sample(sampler.next_2d());
+                            let cos = normal.dot(&sample.direction);
+                            if cos <= 0.0 || sample.pdf <= 0.0 {
+                                continue;
+                            }
+                            if !model.occluded(&Ray::leaving(contact, sample.direction)) {
+                                environment_diffuse += sample.radiance * cos / sample.pdf;
+                            }
+                        }
+                        total_diffuse += environment_diffuse / (samples as f64 * std::f64::consts::PI);
                     }
                     total_i += k_d * total_diffuse;
                 }
+                let mut total = total_i.component_mul(&surface_colour(materials, &c))
+                              + surface_emission(materials, &c);
 
                 // Reflected light
                 {
                     let vv = ray.direction / f64::abs(ray.direction.dot(&normal));
                     let reflected_dir = vv + (2.0 * normal);
-                    let reflected_ray = Ray::new(contact, reflected_dir);
-                    let s = trace_down(&reflected_ray, model, lights, i_a, k_d, k_s, k_t, depth - 1);
-                    total_i += k_s * s;
+                    let reflected_ray = Ray::leaving(contact, reflected_dir);
+                    let s = trace_down(&reflected_ray, model, materials, lights, environment, sampler, shadow_samples,
+                                       i_a, k_d, k_s, k_t, depth - 1);
+                    total += k_s * s;
                 }
 
                 // transmitted light
                 if k_t > 0.0 {
                     // Do something
                 }
-                total_i
+                total
             },
-            None => 0.0
+            None => environment.map_or(Vec3::zeros(), |e| e.radiance(&ray.direction))
         }
-}
\ No newline at end of file
+}
@@ -0,0 +1,363 @@
+//! The xml-tools are what the loaders of XML model formats (AMF and 3MF) have in common: going
+//! through the elements while keeping track of their line and column, skipping bad elements
+//! with a warning in lenient mode, and reading attributes and colours.
+
+use std::collections::HashSet;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use geometry::{LoadError, Material, Strictness, Vec3, Warning};
+
+/// The most objects a file can place, counting every copy of an object placed inside something
+/// which is itself placed more than once.
+pub const MAX_PLACEMENTS: usize = 100_000;
+
+/// The most triangles a file can place, counting every copy.
+pub const MAX_PLACED_FACES: usize = 10_000_000;
+
+/// A colour with each channel (RGBA) in [0, 1].
+pub type Colour = [f64; 4];
+
+/// Tracks the line and column of the element being read.
+#[derive(Copy, Clone, Debug)]
+pub struct Location {
+    offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Move forwards to the next element (or text) after a byte offset.
+    ///
+    /// # Arguments
+    /// * `data` - The whole file
+    /// * `offset` - The byte offset to move to. This must not be before the current offset.
+    fn advance(&mut self, data: &[u8], offset: usize) {
+        let offset = data[offset.min(data.len())..].iter()
+                                                   .position(|b| !b.is_ascii_whitespace())
+                                                   .map_or(data.len(), |skip| offset + skip);
+        for b in &data[self.offset.min(offset)..offset] {
+            if *b == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.offset = offset;
+    }
+
+    pub fn error<E: LoadError>(&self, error: E) -> E {
+        error.at(self.line, self.column)
+    }
+}
+
+/// What every format keeps track of while its file is read.
+pub struct ReadState {
+    strictness: Strictness,
+    /// Where the element being read starts
+    pub location: Location,
+    /// The text in the element being read
+    pub text: String,
+    pub warnings: Vec<Warning>,
+    /// Elements which are valid but describe things Vitrum does not draw
+    unsupported: &'static [&'static str],
+    /// Unsupported elements which have already been warned about
+    ignored: HashSet<String>,
+}
+
+impl ReadState {
+    /// # Arguments
+    /// * `strictness` - Whether bad elements are errors or are skipped with a warning
+    /// * `unsupported` - Elements which are skipped with a warning (the first time they are seen)
+    pub fn new(strictness: Strictness, unsupported: &'static [&'static str]) -> ReadState {
+        ReadState {
+            strictness,
+            location: Location { offset: 0, line: 1, column: 1 },
+            text: String::new(),
+            warnings: Vec::new(),
+            unsupported,
+            ignored: HashSet::new(),
+        }
+    }
+
+    /// Deal with a problem in the element being read. Strict errors are returned, lenient ones
+    /// become warnings.
+    pub fn fail<E: LoadError>(&mut self, error: E) -> Result<(), E> {
+        self.fail_at(error, self.location)
+    }
+
+    /// Deal with a problem in an element read earlier.
+    pub fn fail_at<E: LoadError>(&mut self, error: E, location: Location) -> Result<(), E> {
+        let error = location.error(error);
+        match self.strictness {
+            Strictness::Strict => Err(error),
+            Strictness::Lenient => {
+                self.warnings.push(error.into_warning());
+                Ok(())
+            }
+        }
+    }
+
+    /// Warn about the element being read.
+    pub fn warn(&mut self, message: String) {
+        self.warnings.push(Warning::new(Some(self.location.line), message));
+    }
+}
+
+/// A format read by `read_elements`.
+pub trait ElementHandler {
+    type Error: LoadError + From<quick_xml::Error>;
+
+    fn state(&mut self) -> &mut ReadState;
+
+    /// An element has started. Its text is read after this, and is in the state when it ends.
+    ///
+    /// # Arguments
+    /// * `parent` - The name of the element it is in, or None for the outermost element
+    /// * `name` - The name of the element, without its namespace
+    /// * `element` - The element, with its attributes
+    fn start(&mut self, parent: Option<&str>, name: &str, element: &BytesStart) -> Result<(), Self::Error>;
+
+    /// An element has ended.
+    ///
+    /// # Arguments
+    /// * `parent` - The name of the element it is in, or None for the outermost element
+    /// * `name` - The name of the element, without its namespace
+    fn end(&mut self, parent: Option<&str>, name: &str) -> Result<(), Self::Error>;
+}
+
+/// Go through every element of an XML file. Errors are given the line and column of the
+/// element they are in.
+///
+/// # Arguments
+/// * `data` - The XML
+/// * `handler` - What to do with each element
+pub fn read_elements<H: ElementHandler>(data: &[u8], handler: &mut H) -> Result<(), H::Error> {
+    let mut reader = Reader::from_reader(data);
+    reader.trim_text(true);
+    // The names of the elements that are open, outermost first
+    let mut path: Vec<String> = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        handler.state().location.advance(data, reader.buffer_position());
+        let event = match reader.read_event(&mut buf) {
+            Ok(event) => event,
+            Err(e) => {
+                let location = &mut handler.state().location;
+                location.advance(data, reader.buffer_position());
+                return Err(location.error(e.into()));
+            }
+        };
+        let result = match event {
+            Event::Start(e) => start(handler, &mut path, &e),
+            Event::Empty(e) => start(handler, &mut path, &e).and_then(|_| end(handler, &mut path)),
+            Event::Text(t) => t.unescape_and_decode(&reader)
+                               .map(|text| handler.state().text.push_str(&text))
+                               .map_err(H::Error::from),
+            Event::End(_) => end(handler, &mut path),
+            Event::Eof => break,
+            _ => Ok(())
+        };
+        let location = handler.state().location;
+        result.map_err(|e| location.error(e))?;
+        buf.clear();
+    }
+    Ok(())
+}
+
+fn start<H: ElementHandler>(handler: &mut H, path: &mut Vec<String>, element: &BytesStart) -> Result<(), H::Error> {
+    let name = String::from_utf8_lossy(element.local_name()).into_owned();
+    handler.state().text.clear();
+    handler.start(path.last().map(String::as_str), &name, element)?;
+
+    // Only warn once for each kind of element
+    let state = handler.state();
+    if state.unsupported.contains(&name.as_str()) && state.ignored.insert(name.clone()) {
+        state.warn(format!("<{}> elements are not supported and have been skipped", name));
+    }
+    path.push(name);
+    Ok(())
+}
+
+fn end<H: ElementHandler>(handler: &mut H, path: &mut Vec<String>) -> Result<(), H::Error> {
+    let name = path.pop().unwrap_or_default();
+    handler.end(path.last().map(String::as_str), &name)?;
+    handler.state().text.clear();
+    Ok(())
+}
+
+/// Counts the objects a file places and the triangles they add, so that a small file which
+/// places objects inside objects many times over cannot make a scene too big to hold in memory.
+#[derive(Default)]
+pub struct Budget {
+    placements: usize,
+    faces: usize,
+}
+
+impl Budget {
+    /// Count an object being placed, returning whether the file is still within the budget.
+    ///
+    /// # Arguments
+    /// * `faces` - How many triangles the object adds, not counting the objects it places
+    pub fn place(&mut self, faces: usize) -> bool {
+        self.placements += 1;
+        self.faces += faces;
+        self.placements <= MAX_PLACEMENTS && self.faces <= MAX_PLACED_FACES
+    }
+}
+
+/// A problem with the attributes of an element.
+#[derive(Debug)]
+pub enum AttributeError {
+    /// The attributes are not well formed XML
+    Xml(quick_xml::Error),
+    /// An attribute which the element must have is missing
+    Missing(String),
+}
+
+impl std::convert::From<quick_xml::Error> for AttributeError {
+    fn from(error: quick_xml::Error) -> Self {
+        AttributeError::Xml(error)
+    }
+}
+
+/// The value of an attribute of an element, if it has it.
+pub fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, AttributeError> {
+    for a in element.attributes() {
+        let a = a?;
+        if a.key == name.as_bytes() {
+            return Ok(Some(String::from_utf8_lossy(&a.unescaped_value()?).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// The value of an attribute which an element must have.
+pub fn required(element: &BytesStart, name: &str, element_name: &str) -> Result<String, AttributeError> {
+    attribute(element, name)?.ok_or_else(|| AttributeError::Missing(format!("a {} attribute on <{}>", name, element_name)))
+}
+
+/// Set the diffuse colour and opacity of a material.
+pub fn with_colour(mut material: Material, colour: Colour) -> Material {
+    material.diffuse = Vec3::new(colour[0], colour[1], colour[2]);
+    material.opacity = colour[3];
+    material
+}
+
+/// The name of a colour in the form `#rrggbb`, with the alpha only if it is not opaque.
+pub fn colour_name(colour: &Colour) -> String {
+    let channels: Vec<u8> = colour.iter().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+    let n = if channels[3] == 255 { 3 } else { 4 };
+    channels[..n].iter().fold("#".to_owned(), |name, c| name + &format!("{:02x}", c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{colour_name, read_elements, required, AttributeError, Budget, ElementHandler, ReadState};
+    use super::{MAX_PLACED_FACES, MAX_PLACEMENTS};
+    use geometry::{impl_load_error, Strictness};
+    use quick_xml::events::BytesStart;
+    use std::fmt::{Display, Formatter};
+
+    #[derive(Debug)]
+    enum TestError {
+        Xml(quick_xml::Error),
+        Attribute(AttributeError),
+        At { file: Option<String>, line: usize, column: usize, error: Box<TestError> }
+    }
+
+    impl_load_error!(TestError);
+
+    impl Display for TestError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                TestError::Xml(e) => write!(f, "{}", e),
+                TestError::Attribute(e) => write!(f, "{:?}", e),
+                TestError::At { line, column, error, .. } => write!(f, "{}:{}: {}", line, column, error),
+            }
+        }
+    }
+
+    impl std::convert::From<quick_xml::Error> for TestError {
+        fn from(error: quick_xml::Error) -> Self {
+            TestError::Xml(error)
+        }
+    }
+
+    /// Records the elements and the text in them, failing on `<bad>` elements.
+    struct Elements {
+        state: ReadState,
+        seen: Vec<String>,
+    }
+
+    impl ElementHandler for Elements {
+        type Error = TestError;
+
+        fn state(&mut self) -> &mut ReadState {
+            &mut self.state
+        }
+
+        fn start(&mut self, parent: Option<&str>, name: &str, element: &BytesStart) -> Result<(), TestError> {
+            if name == "bad" {
+                let error = required(element, "id", name).map_err(TestError::Attribute);
+                if let Err(e) = error {
+                    return self.state.fail(e);
+                }
+            }
+            self.seen.push(format!("{}/{}", parent.unwrap_or(""), name));
+            Ok(())
+        }
+
+        fn end(&mut self, _parent: Option<&str>, name: &str) -> Result<(), TestError> {
+            let text = format!("{}={}", name, self.state.text);
+            self.seen.push(text);
+            Ok(())
+        }
+    }
+
+    fn read(data: &str, strictness: Strictness) -> Result<Elements, TestError> {
+        let mut elements = Elements { state: ReadState::new(strictness, &["curve"]), seen: Vec::new() };
+        read_elements(data.as_bytes(), &mut elements)?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn test_read_elements() {
+        let elements = read("<a>\n  <b>text</b><curve/><curve/>\n</a>", Strictness::Strict).unwrap();
+        assert_eq!(elements.seen, vec!["/a", "a/b", "b=text", "a/curve", "curve=", "a/curve", "curve=", "a="]);
+        assert_eq!(elements.state.warnings.len(), 1);
+        assert_eq!(elements.state.warnings[0].to_string(),
+                   "line 2: <curve> elements are not supported and have been skipped");
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = read("<a>\n  <bad/>\n</a>", Strictness::Strict).err().unwrap();
+        assert!(matches!(e, TestError::At { line: 2, column: 3, .. }));
+
+        let elements = read("<a>\n  <bad/>\n</a>", Strictness::Lenient).unwrap();
+        assert_eq!(elements.state.warnings[0].line, Some(2));
+
+        assert!(matches!(read("<a><b></a>", Strictness::Lenient), Err(TestError::At { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::default();
+        assert!(budget.place(MAX_PLACED_FACES));
+        assert!(!budget.place(1));
+
+        let mut budget = Budget::default();
+        assert!((0..MAX_PLACEMENTS).all(|_| budget.place(0)));
+        assert!(!budget.place(0));
+    }
+
+    #[test]
+    fn test_colour_name() {
+        assert_eq!(colour_name(&[1.0, 0.0, 0.0, 1.0]), "#ff0000");
+        assert_eq!(colour_name(&[0.0, 2.0, 0.0, 0.5]), "#00ff0080");
+    }
+}